@@ -20,6 +20,12 @@ pub fn unix_micros() -> u64 {
     SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_micros() as u64
 }
 
+/// Returns true if sequence number `a` comes after `b`, accounting for wrap-around.
+#[inline]
+pub fn seq_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
 /// Wraps the given error in a boxed future.
 pub fn async_error<E: std::error::Error + Send + 'static, T>(
     e: E,
@@ -33,3 +39,16 @@ pub mod constants {
     pub const MiB: u32 = 1024 * KiB;
     pub const GiB: u32 = 1024 * MiB;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seq_wrap_around() {
+        assert!(seq_after(1, 0));
+        assert!(!seq_after(0, 0));
+        assert!(seq_after(0, u32::MAX));
+        assert!(!seq_after(u32::MAX, 0));
+    }
+}
@@ -0,0 +1,200 @@
+//! Deterministic simulation of distributed tests.
+//!
+//! A [`Runtime`] runs a test on a single-threaded Tokio runtime with a virtual clock, and gives it
+//! a simulated [`Network`] to create the transports of its sockets from. Time only advances when
+//! all tasks are idle, so timeouts and backoffs of minutes run instantly, and all randomness is
+//! derived from a single seed. Running a simulation again with the same seed replays the exact
+//! same interleaving of tasks, which makes rare reconnect and timeout bugs reproducible.
+//!
+//! Sources of randomness outside of the simulation, like `rand::thread_rng`, are not controlled
+//! by the seed.
+//!
+//! # Example
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use msg_sim::deterministic::Runtime;
+//!
+//! Runtime::from_env().run(|network| async move {
+//!     let server = network.transport("server");
+//!     let client = network.transport("client");
+//!
+//!     // Create sockets with the transports, and script faults
+//!     tokio::time::sleep(Duration::from_secs(10)).await;
+//!     network.partition("server", "client");
+//!     tokio::time::sleep(Duration::from_secs(60)).await;
+//!     network.heal("server", "client");
+//! });
+//! ```
+
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+};
+
+use tracing::info;
+
+mod network;
+pub use network::{Event, Network, NetworkStream, NetworkTransport};
+
+/// The environment variable from which [`Runtime::from_env`] reads the seed.
+pub const SEED_ENV: &str = "MSG_SIM_SEED";
+
+/// A runtime for deterministic simulations. See the [module docs](self) for more details.
+#[derive(Debug, Clone, Copy)]
+pub struct Runtime {
+    seed: u64,
+}
+
+impl Runtime {
+    /// Creates a runtime with the given seed.
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// Creates a runtime with the seed in the `MSG_SIM_SEED` environment variable, to replay a
+    /// failed simulation, or a random seed otherwise.
+    ///
+    /// # Panics
+    /// Panics if the environment variable is not a valid seed.
+    pub fn from_env() -> Self {
+        let seed = match std::env::var(SEED_ENV) {
+            Ok(seed) => seed.parse().expect("Invalid simulation seed"),
+            Err(_) => rand::random(),
+        };
+
+        Self::new(seed)
+    }
+
+    /// Returns the seed of the runtime.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Runs the simulation created by the given function to completion, on a new network seeded
+    /// by the runtime. All tasks spawned by the simulation are dropped when it completes.
+    ///
+    /// # Panics
+    /// Panics if the simulation panics, after printing the seed to replay it with.
+    pub fn run<F, Fut>(&self, f: F) -> Fut::Output
+    where
+        F: FnOnce(Network) -> Fut,
+        Fut: Future,
+    {
+        let seed = self.seed;
+        info!(seed, "Running deterministic simulation");
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .expect("Failed to build simulation runtime");
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            runtime.block_on(async move { f(Network::new(seed)).await })
+        }));
+
+        match result {
+            Ok(output) => output,
+            Err(panic) => {
+                eprintln!("Simulation failed, replay it with {SEED_ENV}={seed}");
+                panic::resume_unwind(panic)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use msg_transport::{Transport, TransportExt};
+    use rand::Rng;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        time::Instant,
+    };
+
+    use super::*;
+
+    #[test]
+    fn network_partition() {
+        Runtime::new(1).run(|network| async move {
+            let mut server = network.transport("server");
+            server.bind("service".into()).await.unwrap();
+
+            let mut client = network.transport("client");
+            let mut stream = client.connect("service".into()).await.unwrap();
+            let mut accepted = server.accept().await.unwrap();
+
+            network.partition("client", "server");
+
+            // Data stalls until the partition is healed
+            stream.write_all(b"ping").await.unwrap();
+            let mut buf = [0u8; 4];
+            let read = tokio::time::timeout(Duration::from_secs(5), accepted.read_exact(&mut buf));
+            assert!(read.await.is_err());
+
+            // New connections time out
+            let start = Instant::now();
+            let err = client.connect("service".into()).await.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+            assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+            network.heal("server", "client");
+            accepted.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"ping");
+        });
+    }
+
+    #[test]
+    fn network_crash() {
+        Runtime::new(2).run(|network| async move {
+            let mut server = network.transport("server");
+            server.bind("service".into()).await.unwrap();
+
+            let mut client = network.transport("client");
+            let mut stream = client.connect("service".into()).await.unwrap();
+
+            network.crash("server");
+
+            let err = stream.read_u8().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+
+            // The endpoint is released, and can only be bound again after the restart
+            let err = client.connect("service".into()).await.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+            assert!(server.bind("service".into()).await.is_err());
+
+            let mut server = network.transport("server");
+            server.bind("service".into()).await.unwrap();
+            assert!(client.connect("service".into()).await.is_ok());
+        });
+    }
+
+    #[test]
+    fn runtime_replays_seed() {
+        fn simulate(seed: u64) -> Vec<(Duration, Event)> {
+            Runtime::new(seed).run(|network| async move {
+                let mut server = network.transport("server");
+                server.bind("service".into()).await.unwrap();
+
+                let mut rng = network.rng();
+                for i in 0..10 {
+                    tokio::time::sleep(Duration::from_millis(rng.gen_range(0..1000))).await;
+                    let node = format!("client-{i}");
+                    if rng.gen_bool(0.5) {
+                        network.partition(&node, "server");
+                    }
+
+                    let _ = network.transport(node).connect("service".into()).await;
+                }
+
+                network.events()
+            })
+        }
+
+        assert_eq!(simulate(42), simulate(42));
+        assert_ne!(simulate(42), simulate(43));
+    }
+}
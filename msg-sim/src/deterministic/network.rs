@@ -0,0 +1,447 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{ready, Context, Poll, Waker},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf},
+    sync::mpsc,
+    time::Instant,
+};
+use tracing::debug;
+
+use msg_transport::{inproc::InprocAddr, PeerAddress, PeerMetadata, Transport, TransportExt};
+
+use crate::{SimulatedTransport, SimulationConfig};
+
+/// The size of the buffer of each direction of a connection, in bytes.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// How long connecting to a partitioned node takes before it fails.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Something that happened on the [`Network`]. The events of a simulation are the same for every
+/// run with the same seed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A node bound an endpoint.
+    Bind { node: String, addr: InprocAddr },
+    /// A node connected to an endpoint, or failed to.
+    Connect { node: String, addr: InprocAddr, result: Result<(), io::ErrorKind> },
+    /// The connections between two nodes were partitioned.
+    Partition { a: String, b: String },
+    /// The partition between two nodes was healed.
+    Heal { a: String, b: String },
+    /// A node crashed.
+    Crash { node: String },
+}
+
+/// An endpoint bound on the network.
+struct Endpoint {
+    /// The ID of the transport that bound the endpoint.
+    id: u64,
+    node: String,
+    incoming: mpsc::UnboundedSender<NetworkStream>,
+}
+
+struct State {
+    rng: StdRng,
+    start: Instant,
+    endpoints: BTreeMap<InprocAddr, Endpoint>,
+    /// The number of times each node crashed.
+    epochs: BTreeMap<String, u64>,
+    /// The partitioned pairs of nodes, in order.
+    partitions: BTreeSet<(String, String)>,
+    /// The tasks waiting on a connection, by connection ID and direction.
+    wakers: BTreeMap<(u64, bool), Waker>,
+    next_id: u64,
+    events: Vec<(Duration, Event)>,
+}
+
+impl State {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn epoch(&self, node: &str) -> u64 {
+        self.epochs.get(node).copied().unwrap_or_default()
+    }
+
+    fn is_partitioned(&self, a: &str, b: &str) -> bool {
+        self.partitions.contains(&pair(a, b))
+    }
+
+    fn record(&mut self, event: Event) {
+        debug!(?event, "Simulated network event");
+        self.events.push((self.start.elapsed(), event));
+    }
+
+    fn wake_all(&mut self) {
+        for (_, waker) in std::mem::take(&mut self.wakers) {
+            waker.wake();
+        }
+    }
+}
+
+/// Returns the given nodes in order.
+fn pair(a: &str, b: &str) -> (String, String) {
+    if a <= b {
+        (a.to_owned(), b.to_owned())
+    } else {
+        (b.to_owned(), a.to_owned())
+    }
+}
+
+/// A simulated in-memory network between named nodes, shared by all the transports created from
+/// it. Nodes can be partitioned from each other and crashed, and everything that happens on the
+/// network is recorded as an [`Event`].
+///
+/// All randomness is derived from the seed of the network, so that a simulation can be replayed
+/// exactly. It's usually created by a [`Runtime`](super::Runtime).
+#[derive(Clone)]
+pub struct Network {
+    seed: u64,
+    state: Arc<Mutex<State>>,
+}
+
+impl std::fmt::Debug for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Network").field("seed", &self.seed).finish_non_exhaustive()
+    }
+}
+
+impl Network {
+    /// Creates a new network with the given seed. Must be called within a Tokio runtime.
+    pub fn new(seed: u64) -> Self {
+        let state = State {
+            rng: StdRng::seed_from_u64(seed),
+            start: Instant::now(),
+            endpoints: BTreeMap::new(),
+            epochs: BTreeMap::new(),
+            partitions: BTreeSet::new(),
+            wakers: BTreeMap::new(),
+            next_id: 0,
+            events: Vec::new(),
+        };
+
+        Self { seed, state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// Returns the seed of the network.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("Network lock poisoned")
+    }
+
+    /// Returns a new transport on the given node.
+    pub fn transport(&self, node: impl Into<String>) -> NetworkTransport {
+        let node = node.into();
+        let epoch = self.state().epoch(&node);
+
+        NetworkTransport {
+            network: self.clone(),
+            node,
+            epoch,
+            id: None,
+            addr: None,
+            incoming: None,
+        }
+    }
+
+    /// Returns a new transport on the given node, whose connections are impaired according to the
+    /// config. The impairments are seeded from the network.
+    pub fn simulated(
+        &self,
+        node: impl Into<String>,
+        config: SimulationConfig,
+    ) -> SimulatedTransport<NetworkTransport> {
+        let seed = self.state().rng.gen();
+        SimulatedTransport::new(self.transport(node), config).with_seed(seed)
+    }
+
+    /// Returns a new random number generator, seeded from the network.
+    pub fn rng(&self) -> StdRng {
+        StdRng::seed_from_u64(self.state().rng.gen())
+    }
+
+    /// Partitions the given nodes from each other. Data sent on their connections isn't received,
+    /// and new connections between them time out, until the partition is healed.
+    pub fn partition(&self, a: &str, b: &str) {
+        let mut state = self.state();
+        state.partitions.insert(pair(a, b));
+        state.record(Event::Partition { a: a.to_owned(), b: b.to_owned() });
+    }
+
+    /// Heals the partition between the given nodes.
+    pub fn heal(&self, a: &str, b: &str) {
+        let mut state = self.state();
+        state.partitions.remove(&pair(a, b));
+        state.record(Event::Heal { a: a.to_owned(), b: b.to_owned() });
+        state.wake_all();
+    }
+
+    /// Crashes the given node, as if its process was restarted: all of its connections are reset,
+    /// and its endpoints are released. Transports created on the node before the crash can't
+    /// accept or open connections anymore, so the node is restarted by creating new sockets on it.
+    pub fn crash(&self, node: &str) {
+        let mut state = self.state();
+        *state.epochs.entry(node.to_owned()).or_default() += 1;
+        state.endpoints.retain(|_, endpoint| endpoint.node != node);
+        state.record(Event::Crash { node: node.to_owned() });
+        state.wake_all();
+    }
+
+    /// Returns the events that happened on the network so far, with the time since the network
+    /// was created.
+    pub fn events(&self) -> Vec<(Duration, Event)> {
+        self.state().events.clone()
+    }
+}
+
+/// A transport on a node of a simulated [`Network`], created with [`Network::transport`].
+/// Endpoints are addressed by name, like with the [`Inproc`](msg_transport::inproc::Inproc)
+/// transport.
+#[derive(Debug)]
+pub struct NetworkTransport {
+    network: Network,
+    node: String,
+    /// The number of crashes of the node when the transport was created.
+    epoch: u64,
+    /// The ID of the bound endpoint.
+    id: Option<u64>,
+    addr: Option<InprocAddr>,
+    incoming: Option<mpsc::UnboundedReceiver<NetworkStream>>,
+}
+
+impl NetworkTransport {
+    /// Returns the node of the transport.
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+}
+
+impl Drop for NetworkTransport {
+    fn drop(&mut self) {
+        if let (Some(addr), Some(id)) = (&self.addr, self.id) {
+            let mut state = self.network.state();
+            if state.endpoints.get(addr).is_some_and(|endpoint| endpoint.id == id) {
+                state.endpoints.remove(addr);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transport<InprocAddr> for NetworkTransport {
+    type Io = NetworkStream;
+
+    type Error = io::Error;
+
+    type Connect = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+    type Accept = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+
+    fn local_addr(&self) -> Option<InprocAddr> {
+        self.addr.clone()
+    }
+
+    async fn bind(&mut self, addr: InprocAddr) -> Result<(), Self::Error> {
+        let mut state = self.network.state();
+        if state.epoch(&self.node) != self.epoch {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "Node crashed"));
+        }
+
+        if state.endpoints.contains_key(&addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("Address {addr} is already bound"),
+            ));
+        }
+
+        // Release the previous endpoint, if any
+        if let Some(previous) = self.addr.take() {
+            state.endpoints.remove(&previous);
+        }
+
+        let id = state.next_id();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let endpoint = Endpoint { id, node: self.node.clone(), incoming: tx };
+        state.endpoints.insert(addr.clone(), endpoint);
+        state.record(Event::Bind { node: self.node.clone(), addr: addr.clone() });
+
+        self.id = Some(id);
+        self.addr = Some(addr);
+        self.incoming = Some(rx);
+        Ok(())
+    }
+
+    fn connect(&mut self, addr: InprocAddr) -> Self::Connect {
+        let mut state = self.network.state();
+
+        let endpoint = state
+            .endpoints
+            .get(&addr)
+            .filter(|_| state.epoch(&self.node) == self.epoch)
+            .map(|endpoint| (endpoint.node.clone(), endpoint.incoming.clone()));
+
+        let result = match endpoint {
+            None => Err(io::ErrorKind::ConnectionRefused),
+            Some((ref remote, _)) if state.is_partitioned(&self.node, remote) => {
+                Err(io::ErrorKind::TimedOut)
+            }
+            Some(_) => Ok(()),
+        };
+
+        state.record(Event::Connect { node: self.node.clone(), addr: addr.clone(), result });
+
+        match (result, endpoint) {
+            (Ok(()), Some((remote, incoming))) => {
+                let (local_stream, remote_stream) = tokio::io::duplex(BUFFER_SIZE);
+                let remote_epoch = state.epoch(&remote);
+
+                let remote_stream = NetworkStream {
+                    id: state.next_id(),
+                    network: self.network.clone(),
+                    peer: InprocAddr::new(format!("{}:{}", self.node, state.next_id())),
+                    local: remote.clone(),
+                    remote: self.node.clone(),
+                    epochs: (remote_epoch, self.epoch),
+                    stream: remote_stream,
+                };
+
+                let local_stream = NetworkStream {
+                    id: state.next_id(),
+                    network: self.network.clone(),
+                    peer: addr,
+                    local: self.node.clone(),
+                    remote,
+                    epochs: (self.epoch, remote_epoch),
+                    stream: local_stream,
+                };
+
+                drop(state);
+                if incoming.send(remote_stream).is_err() {
+                    return Box::pin(async { Err(io::ErrorKind::ConnectionRefused.into()) });
+                }
+
+                Box::pin(async move { Ok(local_stream) })
+            }
+            (Err(io::ErrorKind::TimedOut), _) => Box::pin(async {
+                tokio::time::sleep(CONNECT_TIMEOUT).await;
+                Err(io::ErrorKind::TimedOut.into())
+            }),
+            (Err(kind), _) => Box::pin(async move { Err(kind.into()) }),
+            (Ok(()), None) => unreachable!("Connected without an endpoint"),
+        }
+    }
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Accept> {
+        let this = self.get_mut();
+
+        let Some(ref mut incoming) = this.incoming else {
+            return Poll::Ready(Box::pin(async { Err(io::ErrorKind::NotConnected.into()) }));
+        };
+
+        // Once the endpoint is released by a crash, no connections come in anymore
+        match ready!(incoming.poll_recv(cx)) {
+            Some(stream) => Poll::Ready(Box::pin(async move { Ok(stream) })),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl TransportExt<InprocAddr> for NetworkTransport {}
+
+/// A connection between two nodes of a simulated [`Network`].
+pub struct NetworkStream {
+    /// The ID of the connection, which orders the wakeups of its tasks.
+    id: u64,
+    network: Network,
+    peer: InprocAddr,
+    local: String,
+    remote: String,
+    /// The number of crashes of the local and remote node when the connection was opened.
+    epochs: (u64, u64),
+    stream: DuplexStream,
+}
+
+impl NetworkStream {
+    /// Returns an error if the connection was reset by a crash, or `Pending` when reading from a
+    /// partitioned connection. Writes are still buffered until the buffer is full. Registers the
+    /// task to be woken up when that changes.
+    fn poll_link(&self, cx: &mut Context<'_>, write: bool) -> Poll<io::Result<()>> {
+        let mut state = self.network.state();
+
+        if (state.epoch(&self.local), state.epoch(&self.remote)) != self.epochs {
+            return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+        }
+
+        state.wakers.insert((self.id, write), cx.waker().clone());
+
+        if !write && state.is_partitioned(&self.local, &self.remote) {
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+impl Drop for NetworkStream {
+    fn drop(&mut self) {
+        let mut state = self.network.state();
+        state.wakers.remove(&(self.id, false));
+        state.wakers.remove(&(self.id, true));
+    }
+}
+
+impl AsyncRead for NetworkStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_link(cx, false))?;
+        Pin::new(&mut this.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for NetworkStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_link(cx, true))?;
+        Pin::new(&mut this.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_link(cx, true))?;
+        Pin::new(&mut this.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+impl PeerAddress<InprocAddr> for NetworkStream {
+    fn peer_addr(&self) -> Result<InprocAddr, io::Error> {
+        Ok(self.peer.clone())
+    }
+}
+
+impl PeerMetadata for NetworkStream {}
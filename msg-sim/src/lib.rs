@@ -6,20 +6,38 @@ use std::{collections::HashMap, io, net::IpAddr, time::Duration};
 mod protocol;
 pub use protocol::Protocol;
 
+#[cfg(feature = "deterministic")]
+pub mod deterministic;
+
+pub mod transport;
+pub use transport::{SimulatedStream, SimulatedTransport};
+
 #[cfg(target_os = "macos")]
 pub mod dummynet;
 #[cfg(target_os = "macos")]
 use dummynet::{PacketFilter, Pipe};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 #[allow(unused)]
 pub struct SimulationConfig {
     /// The latency of the connection.
     pub latency: Option<Duration>,
+    /// The maximum random delay added on top of the latency. Only applied by the
+    /// [`SimulatedTransport`].
+    pub jitter: Option<Duration>,
     /// The bandwidth in Kbps.
     pub bw: Option<u64>,
     /// The packet loss rate in percent.
     pub plr: Option<f64>,
+    /// How long the stream stalls when a packet is lost, before it's retransmitted. Only applied
+    /// by the [`SimulatedTransport`], which defaults to 200ms.
+    pub stall: Option<Duration>,
+    /// The probability in percent that the connection is reset on each packet. Only applied by
+    /// the [`SimulatedTransport`].
+    pub disconnect_rate: Option<f64>,
+    /// The number of bytes after which the connection is reset. Only applied by the
+    /// [`SimulatedTransport`].
+    pub disconnect_after: Option<u64>,
     /// The supported protocols.
     pub protocols: Vec<Protocol>,
 }
@@ -0,0 +1,289 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use rand::{rngs::StdRng, SeedableRng};
+
+use msg_transport::{Address, Transport, TransportExt};
+
+use crate::SimulationConfig;
+
+mod stream;
+pub use stream::SimulatedStream;
+
+/// A transport that wraps another transport, and impairs all of its connections according to a
+/// [`SimulationConfig`]. Unlike the [`Simulator`](crate::Simulator), it works fully in userspace,
+/// so it doesn't need any privileges and works on any platform.
+///
+/// The impairments are applied to the stream of each connection:
+/// - Data is received after the `latency`, plus a random delay of up to `jitter`. The order of the
+///   data is preserved, like on a real stream.
+/// - Data is sent in packets of at most 1460 bytes, at most at the bandwidth of `bw`.
+/// - Each packet is lost with a probability of `plr`, in which case the stream stalls for `stall`
+///   before the packet is retransmitted.
+/// - Each packet resets the connection with a probability of `disconnect_rate`, and the connection
+///   is reset once `disconnect_after` bytes have been sent and received.
+///
+/// To impair both directions of a connection, wrap the transports of both peers.
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use msg_sim::{SimulatedTransport, SimulationConfig};
+/// use msg_transport::tcp::Tcp;
+///
+/// let transport = SimulatedTransport::new(
+///     Tcp::default(),
+///     SimulationConfig { latency: Some(Duration::from_millis(50)), ..Default::default() },
+/// );
+/// ```
+#[derive(Debug)]
+pub struct SimulatedTransport<T> {
+    inner: T,
+    config: Arc<SimulationConfig>,
+    /// The random number generator from which each connection derives its own.
+    rng: StdRng,
+}
+
+impl<T> SimulatedTransport<T> {
+    /// Wraps the given transport.
+    pub fn new(inner: T, config: SimulationConfig) -> Self {
+        Self { inner, config: Arc::new(config), rng: StdRng::from_entropy() }
+    }
+
+    /// Seeds the random number generator of the transport, which makes the jitter and faults of
+    /// its connections reproducible.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Returns the config of the simulation.
+    pub fn config(&self) -> &SimulationConfig {
+        &self.config
+    }
+
+    /// Returns a new random number generator for a connection.
+    fn connection_rng(&mut self) -> StdRng {
+        StdRng::from_rng(&mut self.rng).expect("StdRng never fails")
+    }
+}
+
+#[async_trait]
+impl<T, A> Transport<A> for SimulatedTransport<T>
+where
+    T: Transport<A> + Send + Unpin,
+    T::Io: 'static,
+    T::Connect: 'static,
+    T::Accept: 'static,
+    A: Address,
+{
+    type Io = SimulatedStream<T::Io>;
+
+    type Error = T::Error;
+
+    type Connect = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+    type Accept = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+
+    fn local_addr(&self) -> Option<A> {
+        self.inner.local_addr()
+    }
+
+    async fn bind(&mut self, addr: A) -> Result<(), Self::Error> {
+        self.inner.bind(addr).await
+    }
+
+    fn connect(&mut self, addr: A) -> Self::Connect {
+        let connect = self.inner.connect(addr);
+        let config = Arc::clone(&self.config);
+        let rng = self.connection_rng();
+
+        Box::pin(async move {
+            let io = connect.await?;
+            Ok(SimulatedStream::new(io, config, rng))
+        })
+    }
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Accept> {
+        let this = self.get_mut();
+
+        let accept = ready!(Pin::new(&mut this.inner).poll_accept(cx));
+        let config = Arc::clone(&this.config);
+        let rng = this.connection_rng();
+
+        Poll::Ready(Box::pin(async move {
+            let io = accept.await?;
+            Ok(SimulatedStream::new(io, config, rng))
+        }))
+    }
+}
+
+impl<T, A> TransportExt<A> for SimulatedTransport<T>
+where
+    T: Transport<A> + Send + Unpin,
+    T::Io: 'static,
+    T::Connect: 'static,
+    T::Accept: 'static,
+    A: Address,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use msg_transport::inproc::{Inproc, InprocStream};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        time::Instant,
+    };
+
+    use super::{stream::MAX_PACKET_SIZE, *};
+
+    /// Returns a connected client and server stream, both impaired by the given config with a
+    /// fixed seed.
+    async fn connect(
+        name: &str,
+        config: SimulationConfig,
+    ) -> (SimulatedStream<InprocStream>, SimulatedStream<InprocStream>) {
+        let mut server = SimulatedTransport::new(Inproc::default(), config.clone()).with_seed(1);
+        server.bind(name.into()).await.unwrap();
+
+        let mut client = SimulatedTransport::new(Inproc::default(), config).with_seed(2);
+        let client = client.connect(name.into()).await.unwrap();
+        let server = server.accept().await.unwrap();
+
+        (client, server)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn simulated_latency() {
+        let latency = Duration::from_millis(50);
+        let config = SimulationConfig { latency: Some(latency), ..Default::default() };
+        let (mut client, mut server) = connect("simulated-latency", config).await;
+
+        let start = Instant::now();
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        assert_eq!(start.elapsed(), latency);
+
+        // The latency applies to each direction
+        server.write_all(b"pong").await.unwrap();
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+        assert_eq!(start.elapsed(), latency * 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn simulated_jitter_keeps_order() {
+        let config = SimulationConfig {
+            latency: Some(Duration::from_millis(10)),
+            jitter: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let (mut client, mut server) = connect("simulated-jitter", config).await;
+
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        let writer = tokio::spawn(async move {
+            for chunk in data.chunks(1000) {
+                client.write_all(chunk).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            client
+        });
+
+        let mut received = vec![0u8; 100_000];
+        server.read_exact(&mut received).await.unwrap();
+        assert!(received.iter().enumerate().all(|(i, b)| *b == i as u8));
+        writer.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn simulated_bandwidth() {
+        // 8 Kbps is 1000 bytes per second
+        let config = SimulationConfig { bw: Some(8), ..Default::default() };
+        let (mut client, mut server) = connect("simulated-bandwidth", config).await;
+
+        let start = Instant::now();
+        let writer = tokio::spawn(async move {
+            client.write_all(&[0u8; 5000]).await.unwrap();
+            client
+        });
+
+        let mut buf = [0u8; 5000];
+        server.read_exact(&mut buf).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(4));
+        writer.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn simulated_packet_loss_stalls() {
+        let stall = Duration::from_millis(300);
+        let config =
+            SimulationConfig { plr: Some(100.0), stall: Some(stall), ..Default::default() };
+        let (mut client, _server) = connect("simulated-stall", config).await;
+
+        // Every packet is lost once, and written after the stall
+        let start = Instant::now();
+        client.write_all(b"lost").await.unwrap();
+        assert_eq!(start.elapsed(), stall);
+
+        client.write_all(&[0u8; MAX_PACKET_SIZE * 2]).await.unwrap();
+        assert_eq!(start.elapsed(), stall * 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn simulated_packet_loss_rate() {
+        const PACKETS: u32 = 1000;
+
+        let stall = Duration::from_millis(100);
+        let config =
+            SimulationConfig { plr: Some(10.0), stall: Some(stall), ..Default::default() };
+        let (mut client, mut server) = connect("simulated-loss-rate", config).await;
+
+        let reader = tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_PACKET_SIZE * PACKETS as usize];
+            server.read_exact(&mut buf).await.unwrap();
+        });
+
+        // Each lost packet stalls the stream once, so the elapsed time counts the losses
+        let start = Instant::now();
+        for _ in 0..PACKETS {
+            client.write_all(&[0u8; MAX_PACKET_SIZE]).await.unwrap();
+        }
+        reader.await.unwrap();
+
+        let lost = start.elapsed().as_millis() / stall.as_millis();
+        let rate = lost as f64 / PACKETS as f64 * 100.0;
+        assert!((7.0..=13.0).contains(&rate), "observed loss rate of {rate}%");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn simulated_disconnect() {
+        let config = SimulationConfig { disconnect_after: Some(10), ..Default::default() };
+        let (mut client, mut server) = connect("simulated-disconnect", config).await;
+
+        client.write_all(&[1u8; 6]).await.unwrap();
+        let err = client.write_all(&[2u8; 6]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+
+        // The server only receives the bytes sent before the disconnect, and then is reset too
+        let mut received = [0u8; 10];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, [1, 1, 1, 1, 1, 1, 2, 2, 2, 2]);
+        let err = server.read_u8().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+
+        let config = SimulationConfig { disconnect_rate: Some(100.0), ..Default::default() };
+        let (mut client, _server) = connect("simulated-disconnect-rate", config).await;
+        let err = client.write_all(b"reset").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+}
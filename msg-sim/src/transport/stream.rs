@@ -0,0 +1,282 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use bytes::{Buf, Bytes};
+use rand::{rngs::StdRng, Rng};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{sleep_until, Instant, Sleep},
+};
+use tracing::debug;
+
+use msg_transport::{Address, ConnectionMetadata, PeerAddress, PeerMetadata};
+
+use crate::SimulationConfig;
+
+/// The maximum size of a packet, like the payload of a TCP segment on an Ethernet link.
+pub(super) const MAX_PACKET_SIZE: usize = 1460;
+
+/// How long the stream stalls when a packet is lost, if not configured.
+const DEFAULT_STALL: Duration = Duration::from_millis(200);
+
+/// The size of the chunks read from the underlying stream.
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+/// The maximum amount of delayed data that is buffered before the underlying stream isn't read
+/// anymore, which keeps backpressure intact.
+const MAX_DELAYED_BYTES: usize = 1024 * 1024;
+
+/// A stream impaired according to a [`SimulationConfig`], created by the
+/// [`SimulatedTransport`](super::SimulatedTransport).
+pub struct SimulatedStream<S> {
+    inner: S,
+    config: Arc<SimulationConfig>,
+    rng: StdRng,
+    /// Data read from the underlying stream, with the time at which it's received.
+    delayed: VecDeque<(Instant, Bytes)>,
+    delayed_bytes: usize,
+    /// The time at which the end of the underlying stream is received, once it has been read.
+    eof: Option<Instant>,
+    /// The time at which the next packet can be written.
+    next_write: Option<Instant>,
+    /// Whether the packet being written has already been accepted, in which case its fate has
+    /// been rolled and it's only waiting to be written.
+    accepted: bool,
+    /// The number of bytes that can still be sent and received before the connection is reset.
+    budget: Option<u64>,
+    /// Whether the connection has been reset.
+    reset: bool,
+    read_timer: Pin<Box<Sleep>>,
+    write_timer: Pin<Box<Sleep>>,
+}
+
+impl<S> SimulatedStream<S> {
+    pub(super) fn new(inner: S, config: Arc<SimulationConfig>, rng: StdRng) -> Self {
+        let budget = config.disconnect_after;
+
+        Self {
+            inner,
+            config,
+            rng,
+            delayed: VecDeque::new(),
+            delayed_bytes: 0,
+            eof: None,
+            next_write: None,
+            accepted: false,
+            budget,
+            reset: false,
+            read_timer: Box::pin(sleep_until(Instant::now())),
+            write_timer: Box::pin(sleep_until(Instant::now())),
+        }
+    }
+
+    /// Returns a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the time at which data read now is received, after the latency and jitter. Data
+    /// is never received before data that was read earlier.
+    fn receive_time(&mut self) -> Instant {
+        let mut delay = self.config.latency.unwrap_or_default();
+        if let Some(jitter) = self.config.jitter.filter(|jitter| !jitter.is_zero()) {
+            delay += self.rng.gen_range(Duration::ZERO..=jitter);
+        }
+
+        let at = Instant::now() + delay;
+        match self.delayed.back() {
+            Some((last, _)) => at.max(*last),
+            None => at,
+        }
+    }
+
+    /// Returns `true` with the given probability in percent.
+    fn roll(&mut self, percent: Option<f64>) -> bool {
+        percent.is_some_and(|percent| self.rng.gen_bool((percent / 100.0).clamp(0.0, 1.0)))
+    }
+
+    /// Takes up to `n` bytes from the budget of the connection, and returns how many can be
+    /// transferred.
+    fn take_budget(&mut self, n: usize) -> usize {
+        match self.budget {
+            Some(ref mut budget) => {
+                let n = n.min(*budget as usize);
+                *budget -= n as u64;
+                n
+            }
+            None => n,
+        }
+    }
+
+    fn reset(&mut self) -> io::Error {
+        if !self.reset {
+            debug!("Simulated connection reset");
+            self.reset = true;
+        }
+
+        io::ErrorKind::ConnectionReset.into()
+    }
+}
+
+impl<S: AsyncRead + Unpin> SimulatedStream<S> {
+    /// Reads all available data from the underlying stream into the delayed buffer.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+
+        while self.eof.is_none() && self.delayed_bytes < MAX_DELAYED_BYTES {
+            let mut buf = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut self.inner).poll_read(cx, &mut buf) {
+                Poll::Ready(Ok(())) if buf.filled().is_empty() => {
+                    self.eof = Some(self.receive_time());
+                }
+                Poll::Ready(Ok(())) => {
+                    let n = self.take_budget(buf.filled().len());
+                    if n > 0 {
+                        let at = self.receive_time();
+                        self.delayed.push_back((at, Bytes::copy_from_slice(&buf.filled()[..n])));
+                        self.delayed_bytes += n;
+                    }
+                }
+                Poll::Ready(Err(e)) => return Err(e),
+                Poll::Pending => break,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for SimulatedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.reset {
+                return Poll::Ready(Err(this.reset()));
+            }
+
+            this.poll_fill(cx)?;
+
+            let now = Instant::now();
+            let at = match this.delayed.front_mut() {
+                Some((at, data)) if *at <= now => {
+                    let n = data.len().min(buf.remaining());
+                    buf.put_slice(&data[..n]);
+                    data.advance(n);
+                    this.delayed_bytes -= n;
+
+                    if data.is_empty() {
+                        this.delayed.pop_front();
+                    }
+
+                    return Poll::Ready(Ok(()));
+                }
+                Some((at, _)) => *at,
+                // All data within the budget has been received
+                None if this.budget == Some(0) => return Poll::Ready(Err(this.reset())),
+                None => match this.eof {
+                    Some(at) if at <= now => return Poll::Ready(Ok(())),
+                    Some(at) => at,
+                    None => return Poll::Pending,
+                },
+            };
+
+            this.read_timer.as_mut().reset(at);
+            ready!(this.read_timer.as_mut().poll(cx));
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for SimulatedStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.reset {
+                return Poll::Ready(Err(this.reset()));
+            }
+
+            if let Some(at) = this.next_write.filter(|at| *at > Instant::now()) {
+                this.write_timer.as_mut().reset(at);
+                ready!(this.write_timer.as_mut().poll(cx));
+            }
+
+            // The fate of a packet is rolled once, when it's accepted, and not on every poll
+            if this.accepted {
+                break;
+            }
+
+            this.accepted = true;
+            let lost = this.roll(this.config.plr);
+
+            if this.roll(this.config.disconnect_rate) {
+                return Poll::Ready(Err(this.reset()));
+            }
+
+            // The packet is lost, and retransmitted after the stall
+            if lost {
+                let stall = this.config.stall.unwrap_or(DEFAULT_STALL);
+                this.next_write = Some(Instant::now() + stall);
+            }
+        }
+
+        let len = buf.len().min(MAX_PACKET_SIZE);
+        let len = match this.budget {
+            Some(0) => return Poll::Ready(Err(this.reset())),
+            Some(budget) => len.min(budget as usize),
+            None => len,
+        };
+
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..len]))?;
+        this.accepted = false;
+        this.take_budget(n);
+
+        if let Some(bw) = this.config.bw.filter(|bw| *bw > 0) {
+            // The bandwidth is in Kbps
+            let transmission = Duration::from_secs_f64((n * 8) as f64 / (bw * 1000) as f64);
+            this.next_write = Some(Instant::now() + transmission);
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.reset {
+            return Poll::Ready(Err(this.reset()));
+        }
+
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<S: PeerAddress<A>, A: Address> PeerAddress<A> for SimulatedStream<S> {
+    fn peer_addr(&self) -> Result<A, io::Error> {
+        self.inner.peer_addr()
+    }
+}
+
+impl<S: PeerMetadata> PeerMetadata for SimulatedStream<S> {
+    fn metadata(&self) -> ConnectionMetadata {
+        self.inner.metadata()
+    }
+}
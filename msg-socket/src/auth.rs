@@ -0,0 +1,345 @@
+use std::{io, sync::Arc};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use rustc_hash::FxHashMap;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+use tracing::{debug, error};
+
+use msg_transport::{Address, ConnectionMetadata, PeerMetadata};
+use msg_wire::auth;
+
+/// A synchronous authenticator, which only sees the token sent by the client. The token becomes
+/// the ID of the client's [`Identity`].
+pub trait Authenticator: Send + Sync + Unpin + 'static {
+    fn authenticate(&self, id: &Bytes) -> bool;
+}
+
+/// An asynchronous authenticator, which can take its time to validate the token (e.g. by
+/// calling out to an auth service) without blocking the socket driver. Every
+/// [`Authenticator`] is also an [`AsyncAuthenticator`].
+#[async_trait]
+pub trait AsyncAuthenticator<A: Address>: Send + Sync + 'static {
+    /// Authenticates the client. Returns the identity of the client, or the reason it was
+    /// rejected.
+    async fn authenticate(&self, request: &AuthRequest<A>) -> Result<Identity, String>;
+
+    /// Returns the secret shared with the client with the given ID, for clients that
+    /// authenticate with the challenge-response handshake instead of sending a token. The
+    /// identity of these clients is their ID. Returns `None` by default, which rejects them.
+    async fn shared_secret(&self, _id: &Bytes) -> Option<Bytes> {
+        None
+    }
+}
+
+#[async_trait]
+impl<A: Address, T: Authenticator> AsyncAuthenticator<A> for T {
+    async fn authenticate(&self, request: &AuthRequest<A>) -> Result<Identity, String> {
+        if Authenticator::authenticate(self, request.token()) {
+            Ok(Identity::new(request.token().clone()))
+        } else {
+            Err("Authentication failed".to_string())
+        }
+    }
+}
+
+/// An authenticator for clients that authenticate with a challenge-response handshake, using a
+/// secret shared with the server (see
+/// [`SubOptions::auth_secret`](crate::SubOptions::auth_secret)). The secret is never sent over
+/// the wire. Clients that send a plain token are rejected.
+#[derive(Debug, Default)]
+pub struct SharedSecrets {
+    secrets: FxHashMap<Bytes, Bytes>,
+}
+
+impl SharedSecrets {
+    /// Creates a new, empty set of shared secrets.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the secret shared with the client with the given ID.
+    pub fn with_secret(mut self, id: impl Into<Bytes>, secret: impl Into<Bytes>) -> Self {
+        self.secrets.insert(id.into(), secret.into());
+        self
+    }
+}
+
+#[async_trait]
+impl<A: Address> AsyncAuthenticator<A> for SharedSecrets {
+    async fn authenticate(&self, _request: &AuthRequest<A>) -> Result<Identity, String> {
+        Err("Token authentication is disabled".to_string())
+    }
+
+    async fn shared_secret(&self, id: &Bytes) -> Option<Bytes> {
+        self.secrets.get(id).cloned()
+    }
+}
+
+/// The credentials a client authenticates with.
+#[derive(Clone)]
+pub(crate) enum Credentials {
+    /// A token, which is sent to the server as is.
+    Token(Bytes),
+    /// A client ID and a secret shared with the server, used for the challenge-response
+    /// handshake.
+    Secret { id: Bytes, secret: Bytes },
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Token(token) => f.debug_tuple("Token").field(token).finish(),
+            Self::Secret { id, .. } => {
+                f.debug_struct("Secret").field("id", id).finish_non_exhaustive()
+            }
+        }
+    }
+}
+
+/// An authentication request from a connecting client.
+#[derive(Debug, Clone)]
+pub struct AuthRequest<A: Address> {
+    /// The address of the client.
+    addr: A,
+    /// The token sent by the client.
+    token: Bytes,
+    /// The metadata of the connection, as exposed by the transport.
+    metadata: ConnectionMetadata,
+}
+
+impl<A: Address> AuthRequest<A> {
+    /// Returns the address of the client.
+    pub fn addr(&self) -> &A {
+        &self.addr
+    }
+
+    /// Returns the token sent by the client.
+    pub fn token(&self) -> &Bytes {
+        &self.token
+    }
+
+    /// Returns the metadata of the connection (e.g. the TLS certificates of the client).
+    pub fn metadata(&self) -> &ConnectionMetadata {
+        &self.metadata
+    }
+}
+
+/// The identity of an authenticated client, passed to the [`Authorizer`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    /// The ID of the client.
+    id: Bytes,
+    /// Additional attributes of the client, like the claims of a JWT.
+    attributes: FxHashMap<String, String>,
+}
+
+impl Identity {
+    /// Creates a new identity with the given ID.
+    pub fn new(id: impl Into<Bytes>) -> Self {
+        Self { id: id.into(), attributes: FxHashMap::default() }
+    }
+
+    /// Returns the identity of a client that connected without authenticating: its static public
+    /// key if the transport authenticated it (e.g. with [`Noise`](msg_transport::noise::Noise)), or
+    /// an empty identity otherwise.
+    pub(crate) fn from_metadata(metadata: &ConnectionMetadata) -> Self {
+        metadata
+            .peer_public_key()
+            .map(|key| Self::new(Bytes::copy_from_slice(key)))
+            .unwrap_or_default()
+    }
+
+    /// Adds an attribute to the identity.
+    pub fn with_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.attributes.insert(key.into(), value.into());
+        self
+    }
+
+    /// Returns the ID of the client. If authentication is disabled, this is the static public key
+    /// of the client if the transport authenticated it, or empty otherwise.
+    pub fn id(&self) -> &Bytes {
+        &self.id
+    }
+
+    /// Returns the value of the given attribute.
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.get(key).map(String::as_str)
+    }
+}
+
+/// Decides what an authenticated client is allowed to do. The identity is the one returned by the
+/// socket's authenticator, or an empty identity if the socket has none. Both checks allow
+/// everything by default, so only the ones relevant to the socket need to be implemented.
+pub trait Authorizer: Send + Sync + Unpin + 'static {
+    /// Returns `true` if the client may subscribe to the given topic. Consulted by the
+    /// [`PubSocket`](crate::PubSocket) on every subscription, including catch-up subscriptions.
+    /// The topic is the one requested by the subscriber, so it may contain wildcards
+    /// (e.g. `tenant_b.>`).
+    fn authorize_subscribe(&self, _identity: &Identity, _topic: &str) -> bool {
+        true
+    }
+
+    /// Returns `true` if the client may send the given request. Consulted by the
+    /// [`RepSocket`](crate::RepSocket) on every request, before it is yielded by the socket.
+    /// Unauthorized requests are responded to with
+    /// [`ERR_UNAUTHORIZED`](msg_wire::reqrep::ERR_UNAUTHORIZED).
+    fn authorize_request(&self, _identity: &Identity, _request: &Bytes) -> bool {
+        true
+    }
+}
+
+/// An error that occurred while authenticating a client.
+#[derive(Debug, Error)]
+pub(crate) enum AuthError {
+    #[error("IO error: {0:?}")]
+    Io(#[from] io::Error),
+    #[error("Wire protocol error: {0:?}")]
+    Wire(#[from] auth::Error),
+    #[error("Socket closed")]
+    SocketClosed,
+    #[error("{0}")]
+    Rejected(String),
+}
+
+pub(crate) struct AuthResult<S: AsyncRead + AsyncWrite, A: Address> {
+    pub(crate) identity: Identity,
+    pub(crate) addr: A,
+    pub(crate) stream: S,
+}
+
+/// Runs the server side of the authentication handshake on a new connection. If the client is
+/// rejected, a reject message is sent and the connection is closed.
+pub(crate) async fn authenticate<S, A>(
+    stream: S,
+    addr: A,
+    authenticator: Arc<dyn AsyncAuthenticator<A>>,
+) -> Result<AuthResult<S, A>, AuthError>
+where
+    S: AsyncRead + AsyncWrite + PeerMetadata + Unpin,
+    A: Address,
+{
+    let metadata = stream.metadata();
+    let mut conn = Framed::new(stream, auth::Codec::new_server());
+
+    debug!("Waiting for auth");
+    // Wait for the response
+    let auth = conn.next().await.ok_or(AuthError::SocketClosed)??;
+
+    debug!("Auth received: {:?}", auth);
+
+    let result = match auth {
+        auth::Message::Auth(token) => {
+            let request = AuthRequest { addr: addr.clone(), token, metadata };
+            authenticator.authenticate(&request).await
+        }
+        auth::Message::Hello(id) => challenge(&mut conn, id, authenticator.as_ref()).await?,
+        _ => Err("Invalid auth message".to_string()),
+    };
+
+    // If authentication fails, send a reject message and close the connection
+    let identity = match result {
+        Ok(identity) => identity,
+        Err(reason) => {
+            conn.send(auth::Message::Reject).await?;
+            conn.flush().await?;
+            conn.close().await?;
+            return Err(AuthError::Rejected(reason));
+        }
+    };
+
+    // Send ack
+    conn.send(auth::Message::Ack).await?;
+    conn.flush().await?;
+
+    Ok(AuthResult { identity, addr, stream: conn.into_inner() })
+}
+
+/// Sends a challenge to the client with the given ID, and verifies its response. Returns the
+/// identity of the client, or the reason it was rejected.
+async fn challenge<S, A>(
+    conn: &mut Framed<S, auth::Codec>,
+    id: Bytes,
+    authenticator: &dyn AsyncAuthenticator<A>,
+) -> Result<Result<Identity, String>, AuthError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    A: Address,
+{
+    let Some(secret) = authenticator.shared_secret(&id).await else {
+        return Ok(Err(format!("No shared secret for client {:?}", id)));
+    };
+
+    let challenge = auth::new_challenge();
+    conn.send(auth::Message::Challenge(challenge.clone())).await?;
+    conn.flush().await?;
+
+    debug!("Challenge sent, waiting for response");
+    let auth::Message::Response(response) = conn.next().await.ok_or(AuthError::SocketClosed)??
+    else {
+        return Ok(Err("Invalid challenge response message".to_string()));
+    };
+
+    if !auth::verify_challenge(&secret, &id, &challenge, &response) {
+        return Ok(Err("Invalid challenge response".to_string()));
+    }
+
+    Ok(Ok(Identity::new(id)))
+}
+
+/// Runs the client side of the authentication handshake on a new connection. Returns an error
+/// if the server rejected the client.
+pub(crate) async fn authenticate_client<S>(stream: S, credentials: Credentials) -> io::Result<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut conn = Framed::new(stream, auth::Codec::new_client());
+
+    debug!(?credentials, "Sending auth message");
+    // Send the authentication message
+    match credentials {
+        Credentials::Token(token) => {
+            conn.send(auth::Message::Auth(token)).await?;
+            conn.flush().await?;
+        }
+        Credentials::Secret { id, secret } => {
+            conn.send(auth::Message::Hello(id.clone())).await?;
+            conn.flush().await?;
+
+            debug!("Waiting for challenge from server...");
+            let auth::Message::Challenge(challenge) = next_auth_message(&mut conn).await? else {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Expected challenge"));
+            };
+
+            let response = auth::sign_challenge(&secret, &id, &challenge);
+            conn.send(auth::Message::Response(response)).await?;
+            conn.flush().await?;
+        }
+    }
+
+    debug!("Waiting for ACK from server...");
+
+    // Wait for the response
+    match next_auth_message(&mut conn).await? {
+        auth::Message::Ack => Ok(conn.into_inner()),
+        msg => {
+            error!(?msg, "Unexpected auth ACK result");
+            Err(io::Error::new(io::ErrorKind::PermissionDenied, "rejected"))
+        }
+    }
+}
+
+/// Waits for the next message from the server. A rejection is returned as an error.
+async fn next_auth_message<S>(conn: &mut Framed<S, auth::Codec>) -> io::Result<auth::Message>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match conn.next().await {
+        Some(Ok(msg)) => Ok(msg),
+        Some(Err(e)) => Err(io::Error::new(io::ErrorKind::PermissionDenied, e)),
+        None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed")),
+    }
+}
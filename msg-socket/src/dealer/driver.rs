@@ -0,0 +1,373 @@
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{Future, SinkExt, StreamExt};
+use rustc_hash::FxHashMap;
+use tokio::sync::mpsc;
+use tokio_util::{codec::Framed, sync::PollSender};
+use tracing::{debug, error, trace, warn};
+
+use super::{
+    session::RouterSession, Command, DealerMessage, DealerOptions, OutgoingMessage, SocketState,
+};
+use crate::{authenticate_client, ConnectionState, ExponentialBackoff};
+
+use msg_common::{channel, Channel, JoinMap};
+use msg_transport::{Address, Transport};
+use msg_wire::{compression::try_decompress_payload, routerdealer};
+
+/// Peer channel type, used to exchange messages with the router socket session. Dropping it will
+/// close the session.
+type PeerChannel = Channel<routerdealer::Message, routerdealer::Message>;
+
+pub(crate) struct DealerDriver<T: Transport<A>, A: Address> {
+    /// Options shared with the socket.
+    pub(super) options: Arc<DealerOptions>,
+    /// The transport for this socket.
+    pub(super) transport: T,
+    /// Commands from the socket.
+    pub(super) from_socket: mpsc::Receiver<Command<A>>,
+    /// Outgoing messages from the socket.
+    pub(super) outgoing: mpsc::Receiver<OutgoingMessage>,
+    /// Messages to the socket.
+    pub(super) to_socket: PollSender<DealerMessage<A>>,
+    /// A joinset of authentication tasks.
+    pub(super) connection_tasks: JoinMap<A, Result<T::Io, T::Error>>,
+    /// All router sessions for this dealer socket, keyed by address.
+    pub(super) peers: FxHashMap<A, ConnectionState<PeerChannel, ExponentialBackoff, A>>,
+    /// Messages that have been read from the peers, waiting to be forwarded to the socket. This
+    /// holds at most one message per peer.
+    pub(super) egress: VecDeque<DealerMessage<A>>,
+    /// An outgoing message that has not been dispatched to a peer yet.
+    pub(super) pending: Option<routerdealer::Message>,
+    /// The index of the next peer in the round-robin.
+    pub(super) cursor: usize,
+    /// Socket state. This is shared with the backend task.
+    pub(super) state: Arc<SocketState>,
+}
+
+impl<T, A> Future for DealerDriver<T, A>
+where
+    T: Transport<A> + Send + Sync + Unpin + 'static,
+    A: Address,
+{
+    type Output = ();
+
+    /// This poll implementation prioritizes forwarding messages to the socket over reading new
+    /// ones, so that slow consumers apply backpressure to the router sockets.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        loop {
+            // First, forward any queued messages to the socket.
+            if !this.egress.is_empty() {
+                match this.to_socket.poll_reserve(cx) {
+                    Poll::Ready(Ok(())) => {
+                        let msg = this.egress.pop_front().expect("non-empty egress");
+                        if this.to_socket.send_item(msg).is_err() {
+                            debug!("Socket dropped, shutting down driver");
+                            return Poll::Ready(());
+                        }
+
+                        continue;
+                    }
+                    Poll::Ready(Err(_)) => {
+                        debug!("Socket dropped, shutting down driver");
+                        return Poll::Ready(());
+                    }
+                    Poll::Pending => {}
+                }
+            }
+
+            // Then, poll all the peers to handle incoming messages and reconnections.
+            if this.poll_peers(cx).is_ready() {
+                continue;
+            }
+
+            // Then, poll the socket for new commands.
+            if let Poll::Ready(cmd) = this.from_socket.poll_recv(cx) {
+                match cmd {
+                    Some(Command::Shutdown) | None => {
+                        debug!("Shutting down driver");
+                        return Poll::Ready(());
+                    }
+                    Some(cmd) => this.on_command(cmd),
+                }
+
+                continue;
+            }
+
+            // Then, poll the connection tasks for new connections.
+            if let Poll::Ready(Some(Ok((addr, result)))) = this.connection_tasks.poll_join_next(cx)
+            {
+                match result {
+                    Ok(io) => {
+                        this.on_connection(addr, io);
+                    }
+                    Err(e) => {
+                        error!(err = ?e, ?addr, "Error connecting to router socket");
+                    }
+                }
+
+                continue;
+            }
+
+            // Finally, dispatch outgoing messages to the connected peers. We only take a new
+            // message from the socket once the previous one has been dispatched, which propagates
+            // backpressure to the socket when no peer has capacity.
+            if this.pending.is_none() {
+                match this.outgoing.poll_recv(cx) {
+                    Poll::Ready(Some(msg)) => this.pending = Some(msg.into_wire()),
+                    Poll::Ready(None) => {
+                        debug!("Socket dropped, shutting down driver");
+                        return Poll::Ready(());
+                    }
+                    Poll::Pending => {}
+                }
+            }
+
+            if this.pending.is_some() && this.poll_dispatch(cx).is_ready() {
+                continue;
+            }
+
+            return Poll::Pending;
+        }
+    }
+}
+
+impl<T, A> DealerDriver<T, A>
+where
+    T: Transport<A> + Send + Sync + 'static,
+    A: Address,
+{
+    /// De-activates a peer by setting it to [`ConnectionState::Inactive`].
+    /// This will initialize the backoff stream.
+    fn reset_peer(&mut self, addr: A) {
+        debug!("Resetting peer at {addr:?}");
+        self.peers.insert(
+            addr.clone(),
+            ConnectionState::Inactive {
+                addr,
+                backoff: ExponentialBackoff::new(self.options.initial_backoff, 16),
+            },
+        );
+    }
+
+    /// Returns true if we're already connected to the given peer address.
+    fn is_connected(&self, addr: &A) -> bool {
+        self.peers.get(addr).is_some_and(|s| s.is_active())
+    }
+
+    fn on_command(&mut self, cmd: Command<A>) {
+        debug!("Received command: {:?}", cmd);
+        match cmd {
+            Command::Connect { endpoint } => {
+                if self.peers.contains_key(&endpoint) {
+                    debug!(?endpoint, "Peer already known, ignoring connect command");
+                    return;
+                }
+
+                self.connect(endpoint.clone());
+
+                // Also set the peer to the disconnected state. This will make sure that if the
+                // initial connection attempt fails, it will be retried in `poll_peers`.
+                self.reset_peer(endpoint);
+            }
+            Command::Disconnect { endpoint } => {
+                if self.peers.remove(&endpoint).is_some() {
+                    debug!(?endpoint, "Disconnected from peer");
+                } else {
+                    debug!(?endpoint, "Not connected to peer");
+                };
+            }
+            Command::Shutdown => unreachable!("Handled by the driver"),
+        }
+    }
+
+    fn connect(&mut self, addr: A) {
+        let connect = self.transport.connect(addr.clone());
+        let credentials = self.options.auth.clone();
+
+        self.connection_tasks.spawn(addr.clone(), async move {
+            let io = match connect.await {
+                Ok(io) => io,
+                Err(e) => {
+                    return (addr, Err(e));
+                }
+            };
+
+            if let Some(credentials) = credentials {
+                match authenticate_client(io, credentials).await {
+                    Ok(io) => (addr, Ok(io)),
+                    Err(e) => (addr, Err(e.into())),
+                }
+            } else {
+                (addr, Ok(io))
+            }
+        });
+    }
+
+    fn on_connection(&mut self, addr: A, io: T::Io) {
+        if self.is_connected(&addr) {
+            warn!(?addr, "Already connected to router socket");
+            return;
+        }
+
+        if !self.peers.contains_key(&addr) {
+            debug!(?addr, "Peer was disconnected while connecting, dropping connection");
+            return;
+        }
+
+        debug!("Connection to {:?} established, spawning session", addr);
+
+        let framed =
+            Framed::with_capacity(io, routerdealer::Codec::new(), self.options.read_buffer_size);
+
+        let (session_channel, driver_channel) =
+            channel(self.options.peer_buffer_size, self.options.peer_buffer_size);
+
+        let session =
+            RouterSession::new(addr.clone(), framed, Arc::clone(&self.state), session_channel);
+
+        // Spawn the session
+        tokio::spawn(session);
+
+        self.peers.insert(addr, ConnectionState::Active { channel: driver_channel });
+    }
+
+    /// Tries to dispatch the pending message to the next active peer in the round-robin that has
+    /// capacity.
+    ///
+    /// Returns `Poll::Ready` if the message was dispatched, `Poll::Pending` otherwise. In the
+    /// latter case, the driver will be woken up as soon as any of the peers has capacity again.
+    fn poll_dispatch(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let n_peers = self.peers.len();
+
+        for offset in 0..n_peers {
+            let idx = (self.cursor + offset) % n_peers;
+
+            // NOTE: the iteration order of the map is stable as long as it's not modified, and
+            // the number of peers is usually small, so this is fine.
+            let Some((addr, ConnectionState::Active { channel })) = self.peers.iter_mut().nth(idx)
+            else {
+                continue;
+            };
+
+            // Closed channels are cleaned up in `poll_peers`.
+            if let Poll::Ready(Ok(())) = channel.poll_ready_unpin(cx) {
+                let msg = self.pending.take().expect("pending message");
+                match channel.start_send_unpin(msg) {
+                    Ok(()) => {
+                        trace!(?addr, "Dispatched message to peer");
+                        self.cursor = idx + 1;
+                        return Poll::Ready(());
+                    }
+                    Err(e) => {
+                        // The session was closed in the meantime, put the message back.
+                        self.pending = e.into_inner();
+                    }
+                }
+            }
+        }
+
+        Poll::Pending
+    }
+
+    /// Polls all the peers. If the egress queue is empty, this reads at most one message from
+    /// every active peer, which fair-queues messages between them. If a peer channel is closed,
+    /// the peer is reset and will be reconnected according to the backoff policy.
+    ///
+    /// Returns `Poll::Ready` if any progress was made and this method should be called again.
+    /// Returns `Poll::Pending` if no progress was made.
+    fn poll_peers(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut progress = false;
+
+        // These should be fine as Vec::new() does not allocate
+        let mut inactive = Vec::new();
+        let mut to_retry = Vec::new();
+        let mut to_terminate = Vec::new();
+
+        let should_read = self.egress.is_empty();
+
+        for (addr, state) in self.peers.iter_mut() {
+            match state {
+                ConnectionState::Active { channel } => {
+                    if !should_read {
+                        continue;
+                    }
+
+                    match channel.poll_recv(cx) {
+                        Poll::Ready(Some(msg)) => {
+                            let compression_type = msg.compression_type();
+                            let payload = match try_decompress_payload(
+                                compression_type,
+                                msg.into_payload(),
+                            ) {
+                                Ok(decompressed) => decompressed,
+                                Err(e) => {
+                                    error!(err = ?e, "Failed to decompress message");
+                                    continue;
+                                }
+                            };
+
+                            self.egress.push_back(DealerMessage::new(addr.clone(), payload));
+
+                            progress = true;
+                        }
+                        Poll::Ready(None) => {
+                            error!(source = ?addr, "Router socket stream closed, removing channel");
+                            inactive.push(addr.clone());
+
+                            progress = true;
+                        }
+                        Poll::Pending => {}
+                    }
+                }
+                ConnectionState::Inactive { addr, backoff } => {
+                    // Poll the backoff stream
+                    if let Poll::Ready(item) = backoff.poll_next_unpin(cx) {
+                        if let Some(duration) = item {
+                            progress = true;
+
+                            // Only retry if there are no active connection tasks
+                            if !self.connection_tasks.contains_key(addr) {
+                                debug!(backoff = ?duration, "Retrying connection to {:?}", addr);
+                                to_retry.push(addr.clone());
+                            } else {
+                                debug!(backoff = ?duration, "Not retrying connection to {:?} as there is already a connection task", addr);
+                            }
+                        } else {
+                            error!("Exceeded maximum number of retries for {:?}, terminating connection", addr);
+                            to_terminate.push(addr.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        // Activate retries
+        for addr in to_retry {
+            self.connect(addr);
+        }
+
+        // Queue retries for all the inactive peers.
+        for addr in inactive {
+            self.reset_peer(addr);
+        }
+
+        // Terminate peers that are unreachable.
+        for addr in to_terminate {
+            self.peers.remove(&addr);
+        }
+
+        if progress {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::{fmt, io, time::Duration};
+
+use bytes::Bytes;
+use thiserror::Error;
+
+mod driver;
+use driver::DealerDriver;
+
+mod session;
+
+mod socket;
+pub use socket::*;
+
+mod stats;
+use stats::SocketStats;
+
+use crate::Credentials;
+use msg_transport::Address;
+use msg_wire::{
+    compression::{CompressionType, Compressor},
+    routerdealer,
+};
+
+const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum DealerError {
+    #[error("IO error: {0:?}")]
+    Io(#[from] io::Error),
+    #[error("Authentication error: {0:?}")]
+    Auth(String),
+    #[error("Wire protocol error: {0:?}")]
+    Wire(#[from] routerdealer::Error),
+    #[error("Socket closed")]
+    SocketClosed,
+    #[error("Command channel full")]
+    ChannelFull,
+    #[error("Transport error: {0:?}")]
+    Transport(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[derive(Debug)]
+enum Command<A: Address> {
+    /// Connect to a router socket.
+    Connect { endpoint: A },
+    /// Disconnect from a router socket.
+    Disconnect { endpoint: A },
+    /// Shut down the driver.
+    Shutdown,
+}
+
+#[derive(Debug, Clone)]
+pub struct DealerOptions {
+    /// Optional authentication token. The router socket uses this as the identity of the peer.
+    auth: Option<Credentials>,
+    /// The maximum amount of incoming messages that will be buffered before the socket stops
+    /// reading from its peers.
+    ingress_buffer_size: usize,
+    /// The maximum amount of messages that will be buffered per peer, in each direction.
+    peer_buffer_size: usize,
+    /// The read buffer size for each session.
+    read_buffer_size: usize,
+    /// The initial backoff for reconnecting to a router socket.
+    initial_backoff: Duration,
+    /// Minimum payload size in bytes for compression to be used. If the payload is smaller than
+    /// this threshold, it will not be compressed.
+    min_compress_size: usize,
+}
+
+impl DealerOptions {
+    /// Sets the authentication token for this socket. This will activate the authentication layer
+    /// and send the token to the router socket, which will use it as the identity of this peer.
+    pub fn auth_token(mut self, auth_token: Bytes) -> Self {
+        self.auth = Some(Credentials::Token(auth_token));
+        self
+    }
+
+    /// Sets the ID and the secret this socket authenticates with. Instead of sending the secret,
+    /// the socket proves that it knows it by responding to a challenge from the router socket, which
+    /// must have the same secret (see [`SharedSecrets`](crate::SharedSecrets)).
+    pub fn auth_secret(mut self, id: Bytes, secret: Bytes) -> Self {
+        self.auth = Some(Credentials::Secret { id, secret });
+        self
+    }
+
+    /// Sets the ingress buffer size. This is the maximum amount of incoming messages that will be
+    /// buffered. If the consumer cannot keep up with the incoming messages, the socket stops
+    /// reading from its peers.
+    pub fn ingress_buffer_size(mut self, ingress_buffer_size: usize) -> Self {
+        self.ingress_buffer_size = ingress_buffer_size;
+        self
+    }
+
+    /// Sets the per-peer buffer size. This is the maximum amount of messages that will be
+    /// buffered for a single peer, in each direction. Once a peer's outgoing buffer is full,
+    /// messages are distributed to the other peers.
+    pub fn peer_buffer_size(mut self, peer_buffer_size: usize) -> Self {
+        self.peer_buffer_size = peer_buffer_size;
+        self
+    }
+
+    /// Sets the read buffer size. This sets the size of the read buffer for each session.
+    pub fn read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.read_buffer_size = read_buffer_size;
+        self
+    }
+
+    /// Set the initial backoff for reconnecting to a router socket.
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Sets the minimum payload size in bytes for compression to be used. If the payload is smaller
+    /// than this threshold, it will not be compressed.
+    pub fn min_compress_size(mut self, min_compress_size: usize) -> Self {
+        self.min_compress_size = min_compress_size;
+        self
+    }
+}
+
+impl Default for DealerOptions {
+    fn default() -> Self {
+        Self {
+            auth: None,
+            ingress_buffer_size: DEFAULT_BUFFER_SIZE,
+            peer_buffer_size: 64,
+            read_buffer_size: 8192,
+            initial_backoff: Duration::from_millis(100),
+            min_compress_size: 8192,
+        }
+    }
+}
+
+/// A message received from a router socket.
+/// Includes the source and payload.
+#[derive(Clone)]
+pub struct DealerMessage<A: Address> {
+    /// The source address of the router socket. We need this because
+    /// a dealer socket can connect to multiple router sockets.
+    source: A,
+    /// The message payload.
+    payload: Bytes,
+}
+
+impl<A: Address> fmt::Debug for DealerMessage<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DealerMessage")
+            .field("source", &self.source)
+            .field("payload_size", &self.payload.len())
+            .finish()
+    }
+}
+
+impl<A: Address> DealerMessage<A> {
+    pub fn new(source: A, payload: Bytes) -> Self {
+        Self { source, payload }
+    }
+
+    #[inline]
+    pub fn source(&self) -> &A {
+        &self.source
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    #[inline]
+    pub fn into_payload(self) -> Bytes {
+        self.payload
+    }
+}
+
+/// An outgoing message sent from a [`DealerSocket`] to the backend task.
+#[derive(Debug, Clone)]
+pub(crate) struct OutgoingMessage {
+    /// The compression type used for the message payload.
+    compression_type: CompressionType,
+    /// The message payload.
+    payload: Bytes,
+}
+
+#[allow(unused)]
+impl OutgoingMessage {
+    pub fn new(payload: Bytes) -> Self {
+        Self {
+            // Initialize the compression type to None.
+            // The actual compression type will be set in the `compress` method.
+            compression_type: CompressionType::None,
+            payload,
+        }
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    #[inline]
+    pub fn into_wire(self) -> routerdealer::Message {
+        routerdealer::Message::new(self.compression_type as u8, self.payload)
+    }
+
+    #[inline]
+    pub fn compress(&mut self, compressor: &dyn Compressor) -> Result<(), io::Error> {
+        self.payload = compressor.compress(&self.payload)?;
+        self.compression_type = compressor.compression_type();
+
+        Ok(())
+    }
+}
+
+/// The dealer socket state, shared between the backend task and the socket.
+#[derive(Debug, Default)]
+pub(crate) struct SocketState {
+    pub(crate) stats: SocketStats,
+}
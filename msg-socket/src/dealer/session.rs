@@ -0,0 +1,148 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{Future, SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+use tracing::{debug, error, trace, warn};
+
+use msg_common::Channel;
+use msg_transport::Address;
+use msg_wire::routerdealer;
+
+use super::SocketState;
+
+/// Manages the state of a single router socket connection, represented as a [`Future`].
+#[must_use = "This future must be spawned"]
+pub(super) struct RouterSession<Io, A: Address> {
+    /// The addr of the router socket
+    addr: A,
+    /// The framed connection.
+    conn: Framed<Io, routerdealer::Codec>,
+    /// The socket state, shared between the backend task and the socket.
+    state: Arc<SocketState>,
+    /// Channel for communication with the driver. Sends incoming messages to the driver and
+    /// receives outgoing messages from it. Dropping the driver half will close the session.
+    driver_channel: Channel<routerdealer::Message, routerdealer::Message>,
+    /// Message queued to be sent on the connection.
+    pending_egress: Option<routerdealer::Message>,
+    /// Whether or not the connection should be flushed (i.e. data was written).
+    should_flush: bool,
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address> RouterSession<Io, A> {
+    pub(super) fn new(
+        addr: A,
+        conn: Framed<Io, routerdealer::Codec>,
+        state: Arc<SocketState>,
+        channel: Channel<routerdealer::Message, routerdealer::Message>,
+    ) -> Self {
+        state.stats.increment_active_peers();
+
+        Self {
+            addr,
+            conn,
+            state,
+            driver_channel: channel,
+            pending_egress: None,
+            should_flush: false,
+        }
+    }
+}
+
+impl<Io, A: Address> Drop for RouterSession<Io, A> {
+    fn drop(&mut self) {
+        self.state.stats.decrement_active_peers();
+    }
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address + Unpin> Future for RouterSession<Io, A> {
+    type Output = ();
+
+    /// This poll implementation only reads from the connection when the driver channel has
+    /// capacity, which propagates backpressure to the router socket.
+    #[inline]
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        loop {
+            if this.should_flush {
+                if let Poll::Ready(Ok(_)) = this.conn.poll_flush_unpin(cx) {
+                    this.should_flush = false;
+                }
+            }
+
+            // Write any pending outgoing message to the connection.
+            if this.pending_egress.is_some() && this.conn.poll_ready_unpin(cx).is_ready() {
+                let msg = this.pending_egress.take().expect("pending egress");
+                trace!(?msg, addr = ?this.addr, "Sending message");
+                let msg_len = msg.size();
+
+                match this.conn.start_send_unpin(msg) {
+                    Ok(_) => {
+                        this.state.stats.increment_tx(msg_len);
+                        this.should_flush = true;
+                        continue;
+                    }
+                    Err(e) => {
+                        error!(err = ?e, addr = ?this.addr, "Failed to send message");
+                        let _ = this.conn.poll_close_unpin(cx);
+                        return Poll::Ready(());
+                    }
+                }
+            }
+
+            // Take the next outgoing message from the driver. If the driver dropped its half of
+            // the channel, we're disconnected.
+            if this.pending_egress.is_none() {
+                if let Poll::Ready(item) = this.driver_channel.poll_recv(cx) {
+                    match item {
+                        Some(msg) => {
+                            this.pending_egress = Some(msg);
+                            continue;
+                        }
+                        None => {
+                            debug!(addr = ?this.addr, "Driver channel closed, shutting down session");
+                            let _ = this.conn.poll_close_unpin(cx);
+                            return Poll::Ready(());
+                        }
+                    }
+                }
+            }
+
+            match this.driver_channel.poll_ready_unpin(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(_)) => {
+                    warn!(addr = ?this.addr, "Driver channel closed, shutting down session");
+                    let _ = this.conn.poll_close_unpin(cx);
+                    return Poll::Ready(());
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+
+            match this.conn.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(msg))) => {
+                    this.state.stats.increment_rx(msg.size());
+
+                    if let Err(e) = this.driver_channel.start_send_unpin(msg) {
+                        warn!(err = ?e, addr = ?this.addr, "Failed to send message to driver");
+                    }
+
+                    continue;
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    error!(err = ?e, addr = ?this.addr, "Error receiving message");
+                    return Poll::Ready(());
+                }
+                Poll::Ready(None) => {
+                    error!(addr = ?this.addr, "Router socket stream closed");
+                    return Poll::Ready(());
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
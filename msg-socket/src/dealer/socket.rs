@@ -0,0 +1,280 @@
+use std::{
+    collections::VecDeque,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::Stream;
+use rustc_hash::FxHashMap;
+use tokio::{
+    net::{lookup_host, ToSocketAddrs},
+    sync::mpsc,
+};
+use tokio_util::sync::PollSender;
+use tracing::trace;
+
+use msg_common::JoinMap;
+use msg_transport::{inproc::InprocAddr, Address, Transport};
+use msg_wire::compression::Compressor;
+
+use super::{
+    Command, DealerDriver, DealerError, DealerMessage, DealerOptions, OutgoingMessage, SocketState,
+    SocketStats, DEFAULT_BUFFER_SIZE,
+};
+
+/// A dealer socket. Connects to one or more [`RouterSocket`](crate::RouterSocket)s. Outgoing
+/// messages are distributed round-robin over all connected routers, and incoming messages are
+/// fair-queued between them. Sending and receiving are independent of each other. This socket
+/// implements [`Stream`] and yields incoming [`DealerMessage`]s.
+pub struct DealerSocket<T: Transport<A>, A: Address> {
+    /// Command channel to the socket driver.
+    to_driver: mpsc::Sender<Command<A>>,
+    /// Outgoing message channel to the socket driver.
+    outgoing: mpsc::Sender<OutgoingMessage>,
+    /// Receiver channel from the socket driver.
+    from_driver: mpsc::Receiver<DealerMessage<A>>,
+    /// Options for the socket. These are shared with the backend task.
+    options: Arc<DealerOptions>,
+    /// The pending driver.
+    driver: Option<DealerDriver<T, A>>,
+    /// Socket state. This is shared with the socket frontend.
+    state: Arc<SocketState>,
+    /// Optional message compressor.
+    // NOTE: for now we're using dynamic dispatch, since using generics here
+    // complicates the API a lot. We can always change this later for perf reasons.
+    compressor: Option<Arc<dyn Compressor>>,
+    /// Marker for the transport type.
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<T> DealerSocket<T, SocketAddr>
+where
+    T: Transport<SocketAddr> + Send + Sync + Unpin + 'static,
+{
+    /// Connects to the given endpoint asynchronously.
+    pub async fn connect(&mut self, endpoint: impl ToSocketAddrs) -> Result<(), DealerError> {
+        let mut addrs = lookup_host(endpoint).await?;
+        let mut endpoint = addrs.next().ok_or(DealerError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not find any valid address",
+        )))?;
+
+        // Some transport implementations (e.g. Quinn) can't dial an unspecified
+        // IP address, so replace it with localhost.
+        if endpoint.ip().is_unspecified() {
+            // TODO: support IPv6
+            endpoint.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        }
+
+        self.connect_inner(endpoint).await
+    }
+
+    /// Disconnects from the given endpoint asynchronously.
+    pub async fn disconnect(&mut self, endpoint: impl ToSocketAddrs) -> Result<(), DealerError> {
+        let mut addrs = lookup_host(endpoint).await?;
+        let mut endpoint = addrs.next().ok_or(DealerError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not find any valid address",
+        )))?;
+
+        if endpoint.ip().is_unspecified() {
+            endpoint.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        }
+
+        self.disconnect_inner(endpoint).await
+    }
+}
+
+impl<T> DealerSocket<T, PathBuf>
+where
+    T: Transport<PathBuf> + Send + Sync + Unpin + 'static,
+{
+    /// Connects to the given path asynchronously.
+    pub async fn connect_path(&mut self, path: impl Into<PathBuf>) -> Result<(), DealerError> {
+        self.connect_inner(path.into()).await
+    }
+
+    /// Disconnects from the given path asynchronously.
+    pub async fn disconnect_path(&mut self, path: impl Into<PathBuf>) -> Result<(), DealerError> {
+        self.disconnect_inner(path.into()).await
+    }
+}
+
+impl<T> DealerSocket<T, InprocAddr>
+where
+    T: Transport<InprocAddr> + Send + Sync + Unpin + 'static,
+{
+    /// Connects to the given in-process endpoint asynchronously.
+    pub async fn connect_inproc(&mut self, name: impl Into<InprocAddr>) -> Result<(), DealerError> {
+        self.connect_inner(name.into()).await
+    }
+
+    /// Disconnects from the given in-process endpoint asynchronously.
+    pub async fn disconnect_inproc(
+        &mut self,
+        name: impl Into<InprocAddr>,
+    ) -> Result<(), DealerError> {
+        self.disconnect_inner(name.into()).await
+    }
+}
+
+impl<T, A> DealerSocket<T, A>
+where
+    T: Transport<A> + Send + Sync + Unpin + 'static,
+    A: Address,
+{
+    pub fn new(transport: T) -> Self {
+        Self::with_options(transport, DealerOptions::default())
+    }
+
+    pub fn with_options(transport: T, options: DealerOptions) -> Self {
+        let (to_driver, from_socket) = mpsc::channel(DEFAULT_BUFFER_SIZE);
+        let (outgoing_tx, outgoing_rx) = mpsc::channel(DEFAULT_BUFFER_SIZE);
+        let (to_socket, from_driver) = mpsc::channel(options.ingress_buffer_size);
+
+        let options = Arc::new(options);
+
+        let state = Arc::new(SocketState::default());
+
+        let driver = DealerDriver {
+            options: Arc::clone(&options),
+            transport,
+            from_socket,
+            outgoing: outgoing_rx,
+            to_socket: PollSender::new(to_socket),
+            connection_tasks: JoinMap::new(),
+            peers: FxHashMap::default(),
+            egress: VecDeque::new(),
+            pending: None,
+            cursor: 0,
+            state: Arc::clone(&state),
+        };
+
+        Self {
+            to_driver,
+            outgoing: outgoing_tx,
+            from_driver,
+            driver: Some(driver),
+            options,
+            state,
+            compressor: None,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Sets the message compressor for this socket.
+    pub fn with_compressor<C: Compressor + 'static>(mut self, compressor: C) -> Self {
+        self.compressor = Some(Arc::new(compressor));
+        self
+    }
+
+    /// Asynchronously connects to the endpoint.
+    pub async fn connect_inner(&mut self, endpoint: A) -> Result<(), DealerError> {
+        self.ensure_active_driver();
+        self.send_command(Command::Connect { endpoint }).await
+    }
+
+    /// Immediately send a connect command to the driver.
+    pub fn try_connect_inner(&mut self, endpoint: A) -> Result<(), DealerError> {
+        self.ensure_active_driver();
+        self.try_send_command(Command::Connect { endpoint })
+    }
+
+    /// Asynchronously disconnects from the endpoint.
+    pub async fn disconnect_inner(&mut self, endpoint: A) -> Result<(), DealerError> {
+        self.ensure_active_driver();
+        self.send_command(Command::Disconnect { endpoint }).await
+    }
+
+    /// Immediately send a disconnect command to the driver.
+    pub fn try_disconnect_inner(&mut self, endpoint: A) -> Result<(), DealerError> {
+        self.ensure_active_driver();
+        self.try_send_command(Command::Disconnect { endpoint })
+    }
+
+    /// Sends a message to the next available router socket. If no router is connected, or none
+    /// of them has capacity, the message is queued until there is. This method will wait if the
+    /// socket queue itself is full.
+    pub async fn send(&mut self, message: Bytes) -> Result<(), DealerError> {
+        self.ensure_active_driver();
+        let msg = self.prepare(message)?;
+
+        self.outgoing.send(msg).await.map_err(|_| DealerError::SocketClosed)
+    }
+
+    /// Tries to send a message to the next available router socket immediately. Returns
+    /// [`DealerError::ChannelFull`] if the socket queue is full.
+    pub fn try_send(&mut self, message: Bytes) -> Result<(), DealerError> {
+        use mpsc::error::TrySendError::*;
+
+        self.ensure_active_driver();
+        let msg = self.prepare(message)?;
+
+        self.outgoing.try_send(msg).map_err(|e| match e {
+            Full(_) => DealerError::ChannelFull,
+            Closed(_) => DealerError::SocketClosed,
+        })
+    }
+
+    /// Wraps the payload in an [`OutgoingMessage`], compressing it if a compressor is set and the
+    /// payload is larger than the configured minimum size.
+    fn prepare(&self, message: Bytes) -> Result<OutgoingMessage, DealerError> {
+        let mut msg = OutgoingMessage::new(message);
+
+        let len_before = msg.payload().len();
+        if len_before > self.options.min_compress_size {
+            if let Some(ref compressor) = self.compressor {
+                msg.compress(compressor.as_ref())?;
+
+                trace!("Compressed message from {} to {} bytes", len_before, msg.payload().len());
+            }
+        }
+
+        Ok(msg)
+    }
+
+    /// Sends a command to the driver, returning [`DealerError::SocketClosed`] if the
+    /// driver has been dropped.
+    async fn send_command(&self, command: Command<A>) -> Result<(), DealerError> {
+        self.to_driver.send(command).await.map_err(|_| DealerError::SocketClosed)
+    }
+
+    fn try_send_command(&self, command: Command<A>) -> Result<(), DealerError> {
+        use mpsc::error::TrySendError::*;
+        self.to_driver.try_send(command).map_err(|e| match e {
+            Full(_) => DealerError::ChannelFull,
+            Closed(_) => DealerError::SocketClosed,
+        })
+    }
+
+    /// Ensures that the driver task is running.
+    fn ensure_active_driver(&mut self) {
+        if let Some(driver) = self.driver.take() {
+            tokio::spawn(driver);
+        }
+    }
+
+    pub fn stats(&self) -> &SocketStats {
+        &self.state.stats
+    }
+}
+
+impl<T: Transport<A>, A: Address> Drop for DealerSocket<T, A> {
+    fn drop(&mut self) {
+        // Try to tell the driver to gracefully shut down.
+        let _ = self.to_driver.try_send(Command::Shutdown);
+    }
+}
+
+impl<T: Transport<A> + Unpin, A: Address> Stream for DealerSocket<T, A> {
+    type Item = DealerMessage<A>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.from_driver.poll_recv(cx)
+    }
+}
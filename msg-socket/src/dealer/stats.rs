@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Statistics for a dealer socket. These are shared between the driver task
+/// and the socket.
+#[derive(Debug, Default)]
+pub struct SocketStats {
+    /// Total bytes sent
+    bytes_tx: AtomicUsize,
+    /// Total bytes received
+    bytes_rx: AtomicUsize,
+    /// Total number of active router connections
+    active_peers: AtomicUsize,
+}
+
+impl SocketStats {
+    #[inline]
+    pub(crate) fn increment_tx(&self, bytes: usize) {
+        self.bytes_tx.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn increment_rx(&self, bytes: usize) {
+        self.bytes_rx.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn increment_active_peers(&self) {
+        self.active_peers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn decrement_active_peers(&self) {
+        self.active_peers.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn bytes_tx(&self) -> usize {
+        self.bytes_tx.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn bytes_rx(&self) -> usize {
+        self.bytes_rx.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn active_peers(&self) -> usize {
+        self.active_peers.load(Ordering::Relaxed)
+    }
+}
@@ -2,24 +2,73 @@
 #![cfg_attr(docsrs, feature(doc_cfg, doc_auto_cfg))]
 #![cfg_attr(not(test), warn(unused_crate_dependencies))]
 
-use msg_transport::Address;
-use tokio::io::{AsyncRead, AsyncWrite};
+use std::sync::Arc;
 
+use futures::future::BoxFuture;
+use msg_transport::{Address, DatagramTransport, Datagrams, MultiplexedTransport};
+
+mod dealer;
 #[path = "pub/mod.rs"]
 mod pubs;
+mod pull;
+mod push;
 mod rep;
 mod req;
+mod router;
 mod sub;
 
 mod connection;
 pub use connection::*;
 
-use bytes::Bytes;
-pub use pubs::{PubError, PubOptions, PubSocket};
+mod auth;
+pub use auth::*;
+
+pub use dealer::{DealerError, DealerMessage, DealerOptions, DealerSocket};
+pub use pubs::{LogOptions, PubError, PubOptions, PubSocket, SlowSubscriberPolicy, SyncPolicy};
+pub use pull::*;
+pub use push::{PushError, PushOptions, PushSocket};
 pub use rep::*;
 pub use req::*;
+pub use router::{RouterError, RouterMessage, RouterOptions, RouterSocket};
 pub use sub::*;
 
+/// Returns the datagram handle of a connection, see [`DatagramTransport`]. Sockets are generic
+/// over all transports, so they keep this function for the transports that support datagrams.
+pub(crate) type DatagramsFn<Io> = fn(&Io) -> Option<Arc<dyn Datagrams>>;
+
+/// Returns the datagram handle of the given connection as a trait object.
+pub(crate) fn datagrams_of<T, A>(io: &T::Io) -> Option<Arc<dyn Datagrams>>
+where
+    T: DatagramTransport<A>,
+    A: Address,
+{
+    T::datagrams(io).map(|datagrams| Arc::new(datagrams) as Arc<dyn Datagrams>)
+}
+
+/// Opens or accepts a stream on the connection of another stream, see [`MultiplexedTransport`].
+/// Like [`DatagramsFn`], sockets keep this function for the transports that support it.
+pub(crate) type StreamFn<Io, E> = fn(&Io) -> BoxFuture<'static, Result<Io, E>>;
+
+/// Opens a new stream on the connection of the given stream.
+pub(crate) fn open_stream_of<T, A>(io: &T::Io) -> BoxFuture<'static, Result<T::Io, T::Error>>
+where
+    T: MultiplexedTransport<A>,
+    T::OpenStream: 'static,
+    A: Address,
+{
+    Box::pin(T::open_stream(io))
+}
+
+/// Accepts the next stream opened by the peer on the connection of the given stream.
+pub(crate) fn accept_stream_of<T, A>(io: &T::Io) -> BoxFuture<'static, Result<T::Io, T::Error>>
+where
+    T: MultiplexedTransport<A>,
+    T::AcceptStream: 'static,
+    A: Address,
+{
+    Box::pin(T::accept_stream(io))
+}
+
 pub struct RequestId(u32);
 
 impl RequestId {
@@ -35,13 +84,3 @@ impl RequestId {
         self.0 = self.0.wrapping_add(1);
     }
 }
-
-pub trait Authenticator: Send + Sync + Unpin + 'static {
-    fn authenticate(&self, id: &Bytes) -> bool;
-}
-
-pub(crate) struct AuthResult<S: AsyncRead + AsyncWrite, A: Address> {
-    id: Bytes,
-    addr: A,
-    stream: S,
-}
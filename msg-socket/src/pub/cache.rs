@@ -0,0 +1,28 @@
+use parking_lot::RwLock;
+use rustc_hash::FxHashMap;
+
+use super::{trie::PrefixTrie, PubMessage};
+
+/// A cache of the latest message published on each topic. Used to send a snapshot of the current
+/// state to subscribers as soon as they subscribe, instead of having them wait for the next
+/// publish.
+#[derive(Debug, Default)]
+pub(super) struct LastValueCache {
+    values: RwLock<FxHashMap<String, PubMessage>>,
+}
+
+impl LastValueCache {
+    /// Stores the message as the latest value for its topic.
+    pub(super) fn insert(&self, msg: PubMessage) {
+        self.values.write().insert(msg.topic().to_owned(), msg);
+    }
+
+    /// Returns the latest messages of all the topics that match the given subscription topic,
+    /// following the wildcard semantics of [`PrefixTrie`].
+    pub(super) fn matching(&self, topic: &str) -> Vec<PubMessage> {
+        let mut filter = PrefixTrie::new();
+        filter.insert(topic);
+
+        self.values.read().values().filter(|msg| filter.contains(msg.topic())).cloned().collect()
+    }
+}
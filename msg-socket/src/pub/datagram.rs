@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use tracing::{debug, trace};
+
+use super::trie::PrefixTrie;
+use crate::DatagramsFn;
+use msg_wire::pubsub;
+
+/// The topics whose messages are sent as unreliable datagrams, see
+/// [`PubSocket::with_datagram_topics`](super::PubSocket::with_datagram_topics).
+pub(super) struct DatagramTopics<Io> {
+    /// Returns the datagram handle of a connection.
+    datagrams: DatagramsFn<Io>,
+    /// The topic filter, which follows the same conventions as subscriptions.
+    topics: Arc<PrefixTrie>,
+}
+
+impl<Io> DatagramTopics<Io> {
+    pub(super) fn new(datagrams: DatagramsFn<Io>, topics: PrefixTrie) -> Self {
+        Self { datagrams, topics: Arc::new(topics) }
+    }
+
+    /// Returns the datagram sender for the given connection. `None` if the connection doesn't
+    /// support datagrams.
+    pub(super) fn sender(&self, io: &Io, max_size: Option<usize>) -> Option<DatagramSender> {
+        let datagrams = (self.datagrams)(io)?;
+        let topics = Arc::clone(&self.topics);
+
+        Some(DatagramSender { datagrams, topics, max_size, enabled: false })
+    }
+}
+
+// Not derived, since the connection type doesn't need to be `Clone`.
+impl<Io> Clone for DatagramTopics<Io> {
+    fn clone(&self) -> Self {
+        Self { datagrams: self.datagrams, topics: Arc::clone(&self.topics) }
+    }
+}
+
+/// Sends the messages of a session on the datagram topics as unreliable datagrams.
+pub(super) struct DatagramSender {
+    /// The datagram handle of the connection.
+    datagrams: Arc<dyn msg_transport::Datagrams>,
+    /// The datagram topics, shared with the socket.
+    topics: Arc<PrefixTrie>,
+    /// The maximum size of a datagram. If `None`, only the path MTU limits the size.
+    max_size: Option<usize>,
+    /// Whether the subscriber receives datagrams, which it announces once it's connected.
+    enabled: bool,
+}
+
+impl DatagramSender {
+    /// Starts sending datagrams, once the subscriber announced that it receives them.
+    pub(super) fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    /// Returns `true` if the messages on the given topic are sent as datagrams.
+    pub(super) fn matches(&self, topic: &str) -> bool {
+        self.enabled && self.topics.contains(topic)
+    }
+
+    /// Sends the frame as one or more datagrams, and returns the number of bytes sent. Datagrams
+    /// that can't be sent are dropped, like datagrams lost in the network. If the frame can't
+    /// be fragmented at all, it is returned so that it can be sent on the stream instead.
+    pub(super) fn send(&self, seq: u32, frame: pubsub::Frame) -> Result<usize, pubsub::Frame> {
+        let Some(path_size) = self.datagrams.max_datagram_size() else {
+            return Err(frame);
+        };
+
+        let max_size = self.max_size.map_or(path_size, |max_size| max_size.min(path_size));
+        let Some(fragments) = frame.fragment(seq, max_size) else {
+            return Err(frame);
+        };
+
+        trace!(seq, fragments = fragments.len(), "Sending message as datagrams");
+
+        let mut sent = 0;
+        for fragment in fragments {
+            let len = fragment.len();
+            if let Err(e) = self.datagrams.send_datagram(fragment) {
+                debug!(err = ?e, seq, "Failed to send datagram, dropping message");
+                break;
+            }
+
+            sent += len;
+        }
+
+        Ok(sent)
+    }
+}
@@ -1,21 +1,29 @@
 use std::{
+    collections::VecDeque,
     io,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Weak},
     task::{Context, Poll},
 };
 
-use futures::{stream::FuturesUnordered, Future, SinkExt, StreamExt};
-use tokio::{sync::broadcast, task::JoinSet};
-use tokio_util::codec::Framed;
+use futures::{stream::FuturesUnordered, Future, StreamExt};
+use tokio::task::JoinSet;
 use tracing::{debug, error, info, warn};
 
 use super::{
-    session::SubscriberSession, trie::PrefixTrie, PubError, PubMessage, PubOptions, SocketState,
+    cache::LastValueCache,
+    datagram::DatagramTopics,
+    framed::FramedConn,
+    log::DurableLog,
+    queue::{SessionQueue, SessionQueues},
+    retention::RetentionBuffer,
+    session::SubscriberSession,
+    stats::SessionStats,
+    trie::PrefixTrie,
+    PubError, PubOptions, SocketState,
 };
-use crate::{AuthResult, Authenticator};
-use msg_transport::{Address, PeerAddress, Transport};
-use msg_wire::{auth, pubsub};
+use crate::{authenticate, AsyncAuthenticator, AuthResult, Authorizer, Identity};
+use msg_transport::{Address, PeerAddress, PeerMetadata, Transport};
 
 #[allow(clippy::type_complexity)]
 pub(crate) struct PubDriver<T: Transport<A>, A: Address> {
@@ -26,16 +34,26 @@ pub(crate) struct PubDriver<T: Transport<A>, A: Address> {
     /// The publisher options (shared with the socket)
     pub(super) options: Arc<PubOptions>,
     /// The publisher socket state, shared with the socket front-end.
-    pub(crate) state: Arc<SocketState>,
+    pub(crate) state: Arc<SocketState<A>>,
     /// Optional connection authenticator.
-    pub(super) auth: Option<Arc<dyn Authenticator>>,
+    pub(super) auth: Option<Arc<dyn AsyncAuthenticator<A>>>,
+    /// Optional subscription authorizer.
+    pub(super) authorizer: Option<Arc<dyn Authorizer>>,
     /// A set of pending incoming connections, represented by [`Transport::Accept`].
     pub(super) conn_tasks: FuturesUnordered<T::Accept>,
     /// A joinset of authentication tasks.
     pub(super) auth_tasks: JoinSet<Result<AuthResult<T::Io, A>, PubError>>,
-    /// The receiver end of the message broadcast channel. The sender half is stored by
-    /// [`PubSocket`](super::PubSocket).
-    pub(super) from_socket_bcast: broadcast::Receiver<PubMessage>,
+    /// The message queues of all sessions, owned by [`PubSocket`](super::PubSocket).
+    pub(super) sessions: Weak<SessionQueues>,
+    /// The last-value cache, shared with the socket and all sessions. `None` if disabled.
+    pub(super) last_values: Option<Arc<LastValueCache>>,
+    /// The buffer of messages retained for replay, shared with the socket and all sessions.
+    /// `None` if disabled.
+    pub(super) retention: Option<Arc<RetentionBuffer>>,
+    /// The durable topic log, shared with the socket and all sessions. `None` if disabled.
+    pub(super) log: Option<Arc<DurableLog>>,
+    /// The topics whose messages are sent as datagrams. `None` if disabled.
+    pub(super) datagram_topics: Option<DatagramTopics<T::Io>>,
 }
 
 impl<T, A> Future for PubDriver<T, A>
@@ -55,26 +73,9 @@ where
                 match auth {
                     Ok(auth) => {
                         // Run custom authenticator
-                        debug!("Authentication passed for {:?} ({:?})", auth.id, auth.addr);
-
-                        let mut framed = Framed::new(auth.stream, pubsub::Codec::new());
-                        framed.set_backpressure_boundary(this.options.backpressure_boundary);
-
-                        let session = SubscriberSession {
-                            seq: 0,
-                            session_id: this.id_counter,
-                            from_socket_bcast: this.from_socket_bcast.resubscribe().into(),
-                            state: Arc::clone(&this.state),
-                            pending_egress: None,
-                            conn: framed,
-                            topic_filter: PrefixTrie::new(),
-                            should_flush: false,
-                            flush_interval: this.options.flush_interval.map(tokio::time::interval),
-                        };
-
-                        tokio::spawn(session);
-
-                        this.id_counter = this.id_counter.wrapping_add(1);
+                        debug!("Authentication passed for {:?} ({:?})", auth.identity.id(), auth.addr);
+
+                        this.spawn_session(auth.stream, auth.addr, auth.identity);
                     }
                     Err(e) => {
                         error!(err = ?e, "Error authenticating client");
@@ -148,61 +149,62 @@ where
             let authenticator = Arc::clone(auth);
             debug!("New connection from {:?}, authenticating", addr);
             self.auth_tasks.spawn(async move {
-                let mut conn = Framed::new(io, auth::Codec::new_server());
-
-                debug!("Waiting for auth");
-                // Wait for the response
-                let auth = conn
-                    .next()
-                    .await
-                    .ok_or(PubError::SocketClosed)?
-                    .map_err(|e| PubError::Auth(e.to_string()))?;
-
-                debug!("Auth received: {:?}", auth);
-
-                let auth::Message::Auth(id) = auth else {
-                    conn.send(auth::Message::Reject).await?;
-                    conn.flush().await?;
-                    conn.close().await?;
-                    return Err(PubError::Auth("Invalid auth message".to_string()));
-                };
-
-                // If authentication fails, send a reject message and close the connection
-                if !authenticator.authenticate(&id) {
-                    conn.send(auth::Message::Reject).await?;
-                    conn.flush().await?;
-                    conn.close().await?;
-                    return Err(PubError::Auth("Authentication failed".to_string()));
-                }
-
-                // Send ack
-                conn.send(auth::Message::Ack).await?;
-                conn.flush().await?;
-
-                Ok(AuthResult { id, addr, stream: conn.into_inner() })
+                authenticate(io, addr, authenticator).await.map_err(|e| PubError::Auth(e.to_string()))
             });
         } else {
-            let mut framed = Framed::new(io, pubsub::Codec::new());
-            framed.set_backpressure_boundary(self.options.backpressure_boundary);
-
-            let session = SubscriberSession {
-                seq: 0,
-                session_id: self.id_counter,
-                from_socket_bcast: self.from_socket_bcast.resubscribe().into(),
-                state: Arc::clone(&self.state),
-                pending_egress: None,
-                conn: framed,
-                topic_filter: PrefixTrie::new(),
-                should_flush: false,
-                flush_interval: self.options.flush_interval.map(tokio::time::interval),
-            };
-
-            tokio::spawn(session);
-
-            self.id_counter = self.id_counter.wrapping_add(1);
-            debug!("New connection from {:?}, session ID {}", addr, self.id_counter);
+            let identity = Identity::from_metadata(&io.metadata());
+            self.spawn_session(io, addr, identity);
         }
 
         Ok(())
     }
+
+    /// Spawns a new subscriber session for the given connection and client identity. If the
+    /// socket has been dropped already, the connection is closed instead.
+    fn spawn_session(&mut self, io: T::Io, addr: A, identity: Identity) {
+        let Some(sessions) = self.sessions.upgrade() else {
+            debug!(?addr, "Socket closed, dropping connection");
+            self.state.stats.decrement_active_clients();
+            return;
+        };
+
+        let session_id = self.id_counter;
+        self.id_counter = self.id_counter.wrapping_add(1);
+
+        let stats = Arc::new(SessionStats::default());
+        let queue =
+            Arc::new(SessionQueue::new(self.options.session_buffer_size, Arc::clone(&stats)));
+        sessions.insert(session_id, Arc::clone(&queue));
+        self.state.stats.insert(session_id, addr.clone(), stats);
+
+        let datagrams = self
+            .datagram_topics
+            .as_ref()
+            .and_then(|topics| topics.sender(&io, self.options.max_datagram_size));
+
+        let session = SubscriberSession {
+            session_id,
+            addr,
+            identity,
+            authorizer: self.authorizer.clone(),
+            queue,
+            sessions: Weak::clone(&self.sessions),
+            state: Arc::clone(&self.state),
+            pending_egress: None,
+            snapshot: VecDeque::new(),
+            last_values: self.last_values.clone(),
+            retention: self.retention.clone(),
+            replayed_until: None,
+            log: self.log.clone(),
+            catch_up: VecDeque::new(),
+            datagrams,
+            conn: FramedConn::new(io, self.options.backpressure_boundary),
+            topic_filter: PrefixTrie::new(),
+            should_flush: false,
+            flush_interval: self.options.flush_interval.map(tokio::time::interval),
+        };
+
+        debug!(?session.addr, session_id, "Spawning new session");
+        tokio::spawn(session);
+    }
 }
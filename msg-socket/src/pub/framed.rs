@@ -0,0 +1,160 @@
+use std::{
+    collections::VecDeque,
+    io::{self, IoSlice},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use futures::{Sink, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::FramedRead;
+
+use msg_wire::pubsub;
+
+/// The maximum number of buffers passed to a single vectored write.
+const MAX_IO_SLICES: usize = 64;
+
+/// A subscriber connection. Incoming control messages are decoded like with
+/// [`Framed`](tokio_util::codec::Framed), but outgoing messages are pre-encoded [`pubsub::Frame`]s
+/// that are shared between all sessions. Instead of being copied into a write buffer, their
+/// header and payload are written directly with vectored writes.
+pub(super) struct FramedConn<Io> {
+    /// The read half, which decodes the incoming control messages.
+    inner: FramedRead<Io, pubsub::Codec>,
+    /// The headers and payloads of the frames that haven't been written yet.
+    pending: VecDeque<Bytes>,
+    /// The number of bytes in `pending`.
+    pending_bytes: usize,
+    /// The number of pending bytes above which the frames are written out before accepting new
+    /// ones.
+    backpressure_boundary: usize,
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> FramedConn<Io> {
+    pub(super) fn new(io: Io, backpressure_boundary: usize) -> Self {
+        Self {
+            inner: FramedRead::new(io, pubsub::Codec::new()),
+            pending: VecDeque::new(),
+            pending_bytes: 0,
+            backpressure_boundary,
+        }
+    }
+
+    /// Writes the pending frames to the connection until they are all written, or the connection
+    /// is not ready for more.
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            let mut slices = [IoSlice::new(&[]); MAX_IO_SLICES];
+            let mut count = 0;
+            for (slice, buf) in slices.iter_mut().zip(self.pending.iter()) {
+                *slice = IoSlice::new(buf);
+                count += 1;
+            }
+
+            let io = self.inner.get_mut();
+            let n = ready!(Pin::new(io).poll_write_vectored(cx, &slices[..count]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+
+            self.advance(n);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Removes the first `n` written bytes from the pending frames.
+    fn advance(&mut self, mut n: usize) {
+        self.pending_bytes -= n;
+
+        while n > 0 {
+            let buf = self.pending.front_mut().expect("advanced past the pending frames");
+            if n < buf.len() {
+                buf.advance(n);
+                return;
+            }
+
+            n -= buf.len();
+            self.pending.pop_front();
+        }
+    }
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> Stream for FramedConn<Io> {
+    type Item = Result<pubsub::Message, pubsub::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().inner.poll_next_unpin(cx)
+    }
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> Sink<pubsub::Frame> for FramedConn<Io> {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+
+        while this.pending_bytes >= this.backpressure_boundary {
+            ready!(this.poll_write_pending(cx))?;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, frame: pubsub::Frame) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        this.pending_bytes += frame.len();
+
+        let (header, payload) = frame.into_parts();
+        this.pending.push_back(header);
+        if !payload.is_empty() {
+            this.pending.push_back(payload);
+        }
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+
+        Pin::new(this.inner.get_mut()).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+
+        Pin::new(self.get_mut().inner.get_mut()).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::SinkExt;
+    use tokio_util::codec::FramedRead;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn framed_conn_writes_frames() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut conn = FramedConn::new(server, 16);
+        let mut reader = FramedRead::new(client, pubsub::Codec::new());
+
+        let payloads = (0..20).map(|i| Bytes::from(vec![i; 100])).collect::<Vec<_>>();
+        let writes = payloads.clone();
+        tokio::spawn(async move {
+            for (seq, payload) in writes.into_iter().enumerate() {
+                let msg = pubsub::Message::new(seq as u32, Bytes::from("foo"), payload, 0);
+                conn.send(msg.encode()).await.unwrap();
+            }
+        });
+
+        for (seq, payload) in payloads.into_iter().enumerate() {
+            let msg = reader.next().await.unwrap().unwrap();
+            assert_eq!(msg.seq(), seq as u32);
+            assert_eq!(msg.payload(), &payload);
+        }
+    }
+}
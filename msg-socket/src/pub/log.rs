@@ -0,0 +1,1085 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use rustc_hash::FxHashMap;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error, warn};
+
+use msg_common::{seq_after, unix_micros};
+use msg_wire::compression::CompressionType;
+
+use super::{trie::PrefixTrie, PubMessage, Sequencer};
+
+/// The length of a record header in a segment file: seq (u32), topic seq (u32), timestamp (u64),
+/// compression type (u8) and payload size (u32).
+const RECORD_HEADER_LEN: u64 = 4 + 4 + 8 + 1 + 4;
+
+/// The length of an entry in a segment index file: seq (u32), timestamp (u64) and the offset of
+/// the record in the segment file (u64).
+const INDEX_ENTRY_LEN: u64 = 4 + 8 + 8;
+
+/// The maximum number of commands the writer handles in a single blocking task.
+const MAX_BATCH_SIZE: usize = 1024;
+
+/// The number of messages a catch-up read buffers ahead of its session.
+const CATCH_UP_BUFFER_SIZE: usize = 64;
+
+/// Options for the durable topic log of a [`PubSocket`](super::PubSocket).
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    /// The directory the log is stored in.
+    path: PathBuf,
+    /// The size in bytes after which a new segment is started.
+    segment_size: u64,
+    /// The maximum size in bytes of the log of a single topic.
+    retention_bytes: Option<u64>,
+    /// The maximum age of the messages in the log.
+    retention_age: Option<Duration>,
+    /// When appended messages are synced to disk.
+    sync: SyncPolicy,
+}
+
+/// When the durable log syncs appended messages to disk, with `fsync`. Until a message is synced,
+/// it can be lost if the machine crashes, but not if only the process crashes.
+///
+/// Appended messages are synced together, so syncing every message only costs a sync per batch
+/// of messages that are appended while the previous sync is running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Sync every message before its append completes, i.e. before
+    /// [`PubSocket::publish`](super::PubSocket::publish) returns.
+    #[default]
+    Always,
+    /// Sync after every given number of messages.
+    Messages(u64),
+    /// Sync messages at most the given interval after they are appended.
+    Interval(Duration),
+    /// Never sync, and leave it to the operating system to write the messages to disk.
+    Never,
+}
+
+impl LogOptions {
+    /// Creates the options for a log stored in the given directory. The directory is created if
+    /// it doesn't exist, and an existing log in it is reopened.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            segment_size: 64 * 1024 * 1024,
+            retention_bytes: None,
+            retention_age: None,
+            sync: SyncPolicy::default(),
+        }
+    }
+
+    /// Sets the size in bytes after which a new segment file is started. Retention removes whole
+    /// segments, so this is also the granularity at which data is deleted. Default is 64MiB.
+    pub fn segment_size(mut self, segment_size: u64) -> Self {
+        self.segment_size = segment_size;
+        self
+    }
+
+    /// Sets the maximum size in bytes of the log of a single topic. When it is exceeded, the
+    /// oldest segments are deleted.
+    pub fn retention_bytes(mut self, retention_bytes: u64) -> Self {
+        self.retention_bytes = Some(retention_bytes);
+        self
+    }
+
+    /// Sets the maximum age of the messages in the log. Segments that only contain older messages
+    /// are deleted.
+    pub fn retention_age(mut self, retention_age: Duration) -> Self {
+        self.retention_age = Some(retention_age);
+        self
+    }
+
+    /// Sets when appended messages are synced to disk. Default is [`SyncPolicy::Always`].
+    pub fn sync(mut self, sync: SyncPolicy) -> Self {
+        self.sync = sync;
+        self
+    }
+}
+
+/// An append-only log of published messages, stored on disk. Every topic has its own directory
+/// with segment files, each with an index on the sequence number of the messages.
+///
+/// All file I/O happens on a dedicated writer task, which handles the commands of the socket and
+/// its sessions in order, and runs the actual writes in blocking tasks. Catch-up reads are started
+/// by the writer task, and then read in their own blocking task. This way, neither the publishers
+/// nor the runtime are blocked by the disk.
+///
+/// Messages are only sent to the subscribers once they have been appended, by the writer task, so
+/// that subscribers never receive a message that is missing from the log.
+#[derive(Debug)]
+pub(super) struct DurableLog {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+/// Called by the writer task of a [`DurableLog`] with every message that has been appended (and
+/// synced, if a sync is due), in sequence order.
+pub(super) type OnAppended = Box<dyn FnMut(PubMessage) + Send>;
+
+/// A command for the writer task of a [`DurableLog`].
+#[derive(Debug)]
+enum Command {
+    /// Append the message to the log.
+    Append { msg: PubMessage, appended: oneshot::Sender<io::Result<()>> },
+    /// Start a catch-up read, see [`Topics::read`].
+    Read {
+        filter: PrefixTrie,
+        seq: u32,
+        timestamp: u64,
+        events: mpsc::Sender<io::Result<CatchUpEvent>>,
+    },
+}
+
+impl DurableLog {
+    /// Opens the log in the configured directory, recovering any existing topics, and continues
+    /// the sequences of the given sequencer after the last messages in the log. Spawns the writer
+    /// task of the log, which passes the appended messages to `on_appended`.
+    pub(super) fn open(
+        options: LogOptions,
+        sequencer: &mut Sequencer,
+        on_appended: OnAppended,
+    ) -> io::Result<Self> {
+        let topics = Topics::open(options)?;
+        topics.resume(sequencer);
+
+        let (commands, rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_writer(topics, rx, on_appended));
+
+        Ok(Self { commands })
+    }
+
+    /// Queues the message to be appended to the log. Messages are appended in the order they're
+    /// queued in, so queueing them in sequence order keeps the log in sequence order. If the
+    /// append fails, the message is not passed to the `on_appended` callback of the log.
+    pub(super) fn append(&self, msg: PubMessage) -> Appended {
+        let (appended, rx) = oneshot::channel();
+        let _ = self.commands.send(Command::Append { msg, appended });
+        Appended(rx)
+    }
+
+    /// Starts a catch-up read, see [`Topics::read`]. The read includes all messages that were
+    /// queued to be appended before it.
+    pub(super) fn read(&self, filter: PrefixTrie, seq: u32, timestamp: u64) -> CatchUp {
+        let (events, rx) = mpsc::channel(CATCH_UP_BUFFER_SIZE);
+        let _ = self.commands.send(Command::Read { filter, seq, timestamp, events });
+        CatchUp { events: rx, started: false }
+    }
+}
+
+/// Resolves once a message has been appended to the log, see [`DurableLog::append`].
+#[derive(Debug)]
+pub(super) struct Appended(oneshot::Receiver<io::Result<()>>);
+
+impl Appended {
+    /// Waits until the message has been appended to the log.
+    pub(super) async fn wait(self) -> io::Result<()> {
+        self.0.await.unwrap_or_else(|_| Err(writer_stopped()))
+    }
+}
+
+/// A catch-up read of the log, see [`DurableLog::read`]. The messages are read ahead in a
+/// blocking task, up to a small buffer.
+#[derive(Debug)]
+pub(super) struct CatchUp {
+    events: mpsc::Receiver<io::Result<CatchUpEvent>>,
+    /// Whether the read has started.
+    started: bool,
+}
+
+/// An event of a [`CatchUp`].
+#[derive(Debug)]
+pub(super) enum CatchUpEvent {
+    /// The read started. Messages from the socket up to `until` are part of the read.
+    Started { until: Option<u32> },
+    /// The next message of the read.
+    Message(PubMessage),
+}
+
+impl CatchUp {
+    /// Polls the next event of the read. Returns `None` when the read is complete.
+    pub(super) fn poll_next(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<io::Result<CatchUpEvent>>> {
+        match ready!(self.events.poll_recv(cx)) {
+            Some(event) => {
+                self.started = true;
+                Poll::Ready(Some(event))
+            }
+            // The writer task stopped before it started the read
+            None if !self.started => Poll::Ready(Some(Err(writer_stopped()))),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+fn writer_stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "Log writer stopped")
+}
+
+/// The writer task of a [`DurableLog`]. Handles the queued commands in batches, each in a blocking
+/// task. Stops once the log is dropped, after syncing the remaining messages.
+async fn run_writer(
+    mut topics: Topics,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    mut on_appended: OnAppended,
+) {
+    let mut open = true;
+    while open {
+        let mut batch = Vec::new();
+
+        // Wait for the next command, or until the unsynced messages are due to be synced
+        let next = match topics.sync_deadline() {
+            Some(deadline) => {
+                tokio::time::timeout_at(deadline.into(), commands.recv()).await.ok()
+            }
+            None => Some(commands.recv().await),
+        };
+
+        match next {
+            Some(Some(command)) => batch.push(command),
+            Some(None) => open = false,
+            None => {}
+        }
+
+        while open && batch.len() < MAX_BATCH_SIZE {
+            match commands.try_recv() {
+                Ok(command) => batch.push(command),
+                Err(_) => break,
+            }
+        }
+
+        let handled = tokio::task::spawn_blocking(move || {
+            topics.handle(batch, !open, &mut on_appended);
+            (topics, on_appended)
+        });
+
+        match handled.await {
+            Ok(handled) => (topics, on_appended) = handled,
+            Err(e) => {
+                error!(err = ?e, "Log writer failed");
+                return;
+            }
+        }
+    }
+}
+
+/// The topic logs, which are owned by the writer task of the [`DurableLog`].
+#[derive(Debug)]
+struct Topics {
+    options: LogOptions,
+    topics: FxHashMap<String, TopicLog>,
+    /// The sequence number of the last appended message.
+    last_seq: Option<u32>,
+    /// The number of appended messages that haven't been synced yet.
+    unsynced: u64,
+    /// The time at which the oldest unsynced message was appended.
+    unsynced_since: Option<Instant>,
+}
+
+impl Topics {
+    /// Opens the log in the configured directory, recovering any existing topics.
+    fn open(options: LogOptions) -> io::Result<Self> {
+        fs::create_dir_all(&options.path)?;
+
+        let mut topics = FxHashMap::default();
+        let mut last_seq = None;
+
+        for entry in fs::read_dir(&options.path)? {
+            let entry = entry?;
+            let Some(topic) = entry.file_name().to_str().and_then(decode_topic) else {
+                warn!(path = ?entry.path(), "Ignoring unknown file in log directory");
+                continue;
+            };
+
+            let log = TopicLog::open(entry.path())?;
+            if let Some(seq) = log.segments.back().map(|segment| segment.last_seq) {
+                if last_seq.map_or(true, |last| seq_after(seq, last)) {
+                    last_seq = Some(seq);
+                }
+            }
+
+            topics.insert(topic, log);
+        }
+
+        debug!(path = ?options.path, topics = topics.len(), ?last_seq, "Opened log");
+
+        Ok(Self { options, topics, last_seq, unsynced: 0, unsynced_since: None })
+    }
+
+    /// Continues the sequences of the given sequencer after the last messages in the log.
+    fn resume(&self, sequencer: &mut Sequencer) {
+        if let Some(seq) = self.last_seq {
+            let topic_seqs = self.topics.iter().filter_map(|(topic, log)| {
+                log.segments.back().map(|segment| (topic.clone(), segment.last_topic_seq))
+            });
+
+            sequencer.resume(seq, topic_seqs);
+        }
+    }
+
+    /// Handles a batch of commands of the writer task. Appends are only acknowledged after the log
+    /// is synced, if a sync is due according to the sync policy, and the appended messages are
+    /// passed to `on_appended` before that. If `closing` is set, all unsynced messages are
+    /// synced, unless syncing is disabled.
+    fn handle(
+        &mut self,
+        batch: Vec<Command>,
+        closing: bool,
+        on_appended: &mut dyn FnMut(PubMessage),
+    ) {
+        let mut acks = Vec::new();
+
+        for command in batch {
+            match command {
+                Command::Append { msg, appended } => {
+                    let result = self.append(&msg);
+                    if let Err(ref e) = result {
+                        error!(err = ?e, seq = msg.seq, "Failed to append message to log");
+                    }
+
+                    acks.push((appended, result, msg));
+                }
+                Command::Read { filter, seq, timestamp, events } => {
+                    match self.read(&filter, seq, timestamp) {
+                        Ok(reader) => {
+                            tokio::task::spawn_blocking(move || reader.send_all(events));
+                        }
+                        // The channel is empty, so there is room for the error
+                        Err(e) => {
+                            let _ = events.try_send(Err(e));
+                        }
+                    }
+                }
+            }
+        }
+
+        let sync = match self.options.sync {
+            SyncPolicy::Never => false,
+            _ if closing => self.unsynced > 0,
+            SyncPolicy::Always => self.unsynced > 0,
+            SyncPolicy::Messages(n) => self.unsynced >= n.max(1),
+            SyncPolicy::Interval(interval) => {
+                self.unsynced_since.is_some_and(|since| since.elapsed() >= interval)
+            }
+        };
+
+        if sync {
+            if let Err(e) = self.sync() {
+                error!(err = ?e, "Failed to sync log");
+                for (_, result, _) in acks.iter_mut().filter(|(_, result, _)| result.is_ok()) {
+                    *result = Err(io::Error::new(e.kind(), e.to_string()));
+                }
+            }
+        }
+
+        for (appended, result, msg) in acks {
+            if result.is_ok() {
+                on_appended(msg);
+            }
+
+            let _ = appended.send(result);
+        }
+    }
+
+    /// Returns the time at which the unsynced messages are due to be synced, if they are synced on
+    /// an interval.
+    fn sync_deadline(&self) -> Option<Instant> {
+        match self.options.sync {
+            SyncPolicy::Interval(interval) => self.unsynced_since.map(|since| since + interval),
+            _ => None,
+        }
+    }
+
+    /// Syncs all appended messages to disk.
+    fn sync(&mut self) -> io::Result<()> {
+        for log in self.topics.values_mut() {
+            log.sync()?;
+        }
+
+        self.unsynced = 0;
+        self.unsynced_since = None;
+
+        Ok(())
+    }
+
+    /// Starts a catch-up read of all topics matching the given filter. The reader yields the
+    /// messages in sequence order, starting at the first message with a sequence number of at
+    /// least `seq` and a timestamp of at least `timestamp`, up to the last message that is
+    /// currently in the log.
+    fn read(&self, filter: &PrefixTrie, seq: u32, timestamp: u64) -> io::Result<LogReader> {
+        let mut readers = Vec::new();
+
+        if let Some(until) = self.last_seq {
+            for (name, log) in self.topics.iter().filter(|(name, _)| filter.contains(name)) {
+                if let Some(reader) = log.reader(name, seq, timestamp, until)? {
+                    readers.push(reader);
+                }
+            }
+        }
+
+        Ok(LogReader { until: self.last_seq, peeked: vec![None; readers.len()], readers })
+    }
+
+    /// Appends the message to the log of its topic, and applies the retention limits.
+    fn append(&mut self, msg: &PubMessage) -> io::Result<()> {
+        if !self.topics.contains_key(msg.topic()) {
+            let dir = self.options.path.join(encode_topic(msg.topic()));
+            fs::create_dir_all(&dir)?;
+            self.topics.insert(msg.topic().to_owned(), TopicLog::new(dir));
+        }
+
+        let log = self.topics.get_mut(msg.topic()).expect("topic log exists");
+
+        let now = unix_micros();
+        log.append(msg, now, &self.options)?;
+        log.apply_retention(&self.options, now)?;
+
+        self.last_seq = Some(msg.seq);
+        self.unsynced += 1;
+        self.unsynced_since.get_or_insert_with(Instant::now);
+
+        Ok(())
+    }
+}
+
+/// Metadata of a single segment of a topic log.
+#[derive(Debug)]
+struct Segment {
+    /// The segment ID, which is also its file name.
+    id: u64,
+    last_seq: u32,
+    last_topic_seq: u32,
+    last_timestamp: u64,
+    /// The size of the segment file in bytes.
+    size: u64,
+}
+
+impl Segment {
+    fn log_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{:020}.log", self.id))
+    }
+
+    fn index_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{:020}.idx", self.id))
+    }
+}
+
+/// The log of a single topic.
+#[derive(Debug)]
+struct TopicLog {
+    /// The directory of this topic.
+    dir: PathBuf,
+    /// The segments, from oldest to newest.
+    segments: VecDeque<Segment>,
+    /// The segment and index files of the newest segment, opened for appending.
+    writer: Option<(File, File)>,
+    /// Whether messages were appended since the last sync.
+    dirty: bool,
+}
+
+impl TopicLog {
+    fn new(dir: PathBuf) -> Self {
+        Self { dir, segments: VecDeque::new(), writer: None, dirty: false }
+    }
+
+    /// Opens an existing topic log. Data that was only partially written to the newest segment
+    /// (i.e. not indexed) is truncated.
+    fn open(dir: PathBuf) -> io::Result<Self> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "log") {
+                if let Some(id) = path.file_stem().and_then(|s| s.to_str()?.parse::<u64>().ok()) {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort_unstable();
+
+        let mut log = Self::new(dir);
+        let last_id = ids.last().copied();
+
+        for id in ids {
+            let segment = log.open_segment(id, Some(id) == last_id)?;
+            match segment {
+                Some(segment) => log.segments.push_back(segment),
+                None => {
+                    // Empty segments are removed
+                    let _ = fs::remove_file(log.dir.join(format!("{:020}.log", id)));
+                    let _ = fs::remove_file(log.dir.join(format!("{:020}.idx", id)));
+                }
+            }
+        }
+
+        Ok(log)
+    }
+
+    /// Loads the metadata of a segment from its index. Returns `None` if the segment is empty.
+    fn open_segment(&self, id: u64, recover: bool) -> io::Result<Option<Segment>> {
+        let log_path = self.dir.join(format!("{:020}.log", id));
+        let index_path = self.dir.join(format!("{:020}.idx", id));
+
+        let log_file = OpenOptions::new().read(true).write(true).open(&log_path)?;
+        let mut index = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&index_path)?;
+
+        let log_len = log_file.metadata()?.len();
+        let mut entries = index.metadata()?.len() / INDEX_ENTRY_LEN;
+
+        // Find the last entry that points to a complete record
+        let mut size = 0;
+        let mut last_topic_seq = 0;
+        while entries > 0 {
+            let (_, _, offset) = read_index_entry(&mut index, entries - 1)?;
+
+            if offset + RECORD_HEADER_LEN <= log_len {
+                let mut header = [0; RECORD_HEADER_LEN as usize];
+                let mut reader = &log_file;
+                reader.seek(SeekFrom::Start(offset))?;
+                reader.read_exact(&mut header)?;
+
+                let end = offset + RECORD_HEADER_LEN + (&header[17..]).get_u32() as u64;
+                if end <= log_len {
+                    size = end;
+                    last_topic_seq = (&header[4..]).get_u32();
+                    break;
+                }
+            }
+
+            entries -= 1;
+        }
+
+        if recover && (size < log_len || entries * INDEX_ENTRY_LEN < index.metadata()?.len()) {
+            warn!(path = ?log_path, "Truncating partially written segment");
+            log_file.set_len(size)?;
+            index.set_len(entries * INDEX_ENTRY_LEN)?;
+        }
+
+        if entries == 0 {
+            return Ok(None);
+        }
+
+        let (last_seq, last_timestamp, _) = read_index_entry(&mut index, entries - 1)?;
+
+        Ok(Some(Segment { id, last_seq, last_topic_seq, last_timestamp, size }))
+    }
+
+    fn append(&mut self, msg: &PubMessage, timestamp: u64, options: &LogOptions) -> io::Result<()> {
+        // Start a new segment if the current one is full
+        if self.segments.back().map_or(true, |segment| segment.size >= options.segment_size) {
+            // The full segment isn't written anymore, so it's synced now
+            if options.sync != SyncPolicy::Never {
+                self.sync()?;
+            }
+
+            let id = self.segments.back().map_or(0, |segment| segment.id + 1);
+            let segment = Segment {
+                id,
+                last_seq: msg.seq,
+                last_topic_seq: msg.topic_seq,
+                last_timestamp: 0,
+                size: 0,
+            };
+
+            let log_file = File::create(segment.log_path(&self.dir))?;
+            let index = File::create(segment.index_path(&self.dir))?;
+
+            self.writer = Some((log_file, index));
+            self.segments.push_back(segment);
+        }
+
+        let segment = self.segments.back_mut().expect("at least one segment");
+        let (log_file, index) = match self.writer {
+            Some(ref mut writer) => writer,
+            None => self.writer.insert((
+                OpenOptions::new().append(true).open(segment.log_path(&self.dir))?,
+                OpenOptions::new().append(true).open(segment.index_path(&self.dir))?,
+            )),
+        };
+
+        let mut record = BytesMut::with_capacity(RECORD_HEADER_LEN as usize + msg.payload.len());
+        record.put_u32(msg.seq);
+        record.put_u32(msg.topic_seq);
+        record.put_u64(timestamp);
+        record.put_u8(msg.compression_type as u8);
+        record.put_u32(msg.payload.len() as u32);
+        record.put_slice(&msg.payload);
+
+        let mut entry = BytesMut::with_capacity(INDEX_ENTRY_LEN as usize);
+        entry.put_u32(msg.seq);
+        entry.put_u64(timestamp);
+        entry.put_u64(segment.size);
+
+        // The record is written before the index entry, so that the index only points to
+        // complete records
+        log_file.write_all(&record)?;
+        index.write_all(&entry)?;
+
+        segment.last_seq = msg.seq;
+        segment.last_topic_seq = msg.topic_seq;
+        segment.last_timestamp = timestamp;
+        segment.size += record.len() as u64;
+        self.dirty = true;
+
+        Ok(())
+    }
+
+    /// Syncs the newest segment to disk, if messages were appended since the last sync.
+    fn sync(&mut self) -> io::Result<()> {
+        if let Some((log_file, index)) = self.writer.as_ref().filter(|_| self.dirty) {
+            log_file.sync_data()?;
+            index.sync_data()?;
+        }
+
+        self.dirty = false;
+
+        Ok(())
+    }
+
+    /// Removes the oldest segments that exceed the retention limits. The newest segment is always
+    /// kept.
+    fn apply_retention(&mut self, options: &LogOptions, now: u64) -> io::Result<()> {
+        let mut size: u64 = self.segments.iter().map(|segment| segment.size).sum();
+
+        while self.segments.len() > 1 {
+            let oldest = &self.segments[0];
+
+            let exceeded = options.retention_bytes.is_some_and(|max| size > max) ||
+                options.retention_age.is_some_and(|max| {
+                    now.saturating_sub(oldest.last_timestamp) > max.as_micros() as u64
+                });
+
+            if !exceeded {
+                break;
+            }
+
+            debug!(dir = ?self.dir, id = oldest.id, "Removing segment");
+            fs::remove_file(oldest.log_path(&self.dir))?;
+            fs::remove_file(oldest.index_path(&self.dir))?;
+
+            size -= oldest.size;
+            self.segments.pop_front();
+        }
+
+        Ok(())
+    }
+
+    /// Creates a reader for the messages in this log starting at the given sequence number and
+    /// timestamp. Returns `None` if there are no such messages.
+    fn reader(
+        &self,
+        topic: &str,
+        seq: u32,
+        timestamp: u64,
+        until: u32,
+    ) -> io::Result<Option<TopicReader>> {
+        // Sequence numbers wrap around, so they're compared with `seq_after`
+        let Some(start) = self.segments.iter().position(|segment| {
+            !seq_after(seq, segment.last_seq) && segment.last_timestamp >= timestamp
+        }) else {
+            return Ok(None);
+        };
+
+        let segment = &self.segments[start];
+        let mut index = File::open(segment.index_path(&self.dir))?;
+        let entries = index.metadata()?.len() / INDEX_ENTRY_LEN;
+
+        // Binary search for the first entry at the requested position
+        let (mut low, mut high) = (0, entries);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let (entry_seq, entry_timestamp, _) = read_index_entry(&mut index, mid)?;
+            if !seq_after(seq, entry_seq) && entry_timestamp >= timestamp {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+
+        let offset = if low < entries { read_index_entry(&mut index, low)?.2 } else { 0 };
+
+        Ok(Some(TopicReader {
+            topic: topic.to_owned(),
+            segments: self.segments.range(start..).map(|s| s.log_path(&self.dir)).collect(),
+            offset,
+            current: None,
+            until,
+        }))
+    }
+}
+
+/// Reads the messages of a single topic log, in order.
+#[derive(Debug)]
+struct TopicReader {
+    topic: String,
+    /// The segment files that haven't been opened yet.
+    segments: VecDeque<PathBuf>,
+    /// The offset of the first record to read in the next segment.
+    offset: u64,
+    /// The segment file that is being read.
+    current: Option<BufReader<File>>,
+    /// The sequence number of the last message to read.
+    until: u32,
+}
+
+impl TopicReader {
+    fn next(&mut self) -> io::Result<Option<PubMessage>> {
+        loop {
+            let reader = match self.current {
+                Some(ref mut reader) => reader,
+                None => {
+                    let Some(path) = self.segments.pop_front() else {
+                        return Ok(None);
+                    };
+
+                    let mut file = match File::open(&path) {
+                        Ok(file) => file,
+                        // The segment was removed by retention in the meantime
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                        Err(e) => return Err(e),
+                    };
+
+                    file.seek(SeekFrom::Start(std::mem::take(&mut self.offset)))?;
+                    self.current.insert(BufReader::new(file))
+                }
+            };
+
+            let mut header = [0; RECORD_HEADER_LEN as usize];
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    self.current = None;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+
+            let mut header = &header[..];
+            let seq = header.get_u32();
+            let topic_seq = header.get_u32();
+            let _timestamp = header.get_u64();
+            let compression_type = header.get_u8();
+            let size = header.get_u32();
+
+            // Everything after this was appended after the read started
+            if seq_after(seq, self.until) {
+                self.segments.clear();
+                self.current = None;
+                return Ok(None);
+            }
+
+            let mut payload = vec![0; size as usize];
+            reader.read_exact(&mut payload)?;
+
+            let mut msg = PubMessage::new(self.topic.clone(), Bytes::from(payload));
+            msg.seq = seq;
+            msg.topic_seq = topic_seq;
+            msg.compression_type =
+                CompressionType::try_from(compression_type).map_err(|value| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid compression type {value}"),
+                    )
+                })?;
+
+            return Ok(Some(msg));
+        }
+    }
+}
+
+/// A catch-up read of one or more topic logs, see [`DurableLog::read`]. Yields the messages of
+/// all topics in sequence order.
+#[derive(Debug)]
+pub(super) struct LogReader {
+    readers: Vec<TopicReader>,
+    /// The next message of every reader.
+    peeked: Vec<Option<PubMessage>>,
+    /// The sequence number of the last message in the log when the read started.
+    until: Option<u32>,
+}
+
+impl LogReader {
+    /// Returns the sequence number of the last message this reader yields, if any. Messages from
+    /// the socket up to this sequence number are part of this read.
+    fn until(&self) -> Option<u32> {
+        self.until
+    }
+
+    /// Reads all messages and sends them to the given channel, after the start of the read. Stops
+    /// at the first error, or once the receiver is dropped. Blocks, so must be run in a blocking
+    /// task.
+    fn send_all(mut self, events: mpsc::Sender<io::Result<CatchUpEvent>>) {
+        let mut event = Ok(CatchUpEvent::Started { until: self.until() });
+
+        loop {
+            let failed = event.is_err();
+            if events.blocking_send(event).is_err() || failed {
+                return;
+            }
+
+            event = match self.next() {
+                Ok(Some(msg)) => Ok(CatchUpEvent::Message(msg)),
+                Ok(None) => return,
+                Err(e) => Err(e),
+            };
+        }
+    }
+
+    /// Reads the next message. Returns `None` when the read is complete.
+    fn next(&mut self) -> io::Result<Option<PubMessage>> {
+        let mut next: Option<usize> = None;
+
+        for i in 0..self.readers.len() {
+            if self.peeked[i].is_none() {
+                self.peeked[i] = self.readers[i].next()?;
+            }
+
+            if let Some(ref msg) = self.peeked[i] {
+                let first = next.map_or(true, |j| {
+                    self.peeked[j].as_ref().is_some_and(|other| seq_after(other.seq, msg.seq))
+                });
+
+                if first {
+                    next = Some(i);
+                }
+            }
+        }
+
+        Ok(next.and_then(|i| self.peeked[i].take()))
+    }
+}
+
+/// Reads the index entry at the given position.
+fn read_index_entry(index: &mut File, position: u64) -> io::Result<(u32, u64, u64)> {
+    let mut entry = [0; INDEX_ENTRY_LEN as usize];
+    index.seek(SeekFrom::Start(position * INDEX_ENTRY_LEN))?;
+    index.read_exact(&mut entry)?;
+
+    let mut entry = &entry[..];
+    Ok((entry.get_u32(), entry.get_u64(), entry.get_u64()))
+}
+
+/// Encodes a topic as a directory name (hex), since topics can contain any character.
+fn encode_topic(topic: &str) -> String {
+    topic.bytes().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_topic(name: &str) -> Option<String> {
+    if name.len() % 2 != 0 {
+        return None;
+    }
+
+    let bytes = (0..name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("msg-log-{}", rand::random::<u64>()))
+    }
+
+    fn append(log: &mut Topics, seq: u32, topic: &str, payload: &'static str) {
+        let mut msg = PubMessage::new(topic.to_string(), Bytes::from(payload));
+        msg.seq = seq;
+        msg.topic_seq = seq;
+        log.append(&msg).unwrap();
+    }
+
+    fn filter(topic: &str) -> PrefixTrie {
+        let mut filter = PrefixTrie::new();
+        filter.insert(topic);
+        filter
+    }
+
+    fn read_all(log: &Topics, topic: &str, seq: u32, timestamp: u64) -> Vec<(u32, String)> {
+        let mut reader = log.read(&filter(topic), seq, timestamp).unwrap();
+        let mut messages = Vec::new();
+        while let Some(msg) = reader.next().unwrap() {
+            messages.push((msg.seq, msg.topic));
+        }
+        messages
+    }
+
+    #[test]
+    fn log_read_from_position() {
+        let dir = temp_dir();
+        let mut log = Topics::open(LogOptions::new(&dir).segment_size(64)).unwrap();
+
+        for seq in 0..10 {
+            let topic = if seq % 2 == 0 { "prices.btc" } else { "prices.eth" };
+            append(&mut log, seq, topic, "hello");
+        }
+        append(&mut log, 10, "volume.btc", "hello");
+
+        // Messages of all matching topics are merged in sequence order
+        let messages = read_all(&log, "prices.*", 5, 0);
+        assert_eq!(messages.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(), vec![5, 6, 7, 8, 9]);
+        assert_eq!(messages[0].1, "prices.eth");
+        assert_eq!(messages[1].1, "prices.btc");
+
+        assert_eq!(read_all(&log, "prices.btc", 0, 0).len(), 5);
+        assert!(read_all(&log, "prices.btc", 0, unix_micros() + 1_000_000).is_empty());
+
+        let now = unix_micros();
+        std::thread::sleep(Duration::from_millis(5));
+        append(&mut log, 11, "prices.btc", "hello");
+        assert_eq!(read_all(&log, "prices.btc", 0, now), vec![(11, "prices.btc".to_string())]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn log_read_across_wrap_around() {
+        let dir = temp_dir();
+        let mut log = Topics::open(LogOptions::new(&dir).segment_size(64)).unwrap();
+
+        let mut seq = u32::MAX - 4;
+        for _ in 0..10 {
+            append(&mut log, seq, "foo", "hello");
+            seq = seq.wrapping_add(1);
+        }
+
+        let messages = read_all(&log, "foo", u32::MAX - 1, 0);
+        let seqs = messages.iter().map(|(seq, _)| *seq).collect::<Vec<_>>();
+        assert_eq!(seqs, vec![u32::MAX - 1, u32::MAX, 0, 1, 2, 3, 4]);
+
+        let messages = read_all(&log, "foo", 2, 0);
+        assert_eq!(messages.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn log_reopen() {
+        let dir = temp_dir();
+        let mut log = Topics::open(LogOptions::new(&dir)).unwrap();
+
+        for seq in 0..3 {
+            append(&mut log, seq, "foo", "hello");
+        }
+        append(&mut log, 3, "bar", "hello");
+        drop(log);
+
+        // Simulate a crash halfway through writing a record
+        let segment = dir.join(encode_topic("foo")).join(format!("{:020}.log", 0));
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(&[0, 0, 0, 3, 0, 0]).unwrap();
+
+        let mut log = Topics::open(LogOptions::new(&dir)).unwrap();
+
+        let mut sequencer = Sequencer::default();
+        log.resume(&mut sequencer);
+        let mut msg = PubMessage::new("foo".to_string(), Bytes::from("world"));
+        sequencer.assign(&mut msg);
+        assert_eq!((msg.seq, msg.topic_seq), (4, 3));
+        log.append(&msg).unwrap();
+
+        let mut reader = log.read(&filter("foo"), 2, 0).unwrap();
+        assert_eq!(reader.until(), Some(4));
+        assert_eq!(reader.next().unwrap().unwrap().payload(), "hello");
+        assert_eq!(reader.next().unwrap().unwrap().payload(), "world");
+        assert!(reader.next().unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn log_retention() {
+        let dir = temp_dir();
+        // Every record is 26 bytes, so every segment holds 2 records
+        let options = LogOptions::new(&dir).segment_size(50).retention_bytes(120);
+        let mut log = Topics::open(options).unwrap();
+
+        for seq in 0..20 {
+            append(&mut log, seq, "foo", "hello");
+        }
+
+        let messages = read_all(&log, "foo", 0, 0);
+        assert_eq!(messages.first().unwrap().0, 16);
+        assert_eq!(messages.len(), 4);
+        assert_eq!(fs::read_dir(dir.join(encode_topic("foo"))).unwrap().count(), 2 * 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn log_sync_policy() {
+        fn append_batch(log: &mut Topics, seqs: std::ops::Range<u32>) -> Vec<io::Result<()>> {
+            let (batch, acks): (Vec<_>, Vec<_>) = seqs
+                .clone()
+                .map(|seq| {
+                    let mut msg = PubMessage::new("foo".to_string(), Bytes::from("hello"));
+                    msg.seq = seq;
+                    let (appended, rx) = oneshot::channel();
+                    (Command::Append { msg, appended }, rx)
+                })
+                .unzip();
+
+            // The appended messages are passed on in order
+            let mut appended = Vec::new();
+            log.handle(batch, false, &mut |msg| appended.push(msg.seq));
+            assert_eq!(appended, seqs.collect::<Vec<_>>());
+
+            acks.into_iter().map(|mut rx| rx.try_recv().unwrap()).collect()
+        }
+
+        let dir = temp_dir();
+
+        // Every batch is synced before it's acknowledged
+        let mut log = Topics::open(LogOptions::new(&dir)).unwrap();
+        assert!(append_batch(&mut log, 0..3).iter().all(|result| result.is_ok()));
+        assert_eq!(log.unsynced, 0);
+        assert!(!log.topics["foo"].dirty);
+
+        // Messages are synced once enough of them are appended
+        let mut log = Topics::open(LogOptions::new(&dir).sync(SyncPolicy::Messages(3))).unwrap();
+        append_batch(&mut log, 3..5);
+        assert_eq!(log.unsynced, 2);
+        assert!(log.topics["foo"].dirty);
+        append_batch(&mut log, 5..6);
+        assert_eq!(log.unsynced, 0);
+        assert!(!log.topics["foo"].dirty);
+
+        // Messages are synced on an interval
+        let interval = Duration::from_secs(60);
+        let mut log = Topics::open(LogOptions::new(&dir).sync(SyncPolicy::Interval(interval)))
+            .unwrap();
+        assert_eq!(log.sync_deadline(), None);
+        append_batch(&mut log, 6..7);
+        assert_eq!(log.unsynced, 1);
+        assert!(log.sync_deadline().unwrap() > Instant::now() + interval / 2);
+
+        // Unless syncing is disabled, the remaining messages are synced when the log is closed
+        log.handle(Vec::new(), true, &mut |_| {});
+        assert_eq!(log.unsynced, 0);
+        assert_eq!(log.sync_deadline(), None);
+
+        let mut log = Topics::open(LogOptions::new(&dir).sync(SyncPolicy::Never)).unwrap();
+        append_batch(&mut log, 7..8);
+        log.handle(Vec::new(), true, &mut |_| {});
+        assert_eq!(log.unsynced, 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,12 +1,22 @@
 use bytes::Bytes;
-use std::io;
+use parking_lot::Mutex;
+use rustc_hash::FxHashMap;
+use std::{io, time::Duration};
 use thiserror::Error;
 
+mod cache;
+mod datagram;
 mod driver;
+mod framed;
+mod log;
+pub use log::{LogOptions, SyncPolicy};
+use msg_transport::Address;
 use msg_wire::{
     compression::{CompressionType, Compressor},
     pubsub,
 };
+mod queue;
+mod retention;
 mod session;
 mod socket;
 mod stats;
@@ -44,11 +54,24 @@ pub struct PubOptions {
     /// the session will be flushed on every publish, which can add a lot of overhead.
     flush_interval: Option<std::time::Duration>,
     /// The maximum number of bytes that can be buffered in the session before being flushed.
-    /// This internally sets [`Framed::set_backpressure_boundary`](tokio_util::codec::Framed).
     backpressure_boundary: usize,
     /// Minimum payload size in bytes for compression to be used. If the payload is smaller than
     /// this threshold, it will not be compressed.
     min_compress_size: usize,
+    /// Whether to keep the latest message of every topic, and send it to new subscribers.
+    last_value_cache: bool,
+    /// The maximum number of messages retained for replay.
+    retention_messages: Option<usize>,
+    /// The maximum number of bytes retained for replay.
+    retention_bytes: Option<usize>,
+    /// The maximum age of messages retained for replay.
+    retention_age: Option<Duration>,
+    /// The durable topic log options. `None` if disabled.
+    log: Option<LogOptions>,
+    /// What to do when a session's buffer is full.
+    slow_subscriber_policy: SlowSubscriberPolicy,
+    /// The maximum size of a datagram. If `None`, only the path MTU limits the size.
+    max_datagram_size: Option<usize>,
 }
 
 impl Default for PubOptions {
@@ -59,6 +82,13 @@ impl Default for PubOptions {
             flush_interval: Some(std::time::Duration::from_micros(50)),
             backpressure_boundary: 8192,
             min_compress_size: 8192,
+            last_value_cache: false,
+            retention_messages: None,
+            retention_bytes: None,
+            retention_age: None,
+            log: None,
+            slow_subscriber_policy: SlowSubscriberPolicy::default(),
+            max_datagram_size: None,
         }
     }
 }
@@ -71,14 +101,14 @@ impl PubOptions {
     }
 
     /// Sets the session channel buffer size. This is the amount of messages that can be buffered
-    /// per session before messages start being dropped.
+    /// per session before the [`SlowSubscriberPolicy`] is applied.
     pub fn session_buffer_size(mut self, session_buffer_size: usize) -> Self {
         self.session_buffer_size = session_buffer_size;
         self
     }
 
     /// Sets the maximum number of bytes that can be buffered in the session before being flushed.
-    /// This internally sets [`Framed::set_backpressure_boundary`](tokio_util::codec::Framed).
+    /// Messages are not copied into this buffer, only the references to their encoded frames.
     pub fn backpressure_boundary(mut self, backpressure_boundary: usize) -> Self {
         self.backpressure_boundary = backpressure_boundary;
         self
@@ -97,6 +127,88 @@ impl PubOptions {
         self.min_compress_size = min_compress_size;
         self
     }
+
+    /// Enables the last-value cache. The socket keeps the latest message published on every
+    /// topic, and when a subscriber subscribes, it immediately receives the cached message of each
+    /// matching topic. Note that a subscriber may receive the latest message twice if it is
+    /// published while the subscription is being handled.
+    pub fn last_value_cache(mut self) -> Self {
+        self.last_value_cache = true;
+        self
+    }
+
+    /// Sets the maximum number of published messages retained for replay. Setting any of the
+    /// retention limits enables the retention buffer: subscribers that reconnect ask for all
+    /// retained messages after the last sequence number they've seen, so that short network
+    /// blips don't cause data loss.
+    pub fn retention_messages(mut self, retention_messages: usize) -> Self {
+        self.retention_messages = Some(retention_messages);
+        self
+    }
+
+    /// Sets the maximum size in bytes (topic and payload) of the messages retained for replay.
+    /// See [`PubOptions::retention_messages`].
+    pub fn retention_bytes(mut self, retention_bytes: usize) -> Self {
+        self.retention_bytes = Some(retention_bytes);
+        self
+    }
+
+    /// Sets the maximum age of the messages retained for replay.
+    /// See [`PubOptions::retention_messages`].
+    pub fn retention_age(mut self, retention_age: Duration) -> Self {
+        self.retention_age = Some(retention_age);
+        self
+    }
+
+    /// Enables the durable topic log. Every published message is appended to a log on disk, so
+    /// that subscribers can catch up on messages they missed, even across restarts of the
+    /// publisher. See
+    /// [`SubSocket::catch_up`](crate::SubSocket::catch_up).
+    pub fn durable_log(mut self, log: LogOptions) -> Self {
+        self.log = Some(log);
+        self
+    }
+
+    /// Sets the policy for subscribers that can't keep up, i.e. when a session's buffer is full.
+    /// Defaults to [`SlowSubscriberPolicy::DropNewest`].
+    pub fn slow_subscriber_policy(mut self, policy: SlowSubscriberPolicy) -> Self {
+        self.slow_subscriber_policy = policy;
+        self
+    }
+
+    /// Sets the maximum size in bytes of a datagram, including the fragment header. Messages on
+    /// datagram topics (see [`PubSocket::with_datagram_topics`]) that don't fit in a single
+    /// datagram are fragmented. By default, only the path MTU limits the size.
+    pub fn max_datagram_size(mut self, max_datagram_size: usize) -> Self {
+        self.max_datagram_size = Some(max_datagram_size);
+        self
+    }
+
+    /// Returns true if any of the retention limits is set.
+    fn retention_enabled(&self) -> bool {
+        self.retention_messages.is_some() ||
+            self.retention_bytes.is_some() ||
+            self.retention_age.is_some()
+    }
+}
+
+/// What to do with new messages for a subscriber whose session buffer is full. Dropped messages
+/// are counted in the [socket stats](PubSocket::stats), and show up as gaps on the subscriber
+/// side.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlowSubscriberPolicy {
+    /// Drop the new message for the slow subscriber.
+    #[default]
+    DropNewest,
+    /// Drop the oldest buffered message for the slow subscriber to make room for the new one.
+    DropOldest,
+    /// Wait for the slow subscriber to make room for up to the given timeout, after which the new
+    /// message is dropped. Only applies to [`PubSocket::publish`], [`PubSocket::try_publish`]
+    /// never blocks and drops the new message instead.
+    Block(Duration),
+    /// Drop the new message, and disconnect the slow subscriber once it has dropped the given
+    /// number of messages in total.
+    Disconnect(usize),
 }
 
 /// A message received from a publisher.
@@ -105,10 +217,17 @@ impl PubOptions {
 pub struct PubMessage {
     /// The compression type used for the message payload.
     compression_type: CompressionType,
+    /// The sequence number of the message, assigned by the socket when it is published.
+    seq: u32,
+    /// The sequence number of the message within its topic.
+    topic_seq: u32,
     /// The topic of the message.
     topic: String,
     /// The message payload.
     payload: Bytes,
+    /// The message encoded for the wire, shared by all sessions. Set when the message is
+    /// published.
+    frame: Option<pubsub::Frame>,
 }
 
 #[allow(unused)]
@@ -118,8 +237,11 @@ impl PubMessage {
             // Initialize the compression type to None.
             // The actual compression type will be set in the `compress` method.
             compression_type: CompressionType::None,
+            seq: 0,
+            topic_seq: 0,
             topic,
             payload,
+            frame: None,
         }
     }
 
@@ -139,13 +261,39 @@ impl PubMessage {
     }
 
     #[inline]
-    pub fn into_wire(self, seq: u32) -> pubsub::Message {
+    pub fn seq(&self) -> u32 {
+        self.seq
+    }
+
+    #[inline]
+    pub fn topic_seq(&self) -> u32 {
+        self.topic_seq
+    }
+
+    #[inline]
+    pub fn into_wire(self) -> pubsub::Message {
         pubsub::Message::new(
-            seq,
+            self.seq,
             Bytes::from(self.topic),
             self.payload,
             self.compression_type as u8,
         )
+        .with_topic_seq(self.topic_seq)
+    }
+
+    /// Encodes the message for the wire, so that it only has to be encoded once for all sessions.
+    #[inline]
+    pub(crate) fn encode(&mut self) {
+        self.frame = Some(self.clone().into_wire().encode());
+    }
+
+    /// Returns the message encoded for the wire, encoding it if that hasn't been done yet.
+    #[inline]
+    pub(crate) fn into_frame(self) -> pubsub::Frame {
+        match self.frame {
+            Some(frame) => frame,
+            None => self.into_wire().encode(),
+        }
     }
 
     #[inline]
@@ -159,20 +307,73 @@ impl PubMessage {
 
 /// The publisher socket state, shared between the backend task and the socket.
 #[derive(Debug, Default)]
-pub(crate) struct SocketState {
-    pub(crate) stats: SocketStats,
+pub(crate) struct SocketState<A: Address> {
+    pub(crate) stats: SocketStats<A>,
+    /// Assigns the sequence numbers of published messages.
+    pub(crate) sequencer: Mutex<Sequencer>,
+}
+
+impl<A: Address> SocketState<A> {
+    pub fn new() -> Self {
+        Self { stats: SocketStats::new(), sequencer: Mutex::default() }
+    }
+}
+
+/// Assigns sequence numbers to published messages: one that is global to the socket, and one
+/// per topic. Subscribers use the latter to detect lost messages.
+#[derive(Debug, Default)]
+pub(crate) struct Sequencer {
+    /// The sequence number of the next message.
+    next: u32,
+    /// The sequence number of the next message of every topic.
+    topics: FxHashMap<String, u32>,
+}
+
+impl Sequencer {
+    /// Assigns the next sequence numbers to the message.
+    pub(crate) fn assign(&mut self, msg: &mut PubMessage) {
+        msg.seq = self.next;
+        self.next = self.next.wrapping_add(1);
+
+        match self.topics.get_mut(msg.topic()) {
+            Some(next) => {
+                msg.topic_seq = *next;
+                *next = next.wrapping_add(1);
+            }
+            None => {
+                msg.topic_seq = 0;
+                self.topics.insert(msg.topic.clone(), 1);
+            }
+        }
+    }
+
+    /// Continues the sequences after the given last sequence numbers, i.e. of an existing log.
+    pub(crate) fn resume(&mut self, seq: u32, topics: impl IntoIterator<Item = (String, u32)>) {
+        self.next = seq.wrapping_add(1);
+        self.topics = topics.into_iter().map(|(topic, seq)| (topic, seq.wrapping_add(1))).collect();
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
-
-    use futures::StreamExt;
-    use msg_transport::{quic::Quic, tcp::Tcp};
+    use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+    use futures::{SinkExt, StreamExt};
+    use msg_transport::{
+        ipc::Ipc,
+        quic::{self, Quic},
+        tcp::Tcp,
+    };
     use msg_wire::compression::GzipCompressor;
+    use quinn::congestion::CubicConfig;
+    use tokio::{
+        net::{TcpListener, TcpStream, UnixStream},
+        task::{JoinHandle, JoinSet},
+    };
+    use tokio_util::codec::Framed;
     use tracing::info;
 
-    use crate::{Authenticator, SubOptions, SubSocket};
+    use crate::{Authenticator, Authorizer, Identity, LogPosition, SubOptions, SubSocket};
 
     use super::*;
 
@@ -185,6 +386,21 @@ mod tests {
         }
     }
 
+    /// Spawns a TCP proxy to `upstream`. Aborting the returned task closes all proxied
+    /// connections.
+    fn spawn_proxy(listener: TcpListener, upstream: SocketAddr) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut conns = JoinSet::new();
+            loop {
+                let (mut inbound, _) = listener.accept().await.unwrap();
+                conns.spawn(async move {
+                    let mut outbound = TcpStream::connect(upstream).await.unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+                });
+            }
+        })
+    }
+
     #[tokio::test]
     async fn pubsub_simple() {
         let _ = tracing_subscriber::fmt::try_init();
@@ -234,14 +450,52 @@ mod tests {
         assert_eq!("WORLD", msg.payload());
     }
 
+    /// Only lets clients subscribe to topics prefixed with their own ID.
+    struct TenantAuthorizer;
+
+    impl Authorizer for TenantAuthorizer {
+        fn authorize_subscribe(&self, identity: &Identity, topic: &str) -> bool {
+            let id = identity.id();
+            topic.as_bytes().starts_with(id) && topic.as_bytes().get(id.len()) == Some(&b'.')
+        }
+    }
+
+    #[tokio::test]
+    async fn pubsub_authorizer() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut pub_socket =
+            PubSocket::new(Tcp::default()).with_auth(Auth).with_authorizer(TenantAuthorizer);
+
+        let mut sub_socket = SubSocket::with_options(
+            Tcp::default(),
+            SubOptions::default().auth_token(Bytes::from("tenant_a")),
+        );
+
+        pub_socket.bind("0.0.0.0:0").await.unwrap();
+        let addr = pub_socket.local_addr().unwrap();
+
+        sub_socket.connect(addr).await.unwrap();
+        sub_socket.subscribe("tenant_b.orders".to_string()).await.unwrap();
+        sub_socket.subscribe("tenant_a.orders".to_string()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        pub_socket.publish("tenant_b.orders".to_string(), "SECRET".into()).await.unwrap();
+        pub_socket.publish("tenant_a.orders".to_string(), "WORLD".into()).await.unwrap();
+
+        let msg = sub_socket.next().await.unwrap();
+        assert_eq!("tenant_a.orders", msg.topic());
+        assert_eq!("WORLD", msg.payload());
+    }
+
     #[tokio::test]
     async fn pubsub_auth_quic() {
         let _ = tracing_subscriber::fmt::try_init();
 
-        let mut pub_socket = PubSocket::new(Quic::default()).with_auth(Auth);
+        let mut pub_socket = PubSocket::new(Quic::new(quic::Config::insecure())).with_auth(Auth);
 
         let mut sub_socket = SubSocket::with_options(
-            Quic::default(),
+            Quic::new(quic::Config::insecure()),
             SubOptions::default().auth_token(Bytes::from("client1")),
         );
 
@@ -260,6 +514,63 @@ mod tests {
         assert_eq!("WORLD", msg.payload());
     }
 
+    #[tokio::test]
+    async fn pubsub_datagrams_quic() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let config = || {
+            quic::ConfigBuilder::<Arc<CubicConfig>>::new()
+                .insecure_skip_verification()
+                .datagrams(1024 * 1024)
+                .build()
+                .unwrap()
+        };
+
+        let mut pub_socket = PubSocket::with_options(
+            Quic::new(config()),
+            PubOptions::default().max_datagram_size(512),
+        )
+        .with_datagram_topics(["market.*"]);
+
+        let mut sub_socket = SubSocket::new(Quic::new(config())).with_datagrams();
+
+        pub_socket.bind("0.0.0.0:0").await.unwrap();
+        let addr = pub_socket.local_addr().unwrap();
+
+        sub_socket.connect(addr).await.unwrap();
+        sub_socket.subscribe("market.*".to_string()).await.unwrap();
+        sub_socket.subscribe("orders".to_string()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Fragmented into multiple datagrams
+        let payload: Bytes = (0..5000).map(|i| i as u8).collect::<Vec<_>>().into();
+        pub_socket.publish("market.btc", payload.clone()).await.unwrap();
+
+        let msg = sub_socket.next().await.unwrap();
+        assert_eq!("market.btc", msg.topic());
+        assert_eq!(&payload, msg.payload());
+
+        // Other topics are still sent on the stream
+        pub_socket.publish("orders", Bytes::from("WORLD")).await.unwrap();
+        pub_socket.publish("market.btc", Bytes::from("tick")).await.unwrap();
+
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let msg = sub_socket.next().await.unwrap();
+            assert_eq!(msg.gap(), None);
+            received.push((msg.topic().to_string(), msg.into_payload()));
+        }
+
+        received.sort();
+        assert_eq!(
+            received,
+            vec![
+                ("market.btc".to_string(), Bytes::from("tick")),
+                ("orders".to_string(), Bytes::from("WORLD"))
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn pubsub_many() {
         let _ = tracing_subscriber::fmt::try_init();
@@ -354,9 +665,9 @@ mod tests {
     async fn pubsub_durable_quic() {
         let _ = tracing_subscriber::fmt::try_init();
 
-        let mut pub_socket = PubSocket::new(Quic::default());
+        let mut pub_socket = PubSocket::new(Quic::new(quic::Config::insecure()));
 
-        let mut sub_socket = SubSocket::new(Quic::default());
+        let mut sub_socket = SubSocket::new(Quic::new(quic::Config::insecure()));
 
         // Try to connect and subscribe before the publisher is up
         sub_socket.connect("0.0.0.0:6662").await.unwrap();
@@ -396,4 +707,387 @@ mod tests {
         tokio::time::sleep(Duration::from_millis(100)).await;
         assert_eq!(pub_socket.stats().active_clients(), 1);
     }
+
+    #[tokio::test]
+    async fn pubsub_last_value_cache() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut pub_socket =
+            PubSocket::with_options(Tcp::default(), PubOptions::default().last_value_cache());
+
+        pub_socket.bind("0.0.0.0:0").await.unwrap();
+        let addr = pub_socket.local_addr().unwrap();
+
+        // Publish before anyone is subscribed
+        pub_socket.publish("prices.btc", Bytes::from("1")).await.unwrap();
+        pub_socket.publish("prices.btc", Bytes::from("2")).await.unwrap();
+        pub_socket.publish("prices.eth", Bytes::from("3")).await.unwrap();
+        pub_socket.publish("volume.btc", Bytes::from("4")).await.unwrap();
+
+        let mut sub_socket = SubSocket::new(Tcp::default());
+        sub_socket.connect(addr).await.unwrap();
+        sub_socket.subscribe("prices.*".to_string()).await.unwrap();
+
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let msg = tokio::time::timeout(Duration::from_secs(1), sub_socket.next())
+                .await
+                .unwrap()
+                .unwrap();
+            received.push((msg.topic().to_string(), msg.into_payload()));
+        }
+        received.sort();
+
+        assert_eq!(
+            received,
+            vec![
+                ("prices.btc".to_string(), Bytes::from("2")),
+                ("prices.eth".to_string(), Bytes::from("3"))
+            ]
+        );
+
+        // New messages are still delivered after the snapshot
+        pub_socket.publish("prices.btc", Bytes::from("5")).await.unwrap();
+        let msg = sub_socket.next().await.unwrap();
+        assert_eq!("prices.btc", msg.topic());
+        assert_eq!("5", msg.payload());
+    }
+
+    #[tokio::test]
+    async fn pubsub_last_value_cache_disabled() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut pub_socket = PubSocket::new(Tcp::default());
+
+        pub_socket.bind("0.0.0.0:0").await.unwrap();
+        let addr = pub_socket.local_addr().unwrap();
+
+        pub_socket.publish("HELLO", Bytes::from("WORLD")).await.unwrap();
+
+        let mut sub_socket = SubSocket::new(Tcp::default());
+        sub_socket.connect(addr).await.unwrap();
+        sub_socket.subscribe("HELLO".to_string()).await.unwrap();
+
+        assert!(tokio::time::timeout(Duration::from_millis(200), sub_socket.next()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn pubsub_replay_on_reconnect() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut pub_socket =
+            PubSocket::with_options(Tcp::default(), PubOptions::default().retention_messages(16));
+        pub_socket.bind("127.0.0.1:0").await.unwrap();
+        let upstream = *pub_socket.local_addr().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        let proxy = spawn_proxy(listener, upstream);
+
+        let mut sub_socket = SubSocket::new(Tcp::default());
+        sub_socket.connect(proxy_addr).await.unwrap();
+        sub_socket.subscribe("HELLO".to_string()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        pub_socket.publish("HELLO", Bytes::from("1")).await.unwrap();
+        assert_eq!("1", sub_socket.next().await.unwrap().payload());
+
+        // Cut the connection and publish while the subscriber is disconnected
+        proxy.abort();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        pub_socket.publish("HELLO", Bytes::from("2")).await.unwrap();
+        pub_socket.publish("OTHER", Bytes::from("x")).await.unwrap();
+        pub_socket.publish("HELLO", Bytes::from("3")).await.unwrap();
+
+        let listener = TcpListener::bind(proxy_addr).await.unwrap();
+        let _proxy = spawn_proxy(listener, upstream);
+
+        for expected in ["2", "3"] {
+            let msg = tokio::time::timeout(Duration::from_secs(5), sub_socket.next())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!("HELLO", msg.topic());
+            assert_eq!(expected, msg.payload());
+        }
+
+        // Live messages are delivered after the replayed ones, without duplicates
+        pub_socket.publish("HELLO", Bytes::from("4")).await.unwrap();
+        assert_eq!("4", sub_socket.next().await.unwrap().payload());
+        assert!(tokio::time::timeout(Duration::from_millis(200), sub_socket.next()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn pubsub_gap_detection() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut pub_socket = PubSocket::new(Tcp::default());
+        pub_socket.bind("127.0.0.1:0").await.unwrap();
+        let upstream = *pub_socket.local_addr().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        let proxy = spawn_proxy(listener, upstream);
+
+        let mut sub_socket = SubSocket::new(Tcp::default());
+        sub_socket.connect(proxy_addr).await.unwrap();
+        sub_socket.subscribe("HELLO".to_string()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        pub_socket.publish("HELLO", Bytes::from("1")).await.unwrap();
+        let msg = sub_socket.next().await.unwrap();
+        assert_eq!(msg.gap(), None);
+
+        // Without retention, messages published while disconnected are lost
+        proxy.abort();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        pub_socket.publish("HELLO", Bytes::from("2")).await.unwrap();
+        pub_socket.publish("OTHER", Bytes::from("x")).await.unwrap();
+        pub_socket.publish("HELLO", Bytes::from("3")).await.unwrap();
+
+        let listener = TcpListener::bind(proxy_addr).await.unwrap();
+        let _proxy = spawn_proxy(listener, upstream);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        pub_socket.publish("HELLO", Bytes::from("4")).await.unwrap();
+        let msg =
+            tokio::time::timeout(Duration::from_secs(5), sub_socket.next()).await.unwrap().unwrap();
+        assert_eq!("4", msg.payload());
+        assert_eq!(msg.seq(), 4);
+        assert_eq!(msg.gap(), Some(2));
+
+        // Messages on other topics are not counted
+        let stats = sub_socket.stats();
+        assert_eq!(stats.gaps(&proxy_addr), Some(1));
+        assert_eq!(stats.lost_messages(&proxy_addr), Some(2));
+    }
+
+    /// Connects a subscriber that subscribes to all topics, but never reads. Returns its
+    /// connection and the ID of its session.
+    async fn spawn_stalled_subscriber(
+        pub_socket: &PubSocket<Tcp, SocketAddr>,
+    ) -> (Framed<TcpStream, pubsub::Codec>, u32) {
+        let stream = TcpStream::connect(pub_socket.local_addr().unwrap()).await.unwrap();
+        let local_addr = stream.local_addr().unwrap();
+
+        let mut conn = Framed::new(stream, pubsub::Codec::new());
+        conn.send(pubsub::Message::new_sub(Bytes::from("*"))).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let sessions = pub_socket.stats().sessions();
+        let (session_id, _) = sessions.into_iter().find(|(_, addr)| *addr == local_addr).unwrap();
+
+        (conn, session_id)
+    }
+
+    #[tokio::test]
+    async fn pubsub_slow_subscriber_disconnect() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let options = PubOptions::default()
+            .session_buffer_size(4)
+            .slow_subscriber_policy(SlowSubscriberPolicy::Disconnect(8));
+        let mut pub_socket = PubSocket::with_options(Tcp::default(), options);
+        pub_socket.bind("127.0.0.1:0").await.unwrap();
+
+        let (_conn, session_id) = spawn_stalled_subscriber(&pub_socket).await;
+        assert_eq!(pub_socket.stats().active_clients(), 1);
+
+        let payload = Bytes::from(vec![0; 64 * 1024]);
+        while pub_socket.stats().slow_disconnects() == 0 {
+            pub_socket.publish("HELLO", payload.clone()).await.unwrap();
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(pub_socket.stats().dropped_messages(), 8);
+
+        // The session is removed once its task has shut down
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pub_socket.stats().active_clients() != 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("slow subscriber was not disconnected");
+        assert_eq!(pub_socket.stats().session_dropped_messages(session_id), None);
+    }
+
+    #[tokio::test]
+    async fn pubsub_slow_subscriber_block() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let timeout = Duration::from_millis(50);
+        let options = PubOptions::default()
+            .session_buffer_size(4)
+            .slow_subscriber_policy(SlowSubscriberPolicy::Block(timeout));
+        let mut pub_socket = PubSocket::with_options(Tcp::default(), options);
+        pub_socket.bind("127.0.0.1:0").await.unwrap();
+
+        let (_conn, session_id) = spawn_stalled_subscriber(&pub_socket).await;
+
+        // Once the subscriber's buffer is full, publishing waits for the timeout before dropping
+        let payload = Bytes::from(vec![0; 64 * 1024]);
+        loop {
+            let start = std::time::Instant::now();
+            pub_socket.publish("HELLO", payload.clone()).await.unwrap();
+            tokio::task::yield_now().await;
+
+            if pub_socket.stats().dropped_messages() > 0 {
+                assert!(start.elapsed() >= timeout);
+                break;
+            }
+        }
+
+        assert_eq!(pub_socket.stats().session_dropped_messages(session_id), Some(1));
+        assert_eq!(pub_socket.stats().active_clients(), 1);
+    }
+
+    #[tokio::test]
+    async fn pubsub_session_stats_same_address() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let path = std::env::temp_dir().join(format!("msg-pub-{}.sock", rand::random::<u64>()));
+        let mut pub_socket = PubSocket::new(Ipc::default());
+        pub_socket.bind(path.clone()).await.unwrap();
+
+        // All peers of an IPC socket have the same address
+        let sub1 = UnixStream::connect(&path).await.unwrap();
+        let _sub2 = UnixStream::connect(&path).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let sessions = pub_socket.stats().sessions();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].1, sessions[1].1);
+
+        // Closing one session doesn't remove the stats of the other
+        drop(sub1);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pub_socket.stats().active_clients() != 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("session was not closed");
+
+        let sessions = pub_socket.stats().sessions();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(pub_socket.stats().session_dropped_messages(sessions[0].0), Some(0));
+    }
+
+    #[tokio::test]
+    async fn pubsub_durable_log_catch_up() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let dir = std::env::temp_dir().join(format!("msg-log-{}", rand::random::<u64>()));
+        let options = || PubOptions::default().durable_log(LogOptions::new(&dir));
+
+        let mut pub_socket = PubSocket::with_options(Tcp::default(), options());
+        pub_socket.bind("127.0.0.1:0").await.unwrap();
+        for i in 0..3 {
+            pub_socket.publish("HELLO", Bytes::from(i.to_string())).await.unwrap();
+        }
+        pub_socket.publish("OTHER", Bytes::from("x")).await.unwrap();
+        drop(pub_socket);
+
+        // Restart the publisher with the same log
+        let mut pub_socket = PubSocket::with_options(Tcp::default(), options());
+        pub_socket.bind("127.0.0.1:0").await.unwrap();
+        pub_socket.publish("HELLO", Bytes::from("3")).await.unwrap();
+
+        let mut sub_socket = SubSocket::new(Tcp::default());
+        sub_socket.connect(pub_socket.local_addr().unwrap()).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        sub_socket.catch_up("HELLO", LogPosition::Seq(1)).await.unwrap();
+
+        for expected in ["1", "2", "3"] {
+            let msg = tokio::time::timeout(Duration::from_secs(1), sub_socket.next())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!("HELLO", msg.topic());
+            assert_eq!(expected, msg.payload());
+        }
+
+        // New messages follow the catch-up
+        pub_socket.publish("HELLO", Bytes::from("4")).await.unwrap();
+        assert_eq!("4", sub_socket.next().await.unwrap().payload());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn pubsub_durable_log_append_failure() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let dir = std::env::temp_dir().join(format!("msg-log-{}", rand::random::<u64>()));
+        let options = PubOptions::default().durable_log(LogOptions::new(&dir));
+        let mut pub_socket = PubSocket::with_options(Tcp::default(), options);
+        pub_socket.bind("127.0.0.1:0").await.unwrap();
+
+        let mut sub_socket = SubSocket::new(Tcp::default());
+        sub_socket.connect(pub_socket.local_addr().unwrap()).await.unwrap();
+        sub_socket.subscribe("*").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // A file in place of the directory of the topic makes appending to it fail
+        let topic_dir = "BROKEN".bytes().map(|b| format!("{b:02x}")).collect::<String>();
+        std::fs::write(dir.join(topic_dir), b"").unwrap();
+
+        // Messages that can't be appended are not sent to the subscribers
+        assert!(pub_socket.publish("BROKEN", Bytes::from("lost")).await.is_err());
+        pub_socket.publish("HELLO", Bytes::from("logged")).await.unwrap();
+
+        let msg =
+            tokio::time::timeout(Duration::from_secs(1), sub_socket.next()).await.unwrap().unwrap();
+        assert_eq!("HELLO", msg.topic());
+        assert_eq!("logged", msg.payload());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn pubsub_durable_log_catch_up_overflow() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let dir = std::env::temp_dir().join(format!("msg-log-{}", rand::random::<u64>()));
+        let options = PubOptions::default()
+            .session_buffer_size(4)
+            .slow_subscriber_policy(SlowSubscriberPolicy::Disconnect(1))
+            .durable_log(LogOptions::new(&dir).sync(SyncPolicy::Never));
+        let mut pub_socket = PubSocket::with_options(Tcp::default(), options);
+        pub_socket.bind("127.0.0.1:0").await.unwrap();
+
+        // Enough messages to fill the buffers of the connection, so that the catch-up stalls
+        let payload = Bytes::from(vec![0; 16 * 1024]);
+        for _ in 0..1000 {
+            pub_socket.publish("HELLO", payload.clone()).await.unwrap();
+        }
+
+        let stream = TcpStream::connect(pub_socket.local_addr().unwrap()).await.unwrap();
+        let mut conn = Framed::new(stream, pubsub::Codec::new());
+        conn.send(pubsub::Message::new_catch_up(Bytes::from("HELLO"), 0, 0)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Publish more messages than fit in the session buffer while the catch-up is stalled
+        for _ in 0..100 {
+            pub_socket.publish("HELLO", Bytes::from("live")).await.unwrap();
+        }
+
+        // None of them are lost, and the subscriber is not disconnected
+        for seq in 0..1100 {
+            let msg = tokio::time::timeout(Duration::from_secs(5), conn.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            assert_eq!(msg.seq(), seq);
+        }
+
+        assert_eq!(pub_socket.stats().dropped_messages(), 0);
+        assert_eq!(pub_socket.stats().active_clients(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,309 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures::task::AtomicWaker;
+use parking_lot::{Mutex, RwLock};
+use rustc_hash::FxHashMap;
+use tokio::{sync::Notify, time::Instant};
+
+use super::{stats::SessionStats, PubMessage, SlowSubscriberPolicy};
+
+/// The message queues of all active sessions. Owned by the socket: when the last socket handle is
+/// dropped, all queues are closed and the sessions shut down.
+#[derive(Debug, Default)]
+pub(super) struct SessionQueues {
+    queues: RwLock<FxHashMap<u32, Arc<SessionQueue>>>,
+}
+
+impl SessionQueues {
+    /// Registers the queue of a new session.
+    pub(super) fn insert(&self, session_id: u32, queue: Arc<SessionQueue>) {
+        self.queues.write().insert(session_id, queue);
+    }
+
+    /// Removes the queue of a session that has shut down.
+    pub(super) fn remove(&self, session_id: u32) {
+        self.queues.write().remove(&session_id);
+    }
+
+    /// Pushes the message to the queue of every session. Returns the number of sessions that
+    /// dropped the message, and the number of sessions that were disconnected.
+    pub(super) fn push(&self, msg: &PubMessage, policy: SlowSubscriberPolicy) -> (usize, usize) {
+        let mut dropped = 0;
+        let mut disconnected = 0;
+
+        for queue in self.queues.read().values() {
+            match queue.push(msg.clone(), policy) {
+                Push::Queued | Push::Deferred => {}
+                Push::Dropped => dropped += 1,
+                Push::Disconnected => {
+                    dropped += 1;
+                    disconnected += 1;
+                }
+            }
+        }
+
+        (dropped, disconnected)
+    }
+
+    /// Waits until every queue has room for a new message, or until the timeout expires.
+    pub(super) async fn wait_for_capacity(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let queues = self.queues.read().values().cloned().collect::<Vec<_>>();
+
+        for queue in queues {
+            if tokio::time::timeout_at(deadline, queue.ready()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for SessionQueues {
+    fn drop(&mut self) {
+        for queue in self.queues.get_mut().values() {
+            queue.close();
+        }
+    }
+}
+
+/// The outcome of pushing a message to a session queue.
+#[derive(Debug, PartialEq, Eq)]
+enum Push {
+    /// The message was queued, possibly by dropping the oldest queued message.
+    Queued,
+    /// The queue was full, and the message was dropped.
+    Dropped,
+    /// The queue was full and the session dropped too many messages, so it was closed.
+    Disconnected,
+    /// The queue was full while the session is catching up, so the message is left to be read
+    /// from the durable log.
+    Deferred,
+}
+
+/// A bounded queue of messages to be sent by a single session. Unlike a broadcast channel, it
+/// applies the [`SlowSubscriberPolicy`] when the session can't keep up.
+#[derive(Debug)]
+pub(super) struct SessionQueue {
+    inner: Mutex<Inner>,
+    /// The maximum number of queued messages.
+    capacity: usize,
+    /// The session statistics, where dropped messages are recorded.
+    stats: Arc<SessionStats>,
+    /// Wakes up the session when a message is queued or the queue is closed.
+    waker: AtomicWaker,
+    /// Notifies blocked publishers when a message is taken from the queue.
+    space: Notify,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    messages: VecDeque<PubMessage>,
+    closed: bool,
+    /// Whether the session is catching up from the durable log, in which case the slow
+    /// subscriber policy doesn't apply.
+    catching_up: bool,
+    /// Whether messages were left out of the queue while catching up.
+    overflowed: bool,
+}
+
+impl SessionQueue {
+    pub(super) fn new(capacity: usize, stats: Arc<SessionStats>) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            capacity,
+            stats,
+            waker: AtomicWaker::new(),
+            space: Notify::new(),
+        }
+    }
+
+    fn push(&self, msg: PubMessage, policy: SlowSubscriberPolicy) -> Push {
+        let mut inner = self.inner.lock();
+        if inner.closed {
+            return Push::Queued;
+        }
+
+        let outcome = if inner.messages.len() < self.capacity {
+            inner.messages.push_back(msg);
+            Push::Queued
+        } else if inner.catching_up {
+            // Every message is appended to the log before it's queued, so the session can read
+            // it from the log once the current catch-up is done
+            inner.overflowed = true;
+            Push::Deferred
+        } else {
+            let dropped = self.stats.record_dropped();
+
+            match policy {
+                SlowSubscriberPolicy::DropOldest => {
+                    inner.messages.pop_front();
+                    inner.messages.push_back(msg);
+                    Push::Queued
+                }
+                SlowSubscriberPolicy::Disconnect(max) if dropped >= max => {
+                    inner.closed = true;
+                    Push::Disconnected
+                }
+                _ => Push::Dropped,
+            }
+        };
+
+        drop(inner);
+        self.waker.wake();
+
+        outcome
+    }
+
+    /// Takes the next message from the queue. Returns `None` once the queue is closed.
+    pub(super) fn poll_pop(&self, cx: &mut Context<'_>) -> Poll<Option<PubMessage>> {
+        self.waker.register(cx.waker());
+
+        let mut inner = self.inner.lock();
+        if inner.closed {
+            return Poll::Ready(None);
+        }
+
+        match inner.messages.pop_front() {
+            Some(msg) => {
+                drop(inner);
+                self.space.notify_waiters();
+                Poll::Ready(Some(msg))
+            }
+            None => Poll::Pending,
+        }
+    }
+
+    /// Marks the session as catching up from the durable log. Until the catch-up is finished, the
+    /// messages that don't fit in the queue are left out, instead of applying the slow subscriber
+    /// policy.
+    pub(super) fn start_catch_up(&self) {
+        self.inner.lock().catching_up = true;
+    }
+
+    /// Finishes the catch-up of the session. If messages were left out of the queue while catching
+    /// up, the queue is cleared instead and `true` is returned: the session is still catching up,
+    /// and has to read the queued and left out messages from the log.
+    pub(super) fn finish_catch_up(&self) -> bool {
+        let mut inner = self.inner.lock();
+        if !inner.overflowed {
+            inner.catching_up = false;
+            return false;
+        }
+
+        inner.overflowed = false;
+        inner.messages.clear();
+
+        true
+    }
+
+    /// Returns true if the queue is closed.
+    pub(super) fn is_closed(&self) -> bool {
+        self.inner.lock().closed
+    }
+
+    /// Closes the queue. Queued messages are discarded.
+    pub(super) fn close(&self) {
+        let mut inner = self.inner.lock();
+        inner.closed = true;
+        inner.messages.clear();
+        drop(inner);
+
+        self.waker.wake();
+        self.space.notify_waiters();
+    }
+
+    /// Waits until the queue has room for a new message.
+    async fn ready(&self) {
+        loop {
+            // Create the future before checking, so that no notification is missed
+            let notified = self.space.notified();
+
+            {
+                let inner = self.inner.lock();
+                if inner.closed || inner.catching_up || inner.messages.len() < self.capacity {
+                    return;
+                }
+            }
+
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::task::noop_waker_ref;
+
+    use super::*;
+
+    fn message(seq: u32) -> PubMessage {
+        let mut msg = PubMessage::new("foo".to_string(), Bytes::new());
+        msg.seq = seq;
+        msg
+    }
+
+    fn queue_with(policy: SlowSubscriberPolicy) -> (SessionQueue, Vec<Push>) {
+        let queue = SessionQueue::new(2, Arc::default());
+        let outcomes = (0..4).map(|seq| queue.push(message(seq), policy)).collect();
+
+        (queue, outcomes)
+    }
+
+    fn drain(queue: &SessionQueue) -> Vec<u32> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut seqs = Vec::new();
+        while let Poll::Ready(Some(msg)) = queue.poll_pop(&mut cx) {
+            seqs.push(msg.seq());
+        }
+
+        seqs
+    }
+
+    #[test]
+    fn queue_drop_policies() {
+        let (queue, outcomes) = queue_with(SlowSubscriberPolicy::DropNewest);
+        assert_eq!(outcomes, [Push::Queued, Push::Queued, Push::Dropped, Push::Dropped]);
+        assert_eq!(drain(&queue), [0, 1]);
+        assert_eq!(queue.stats.dropped_messages(), 2);
+
+        let (queue, outcomes) = queue_with(SlowSubscriberPolicy::DropOldest);
+        assert!(outcomes.iter().all(|outcome| *outcome == Push::Queued));
+        assert_eq!(drain(&queue), [2, 3]);
+        assert_eq!(queue.stats.dropped_messages(), 2);
+    }
+
+    #[test]
+    fn queue_catch_up_overflow() {
+        let queue = SessionQueue::new(2, Arc::default());
+        queue.start_catch_up();
+
+        let policy = SlowSubscriberPolicy::Disconnect(1);
+        let outcomes = (0..4).map(|seq| queue.push(message(seq), policy)).collect::<Vec<_>>();
+        assert_eq!(outcomes, [Push::Queued, Push::Queued, Push::Deferred, Push::Deferred]);
+
+        // The policy doesn't apply, and the queue is cleared so that the session reads all of the
+        // messages from the log
+        assert!(!queue.is_closed());
+        assert_eq!(queue.stats.dropped_messages(), 0);
+        assert!(queue.finish_catch_up());
+        assert_eq!(drain(&queue), []);
+
+        // Once caught up, the policy applies again
+        assert!(!queue.finish_catch_up());
+        let outcomes = (4..7).map(|seq| queue.push(message(seq), policy)).collect::<Vec<_>>();
+        assert_eq!(outcomes, [Push::Queued, Push::Queued, Push::Disconnected]);
+    }
+
+    #[test]
+    fn queue_disconnect_policy() {
+        let (queue, outcomes) = queue_with(SlowSubscriberPolicy::Disconnect(2));
+        assert_eq!(outcomes, [Push::Queued, Push::Queued, Push::Dropped, Push::Disconnected]);
+        assert_eq!(drain(&queue), []);
+    }
+}
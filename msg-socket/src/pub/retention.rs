@@ -0,0 +1,147 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use parking_lot::{Mutex, MutexGuard};
+
+use msg_common::seq_after;
+
+use super::{trie::PrefixTrie, PubMessage};
+
+/// A bounded buffer of the most recently published messages, used to replay the messages a
+/// subscriber missed while it was reconnecting.
+#[derive(Debug)]
+pub(super) struct RetentionBuffer {
+    ring: Mutex<Ring>,
+}
+
+impl RetentionBuffer {
+    pub(super) fn new(
+        max_messages: Option<usize>,
+        max_bytes: Option<usize>,
+        max_age: Option<Duration>,
+    ) -> Self {
+        Self {
+            ring: Mutex::new(Ring {
+                messages: VecDeque::new(),
+                bytes: 0,
+                max_messages,
+                max_bytes,
+                max_age,
+            }),
+        }
+    }
+
+    /// Locks the buffer. Holding the lock while sequencing and broadcasting a message ensures
+    /// the buffer is in the same order as the broadcast channel.
+    pub(super) fn lock(&self) -> MutexGuard<'_, Ring> {
+        self.ring.lock()
+    }
+
+    /// Returns all retained messages after the given sequence number that match the filter, and
+    /// the sequence number of the last retained message (if any). Messages on the broadcast
+    /// channel up to and including that sequence number have been replayed already.
+    pub(super) fn replay(&self, after: u32, filter: &PrefixTrie) -> (Vec<PubMessage>, Option<u32>) {
+        let mut ring = self.ring.lock();
+        ring.evict(Instant::now());
+
+        let last = ring.messages.back().map(|(_, msg)| msg.seq);
+        let messages = ring
+            .messages
+            .iter()
+            .map(|(_, msg)| msg)
+            .filter(|msg| seq_after(msg.seq, after) && filter.contains(msg.topic()))
+            .cloned()
+            .collect();
+
+        (messages, last)
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Ring {
+    /// The retained messages, with the time they were published.
+    messages: VecDeque<(Instant, PubMessage)>,
+    /// The total size of the retained messages in bytes.
+    bytes: usize,
+    /// The maximum number of retained messages.
+    max_messages: Option<usize>,
+    /// The maximum size of the retained messages in bytes.
+    max_bytes: Option<usize>,
+    /// The maximum age of a retained message.
+    max_age: Option<Duration>,
+}
+
+impl Ring {
+    /// Retains the message, evicting the oldest messages that exceed the limits.
+    pub(super) fn push(&mut self, msg: PubMessage) {
+        let now = Instant::now();
+        self.bytes += msg_size(&msg);
+        self.messages.push_back((now, msg));
+        self.evict(now);
+    }
+
+    fn evict(&mut self, now: Instant) {
+        while let Some((published, msg)) = self.messages.front() {
+            let exceeded = self.max_messages.is_some_and(|max| self.messages.len() > max) ||
+                self.max_bytes.is_some_and(|max| self.bytes > max) ||
+                self.max_age.is_some_and(|max| now.duration_since(*published) > max);
+
+            if !exceeded {
+                break;
+            }
+
+            self.bytes -= msg_size(msg);
+            self.messages.pop_front();
+        }
+    }
+}
+
+fn msg_size(msg: &PubMessage) -> usize {
+    msg.topic().len() + msg.payload().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn buffer(max_messages: Option<usize>, max_bytes: Option<usize>) -> RetentionBuffer {
+        let buffer = RetentionBuffer::new(max_messages, max_bytes, None);
+        for seq in 0..10 {
+            let mut msg = PubMessage::new("foo.bar".to_string(), Bytes::from("hello"));
+            msg.seq = seq;
+            buffer.lock().push(msg);
+        }
+
+        buffer
+    }
+
+    #[test]
+    fn retention_limits() {
+        let mut filter = PrefixTrie::new();
+        filter.insert("foo.*");
+
+        let (messages, last) = buffer(Some(4), None).replay(0, &filter);
+        assert_eq!(messages.iter().map(|msg| msg.seq).collect::<Vec<_>>(), vec![6, 7, 8, 9]);
+        assert_eq!(last, Some(9));
+
+        // Every message is 12 bytes
+        let (messages, _) = buffer(None, Some(30)).replay(0, &filter);
+        assert_eq!(messages.iter().map(|msg| msg.seq).collect::<Vec<_>>(), vec![8, 9]);
+    }
+
+    #[test]
+    fn retention_replay_filter() {
+        let (messages, last) = buffer(None, None).replay(7, &PrefixTrie::new());
+        assert!(messages.is_empty());
+        assert_eq!(last, Some(9));
+
+        let mut filter = PrefixTrie::new();
+        filter.insert("foo.bar");
+        let (messages, _) = buffer(None, None).replay(7, &filter);
+        assert_eq!(messages.iter().map(|msg| msg.seq).collect::<Vec<_>>(), vec![8, 9]);
+    }
+}
@@ -1,32 +1,67 @@
 use std::{
     borrow::Cow,
+    collections::VecDeque,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Weak},
     task::{Context, Poll},
 };
 
 use futures::{Future, SinkExt, StreamExt};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio_stream::wrappers::BroadcastStream;
-use tokio_util::codec::Framed;
 use tracing::{debug, error, trace, warn};
 
-use super::{trie::PrefixTrie, PubMessage, SocketState};
+use super::{
+    cache::LastValueCache,
+    datagram::DatagramSender,
+    framed::FramedConn,
+    log::{CatchUp, CatchUpEvent, DurableLog},
+    queue::{SessionQueue, SessionQueues},
+    retention::RetentionBuffer,
+    trie::PrefixTrie,
+    PubMessage, SocketState,
+};
+use crate::{Authorizer, Identity};
+use msg_common::seq_after;
+use msg_transport::Address;
 use msg_wire::pubsub;
 
-pub(super) struct SubscriberSession<Io> {
-    /// The sequence number of this session.
-    pub(super) seq: u32,
+pub(super) struct SubscriberSession<Io, A: Address> {
     /// The ID of this session.
     pub(super) session_id: u32,
+    /// The address of the subscriber.
+    pub(super) addr: A,
+    /// The identity the subscriber authenticated with. Empty if authentication is disabled.
+    pub(super) identity: Identity,
+    /// The subscription authorizer, shared with the driver. `None` if disabled.
+    pub(super) authorizer: Option<Arc<dyn Authorizer>>,
     /// Messages from the socket.
-    pub(super) from_socket_bcast: BroadcastStream<PubMessage>,
+    pub(super) queue: Arc<SessionQueue>,
+    /// The message queues of all sessions, from which this session's queue is removed when it
+    /// shuts down.
+    pub(super) sessions: Weak<SessionQueues>,
     /// Messages queued to be sent on the connection
-    pub(super) pending_egress: Option<pubsub::Message>,
+    pub(super) pending_egress: Option<pubsub::Frame>,
+    /// Cached messages for newly subscribed topics and replayed messages, sent before any new
+    /// messages.
+    pub(super) snapshot: VecDeque<PubMessage>,
+    /// The last-value cache, shared with the driver. `None` if disabled.
+    pub(super) last_values: Option<Arc<LastValueCache>>,
+    /// The buffer of messages retained for replay, shared with the driver. `None` if disabled.
+    pub(super) retention: Option<Arc<RetentionBuffer>>,
+    /// The sequence number of the last replayed message. Messages from the socket up to this
+    /// sequence number have been sent already.
+    pub(super) replayed_until: Option<u32>,
+    /// The durable topic log, shared with the driver. `None` if disabled.
+    pub(super) log: Option<Arc<DurableLog>>,
+    /// Catch-up reads from the log, sent after the cached and replayed messages.
+    pub(super) catch_up: VecDeque<CatchUp>,
+    /// Sends the messages on the datagram topics as datagrams. `None` if disabled, or if the
+    /// connection doesn't support datagrams.
+    pub(super) datagrams: Option<DatagramSender>,
     /// The socket state, shared between the backend task and the socket.
-    pub(super) state: Arc<SocketState>,
+    pub(super) state: Arc<SocketState<A>>,
     /// The framed connection.
-    pub(super) conn: Framed<Io, pubsub::Codec>,
+    pub(super) conn: FramedConn<Io>,
     /// The topic filter (a prefix trie that works with strings)
     pub(super) topic_filter: PrefixTrie,
     /// Whether or not the connection should be flushed (i.e. data was written).
@@ -35,39 +70,147 @@ pub(super) struct SubscriberSession<Io> {
     pub(super) flush_interval: Option<tokio::time::Interval>,
 }
 
-impl<Io: AsyncRead + AsyncWrite + Unpin> SubscriberSession<Io> {
+impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address> SubscriberSession<Io, A> {
     #[inline]
     fn on_outgoing(&mut self, msg: PubMessage) {
         // Check if the message matches the topic filter
         if self.topic_filter.contains(msg.topic()) {
             trace!(topic = msg.topic(), "Message matches topic filter, adding to egress queue");
 
-            // Generate the wire message and increment the sequence number
-            self.pending_egress = Some(msg.into_wire(self.seq));
-            self.seq = self.seq.wrapping_add(1);
+            self.pending_egress = Some(msg.into_frame());
         } else {
             trace!(topic = msg.topic(), "Message does not match topic filter, discarding");
         }
     }
 
+    /// Handles a new message from the socket, which is sent as datagrams if its topic is a
+    /// datagram topic.
+    #[inline]
+    fn on_published(&mut self, msg: PubMessage) {
+        if let Some(ref datagrams) = self.datagrams {
+            if datagrams.matches(msg.topic()) && self.topic_filter.contains(msg.topic()) {
+                match datagrams.send(msg.seq(), msg.into_frame()) {
+                    Ok(len) => self.state.stats.increment_tx(len),
+                    Err(frame) => {
+                        trace!(size = frame.len(), "Message too large for datagrams");
+                        self.pending_egress = Some(frame);
+                    }
+                }
+
+                return;
+            }
+        }
+
+        self.on_outgoing(msg);
+    }
+
     #[inline]
     fn on_incoming(&mut self, msg: pubsub::Message) {
         // The only incoming messages we should have are control messages.
         match msg_to_control(&msg) {
             ControlMsg::Subscribe(topic) => {
+                if !self.is_authorized(&topic) {
+                    return;
+                }
+
                 debug!("Subscribing to topic {:?}", topic);
-                self.topic_filter.insert(&topic)
+                self.topic_filter.insert(&topic);
+
+                // Queue the latest value of every matching topic
+                if let Some(ref last_values) = self.last_values {
+                    let snapshot = last_values.matching(&topic);
+                    trace!(topic = ?topic, count = snapshot.len(), "Sending cached messages");
+                    self.snapshot.extend(snapshot);
+                }
             }
             ControlMsg::Unsubscribe(topic) => {
                 debug!("Unsubscribing from topic {:?}", topic);
                 self.topic_filter.remove(&topic)
             }
+            ControlMsg::CatchUp { topic, seq, timestamp } => {
+                if !self.is_authorized(&topic) {
+                    return;
+                }
+
+                debug!(seq, timestamp, "Subscribing to topic {:?} with catch-up", topic);
+                self.topic_filter.insert(&topic);
+
+                let mut filter = PrefixTrie::new();
+                filter.insert(&topic);
+                self.start_catch_up(filter, seq, timestamp);
+            }
+            ControlMsg::Replay(seq) => {
+                let Some(ref retention) = self.retention else {
+                    debug!(seq, "Replay requested, but retention is disabled");
+                    return;
+                };
+
+                let (messages, last) = retention.replay(seq, &self.topic_filter);
+                debug!(seq, count = messages.len(), "Replaying retained messages");
+
+                self.snapshot.extend(messages);
+                if let Some(last) = last {
+                    self.mark_replayed(last);
+                }
+            }
+            ControlMsg::Datagrams => match self.datagrams {
+                Some(ref mut datagrams) => {
+                    debug!(session_id = self.session_id, "Subscriber receives datagrams");
+                    datagrams.enable();
+                }
+                None => debug!("Subscriber receives datagrams, but they are disabled"),
+            },
             ControlMsg::Close => {
                 debug!("Closing session after receiving close message {}", self.session_id);
             }
         }
     }
 
+    /// Returns `true` if the subscriber may subscribe to the given topic.
+    fn is_authorized(&self, topic: &str) -> bool {
+        let Some(ref authorizer) = self.authorizer else { return true };
+
+        let authorized = authorizer.authorize_subscribe(&self.identity, topic);
+        if !authorized {
+            warn!(session_id = self.session_id, "Unauthorized subscription to topic {:?}", topic);
+        }
+
+        authorized
+    }
+
+    /// Starts a catch-up read of the topics matching the filter from the log. New messages from
+    /// the socket are held back until all catch-up reads are complete.
+    fn start_catch_up(&mut self, filter: PrefixTrie, seq: u32, timestamp: u64) {
+        let Some(ref log) = self.log else {
+            debug!("Catch-up requested, but the log is disabled");
+            return;
+        };
+
+        self.queue.start_catch_up();
+        self.catch_up.push_back(log.read(filter, seq, timestamp));
+    }
+
+    /// Removes the current catch-up read, which is complete. Once all reads are complete, the
+    /// messages from the socket that were left out of the queue in the meantime are read from the
+    /// log, after the messages that have been read already.
+    fn finish_catch_up(&mut self) {
+        self.catch_up.pop_front();
+
+        if self.catch_up.is_empty() && self.queue.finish_catch_up() {
+            let seq = self.replayed_until.map_or(0, |seq| seq.wrapping_add(1));
+            debug!(session_id = self.session_id, seq, "Queue overflowed while catching up");
+
+            self.start_catch_up(self.topic_filter.clone(), seq, 0);
+        }
+    }
+
+    /// Marks the messages from the socket up to the given sequence number as replayed.
+    fn mark_replayed(&mut self, seq: u32) {
+        if self.replayed_until.map_or(true, |last| seq_after(seq, last)) {
+            self.replayed_until = Some(seq);
+        }
+    }
+
     #[inline]
     fn should_flush(&mut self, cx: &mut Context<'_>) -> bool {
         if self.should_flush {
@@ -88,8 +231,13 @@ impl<Io: AsyncRead + AsyncWrite + Unpin> SubscriberSession<Io> {
     }
 }
 
-impl<Io> Drop for SubscriberSession<Io> {
+impl<Io, A: Address> Drop for SubscriberSession<Io, A> {
     fn drop(&mut self) {
+        if let Some(sessions) = self.sessions.upgrade() {
+            sessions.remove(self.session_id);
+        }
+
+        self.state.stats.remove(self.session_id);
         self.state.stats.decrement_active_clients();
     }
 }
@@ -99,6 +247,13 @@ enum ControlMsg<'a> {
     Subscribe(Cow<'a, str>),
     /// Unsubscribe from a topic.
     Unsubscribe(Cow<'a, str>),
+    /// Subscribe to a topic, and first send the matching messages in the log from the given
+    /// position.
+    CatchUp { topic: Cow<'a, str>, seq: u32, timestamp: u64 },
+    /// Replay the retained messages after the given sequence number.
+    Replay(u32),
+    /// The subscriber receives messages as datagrams.
+    Datagrams,
     /// Close the session.
     Close,
 }
@@ -106,14 +261,25 @@ enum ControlMsg<'a> {
 /// Converts the message to a control message. If the message is not a control message,
 /// the session is closed.
 #[inline]
-fn msg_to_control(msg: &pubsub::Message) -> ControlMsg {
+fn msg_to_control(msg: &pubsub::Message) -> ControlMsg<'_> {
     if msg.payload_size() == 0 {
-        if msg.topic().starts_with(b"MSG.SUB.") {
+        if msg.topic().starts_with(b"MSG.CATCHUP.") {
+            let topic = msg.topic().strip_prefix(b"MSG.CATCHUP.").unwrap();
+            ControlMsg::CatchUp {
+                topic: String::from_utf8_lossy(topic),
+                seq: msg.seq(),
+                timestamp: msg.timestamp(),
+            }
+        } else if msg.topic().starts_with(b"MSG.SUB.") {
             let topic = msg.topic().strip_prefix(b"MSG.SUB.").unwrap();
             ControlMsg::Subscribe(String::from_utf8_lossy(topic))
         } else if msg.topic().starts_with(b"MSG.UNSUB.") {
             let topic = msg.topic().strip_prefix(b"MSG.UNSUB.").unwrap();
             ControlMsg::Unsubscribe(String::from_utf8_lossy(topic))
+        } else if msg.topic() == b"MSG.REPLAY".as_slice() {
+            ControlMsg::Replay(msg.seq())
+        } else if msg.topic() == b"MSG.DATAGRAMS".as_slice() {
+            ControlMsg::Datagrams
         } else {
             ControlMsg::Close
         }
@@ -123,7 +289,7 @@ fn msg_to_control(msg: &pubsub::Message) -> ControlMsg {
     }
 }
 
-impl<Io: AsyncRead + AsyncWrite + Unpin> Future for SubscriberSession<Io> {
+impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address> Future for SubscriberSession<Io, A> {
     type Output = ();
 
     #[inline]
@@ -131,6 +297,13 @@ impl<Io: AsyncRead + AsyncWrite + Unpin> Future for SubscriberSession<Io> {
         let this = self.get_mut();
 
         loop {
+            // If the queue was closed because this subscriber is too slow, shut down right away,
+            // since the connection may never become ready again.
+            if this.queue.is_closed() {
+                debug!("Queue closed, shutting down session {}", this.session_id);
+                return Poll::Ready(());
+            }
+
             // First check if we should flush the connection. We only do this if we have written
             // some data and the flush interval has elapsed. Only when we have succesfully flushed
             // the data will we reset the `should_flush` flag.
@@ -143,8 +316,8 @@ impl<Io: AsyncRead + AsyncWrite + Unpin> Future for SubscriberSession<Io> {
             // Then, try to drain the egress queue.
             if this.conn.poll_ready_unpin(cx).is_ready() {
                 if let Some(msg) = this.pending_egress.take() {
-                    debug!(?msg, "Sending message");
-                    let msg_len = msg.size();
+                    trace!(size = msg.len(), "Sending message");
+                    let msg_len = msg.len();
 
                     match this.conn.start_send_unpin(msg) {
                         Ok(_) => {
@@ -166,21 +339,60 @@ impl<Io: AsyncRead + AsyncWrite + Unpin> Future for SubscriberSession<Io> {
                 return Poll::Pending;
             }
 
-            // Poll outgoing messages
-            if let Poll::Ready(item) = this.from_socket_bcast.poll_next_unpin(cx) {
-                match item {
-                    Some(Ok(msg)) => {
-                        this.on_outgoing(msg);
-                        continue;
-                    }
-                    Some(Err(e)) => {
-                        warn!(err = ?e, session_id = this.session_id, "Receiver lagging behind");
-                        continue;
+            // Send the cached and replayed messages before any new messages
+            if let Some(msg) = this.snapshot.pop_front() {
+                this.on_outgoing(msg);
+                continue;
+            }
+
+            // Then the catch-up reads from the log
+            if let Some(catch_up) = this.catch_up.front_mut() {
+                if let Poll::Ready(event) = catch_up.poll_next(cx) {
+                    match event {
+                        Some(Ok(CatchUpEvent::Started { until })) => {
+                            if let Some(until) = until {
+                                this.mark_replayed(until);
+                            }
+                        }
+                        Some(Ok(CatchUpEvent::Message(msg))) => this.on_outgoing(msg),
+                        Some(Err(e)) => {
+                            let session_id = this.session_id;
+                            error!(err = ?e, session_id, "Failed to read from log");
+                            this.finish_catch_up();
+                        }
+                        None => {
+                            debug!(session_id = this.session_id, "Catch-up complete");
+                            this.finish_catch_up();
+                        }
                     }
-                    None => {
-                        debug!("Socket closed, shutting down session {}", this.session_id);
-                        let _ = this.conn.poll_close_unpin(cx);
-                        return Poll::Ready(());
+
+                    continue;
+                }
+            }
+
+            // Poll outgoing messages. While catching up, new messages are not sent.
+            if this.catch_up.is_empty() {
+                if let Poll::Ready(item) = this.queue.poll_pop(cx) {
+                    match item {
+                        Some(msg) => {
+                            // Skip the messages that have been replayed already
+                            if let Some(last) = this.replayed_until {
+                                if !seq_after(msg.seq(), last) {
+                                    trace!(seq = msg.seq(), "Message already replayed, discarding");
+                                    continue;
+                                }
+
+                                this.replayed_until = None;
+                            }
+
+                            this.on_published(msg);
+                            continue;
+                        }
+                        None => {
+                            debug!("Queue closed, shutting down session {}", this.session_id);
+                            let _ = this.conn.poll_close_unpin(cx);
+                            return Poll::Ready(());
+                        }
                     }
                 }
             }
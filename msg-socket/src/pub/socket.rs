@@ -1,18 +1,32 @@
-use std::{io, net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Weak},
+};
 
 use bytes::Bytes;
 use futures::stream::FuturesUnordered;
 use tokio::{
     net::{lookup_host, ToSocketAddrs},
-    sync::broadcast,
     task::JoinSet,
 };
 use tracing::{debug, trace, warn};
 
-use super::{driver::PubDriver, stats::SocketStats, PubError, PubMessage, PubOptions, SocketState};
-use crate::Authenticator;
+use super::{
+    cache::LastValueCache,
+    datagram::DatagramTopics,
+    driver::PubDriver,
+    log::{Appended, DurableLog},
+    queue::SessionQueues,
+    retention::RetentionBuffer,
+    stats::SocketStats,
+    trie::PrefixTrie,
+    PubError, PubMessage, PubOptions, SlowSubscriberPolicy, SocketState,
+};
+use crate::{datagrams_of, AsyncAuthenticator, Authenticator, Authorizer};
 
-use msg_transport::{Address, Transport};
+use msg_transport::{inproc::InprocAddr, Address, DatagramTransport, Transport};
 use msg_wire::compression::Compressor;
 
 /// A publisher socket. This is thread-safe and can be cloned.
@@ -21,19 +35,36 @@ pub struct PubSocket<T: Transport<A>, A: Address> {
     /// The reply socket options, shared with the driver.
     options: Arc<PubOptions>,
     /// The reply socket state, shared with the driver.
-    state: Arc<SocketState>,
+    state: Arc<SocketState<A>>,
     /// The transport used by this socket. This value is temporary and will be moved
     /// to the driver task once the socket is bound.
     transport: Option<T>,
-    /// The broadcast channel to all active
-    /// [`SubscriberSession`](super::session::SubscriberSession)s.
-    to_sessions_bcast: Option<broadcast::Sender<PubMessage>>,
+    /// The message queues of all active
+    /// [`SubscriberSession`](super::session::SubscriberSession)s. Only set once the socket is
+    /// bound. The driver only holds a weak reference, so that the sessions are closed when the
+    /// socket is dropped.
+    sessions: Option<Arc<SessionQueues>>,
     /// Optional connection authenticator.
-    auth: Option<Arc<dyn Authenticator>>,
+    auth: Option<Arc<dyn AsyncAuthenticator<A>>>,
+    /// Optional subscription authorizer.
+    authorizer: Option<Arc<dyn Authorizer>>,
     /// Optional message compressor.
     // NOTE: for now we're using dynamic dispatch, since using generics here
     // complicates the API a lot. We can always change this later for perf reasons.
     compressor: Option<Arc<dyn Compressor>>,
+    /// The last-value cache, shared with the driver. Only set if enabled in the options.
+    last_values: Option<Arc<LastValueCache>>,
+    /// The buffer of messages retained for replay, shared with the driver. Only set if any of the
+    /// retention limits is set in the options.
+    retention: Option<Arc<RetentionBuffer>>,
+    /// The durable topic log, shared with the driver. Only set once the socket is bound, if
+    /// enabled in the options.
+    log: Option<Arc<DurableLog>>,
+    /// Sends published messages to the subscribers. Only set once the socket is bound. If the
+    /// durable log is enabled, this is done by the writer task of the log instead.
+    fanout: Option<Arc<Fanout<A>>>,
+    /// The topics whose messages are sent as datagrams. `None` if disabled.
+    datagram_topics: Option<DatagramTopics<T::Io>>,
     /// The local address this socket is bound to.
     local_addr: Option<A>,
 }
@@ -65,6 +96,47 @@ where
     }
 }
 
+impl<T> PubSocket<T, InprocAddr>
+where
+    T: Transport<InprocAddr> + Send + Unpin + 'static,
+{
+    /// Binds the socket to the given in-process endpoint name.
+    ///
+    /// This method is only available for transports that support [`InprocAddr`] as address type,
+    /// like [`Inproc`](msg_transport::inproc::Inproc).
+    pub async fn bind(&mut self, name: impl Into<InprocAddr>) -> Result<(), PubError> {
+        self.try_bind(vec![name.into()]).await
+    }
+}
+
+impl<T, A> PubSocket<T, A>
+where
+    T: DatagramTransport<A> + Send + Unpin + 'static,
+    A: Address,
+{
+    /// Sends the messages on the given topics as unreliable datagrams instead of on the stream of
+    /// the connection, to subscribers that receive datagrams (see
+    /// [`SubSocket::with_datagrams`](crate::SubSocket::with_datagrams)). Lost messages are not
+    /// retransmitted, and subscribers drop messages that arrive after a newer message on the same
+    /// topic. Both show up as gaps, see [`PubMessage::gap`](crate::PubMessage::gap).
+    ///
+    /// Topics can contain wildcards, like subscriptions. Messages that don't fit in a single
+    /// datagram are fragmented. Cached, replayed and catch-up messages are always sent on the
+    /// stream.
+    pub fn with_datagram_topics<S: AsRef<str>>(
+        mut self,
+        topics: impl IntoIterator<Item = S>,
+    ) -> Self {
+        let mut filter = PrefixTrie::new();
+        for topic in topics {
+            filter.insert(topic.as_ref());
+        }
+
+        self.datagram_topics = Some(DatagramTopics::new(datagrams_of::<T, A>, filter));
+        self
+    }
+}
+
 impl<T, A> PubSocket<T, A>
 where
     T: Transport<A> + Send + Unpin + 'static,
@@ -77,14 +149,29 @@ where
 
     /// Creates a new publisher socket with the given transport and options.
     pub fn with_options(transport: T, options: PubOptions) -> Self {
+        let last_values = options.last_value_cache.then(Arc::default);
+        let retention = options.retention_enabled().then(|| {
+            Arc::new(RetentionBuffer::new(
+                options.retention_messages,
+                options.retention_bytes,
+                options.retention_age,
+            ))
+        });
+
         Self {
             local_addr: None,
-            to_sessions_bcast: None,
+            sessions: None,
             options: Arc::new(options),
             transport: Some(transport),
-            state: Arc::new(SocketState::default()),
+            state: Arc::new(SocketState::new()),
             auth: None,
+            authorizer: None,
             compressor: None,
+            last_values,
+            retention,
+            log: None,
+            fanout: None,
+            datagram_topics: None,
         }
     }
 
@@ -94,6 +181,20 @@ where
         self
     }
 
+    /// Sets the asynchronous connection authenticator for this socket, which also has access to
+    /// the address of the client and the metadata of the connection.
+    pub fn with_async_auth<O: AsyncAuthenticator<A>>(mut self, authenticator: O) -> Self {
+        self.auth = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Sets the subscription authorizer for this socket, which is consulted on every
+    /// subscription. Unauthorized subscriptions are ignored.
+    pub fn with_authorizer<O: Authorizer>(mut self, authorizer: O) -> Self {
+        self.authorizer = Some(Arc::new(authorizer));
+        self
+    }
+
     /// Sets the message compressor for this socket.
     pub fn with_compressor<C: Compressor + 'static>(mut self, compressor: C) -> Self {
         self.compressor = Some(Arc::new(compressor));
@@ -104,8 +205,26 @@ where
     ///
     /// This also spawns the socket driver task.
     pub async fn try_bind(&mut self, addresses: Vec<A>) -> Result<(), PubError> {
-        let (to_sessions_bcast, from_socket_bcast) =
-            broadcast::channel(self.options.session_buffer_size);
+        let sessions = Arc::new(SessionQueues::default());
+        let fanout = Arc::new(Fanout {
+            sessions: Arc::downgrade(&sessions),
+            last_values: self.last_values.clone(),
+            retention: self.retention.clone(),
+            policy: self.options.slow_subscriber_policy,
+            state: Arc::clone(&self.state),
+        });
+
+        // Open the log first, so that the sequence numbers continue where it left off
+        if let Some(ref log_options) = self.options.log {
+            let on_appended = {
+                let fanout = Arc::clone(&fanout);
+                Box::new(move |msg| fanout.send(msg))
+            };
+
+            let mut sequencer = self.state.sequencer.lock();
+            let log = DurableLog::open(log_options.clone(), &mut sequencer, on_appended)?;
+            self.log = Some(Arc::new(log));
+        }
 
         let mut transport = self.transport.take().expect("Transport has been moved already");
 
@@ -134,20 +253,30 @@ where
             options: Arc::clone(&self.options),
             state: Arc::clone(&self.state),
             auth: self.auth.take(),
+            authorizer: self.authorizer.take(),
             auth_tasks: JoinSet::new(),
             conn_tasks: FuturesUnordered::new(),
-            from_socket_bcast,
+            sessions: Arc::downgrade(&sessions),
+            last_values: self.last_values.clone(),
+            retention: self.retention.clone(),
+            log: self.log.clone(),
+            datagram_topics: self.datagram_topics.take(),
         };
 
         tokio::spawn(backend);
 
         self.local_addr = Some(local_addr);
-        self.to_sessions_bcast = Some(to_sessions_bcast);
+        self.sessions = Some(sessions);
+        self.fanout = Some(fanout);
 
         Ok(())
     }
 
     /// Publishes a message to the given topic. If the topic doesn't exist, this is a no-op.
+    ///
+    /// If the durable log is enabled, the message is only sent to the subscribers once it has been
+    /// appended to the log (and synced, according to the [`SyncPolicy`](super::SyncPolicy)), and
+    /// this returns after that. If the append fails, this fails, and the message is not sent.
     pub async fn publish(&self, topic: impl Into<String>, message: Bytes) -> Result<(), PubError> {
         let topic = topic.into();
         let mut msg = PubMessage::new(topic, message);
@@ -164,9 +293,15 @@ where
             }
         }
 
-        // Broadcast the message directly to all active sessions.
-        if self.to_sessions_bcast.as_ref().ok_or(PubError::SocketClosed)?.send(msg).is_err() {
-            debug!("No active subscriber sessions");
+        // Give slow subscribers the chance to make room before the message is broadcast
+        if let SlowSubscriberPolicy::Block(timeout) = self.options.slow_subscriber_policy {
+            if let Some(ref sessions) = self.sessions {
+                sessions.wait_for_capacity(timeout).await;
+            }
+        }
+
+        if let Some(appended) = self.broadcast(msg)? {
+            appended.wait().await?;
         }
 
         Ok(())
@@ -174,6 +309,9 @@ where
 
     /// Publishes a message to the given topic, compressing the payload if a compressor is set.
     /// If the topic doesn't exist, this is a no-op.
+    ///
+    /// Unlike [`publish`](Self::publish), this doesn't wait for the message to be appended to the
+    /// durable log. Failures to append are only logged, and the message is not sent.
     pub fn try_publish(&self, topic: String, message: Bytes) -> Result<(), PubError> {
         let mut msg = PubMessage::new(topic, message);
 
@@ -187,15 +325,34 @@ where
             debug!("Compressed message from {} to {} bytes", len_before, msg.payload().len(),);
         }
 
-        // Broadcast the message directly to all active sessions.
-        if self.to_sessions_bcast.as_ref().ok_or(PubError::SocketClosed)?.send(msg).is_err() {
-            debug!("No active subscriber sessions");
-        }
+        self.broadcast(msg).map(|_| ())
+    }
 
-        Ok(())
+    /// Assigns the next sequence number to the message and broadcasts it to all active sessions.
+    /// If the durable log is enabled, the message is broadcast once it has been appended, and the
+    /// pending append is returned.
+    fn broadcast(&self, mut msg: PubMessage) -> Result<Option<Appended>, PubError> {
+        let fanout = self.fanout.as_ref().ok_or(PubError::SocketClosed)?;
+
+        // Hold the lock until the message is broadcast or queued for the log, so that messages
+        // are broadcast, logged and retained in sequence order even if the socket is shared. No
+        // I/O happens while it's held, the message is only queued for the writer task of the log.
+        let mut sequencer = self.state.sequencer.lock();
+        sequencer.assign(&mut msg);
+
+        // Encode the message once, instead of in every session
+        msg.encode();
+
+        match self.log {
+            Some(ref log) => Ok(Some(log.append(msg))),
+            None => {
+                fanout.send(msg);
+                Ok(None)
+            }
+        }
     }
 
-    pub fn stats(&self) -> &SocketStats {
+    pub fn stats(&self) -> &SocketStats<A> {
         &self.state.stats
     }
 
@@ -204,3 +361,41 @@ where
         self.local_addr.as_ref()
     }
 }
+
+/// Sends published messages to the subscribers: caches and retains them, and pushes them to the
+/// queues of all active sessions.
+struct Fanout<A: Address> {
+    /// The message queues of all sessions, owned by the socket.
+    sessions: Weak<SessionQueues>,
+    last_values: Option<Arc<LastValueCache>>,
+    retention: Option<Arc<RetentionBuffer>>,
+    policy: SlowSubscriberPolicy,
+    state: Arc<SocketState<A>>,
+}
+
+impl<A: Address> Fanout<A> {
+    fn send(&self, msg: PubMessage) {
+        // Cache the message before broadcasting it, so that no subscriber misses it.
+        if let Some(ref last_values) = self.last_values {
+            last_values.insert(msg.clone());
+        }
+
+        if let Some(ref retention) = self.retention {
+            retention.lock().push(msg.clone());
+        }
+
+        let Some(sessions) = self.sessions.upgrade() else {
+            return;
+        };
+
+        let (dropped, disconnected) = sessions.push(&msg, self.policy);
+        if dropped > 0 {
+            trace!(seq = msg.seq(), dropped, "Dropped message for slow subscribers");
+            if disconnected > 0 {
+                warn!(disconnected, "Disconnecting slow subscribers");
+            }
+
+            self.state.stats.record_dropped(dropped, disconnected);
+        }
+    }
+}
@@ -1,18 +1,43 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use msg_transport::Address;
+use parking_lot::RwLock;
 
 /// Statistics for a reply socket. These are shared between the driver task
 /// and the socket.
 #[derive(Debug, Default)]
-pub struct SocketStats {
+pub struct SocketStats<A: Address> {
     /// Total bytes sent
     bytes_tx: AtomicUsize,
     /// Total number of active request clients
     active_clients: AtomicUsize,
-    // / Total number of dropped messages due to a slow consumer
-    // dropped_messages: AtomicUsize,
+    /// Total number of dropped messages due to a slow consumer
+    dropped_messages: AtomicUsize,
+    /// Total number of subscribers disconnected for being too slow
+    slow_disconnects: AtomicUsize,
+    /// Individual session stats for each subscriber, with its address, by session ID
+    session_stats: RwLock<HashMap<u32, (A, Arc<SessionStats>)>>,
 }
 
-impl SocketStats {
+impl<A: Address> SocketStats<A> {
+    pub fn new() -> Self {
+        Self {
+            bytes_tx: AtomicUsize::new(0),
+            active_clients: AtomicUsize::new(0),
+            dropped_messages: AtomicUsize::new(0),
+            slow_disconnects: AtomicUsize::new(0),
+            session_stats: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl<A: Address> SocketStats<A> {
     #[inline]
     pub(crate) fn increment_tx(&self, bytes: usize) {
         self.bytes_tx.fetch_add(bytes, Ordering::Relaxed);
@@ -28,6 +53,22 @@ impl SocketStats {
         self.active_clients.fetch_sub(1, Ordering::Relaxed);
     }
 
+    #[inline]
+    pub(crate) fn record_dropped(&self, dropped: usize, disconnected: usize) {
+        self.dropped_messages.fetch_add(dropped, Ordering::Relaxed);
+        self.slow_disconnects.fetch_add(disconnected, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn insert(&self, session_id: u32, addr: A, stats: Arc<SessionStats>) {
+        self.session_stats.write().insert(session_id, (addr, stats));
+    }
+
+    #[inline]
+    pub(crate) fn remove(&self, session_id: u32) {
+        self.session_stats.write().remove(&session_id);
+    }
+
     #[inline]
     pub fn bytes_tx(&self) -> usize {
         self.bytes_tx.load(Ordering::Relaxed)
@@ -37,4 +78,45 @@ impl SocketStats {
     pub fn active_clients(&self) -> usize {
         self.active_clients.load(Ordering::Relaxed)
     }
+
+    #[inline]
+    pub fn dropped_messages(&self) -> usize {
+        self.dropped_messages.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn slow_disconnects(&self) -> usize {
+        self.slow_disconnects.load(Ordering::Relaxed)
+    }
+
+    /// Returns the IDs of the active subscriber sessions, with the address of their subscriber.
+    /// Subscribers can share an address, e.g. all subscribers of an IPC socket.
+    pub fn sessions(&self) -> Vec<(u32, A)> {
+        self.session_stats.read().iter().map(|(id, (addr, _))| (*id, addr.clone())).collect()
+    }
+
+    /// Returns the number of messages dropped for the subscriber session with the given ID.
+    #[inline]
+    pub fn session_dropped_messages(&self, session_id: u32) -> Option<usize> {
+        self.session_stats.read().get(&session_id).map(|(_, stats)| stats.dropped_messages())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SessionStats {
+    /// Total number of messages dropped for this session
+    dropped_messages: AtomicUsize,
+}
+
+impl SessionStats {
+    /// Records a dropped message, and returns the total number of dropped messages.
+    #[inline]
+    pub(crate) fn record_dropped(&self) -> usize {
+        self.dropped_messages.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    #[inline]
+    pub fn dropped_messages(&self) -> usize {
+        self.dropped_messages.load(Ordering::Relaxed)
+    }
 }
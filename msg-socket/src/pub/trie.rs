@@ -2,6 +2,7 @@ use std::collections::hash_map::Entry;
 
 use rustc_hash::FxHashMap;
 
+#[derive(Debug, Clone)]
 struct Node {
     children: FxHashMap<String, Node>,
     catch_all: bool,
@@ -14,6 +15,7 @@ impl Node {
     }
 }
 
+#[derive(Debug, Clone)]
 pub(super) struct PrefixTrie {
     root: Node,
 }
@@ -0,0 +1,172 @@
+use std::{fmt, time::Duration};
+
+use bytes::Bytes;
+use thiserror::Error;
+
+mod driver;
+use driver::PullDriver;
+
+mod session;
+
+mod socket;
+pub use socket::*;
+
+mod stats;
+use stats::SocketStats;
+
+use crate::Credentials;
+use msg_transport::Address;
+use msg_wire::pushpull;
+
+const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum PullError {
+    #[error("IO error: {0:?}")]
+    Io(#[from] std::io::Error),
+    #[error("Authentication error: {0:?}")]
+    Auth(String),
+    #[error("Wire protocol error: {0:?}")]
+    Wire(#[from] pushpull::Error),
+    #[error("Socket closed")]
+    SocketClosed,
+    #[error("Command channel full")]
+    ChannelFull,
+    #[error("Transport error: {0:?}")]
+    Transport(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[derive(Debug)]
+enum Command<A: Address> {
+    /// Connect to a push socket.
+    Connect { endpoint: A },
+    /// Disconnect from a push socket.
+    Disconnect { endpoint: A },
+    /// Shut down the driver.
+    Shutdown,
+}
+
+#[derive(Debug, Clone)]
+pub struct PullOptions {
+    /// Optional authentication token.
+    auth: Option<Credentials>,
+    /// The maximum amount of incoming messages that will be buffered before the socket stops
+    /// reading from its peers.
+    ingress_buffer_size: usize,
+    /// The maximum amount of incoming messages that will be buffered per peer.
+    peer_buffer_size: usize,
+    /// The read buffer size for each session.
+    read_buffer_size: usize,
+    /// The initial backoff for reconnecting to a push socket.
+    initial_backoff: Duration,
+}
+
+impl PullOptions {
+    /// Sets the authentication token for this socket. This will activate the authentication layer
+    /// and send the token to the push socket.
+    pub fn auth_token(mut self, auth_token: Bytes) -> Self {
+        self.auth = Some(Credentials::Token(auth_token));
+        self
+    }
+
+    /// Sets the ID and the secret this socket authenticates with. Instead of sending the secret,
+    /// the socket proves that it knows it by responding to a challenge from the push socket, which
+    /// must have the same secret (see [`SharedSecrets`](crate::SharedSecrets)).
+    pub fn auth_secret(mut self, id: Bytes, secret: Bytes) -> Self {
+        self.auth = Some(Credentials::Secret { id, secret });
+        self
+    }
+
+    /// Sets the ingress buffer size. This is the maximum amount of incoming messages that will be
+    /// buffered. If the consumer cannot keep up with the incoming messages, the socket stops
+    /// reading from its peers, which will make the push sockets distribute messages to other
+    /// peers instead.
+    pub fn ingress_buffer_size(mut self, ingress_buffer_size: usize) -> Self {
+        self.ingress_buffer_size = ingress_buffer_size;
+        self
+    }
+
+    /// Sets the per-peer buffer size. This is the maximum amount of messages that will be read
+    /// from a single peer before it is fair-queued with the others.
+    pub fn peer_buffer_size(mut self, peer_buffer_size: usize) -> Self {
+        self.peer_buffer_size = peer_buffer_size;
+        self
+    }
+
+    /// Sets the read buffer size. This sets the size of the read buffer for each session.
+    pub fn read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.read_buffer_size = read_buffer_size;
+        self
+    }
+
+    /// Set the initial backoff for reconnecting to a push socket.
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+}
+
+impl Default for PullOptions {
+    fn default() -> Self {
+        Self {
+            auth: None,
+            ingress_buffer_size: DEFAULT_BUFFER_SIZE,
+            peer_buffer_size: 64,
+            read_buffer_size: 8192,
+            initial_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// A message received from a push socket.
+/// Includes the source and payload.
+#[derive(Clone)]
+pub struct PullMessage<A: Address> {
+    /// The source address of the push socket. We need this because
+    /// a pull socket can connect to multiple push sockets.
+    source: A,
+    /// The message payload.
+    payload: Bytes,
+}
+
+impl<A: Address> fmt::Debug for PullMessage<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PullMessage")
+            .field("source", &self.source)
+            .field("payload_size", &self.payload.len())
+            .finish()
+    }
+}
+
+impl<A: Address> PullMessage<A> {
+    pub fn new(source: A, payload: Bytes) -> Self {
+        Self { source, payload }
+    }
+
+    #[inline]
+    pub fn source(&self) -> &A {
+        &self.source
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    #[inline]
+    pub fn into_payload(self) -> Bytes {
+        self.payload
+    }
+}
+
+/// The pull socket state, shared between the backend task and the socket.
+#[derive(Debug, Default)]
+pub(crate) struct SocketState<A: Address> {
+    pub(crate) stats: SocketStats<A>,
+}
+
+impl<A: Address> SocketState<A> {
+    pub fn new() -> Self {
+        Self { stats: SocketStats::new() }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{Future, SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::Framed;
+use tracing::{debug, error, warn};
+
+use msg_common::{unix_micros, Channel};
+use msg_transport::Address;
+use msg_wire::pushpull;
+
+use super::stats::SessionStats;
+
+/// Manages the state of a single push socket connection, represented as a [`Future`].
+#[must_use = "This future must be spawned"]
+pub(super) struct PusherSession<Io, A: Address> {
+    /// The addr of the push socket
+    addr: A,
+    /// The framed connection.
+    conn: Framed<Io, pushpull::Codec>,
+    /// The session stats
+    stats: Arc<SessionStats>,
+    /// Channel for communication with the driver. Sends new messages from the associated push
+    /// socket. The driver never sends anything, but dropping its half will close the session.
+    driver_channel: Channel<pushpull::Message, ()>,
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address> PusherSession<Io, A> {
+    pub(super) fn new(
+        addr: A,
+        conn: Framed<Io, pushpull::Codec>,
+        channel: Channel<pushpull::Message, ()>,
+    ) -> Self {
+        Self { addr, conn, stats: Arc::new(SessionStats::default()), driver_channel: channel }
+    }
+
+    /// Returns a reference to the session stats.
+    pub(super) fn stats(&self) -> Arc<SessionStats> {
+        Arc::clone(&self.stats)
+    }
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address + Unpin> Future for PusherSession<Io, A> {
+    type Output = ();
+
+    /// This poll implementation only reads from the connection when the driver channel has
+    /// capacity, which propagates backpressure to the push socket.
+    #[inline]
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        loop {
+            // If the driver dropped its half of the channel, we're disconnected.
+            if let Poll::Ready(None) = this.driver_channel.poll_recv(cx) {
+                debug!(addr = ?this.addr, "Driver channel closed, shutting down session");
+                let _ = this.conn.poll_close_unpin(cx);
+                return Poll::Ready(());
+            }
+
+            match this.driver_channel.poll_ready_unpin(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(_)) => {
+                    warn!(addr = ?this.addr, "Driver channel closed, shutting down session");
+                    let _ = this.conn.poll_close_unpin(cx);
+                    return Poll::Ready(());
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+
+            match this.conn.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(msg))) => {
+                    let now = unix_micros();
+
+                    this.stats.increment_rx(msg.payload().len());
+                    this.stats.update_latency(now.saturating_sub(msg.timestamp()));
+
+                    if let Err(e) = this.driver_channel.start_send_unpin(msg) {
+                        warn!(err = ?e, addr = ?this.addr, "Failed to send message to driver");
+                    }
+
+                    continue;
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    error!(err = ?e, addr = ?this.addr, "Error receiving message");
+                    return Poll::Ready(());
+                }
+                Poll::Ready(None) => {
+                    error!(addr = ?this.addr, "Push socket stream closed");
+                    return Poll::Ready(());
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
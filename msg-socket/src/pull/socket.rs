@@ -0,0 +1,218 @@
+use std::{
+    collections::VecDeque,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::Stream;
+use rustc_hash::FxHashMap;
+use tokio::{
+    net::{lookup_host, ToSocketAddrs},
+    sync::mpsc,
+};
+use tokio_util::sync::PollSender;
+
+use msg_common::JoinMap;
+use msg_transport::{inproc::InprocAddr, Address, Transport};
+
+use super::{
+    Command, PullDriver, PullError, PullMessage, PullOptions, SocketState, SocketStats,
+    DEFAULT_BUFFER_SIZE,
+};
+
+/// A pull socket. Receives messages from one or more connected [`PushSocket`](crate::PushSocket)s,
+/// fair-queued between them. This socket implements [`Stream`] and yields incoming
+/// [`PullMessage`]s.
+pub struct PullSocket<T: Transport<A>, A: Address> {
+    /// Command channel to the socket driver.
+    to_driver: mpsc::Sender<Command<A>>,
+    /// Receiver channel from the socket driver.
+    from_driver: mpsc::Receiver<PullMessage<A>>,
+    /// Options for the socket. These are shared with the backend task.
+    #[allow(unused)]
+    options: Arc<PullOptions>,
+    /// The pending driver.
+    driver: Option<PullDriver<T, A>>,
+    /// Socket state. This is shared with the socket frontend.
+    state: Arc<SocketState<A>>,
+    /// Marker for the transport type.
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<T> PullSocket<T, SocketAddr>
+where
+    T: Transport<SocketAddr> + Send + Sync + Unpin + 'static,
+{
+    /// Connects to the given endpoint asynchronously.
+    pub async fn connect(&mut self, endpoint: impl ToSocketAddrs) -> Result<(), PullError> {
+        let mut addrs = lookup_host(endpoint).await?;
+        let mut endpoint = addrs.next().ok_or(PullError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not find any valid address",
+        )))?;
+
+        // Some transport implementations (e.g. Quinn) can't dial an unspecified
+        // IP address, so replace it with localhost.
+        if endpoint.ip().is_unspecified() {
+            // TODO: support IPv6
+            endpoint.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        }
+
+        self.connect_inner(endpoint).await
+    }
+
+    /// Disconnects from the given endpoint asynchronously.
+    pub async fn disconnect(&mut self, endpoint: impl ToSocketAddrs) -> Result<(), PullError> {
+        let mut addrs = lookup_host(endpoint).await?;
+        let mut endpoint = addrs.next().ok_or(PullError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not find any valid address",
+        )))?;
+
+        if endpoint.ip().is_unspecified() {
+            endpoint.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        }
+
+        self.disconnect_inner(endpoint).await
+    }
+}
+
+impl<T> PullSocket<T, PathBuf>
+where
+    T: Transport<PathBuf> + Send + Sync + Unpin + 'static,
+{
+    /// Connects to the given path asynchronously.
+    pub async fn connect_path(&mut self, path: impl Into<PathBuf>) -> Result<(), PullError> {
+        self.connect_inner(path.into()).await
+    }
+
+    /// Disconnects from the given path asynchronously.
+    pub async fn disconnect_path(&mut self, path: impl Into<PathBuf>) -> Result<(), PullError> {
+        self.disconnect_inner(path.into()).await
+    }
+}
+
+impl<T> PullSocket<T, InprocAddr>
+where
+    T: Transport<InprocAddr> + Send + Sync + Unpin + 'static,
+{
+    /// Connects to the given in-process endpoint asynchronously.
+    pub async fn connect_inproc(&mut self, name: impl Into<InprocAddr>) -> Result<(), PullError> {
+        self.connect_inner(name.into()).await
+    }
+
+    /// Disconnects from the given in-process endpoint asynchronously.
+    pub async fn disconnect_inproc(
+        &mut self,
+        name: impl Into<InprocAddr>,
+    ) -> Result<(), PullError> {
+        self.disconnect_inner(name.into()).await
+    }
+}
+
+impl<T, A> PullSocket<T, A>
+where
+    T: Transport<A> + Send + Sync + Unpin + 'static,
+    A: Address,
+{
+    pub fn new(transport: T) -> Self {
+        Self::with_options(transport, PullOptions::default())
+    }
+
+    pub fn with_options(transport: T, options: PullOptions) -> Self {
+        let (to_driver, from_socket) = mpsc::channel(DEFAULT_BUFFER_SIZE);
+        let (to_socket, from_driver) = mpsc::channel(options.ingress_buffer_size);
+
+        let options = Arc::new(options);
+
+        let state = Arc::new(SocketState::new());
+
+        let driver = PullDriver {
+            options: Arc::clone(&options),
+            transport,
+            from_socket,
+            to_socket: PollSender::new(to_socket),
+            connection_tasks: JoinMap::new(),
+            peers: FxHashMap::default(),
+            egress: VecDeque::new(),
+            state: Arc::clone(&state),
+        };
+
+        Self {
+            to_driver,
+            from_driver,
+            driver: Some(driver),
+            options,
+            state,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Asynchronously connects to the endpoint.
+    pub async fn connect_inner(&mut self, endpoint: A) -> Result<(), PullError> {
+        self.ensure_active_driver();
+        self.send_command(Command::Connect { endpoint }).await
+    }
+
+    /// Immediately send a connect command to the driver.
+    pub fn try_connect_inner(&mut self, endpoint: A) -> Result<(), PullError> {
+        self.ensure_active_driver();
+        self.try_send_command(Command::Connect { endpoint })
+    }
+
+    /// Asynchronously disconnects from the endpoint.
+    pub async fn disconnect_inner(&mut self, endpoint: A) -> Result<(), PullError> {
+        self.ensure_active_driver();
+        self.send_command(Command::Disconnect { endpoint }).await
+    }
+
+    /// Immediately send a disconnect command to the driver.
+    pub fn try_disconnect_inner(&mut self, endpoint: A) -> Result<(), PullError> {
+        self.ensure_active_driver();
+        self.try_send_command(Command::Disconnect { endpoint })
+    }
+
+    /// Sends a command to the driver, returning [`PullError::SocketClosed`] if the
+    /// driver has been dropped.
+    async fn send_command(&self, command: Command<A>) -> Result<(), PullError> {
+        self.to_driver.send(command).await.map_err(|_| PullError::SocketClosed)
+    }
+
+    fn try_send_command(&self, command: Command<A>) -> Result<(), PullError> {
+        use mpsc::error::TrySendError::*;
+        self.to_driver.try_send(command).map_err(|e| match e {
+            Full(_) => PullError::ChannelFull,
+            Closed(_) => PullError::SocketClosed,
+        })
+    }
+
+    /// Ensures that the driver task is running.
+    fn ensure_active_driver(&mut self) {
+        if let Some(driver) = self.driver.take() {
+            tokio::spawn(driver);
+        }
+    }
+
+    pub fn stats(&self) -> &SocketStats<A> {
+        &self.state.stats
+    }
+}
+
+impl<T: Transport<A>, A: Address> Drop for PullSocket<T, A> {
+    fn drop(&mut self) {
+        // Try to tell the driver to gracefully shut down.
+        let _ = self.to_driver.try_send(Command::Shutdown);
+    }
+}
+
+impl<T: Transport<A> + Unpin, A: Address> Stream for PullSocket<T, A> {
+    type Item = PullMessage<A>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.from_driver.poll_recv(cx)
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{stream::FuturesUnordered, Future, StreamExt};
+use tokio::{sync::mpsc, task::JoinSet};
+use tokio_util::{codec::Framed, sync::PollSender};
+use tracing::{debug, error, info, trace, warn};
+
+use super::{session::PullerSession, PushError, PushMessage, PushOptions, SocketState};
+use crate::{authenticate, AsyncAuthenticator, AuthResult};
+use msg_transport::{Address, PeerAddress, Transport};
+use msg_wire::pushpull;
+
+/// A handle to a spawned [`PullerSession`].
+pub(super) struct PeerHandle {
+    /// The ID of the session.
+    session_id: u32,
+    /// The sender half of the session channel. Its capacity is the peer high-water mark.
+    sender: PollSender<PushMessage>,
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) struct PushDriver<T: Transport<A>, A: Address> {
+    /// Session ID counter.
+    pub(super) id_counter: u32,
+    /// The server transport used to accept incoming connections.
+    pub(super) transport: T,
+    /// The push socket options (shared with the socket)
+    pub(super) options: Arc<PushOptions>,
+    /// The push socket state, shared with the socket front-end.
+    pub(crate) state: Arc<SocketState>,
+    /// Optional connection authenticator.
+    pub(super) auth: Option<Arc<dyn AsyncAuthenticator<A>>>,
+    /// A set of pending incoming connections, represented by [`Transport::Accept`].
+    pub(super) conn_tasks: FuturesUnordered<T::Accept>,
+    /// A joinset of authentication tasks.
+    pub(super) auth_tasks: JoinSet<Result<AuthResult<T::Io, A>, PushError>>,
+    /// Messages from the socket.
+    pub(super) from_socket: mpsc::Receiver<PushMessage>,
+    /// All connected peers, in round-robin order.
+    pub(super) peers: Vec<PeerHandle>,
+    /// The index of the next peer in the round-robin.
+    pub(super) cursor: usize,
+    /// A message received from the socket that has not been dispatched to a peer yet.
+    pub(super) pending: Option<PushMessage>,
+}
+
+impl<T, A> Future for PushDriver<T, A>
+where
+    T: Transport<A> + Unpin + 'static,
+    A: Address,
+{
+    type Output = Result<(), PushError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        loop {
+            // First, poll the joinset of authentication tasks. If a new connection has been handled
+            // we spawn a new session for it.
+            if let Poll::Ready(Some(Ok(auth))) = this.auth_tasks.poll_join_next(cx) {
+                match auth {
+                    Ok(auth) => {
+                        debug!("Authentication passed for {:?} ({:?})", auth.identity.id(), auth.addr);
+                        this.spawn_session(auth.stream);
+                    }
+                    Err(e) => {
+                        error!(err = ?e, "Error authenticating client");
+                        this.state.stats.decrement_active_clients();
+                    }
+                }
+
+                continue;
+            }
+
+            // Then poll the incoming connection tasks. If a new connection has been accepted, spawn
+            // a new authentication task for it.
+            if let Poll::Ready(Some(incoming)) = this.conn_tasks.poll_next_unpin(cx) {
+                match incoming {
+                    Ok(io) => {
+                        if let Err(e) = this.on_incoming(io) {
+                            error!(err = ?e, "Error accepting incoming connection");
+                            this.state.stats.decrement_active_clients();
+                        }
+                    }
+                    Err(e) => {
+                        error!(err = ?e, "Error accepting incoming connection");
+
+                        // Active clients have already been incremented in the initial call to
+                        // `poll_accept`, so we need to decrement them here.
+                        this.state.stats.decrement_active_clients();
+                    }
+                }
+
+                continue;
+            }
+
+            // Poll the transport for new incoming connection futures and push them to the
+            // incoming connection tasks.
+            if let Poll::Ready(accept) = Pin::new(&mut this.transport).poll_accept(cx) {
+                if let Some(max) = this.options.max_clients {
+                    if this.state.stats.active_clients() >= max {
+                        warn!("Max connections reached ({}), rejecting incoming connection", max);
+                        continue;
+                    }
+                }
+
+                // Increment the active clients counter. If the authentication fails,
+                // this counter will be decremented.
+                this.state.stats.increment_active_clients();
+
+                this.conn_tasks.push(accept);
+
+                continue;
+            }
+
+            // Finally, dispatch messages from the socket to the connected peers. We only take a
+            // new message from the socket once the previous one has been dispatched, which
+            // propagates backpressure to the socket when all peers are at their high-water mark.
+            if this.pending.is_none() {
+                match this.from_socket.poll_recv(cx) {
+                    Poll::Ready(Some(msg)) => this.pending = Some(msg),
+                    Poll::Ready(None) => {
+                        debug!("Socket dropped, shutting down driver");
+                        return Poll::Ready(Ok(()));
+                    }
+                    Poll::Pending => {}
+                }
+            }
+
+            if this.pending.is_some() && this.poll_dispatch(cx).is_ready() {
+                continue;
+            }
+
+            return Poll::Pending;
+        }
+    }
+}
+
+impl<T, A> PushDriver<T, A>
+where
+    T: Transport<A> + Unpin + 'static,
+    A: Address,
+{
+    /// Tries to dispatch the pending message to the next peer in the round-robin that is below its
+    /// high-water mark. Peers whose session has been closed are removed.
+    ///
+    /// Returns `Poll::Ready` if the message was dispatched, `Poll::Pending` otherwise. In the
+    /// latter case, the driver will be woken up as soon as any of the peers has capacity again.
+    fn poll_dispatch(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let n_peers = self.peers.len();
+        let mut dispatched = false;
+        let mut has_closed = false;
+
+        for offset in 0..n_peers {
+            let idx = (self.cursor + offset) % n_peers;
+            let peer = &mut self.peers[idx];
+
+            match peer.sender.poll_reserve(cx) {
+                Poll::Ready(Ok(())) => {
+                    let msg = self.pending.take().expect("pending message");
+                    match peer.sender.send_item(msg) {
+                        Ok(()) => {
+                            trace!(session_id = peer.session_id, "Dispatched message to peer");
+                            self.cursor = idx + 1;
+                            dispatched = true;
+                            break;
+                        }
+                        Err(e) => {
+                            // The session was closed in the meantime, put the message back.
+                            self.pending = e.into_inner();
+                            has_closed = true;
+                        }
+                    }
+                }
+                Poll::Ready(Err(_)) => {
+                    has_closed = true;
+                }
+                Poll::Pending => {}
+            }
+        }
+
+        if has_closed {
+            self.peers.retain(|peer| {
+                if peer.sender.is_closed() {
+                    debug!(session_id = peer.session_id, "Removing closed peer session");
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+
+        if dispatched {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Spawns a new [`PullerSession`] for the given connection and adds it to the round-robin.
+    fn spawn_session(&mut self, io: T::Io) {
+        let mut framed = Framed::new(io, pushpull::Codec::new());
+        framed.set_backpressure_boundary(self.options.backpressure_boundary);
+
+        let (tx, rx) = mpsc::channel(self.options.peer_hwm);
+
+        let session = PullerSession {
+            session_id: self.id_counter,
+            from_driver: rx,
+            state: Arc::clone(&self.state),
+            pending_egress: None,
+            conn: framed,
+            should_flush: false,
+            flush_interval: self.options.flush_interval.map(tokio::time::interval),
+        };
+
+        tokio::spawn(session);
+
+        self.peers.push(PeerHandle { session_id: self.id_counter, sender: PollSender::new(tx) });
+
+        self.id_counter = self.id_counter.wrapping_add(1);
+    }
+
+    /// Handles an incoming connection. If this returns an error, the active connections counter
+    /// should be decremented.
+    fn on_incoming(&mut self, io: T::Io) -> Result<(), io::Error> {
+        let addr = io.peer_addr()?;
+
+        info!("New connection from {:?}", addr);
+
+        // If authentication is enabled, start the authentication process
+        if let Some(ref auth) = self.auth {
+            let authenticator = Arc::clone(auth);
+            debug!("New connection from {:?}, authenticating", addr);
+            self.auth_tasks.spawn(async move {
+                authenticate(io, addr, authenticator).await.map_err(|e| PushError::Auth(e.to_string()))
+            });
+        } else {
+            self.spawn_session(io);
+            debug!("New connection from {:?}, session ID {}", addr, self.id_counter);
+        }
+
+        Ok(())
+    }
+}
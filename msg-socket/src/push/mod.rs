@@ -0,0 +1,295 @@
+use bytes::Bytes;
+use std::io;
+use thiserror::Error;
+
+mod driver;
+use msg_wire::{
+    compression::{CompressionType, Compressor},
+    pushpull,
+};
+mod session;
+mod socket;
+mod stats;
+pub use socket::*;
+use stats::SocketStats;
+
+const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum PushError {
+    #[error("IO error: {0:?}")]
+    Io(#[from] io::Error),
+    #[error("Wire protocol error: {0:?}")]
+    Wire(#[from] pushpull::Error),
+    #[error("Authentication error: {0}")]
+    Auth(String),
+    #[error("Socket closed")]
+    SocketClosed,
+    #[error("Command channel full")]
+    ChannelFull,
+    #[error("Transport error: {0:?}")]
+    Transport(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[derive(Debug)]
+pub struct PushOptions {
+    /// The maximum number of concurrent clients.
+    max_clients: Option<usize>,
+    /// The high-water mark for each connected peer. This is the maximum number of outgoing
+    /// messages that can be queued for a single peer before it is skipped in the round-robin.
+    peer_hwm: usize,
+    /// The interval at which each session should be flushed. If this is `None`,
+    /// the session will be flushed on every push, which can add a lot of overhead.
+    flush_interval: Option<std::time::Duration>,
+    /// The maximum number of bytes that can be buffered in the session before being flushed.
+    /// This internally sets [`Framed::set_backpressure_boundary`](tokio_util::codec::Framed).
+    backpressure_boundary: usize,
+    /// Minimum payload size in bytes for compression to be used. If the payload is smaller than
+    /// this threshold, it will not be compressed.
+    min_compress_size: usize,
+}
+
+impl Default for PushOptions {
+    fn default() -> Self {
+        Self {
+            max_clients: None,
+            peer_hwm: 1024,
+            flush_interval: Some(std::time::Duration::from_micros(50)),
+            backpressure_boundary: 8192,
+            min_compress_size: 8192,
+        }
+    }
+}
+
+impl PushOptions {
+    /// Sets the maximum number of concurrent clients.
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = Some(max_clients);
+        self
+    }
+
+    /// Sets the per-peer high-water mark. This is the amount of messages that can be queued for a
+    /// single peer. Once a peer reaches its high-water mark, messages are distributed to the other
+    /// peers. If all peers are at their high-water mark, [`PushSocket::push`] will wait until
+    /// there is capacity again.
+    pub fn peer_hwm(mut self, peer_hwm: usize) -> Self {
+        self.peer_hwm = peer_hwm;
+        self
+    }
+
+    /// Sets the maximum number of bytes that can be buffered in the session before being flushed.
+    /// This internally sets [`Framed::set_backpressure_boundary`](tokio_util::codec::Framed).
+    pub fn backpressure_boundary(mut self, backpressure_boundary: usize) -> Self {
+        self.backpressure_boundary = backpressure_boundary;
+        self
+    }
+
+    /// Sets the interval at which each session should be flushed. If this is `None`,
+    /// the session will be flushed on every push, which can add a lot of overhead.
+    pub fn flush_interval(mut self, flush_interval: std::time::Duration) -> Self {
+        self.flush_interval = Some(flush_interval);
+        self
+    }
+
+    /// Sets the minimum payload size in bytes for compression to be used. If the payload is smaller
+    /// than this threshold, it will not be compressed.
+    pub fn min_compress_size(mut self, min_compress_size: usize) -> Self {
+        self.min_compress_size = min_compress_size;
+        self
+    }
+}
+
+/// A message sent from a [`PushSocket`] to the backend task.
+#[derive(Debug, Clone)]
+pub(crate) struct PushMessage {
+    /// The compression type used for the message payload.
+    compression_type: CompressionType,
+    /// The message payload.
+    payload: Bytes,
+}
+
+#[allow(unused)]
+impl PushMessage {
+    pub fn new(payload: Bytes) -> Self {
+        Self {
+            // Initialize the compression type to None.
+            // The actual compression type will be set in the `compress` method.
+            compression_type: CompressionType::None,
+            payload,
+        }
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    #[inline]
+    pub fn into_wire(self) -> pushpull::Message {
+        pushpull::Message::new(self.compression_type as u8, self.payload)
+    }
+
+    #[inline]
+    pub fn compress(&mut self, compressor: &dyn Compressor) -> Result<(), io::Error> {
+        self.payload = compressor.compress(&self.payload)?;
+        self.compression_type = compressor.compression_type();
+
+        Ok(())
+    }
+}
+
+/// The push socket state, shared between the backend task and the socket.
+#[derive(Debug, Default)]
+pub(crate) struct SocketState {
+    pub(crate) stats: SocketStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use futures::StreamExt;
+    use msg_transport::{
+        quic::{self, Quic},
+        tcp::Tcp,
+    };
+    use msg_wire::compression::GzipCompressor;
+    use tracing::info;
+
+    use crate::{Authenticator, PullOptions, PullSocket};
+
+    use super::*;
+
+    struct Auth;
+
+    impl Authenticator for Auth {
+        fn authenticate(&self, id: &Bytes) -> bool {
+            info!("Auth request from: {:?}", id);
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn pushpull_simple() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut push_socket = PushSocket::new(Tcp::default());
+        let mut pull_socket = PullSocket::new(Tcp::default());
+
+        push_socket.bind("0.0.0.0:0").await.unwrap();
+        let addr = push_socket.local_addr().unwrap();
+
+        pull_socket.connect(addr).await.unwrap();
+
+        push_socket.push(Bytes::from("WORLD")).await.unwrap();
+
+        let msg = pull_socket.next().await.unwrap();
+        info!("Received message: {:?}", msg);
+        assert_eq!("WORLD", msg.payload());
+    }
+
+    #[tokio::test]
+    async fn pushpull_auth_quic() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut push_socket = PushSocket::new(Quic::new(quic::Config::insecure())).with_auth(Auth);
+        let mut pull_socket = PullSocket::with_options(
+            Quic::new(quic::Config::insecure()),
+            PullOptions::default().auth_token(Bytes::from("client1")),
+        );
+
+        push_socket.bind("0.0.0.0:0").await.unwrap();
+        let addr = push_socket.local_addr().unwrap();
+
+        pull_socket.connect(addr).await.unwrap();
+
+        push_socket.push(Bytes::from("WORLD")).await.unwrap();
+
+        let msg = pull_socket.next().await.unwrap();
+        assert_eq!("WORLD", msg.payload());
+    }
+
+    #[tokio::test]
+    async fn pushpull_round_robin_compressed() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut push_socket =
+            PushSocket::with_options(Tcp::default(), PushOptions::default().min_compress_size(0))
+                .with_compressor(GzipCompressor::new(6));
+
+        push_socket.bind("0.0.0.0:0").await.unwrap();
+        let addr = push_socket.local_addr().unwrap();
+
+        let mut pull1 = PullSocket::new(Tcp::default());
+        let mut pull2 = PullSocket::new(Tcp::default());
+
+        pull1.connect(addr).await.unwrap();
+        pull2.connect(addr).await.unwrap();
+
+        // Wait for both peers to be registered with the push socket
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(push_socket.stats().active_clients(), 2);
+
+        for i in 0..10 {
+            push_socket.push(Bytes::from(format!("MSG-{i}"))).await.unwrap();
+        }
+
+        let mut received = HashSet::new();
+        for _ in 0..5 {
+            received.insert(pull1.next().await.unwrap().into_payload());
+            received.insert(pull2.next().await.unwrap().into_payload());
+        }
+
+        // Every message is delivered exactly once, and both peers got an equal share.
+        assert_eq!(received.len(), 10);
+    }
+
+    #[tokio::test]
+    async fn pushpull_fair_queue() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut push1 = PushSocket::new(Tcp::default());
+        let mut push2 = PushSocket::new(Tcp::default());
+
+        push1.bind("0.0.0.0:0").await.unwrap();
+        push2.bind("0.0.0.0:0").await.unwrap();
+
+        let mut pull_socket = PullSocket::new(Tcp::default());
+        pull_socket.connect(push1.local_addr().unwrap()).await.unwrap();
+        pull_socket.connect(push2.local_addr().unwrap()).await.unwrap();
+
+        for _ in 0..3 {
+            push1.push(Bytes::from("ONE")).await.unwrap();
+            push2.push(Bytes::from("TWO")).await.unwrap();
+        }
+
+        let mut sources = HashSet::new();
+        for _ in 0..6 {
+            let msg = pull_socket.next().await.unwrap();
+            sources.insert(*msg.source());
+        }
+
+        assert_eq!(sources.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn push_max_clients() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut push_socket =
+            PushSocket::with_options(Tcp::default(), PushOptions::default().max_clients(1));
+
+        push_socket.bind("0.0.0.0:0").await.unwrap();
+        let addr = push_socket.local_addr().unwrap();
+
+        let mut pull1 = PullSocket::new(Tcp::default());
+        let mut pull2 = PullSocket::new(Tcp::default());
+
+        pull1.connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(push_socket.stats().active_clients(), 1);
+        pull2.connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(push_socket.stats().active_clients(), 1);
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{Future, SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+};
+use tokio_util::codec::Framed;
+use tracing::{debug, error, trace, warn};
+
+use super::{PushMessage, SocketState};
+use msg_wire::pushpull;
+
+/// Manages the state of a single connected pull socket, represented as a [`Future`].
+#[must_use = "This future must be spawned"]
+pub(super) struct PullerSession<Io> {
+    /// The ID of this session.
+    pub(super) session_id: u32,
+    /// Messages from the driver. The capacity of this channel is the peer high-water mark.
+    pub(super) from_driver: mpsc::Receiver<PushMessage>,
+    /// Messages queued to be sent on the connection
+    pub(super) pending_egress: Option<pushpull::Message>,
+    /// The socket state, shared between the backend task and the socket.
+    pub(super) state: Arc<SocketState>,
+    /// The framed connection.
+    pub(super) conn: Framed<Io, pushpull::Codec>,
+    /// Whether or not the connection should be flushed (i.e. data was written).
+    pub(super) should_flush: bool,
+    /// Interval for flushing the connection. This is secondary to `should_flush`.
+    pub(super) flush_interval: Option<tokio::time::Interval>,
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> PullerSession<Io> {
+    #[inline]
+    fn should_flush(&mut self, cx: &mut Context<'_>) -> bool {
+        if self.should_flush {
+            if let Some(interval) = self.flush_interval.as_mut() {
+                interval.poll_tick(cx).is_ready()
+            } else {
+                true
+            }
+        } else {
+            // If we shouldn't flush, reset the interval so we don't get woken up
+            // every time the interval expires
+            if let Some(interval) = self.flush_interval.as_mut() {
+                interval.reset()
+            }
+
+            false
+        }
+    }
+}
+
+impl<Io> Drop for PullerSession<Io> {
+    fn drop(&mut self) {
+        self.state.stats.decrement_active_clients();
+    }
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin> Future for PullerSession<Io> {
+    type Output = ();
+
+    #[inline]
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        loop {
+            // First check if we should flush the connection. We only do this if we have written
+            // some data and the flush interval has elapsed. Only when we have succesfully flushed
+            // the data will we reset the `should_flush` flag.
+            if this.should_flush(cx) {
+                if let Poll::Ready(Ok(_)) = this.conn.poll_flush_unpin(cx) {
+                    this.should_flush = false;
+                }
+            }
+
+            // Then, try to drain the egress queue.
+            if this.conn.poll_ready_unpin(cx).is_ready() {
+                if let Some(msg) = this.pending_egress.take() {
+                    trace!(?msg, session_id = this.session_id, "Sending message");
+                    let msg_len = msg.size();
+
+                    match this.conn.start_send_unpin(msg) {
+                        Ok(_) => {
+                            this.state.stats.increment_tx(msg_len);
+
+                            this.should_flush = true;
+                            // We might be able to send more queued messages
+                            continue;
+                        }
+                        Err(e) => {
+                            error!(err = ?e, "Failed to send message to socket");
+                            let _ = this.conn.poll_close_unpin(cx);
+                            // End this stream as we can't send any more messages
+                            return Poll::Ready(());
+                        }
+                    }
+                }
+            } else {
+                return Poll::Pending;
+            }
+
+            // Poll outgoing messages. We only take a new message from the driver once the previous
+            // one has been handed to the connection, so that the channel capacity acts as the
+            // high-water mark for this peer.
+            if let Poll::Ready(item) = this.from_driver.poll_recv(cx) {
+                match item {
+                    Some(msg) => {
+                        this.pending_egress = Some(msg.into_wire());
+                        continue;
+                    }
+                    None => {
+                        debug!("Socket closed, shutting down session {}", this.session_id);
+                        let _ = this.conn.poll_close_unpin(cx);
+                        return Poll::Ready(());
+                    }
+                }
+            }
+
+            // Poll the connection to detect when the peer goes away. Pull sockets don't send any
+            // messages, so anything we receive here is discarded.
+            if let Poll::Ready(item) = this.conn.poll_next_unpin(cx) {
+                match item {
+                    Some(Ok(msg)) => {
+                        warn!(?msg, session_id = this.session_id, "Unexpected incoming message");
+                        continue;
+                    }
+                    Some(Err(e)) => {
+                        error!(err = ?e, session_id = this.session_id, "Error reading from socket");
+                        let _ = this.conn.poll_close_unpin(cx);
+                        return Poll::Ready(());
+                    }
+                    None => {
+                        warn!("Connection closed, shutting down session {}", this.session_id);
+                        return Poll::Ready(());
+                    }
+                }
+            }
+
+            return Poll::Pending;
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::{io, net::SocketAddr, path::PathBuf, sync::Arc};
+
+use bytes::Bytes;
+use futures::stream::FuturesUnordered;
+use tokio::{
+    net::{lookup_host, ToSocketAddrs},
+    sync::mpsc,
+    task::JoinSet,
+};
+use tracing::{debug, trace, warn};
+
+use super::{
+    driver::PushDriver, stats::SocketStats, PushError, PushMessage, PushOptions, SocketState,
+    DEFAULT_BUFFER_SIZE,
+};
+use crate::{AsyncAuthenticator, Authenticator};
+
+use msg_transport::{inproc::InprocAddr, Address, Transport};
+use msg_wire::compression::Compressor;
+
+/// A push socket. Messages are distributed round-robin over all connected
+/// [`PullSocket`](crate::PullSocket)s. This is thread-safe and can be cloned.
+#[derive(Clone)]
+pub struct PushSocket<T: Transport<A>, A: Address> {
+    /// The push socket options, shared with the driver.
+    options: Arc<PushOptions>,
+    /// The push socket state, shared with the driver.
+    state: Arc<SocketState>,
+    /// The transport used by this socket. This value is temporary and will be moved
+    /// to the driver task once the socket is bound.
+    transport: Option<T>,
+    /// Channel to the socket driver.
+    to_driver: Option<mpsc::Sender<PushMessage>>,
+    /// Optional connection authenticator.
+    auth: Option<Arc<dyn AsyncAuthenticator<A>>>,
+    /// Optional message compressor.
+    // NOTE: for now we're using dynamic dispatch, since using generics here
+    // complicates the API a lot. We can always change this later for perf reasons.
+    compressor: Option<Arc<dyn Compressor>>,
+    /// The local address this socket is bound to.
+    local_addr: Option<A>,
+}
+
+impl<T> PushSocket<T, SocketAddr>
+where
+    T: Transport<SocketAddr> + Send + Unpin + 'static,
+{
+    /// Binds the socket to the given socket addres
+    ///
+    /// This method is only available for transports that support [`SocketAddr`] as address type,
+    /// like [`Tcp`](msg_transport::tcp::Tcp) and [`Quic`](msg_transport::quic::Quic).
+    pub async fn bind(&mut self, addr: impl ToSocketAddrs) -> Result<(), PushError> {
+        let addrs = lookup_host(addr).await?;
+        self.try_bind(addrs.collect()).await
+    }
+}
+
+impl<T> PushSocket<T, PathBuf>
+where
+    T: Transport<PathBuf> + Send + Unpin + 'static,
+{
+    /// Binds the socket to the given path.
+    ///
+    /// This method is only available for transports that support [`PathBuf`] as address type,
+    /// like [`Ipc`](msg_transport::ipc::Ipc).
+    pub async fn bind(&mut self, path: impl Into<PathBuf>) -> Result<(), PushError> {
+        self.try_bind(vec![path.into()]).await
+    }
+}
+
+impl<T> PushSocket<T, InprocAddr>
+where
+    T: Transport<InprocAddr> + Send + Unpin + 'static,
+{
+    /// Binds the socket to the given in-process endpoint name.
+    ///
+    /// This method is only available for transports that support [`InprocAddr`] as address type,
+    /// like [`Inproc`](msg_transport::inproc::Inproc).
+    pub async fn bind(&mut self, name: impl Into<InprocAddr>) -> Result<(), PushError> {
+        self.try_bind(vec![name.into()]).await
+    }
+}
+
+impl<T, A> PushSocket<T, A>
+where
+    T: Transport<A> + Send + Unpin + 'static,
+    A: Address,
+{
+    /// Creates a new push socket with the default [`PushOptions`].
+    pub fn new(transport: T) -> Self {
+        Self::with_options(transport, PushOptions::default())
+    }
+
+    /// Creates a new push socket with the given transport and options.
+    pub fn with_options(transport: T, options: PushOptions) -> Self {
+        Self {
+            local_addr: None,
+            to_driver: None,
+            options: Arc::new(options),
+            transport: Some(transport),
+            state: Arc::new(SocketState::default()),
+            auth: None,
+            compressor: None,
+        }
+    }
+
+    /// Sets the connection authenticator for this socket.
+    pub fn with_auth<O: Authenticator>(mut self, authenticator: O) -> Self {
+        self.auth = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Sets the asynchronous connection authenticator for this socket, which also has access to
+    /// the address of the client and the metadata of the connection.
+    pub fn with_async_auth<O: AsyncAuthenticator<A>>(mut self, authenticator: O) -> Self {
+        self.auth = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Sets the message compressor for this socket.
+    pub fn with_compressor<C: Compressor + 'static>(mut self, compressor: C) -> Self {
+        self.compressor = Some(Arc::new(compressor));
+        self
+    }
+
+    /// Binds the socket to the given addresses in order until one succeeds.
+    ///
+    /// This also spawns the socket driver task.
+    pub async fn try_bind(&mut self, addresses: Vec<A>) -> Result<(), PushError> {
+        let (to_driver, from_socket) = mpsc::channel(DEFAULT_BUFFER_SIZE);
+
+        let mut transport = self.transport.take().expect("Transport has been moved already");
+
+        for addr in addresses {
+            match transport.bind(addr.clone()).await {
+                Ok(_) => break,
+                Err(e) => {
+                    warn!(err = ?e, "Failed to bind to {:?}, trying next address", addr);
+                    continue;
+                }
+            }
+        }
+
+        let Some(local_addr) = transport.local_addr() else {
+            return Err(PushError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not bind to any valid address",
+            )));
+        };
+
+        debug!("Listening on {:?}", local_addr);
+
+        let backend = PushDriver {
+            id_counter: 0,
+            transport,
+            options: Arc::clone(&self.options),
+            state: Arc::clone(&self.state),
+            auth: self.auth.take(),
+            auth_tasks: JoinSet::new(),
+            conn_tasks: FuturesUnordered::new(),
+            from_socket,
+            peers: Vec::new(),
+            cursor: 0,
+            pending: None,
+        };
+
+        tokio::spawn(backend);
+
+        self.local_addr = Some(local_addr);
+        self.to_driver = Some(to_driver);
+
+        Ok(())
+    }
+
+    /// Pushes a message to the next available peer. If no peers are connected, or all of them are
+    /// at their high-water mark, the message is queued until there is capacity. This method will
+    /// wait if the socket queue itself is full.
+    pub async fn push(&self, message: Bytes) -> Result<(), PushError> {
+        let msg = self.prepare(message)?;
+
+        self.to_driver
+            .as_ref()
+            .ok_or(PushError::SocketClosed)?
+            .send(msg)
+            .await
+            .map_err(|_| PushError::SocketClosed)
+    }
+
+    /// Tries to push a message to the next available peer immediately. Returns
+    /// [`PushError::ChannelFull`] if the socket queue is full.
+    pub fn try_push(&self, message: Bytes) -> Result<(), PushError> {
+        use mpsc::error::TrySendError::*;
+
+        let msg = self.prepare(message)?;
+
+        self.to_driver.as_ref().ok_or(PushError::SocketClosed)?.try_send(msg).map_err(|e| match e {
+            Full(_) => PushError::ChannelFull,
+            Closed(_) => PushError::SocketClosed,
+        })
+    }
+
+    /// Wraps the payload in a [`PushMessage`], compressing it if a compressor is set and the
+    /// payload is larger than the configured minimum size.
+    fn prepare(&self, message: Bytes) -> Result<PushMessage, PushError> {
+        let mut msg = PushMessage::new(message);
+
+        let len_before = msg.payload().len();
+        if len_before > self.options.min_compress_size {
+            if let Some(ref compressor) = self.compressor {
+                msg.compress(compressor.as_ref())?;
+
+                trace!("Compressed message from {} to {} bytes", len_before, msg.payload().len());
+            }
+        }
+
+        Ok(msg)
+    }
+
+    pub fn stats(&self) -> &SocketStats {
+        &self.state.stats
+    }
+
+    /// Returns the local address this socket is bound to. `None` if the socket is not bound.
+    pub fn local_addr(&self) -> Option<&A> {
+        self.local_addr.as_ref()
+    }
+}
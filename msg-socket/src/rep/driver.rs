@@ -2,12 +2,18 @@ use std::{
     collections::VecDeque,
     io,
     pin::Pin,
-    sync::Arc,
+    sync::{atomic::Ordering, Arc},
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 use bytes::Bytes;
-use futures::{stream::FuturesUnordered, Future, FutureExt, SinkExt, Stream, StreamExt};
+use futures::{
+    future::BoxFuture,
+    stream::{BoxStream, FuturesUnordered},
+    Future, FutureExt, SinkExt, Stream, StreamExt,
+};
+use rustc_hash::FxHashMap;
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     sync::{mpsc, oneshot},
@@ -17,11 +23,14 @@ use tokio_stream::{StreamMap, StreamNotifyClose};
 use tokio_util::codec::Framed;
 use tracing::{debug, error, info, trace, warn};
 
-use crate::{rep::SocketState, AuthResult, Authenticator, PubError, RepOptions, Request};
+use crate::{
+    rep::{Response, SocketState},
+    authenticate, AsyncAuthenticator, AuthResult, Authorizer, Identity, PubError, RepOptions, Request,
+    StreamFn,
+};
 
-use msg_transport::{Address, PeerAddress, Transport};
+use msg_transport::{Address, PeerAddress, PeerMetadata, Transport};
 use msg_wire::{
-    auth,
     compression::{try_decompress_payload, Compressor},
     reqrep,
 };
@@ -30,12 +39,37 @@ pub(crate) struct PeerState<T: AsyncRead + AsyncWrite, A: Address> {
     pending_requests: FuturesUnordered<PendingRequest>,
     conn: Framed<T, reqrep::Codec>,
     addr: A,
+    /// The identity the peer authenticated with. Empty if authentication is disabled.
+    identity: Identity,
     egress_queue: VecDeque<reqrep::Message>,
     state: Arc<SocketState>,
     should_flush: bool,
     compressor: Option<Arc<dyn Compressor>>,
+    /// Streaming responses that are being sent, by request ID.
+    streams: FxHashMap<u32, ResponseStream>,
+    /// Credit received for requests that have not been responded to yet.
+    credits: FxHashMap<u32, u32>,
+    /// Options shared with the socket.
+    options: Arc<RepOptions>,
+    /// Whether the session stopped reading because the pending requests limit was reached.
+    throttled: bool,
+    /// Whether this is an additional stream of the connection, which the peer closes once its
+    /// request is done.
+    substream: bool,
+}
+
+/// A streaming response that is being sent to the peer.
+struct ResponseStream {
+    /// The chunks of the response.
+    chunks: BoxStream<'static, Bytes>,
+    /// The number of chunks the peer is willing to receive.
+    credit: u32,
 }
 
+/// A pending stream opened by a peer on an existing connection, with the address and identity of
+/// the peer.
+type AcceptStreamTask<Io, E, A> = BoxFuture<'static, (A, Identity, Result<Io, E>)>;
+
 #[allow(clippy::type_complexity)]
 pub(crate) struct RepDriver<T: Transport<A>, A: Address> {
     /// The server transport used to accept incoming connections.
@@ -45,12 +79,15 @@ pub(crate) struct RepDriver<T: Transport<A>, A: Address> {
     #[allow(unused)]
     /// Options shared with socket.
     pub(crate) options: Arc<RepOptions>,
-    /// [`StreamMap`] of connected peers. The key is the peer's address.
-    pub(crate) peer_states: StreamMap<A, StreamNotifyClose<PeerState<T::Io, A>>>,
+    /// [`StreamMap`] of connected peers. The key is the peer's address and the ID of the stream,
+    /// which is 0 for the first stream of a connection.
+    pub(crate) peer_states: StreamMap<(A, u64), StreamNotifyClose<PeerState<T::Io, A>>>,
     /// Sender to the socket front-end. Used to notify the socket of incoming requests.
     pub(crate) to_socket: mpsc::Sender<Request<A>>,
     /// Optional connection authenticator.
-    pub(crate) auth: Option<Arc<dyn Authenticator>>,
+    pub(crate) auth: Option<Arc<dyn AsyncAuthenticator<A>>>,
+    /// Optional request authorizer.
+    pub(crate) authorizer: Option<Arc<dyn Authorizer>>,
     /// Optional message compressor. This is shared with the socket to keep
     /// the API consistent with other socket types (e.g. `PubSocket`)
     pub(crate) compressor: Option<Arc<dyn Compressor>>,
@@ -58,6 +95,13 @@ pub(crate) struct RepDriver<T: Transport<A>, A: Address> {
     pub(super) conn_tasks: FuturesUnordered<T::Accept>,
     /// A joinset of authentication tasks.
     pub(crate) auth_tasks: JoinSet<Result<AuthResult<T::Io, A>, PubError>>,
+    /// Accepts the streams that peers open for their requests, if enabled. See
+    /// [`RepSocket::with_request_streams`](crate::RepSocket::with_request_streams).
+    pub(crate) accept_stream: Option<StreamFn<T::Io, T::Error>>,
+    /// The pending streams of the connected peers, one per connection.
+    pub(crate) stream_tasks: FuturesUnordered<AcceptStreamTask<T::Io, T::Error, A>>,
+    /// The ID of the last accepted stream.
+    pub(crate) last_stream_id: u64,
 }
 
 impl<T, A> Future for RepDriver<T, A>
@@ -71,7 +115,8 @@ where
         let this = self.get_mut();
 
         loop {
-            if let Poll::Ready(Some((peer, msg))) = this.peer_states.poll_next_unpin(cx) {
+            if let Poll::Ready(Some((key, msg))) = this.peer_states.poll_next_unpin(cx) {
+                let (peer, stream_id) = key;
                 match msg {
                     Some(Ok(mut request)) => {
                         debug!("Received request from peer {:?}", peer);
@@ -79,10 +124,24 @@ where
                         let size = request.msg().len();
 
                         // decompress the payload
-                        match try_decompress_payload(request.compression_type, request.msg) {
+                        let payload = std::mem::take(&mut request.msg);
+                        match try_decompress_payload(request.compression_type, payload) {
                             Ok(decompressed) => request.msg = decompressed,
                             Err(e) => {
                                 error!(err = ?e, "Failed to decompress message");
+                                let _ = request.respond_err(
+                                    reqrep::ERR_DECODE,
+                                    "failed to decompress request",
+                                );
+                                continue;
+                            }
+                        }
+
+                        if let Some(ref authorizer) = this.authorizer {
+                            if !authorizer.authorize_request(&request.identity, &request.msg) {
+                                warn!("Unauthorized request from peer {:?}", peer);
+                                let _ = request
+                                    .respond_err(reqrep::ERR_UNAUTHORIZED, "unauthorized request");
                                 continue;
                             }
                         }
@@ -93,6 +152,10 @@ where
                     Some(Err(e)) => {
                         error!(err = ?e, "Error receiving message from peer {:?}", peer);
                     }
+                    // Additional streams are closed once their request is done
+                    None if stream_id > 0 => {
+                        trace!("Stream {} of peer {:?} closed", stream_id, peer);
+                    }
                     None => {
                         warn!("Peer {:?} disconnected", peer);
                         this.state.stats.decrement_active_clients();
@@ -106,20 +169,10 @@ where
                 match auth {
                     Ok(auth) => {
                         // Run custom authenticator
-                        info!("Authentication passed for {:?} ({:?})", auth.id, auth.addr);
-
-                        this.peer_states.insert(
-                            auth.addr.clone(),
-                            StreamNotifyClose::new(PeerState {
-                                pending_requests: FuturesUnordered::new(),
-                                conn: Framed::new(auth.stream, reqrep::Codec::new()),
-                                addr: auth.addr,
-                                egress_queue: VecDeque::with_capacity(128),
-                                state: Arc::clone(&this.state),
-                                should_flush: false,
-                                compressor: this.compressor.clone(),
-                            }),
-                        );
+                        info!("Authentication passed for {:?} ({:?})", auth.identity.id(), auth.addr);
+
+                        this.accept_next_stream(&auth.stream, &auth.addr, &auth.identity);
+                        this.add_peer(auth.stream, auth.addr, auth.identity, 0);
                     }
                     Err(e) => {
                         error!(err = ?e, "Error authenticating client");
@@ -130,6 +183,23 @@ where
                 continue;
             }
 
+            if let Poll::Ready(Some(accepted)) = this.stream_tasks.poll_next_unpin(cx) {
+                let (addr, identity, result) = accepted;
+                match result {
+                    Ok(io) => {
+                        // Accept the next stream, and handle the requests on this one
+                        this.accept_next_stream(&io, &addr, &identity);
+                        this.last_stream_id += 1;
+                        this.add_peer(io, addr, identity, this.last_stream_id);
+                    }
+                    Err(e) => {
+                        debug!(err = ?e, "Stopped accepting streams from peer {:?}", addr);
+                    }
+                }
+
+                continue;
+            }
+
             if let Poll::Ready(Some(incoming)) = this.conn_tasks.poll_next_unpin(cx) {
                 match incoming {
                     Ok(io) => {
@@ -195,55 +265,140 @@ where
             let authenticator = Arc::clone(auth);
             debug!("New connection from {:?}, authenticating", addr);
             self.auth_tasks.spawn(async move {
-                let mut conn = Framed::new(io, auth::Codec::new_server());
-
-                debug!("Waiting for auth");
-                // Wait for the response
-                let auth = conn
-                    .next()
-                    .await
-                    .ok_or(PubError::SocketClosed)?
-                    .map_err(|e| PubError::Auth(e.to_string()))?;
-
-                debug!("Auth received: {:?}", auth);
-
-                let auth::Message::Auth(id) = auth else {
-                    conn.send(auth::Message::Reject).await?;
-                    conn.flush().await?;
-                    conn.close().await?;
-                    return Err(PubError::Auth("Invalid auth message".to_string()));
-                };
+                authenticate(io, addr, authenticator).await.map_err(|e| PubError::Auth(e.to_string()))
+            });
+        } else {
+            let identity = Identity::from_metadata(&io.metadata());
+            self.accept_next_stream(&io, &addr, &identity);
+            self.add_peer(io, addr, identity, 0);
+        }
 
-                // If authentication fails, send a reject message and close the connection
-                if !authenticator.authenticate(&id) {
-                    conn.send(auth::Message::Reject).await?;
-                    conn.flush().await?;
-                    conn.close().await?;
-                    return Err(PubError::Auth("Authentication failed".to_string()));
+        Ok(())
+    }
+
+    /// Starts handling the requests of a peer on the given stream.
+    fn add_peer(&mut self, io: T::Io, addr: A, identity: Identity, stream_id: u64) {
+        self.peer_states.insert(
+            (addr.clone(), stream_id),
+            StreamNotifyClose::new(PeerState {
+                pending_requests: FuturesUnordered::new(),
+                conn: Framed::new(io, reqrep::Codec::new()),
+                addr,
+                identity,
+                egress_queue: VecDeque::with_capacity(128),
+                state: Arc::clone(&self.state),
+                should_flush: false,
+                compressor: self.compressor.clone(),
+                streams: FxHashMap::default(),
+                credits: FxHashMap::default(),
+                options: Arc::clone(&self.options),
+                throttled: false,
+                substream: stream_id > 0,
+            }),
+        );
+    }
+
+    /// Accepts the next stream that the peer opens on the connection of the given stream, if
+    /// enabled. The streams of a connection share the identity of the peer.
+    fn accept_next_stream(&mut self, io: &T::Io, addr: &A, identity: &Identity) {
+        if let Some(accept_stream) = self.accept_stream {
+            let accept = accept_stream(io);
+            let (addr, identity) = (addr.clone(), identity.clone());
+            self.stream_tasks.push(Box::pin(async move { (addr, identity, accept.await) }));
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin, A: Address + Unpin> PeerState<T, A> {
+    /// Creates a response message, compressing the payload if a compressor is set.
+    fn encode(&self, id: u32, mut payload: Bytes, kind: reqrep::Kind) -> Option<reqrep::Message> {
+        let mut compression_type = 0;
+        let len_before = payload.len();
+        if let Some(ref compressor) = self.compressor {
+            match compressor.compress(&payload) {
+                Ok(compressed) => {
+                    payload = compressed;
+                    compression_type = compressor.compression_type() as u8;
                 }
+                Err(e) => {
+                    error!(err = ?e, "Failed to compress message");
+                    return None;
+                }
+            }
 
-                // Send ack
-                conn.send(auth::Message::Ack).await?;
-                conn.flush().await?;
+            debug!("Compressed message {} from {} to {} bytes", id, len_before, payload.len())
+        }
 
-                Ok(AuthResult { id, addr, stream: conn.into_inner() })
-            });
-        } else {
-            self.peer_states.insert(
-                addr.clone(),
-                StreamNotifyClose::new(PeerState {
-                    pending_requests: FuturesUnordered::new(),
-                    conn: Framed::new(io, reqrep::Codec::new()),
-                    addr,
-                    egress_queue: VecDeque::with_capacity(128),
-                    state: Arc::clone(&self.state),
-                    should_flush: false,
-                    compressor: self.compressor.clone(),
-                }),
-            );
+        Some(reqrep::Message::new(id, compression_type, payload).with_kind(kind))
+    }
+
+    /// Returns `true` if the pending requests limit (either global or for this peer) is reached.
+    fn at_capacity(&self) -> bool {
+        if let Some(max) = self.options.max_pending_requests_per_peer {
+            if self.pending_requests.len() >= max {
+                return true;
+            }
         }
 
-        Ok(())
+        if let Some(max) = self.options.max_pending_requests {
+            if self.state.stats.pending_requests() >= max {
+                self.state.throttled.store(true, Ordering::Relaxed);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Polls the streaming responses with remaining credit, and pushes their chunks onto the
+    /// egress queue. A stream is only polled when the peer has credit for it, so that a slow
+    /// peer can never make us buffer more than the credit it has given us. Returns `true` if
+    /// any progress was made.
+    fn poll_streams(&mut self, cx: &mut Context<'_>) -> bool {
+        let mut progress = false;
+        let mut finished = Vec::new();
+        let mut chunks = Vec::new();
+
+        for (&id, stream) in self.streams.iter_mut() {
+            if stream.credit == 0 {
+                continue;
+            }
+
+            match stream.chunks.poll_next_unpin(cx) {
+                Poll::Ready(Some(chunk)) => {
+                    stream.credit -= 1;
+                    chunks.push((id, chunk));
+                    progress = true;
+                }
+                Poll::Ready(None) => {
+                    finished.push(id);
+                    progress = true;
+                }
+                Poll::Pending => {}
+            }
+        }
+
+        for (id, chunk) in chunks {
+            if let Some(msg) = self.encode(id, chunk, reqrep::Kind::Chunk) {
+                self.egress_queue.push_back(msg);
+            }
+        }
+
+        for id in finished {
+            debug!("Streaming response {} finished", id);
+            self.streams.remove(&id);
+            self.egress_queue
+                .push_back(reqrep::Message::new(id, 0, Bytes::new()).with_kind(reqrep::Kind::End));
+        }
+
+        progress
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite, A: Address> Drop for PeerState<T, A> {
+    fn drop(&mut self) {
+        // Requests that were never responded to are no longer pending
+        self.state.stats.decrement_pending_requests(self.pending_requests.len());
     }
 }
 
@@ -285,57 +440,128 @@ impl<T: AsyncRead + AsyncWrite + Unpin, A: Address + Unpin> Stream for PeerState
             }
 
             // Then we check for completed requests, and push them onto the egress queue.
-            if let Poll::Ready(Some(Some((id, mut payload)))) =
-                this.pending_requests.poll_next_unpin(cx)
-            {
-                let mut compression_type = 0;
-                let len_before = payload.len();
-                if let Some(ref compressor) = this.compressor {
-                    match compressor.compress(&payload) {
-                        Ok(compressed) => {
-                            payload = compressed;
-                            compression_type = compressor.compression_type() as u8;
-                        }
-                        Err(e) => {
-                            error!(err = ?e, "Failed to compress message");
-                            continue;
-                        }
+            if let Poll::Ready(Some((id, response))) = this.pending_requests.poll_next_unpin(cx) {
+                this.state.stats.decrement_pending_requests(1);
+
+                // Sessions that stopped reading because of the global limit run on this same
+                // task, so waking it up lets them resume.
+                if this.state.throttled.swap(false, Ordering::Relaxed) {
+                    cx.waker().wake_by_ref();
+                }
+
+                // The credit received for the request is only used by a streaming response
+                let credit = this.credits.remove(&id).unwrap_or_default();
+
+                // The request was dropped without a response, or cancelled by the peer
+                let Some(response) = response else {
+                    continue;
+                };
+
+                let payload = match response {
+                    Response::Ok(payload) => payload,
+                    Response::Err { code, message } => {
+                        debug!("Sending error response {} to request {}", code, id);
+                        this.egress_queue.push_back(reqrep::Message::error(id, code, &message));
+                        continue;
+                    }
+                    Response::Stream(chunks) => {
+                        this.streams.insert(id, ResponseStream { chunks, credit });
+                        continue;
                     }
+                };
 
-                    debug!(
-                        "Compressed message {} from {} to {} bytes",
-                        id,
-                        len_before,
-                        payload.len()
-                    )
+                if let Some(msg) = this.encode(id, payload, reqrep::Kind::Data) {
+                    this.egress_queue.push_back(msg);
                 }
 
-                let msg = reqrep::Message::new(id, compression_type, payload);
-                this.egress_queue.push_back(msg);
+                continue;
+            }
 
+            // Then we poll the streaming responses that the peer has given us credit for.
+            if this.poll_streams(cx) {
                 continue;
             }
 
+            // Stop reading new requests if too many are pending, which propagates backpressure
+            // to the client.
+            if this.at_capacity() {
+                if !this.throttled {
+                    debug!("Too many pending requests, pausing reads from peer {:?}", this.addr);
+                    this.throttled = true;
+                    this.state.stats.increment_throttled();
+                }
+
+                return Poll::Pending;
+            }
+
+            this.throttled = false;
+
             // Finally we accept incoming requests from the peer.
             match this.conn.poll_next_unpin(cx) {
                 Poll::Ready(Some(result)) => {
                     trace!("Received message from peer {:?}: {:?}", this.addr, result);
                     let msg = result?;
 
+                    if msg.kind() == reqrep::Kind::Credit {
+                        let id = msg.id();
+                        let credit = msg.to_credit().unwrap_or_default();
+                        trace!("Received {} credit for request {}", credit, id);
+
+                        if let Some(stream) = this.streams.get_mut(&id) {
+                            stream.credit = stream.credit.saturating_add(credit);
+                        } else if this.pending_requests.iter().any(|p| p.msg_id == id) {
+                            // The request has not been responded to yet
+                            *this.credits.entry(id).or_default() += credit;
+                        }
+
+                        continue;
+                    }
+
+                    if msg.kind() != reqrep::Kind::Data && msg.kind() != reqrep::Kind::Cancel {
+                        warn!(kind = ?msg.kind(), "Unexpected message from peer {:?}", this.addr);
+                        continue;
+                    }
+
+                    if msg.kind() == reqrep::Kind::Cancel {
+                        debug!("Request {} cancelled by peer {:?}", msg.id(), this.addr);
+
+                        // Closing the receiver drops the response, and lets the request handler
+                        // know that it can stop working on it.
+                        for pending in this.pending_requests.iter_mut() {
+                            if pending.msg_id == msg.id() {
+                                pending.response.close();
+                            }
+                        }
+
+                        this.credits.remove(&msg.id());
+                        this.streams.remove(&msg.id());
+
+                        continue;
+                    }
+
+                    let deadline = msg
+                        .header()
+                        .timeout_ms()
+                        .map(|ms| Instant::now() + Duration::from_millis(ms as u64));
+
                     let (tx, rx) = oneshot::channel();
 
                     // Add the pending request to the list
                     this.pending_requests.push(PendingRequest { msg_id: msg.id(), response: rx });
+                    this.state.stats.increment_pending_requests();
 
                     let request = Request {
                         source: this.addr.clone(),
+                        identity: this.identity.clone(),
                         response: tx,
+                        deadline,
                         compression_type: msg.header().compression_type(),
                         msg: msg.into_payload(),
                     };
 
                     return Poll::Ready(Some(Ok(request)));
                 }
+                Poll::Ready(None) if this.substream => return Poll::Ready(None),
                 Poll::Ready(None) => {
                     error!("Framed closed unexpectedly (peer {:?})", this.addr);
                     return Poll::Ready(None);
@@ -350,16 +576,16 @@ impl<T: AsyncRead + AsyncWrite + Unpin, A: Address + Unpin> Stream for PeerState
 
 struct PendingRequest {
     msg_id: u32,
-    response: oneshot::Receiver<Bytes>,
+    response: oneshot::Receiver<Response>,
 }
 
 impl Future for PendingRequest {
-    type Output = Option<(u32, Bytes)>;
+    type Output = (u32, Option<Response>);
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         match self.response.poll_unpin(cx) {
-            Poll::Ready(Ok(response)) => Poll::Ready(Some((self.msg_id, response))),
-            Poll::Ready(Err(_)) => Poll::Ready(None),
+            Poll::Ready(Ok(response)) => Poll::Ready((self.msg_id, Some(response))),
+            Poll::Ready(Err(_)) => Poll::Ready((self.msg_id, None)),
             Poll::Pending => Poll::Pending,
         }
     }
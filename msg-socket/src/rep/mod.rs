@@ -1,8 +1,12 @@
 use bytes::Bytes;
+use futures::{stream::BoxStream, Stream, StreamExt};
 use msg_transport::Address;
+use std::{sync::atomic::AtomicBool, time::Instant};
 use thiserror::Error;
 use tokio::sync::oneshot;
 
+use crate::Identity;
+
 mod driver;
 mod socket;
 mod stats;
@@ -29,11 +33,21 @@ pub struct RepOptions {
     /// The maximum number of concurrent clients.
     max_clients: Option<usize>,
     min_compress_size: usize,
+    /// The maximum number of requests that can be pending (received, but not responded to) over
+    /// all clients.
+    max_pending_requests: Option<usize>,
+    /// The maximum number of requests that can be pending for a single client.
+    max_pending_requests_per_peer: Option<usize>,
 }
 
 impl Default for RepOptions {
     fn default() -> Self {
-        Self { max_clients: None, min_compress_size: 8192 }
+        Self {
+            max_clients: None,
+            min_compress_size: 8192,
+            max_pending_requests: None,
+            max_pending_requests_per_peer: None,
+        }
     }
 }
 
@@ -50,22 +64,52 @@ impl RepOptions {
         self.min_compress_size = min_compress_size;
         self
     }
+
+    /// Sets the maximum number of pending requests over all clients. Once the limit is reached,
+    /// the socket stops reading from the connections until requests are responded to.
+    pub fn max_pending_requests(mut self, max_pending_requests: usize) -> Self {
+        self.max_pending_requests = Some(max_pending_requests);
+        self
+    }
+
+    /// Sets the maximum number of pending requests for a single client. Once the limit is
+    /// reached, the socket stops reading from that client until its requests are responded to.
+    pub fn max_pending_requests_per_peer(mut self, max_pending_requests: usize) -> Self {
+        self.max_pending_requests_per_peer = Some(max_pending_requests);
+        self
+    }
 }
 
 /// The request socket state, shared between the backend task and the socket.
 #[derive(Debug, Default)]
 pub(crate) struct SocketState {
     pub(crate) stats: SocketStats,
+    /// Whether any session stopped reading because the global pending requests limit was reached.
+    pub(crate) throttled: AtomicBool,
+}
+
+/// A response to a request, sent from the [`Request`] to the backend task.
+pub(crate) enum Response {
+    /// A successful response.
+    Ok(Bytes),
+    /// An error response.
+    Err { code: u16, message: String },
+    /// A streaming response.
+    Stream(BoxStream<'static, Bytes>),
 }
 
 /// A request received by the socket.
 pub struct Request<A: Address> {
     /// The source address of the request.
     source: A,
+    /// The identity the client authenticated with. Empty if authentication is disabled.
+    identity: Identity,
     /// The compression type used for the request payload
     compression_type: u8,
     /// The oneshot channel to respond to the request.
-    response: oneshot::Sender<Bytes>,
+    response: oneshot::Sender<Response>,
+    /// The deadline of the request, as propagated by the requester.
+    deadline: Option<Instant>,
     /// The message payload.
     msg: Bytes,
 }
@@ -76,28 +120,87 @@ impl<A: Address> Request<A> {
         &self.source
     }
 
+    /// Returns the identity the client authenticated with. Empty if authentication is disabled.
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+
     /// Returns a reference to the message.
     pub fn msg(&self) -> &Bytes {
         &self.msg
     }
 
+    /// Returns the deadline of the request, if the requester set one. After the deadline, the
+    /// requester will no longer wait for the response.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Returns `true` if the deadline of the request has passed.
+    pub fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Returns `true` if the request was cancelled by the requester. Responding to a cancelled
+    /// request returns an error.
+    pub fn is_cancelled(&self) -> bool {
+        self.response.is_closed()
+    }
+
     /// Responds to the request.
     pub fn respond(self, response: Bytes) -> Result<(), PubError> {
-        self.response.send(response).map_err(|_| PubError::SocketClosed)
+        self.response.send(Response::Ok(response)).map_err(|_| PubError::SocketClosed)
+    }
+
+    /// Responds to the request with a stream of chunks, which the requester receives through
+    /// [`ReqSocket::request_stream`](crate::ReqSocket::request_stream). The stream is only polled
+    /// as fast as the requester consumes the chunks.
+    pub fn respond_stream<S>(self, chunks: S) -> Result<(), PubError>
+    where
+        S: Stream<Item = Bytes> + Send + 'static,
+    {
+        self.response.send(Response::Stream(chunks.boxed())).map_err(|_| PubError::SocketClosed)
+    }
+
+    /// Responds to the request with an error. The requester will receive a
+    /// [`ReqError::Remote`](crate::ReqError::Remote) with the given code and message. Error codes
+    /// below 100 are reserved for the protocol.
+    pub fn respond_err(self, code: u16, message: impl Into<String>) -> Result<(), PubError> {
+        self.response
+            .send(Response::Err { code, message: message.into() })
+            .map_err(|_| PubError::SocketClosed)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{net::SocketAddr, time::Duration};
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
     use futures::StreamExt;
-    use msg_transport::tcp::Tcp;
-    use msg_wire::compression::{GzipCompressor, SnappyCompressor};
+    use msg_transport::{
+        inproc::Inproc,
+        noise::{self, Keypair, Noise},
+        tcp::Tcp,
+    };
+    use msg_wire::{
+        compression::{GzipCompressor, SnappyCompressor},
+        reqrep,
+    };
     use rand::Rng;
     use tracing::{debug, info};
 
-    use crate::{req::ReqSocket, Authenticator, ReqOptions};
+    use crate::{
+        req::ReqSocket, AsyncAuthenticator, AuthRequest, Authenticator, Authorizer,
+        LeastOutstanding, LoadBalancer, RandomOfTwo, ReqError, ReqOptions, RequestOptions,
+        RetryPolicy, SharedSecrets,
+    };
 
     use super::*;
 
@@ -270,4 +373,734 @@ mod tests {
         let res: Bytes = req.request(Bytes::from("hello")).await.unwrap();
         assert_eq!(res, Bytes::from("world"));
     }
+
+    /// Spawns a reply socket that counts the requests it receives.
+    async fn counting_rep() -> (SocketAddr, Arc<AtomicUsize>) {
+        let mut rep = RepSocket::new(Tcp::default());
+        rep.bind(localhost()).await.unwrap();
+        let addr = *rep.local_addr().unwrap();
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&count);
+        tokio::spawn(async move {
+            while let Some(req) = rep.next().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                req.respond(Bytes::from("world")).unwrap();
+            }
+        });
+
+        (addr, count)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_round_robin() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let (addr1, count1) = counting_rep().await;
+        let (addr2, count2) = counting_rep().await;
+
+        let mut req = ReqSocket::new(Tcp::default());
+        req.connect(addr1).await.unwrap();
+        req.connect(addr2).await.unwrap();
+
+        // Wait for both connections to be established
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        for _ in 0..100 {
+            let res = req.request(Bytes::from("hello")).await.unwrap();
+            assert_eq!(res, Bytes::from("world"));
+        }
+
+        assert_eq!(count1.load(Ordering::Relaxed), 50);
+        assert_eq!(count2.load(Ordering::Relaxed), 50);
+    }
+
+    async fn load_balanced_requests<L: LoadBalancer>(load_balancer: L) {
+        let (addr1, count1) = counting_rep().await;
+        let (addr2, count2) = counting_rep().await;
+
+        let mut req = ReqSocket::new(Tcp::default()).with_load_balancer(load_balancer);
+        req.connect(addr1).await.unwrap();
+        req.connect(addr2).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let req = Arc::new(req);
+        let handles = (0..100)
+            .map(|_| {
+                let req = Arc::clone(&req);
+                tokio::spawn(async move { req.request(Bytes::from("hello")).await })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            assert_eq!(handle.await.unwrap().unwrap(), Bytes::from("world"));
+        }
+
+        assert_eq!(count1.load(Ordering::Relaxed) + count2.load(Ordering::Relaxed), 100);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_least_outstanding() {
+        let _ = tracing_subscriber::fmt::try_init();
+        load_balanced_requests(LeastOutstanding).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_random_of_two() {
+        let _ = tracing_subscriber::fmt::try_init();
+        load_balanced_requests(RandomOfTwo).await;
+    }
+
+    /// Spawns an endpoint that accepts a single connection, and drops it (together with the
+    /// listener) as soon as it receives a request.
+    async fn faulty_rep() -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind(localhost()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::AsyncReadExt;
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            drop(listener);
+            let _ = stream.read(&mut [0u8; 1024]).await;
+        });
+
+        addr
+    }
+
+    /// Sends 10 concurrent requests over a socket connected to a faulty and a healthy endpoint.
+    async fn requests_with_faulty_endpoint(
+        options: ReqOptions,
+    ) -> (Vec<Result<Bytes, ReqError>>, Arc<AtomicUsize>) {
+        let (addr, count) = counting_rep().await;
+        let faulty_addr = faulty_rep().await;
+
+        let mut req = ReqSocket::with_options(Tcp::default(), options);
+        req.connect(faulty_addr).await.unwrap();
+        req.connect(addr).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let req = Arc::new(req);
+        let handles = (0..10)
+            .map(|_| {
+                let req = Arc::clone(&req);
+                tokio::spawn(async move { req.request(Bytes::from("hello")).await })
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = Vec::new();
+        for handle in handles {
+            results.push(handle.await.unwrap());
+        }
+
+        (results, count)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_failover() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let options = ReqOptions::default().retry_policy(RetryPolicy::Retry);
+        let (results, count) = requests_with_faulty_endpoint(options).await;
+
+        // The requests sent to the faulty endpoint are retried on the healthy one
+        for result in results {
+            assert_eq!(result.unwrap(), Bytes::from("world"));
+        }
+
+        assert_eq!(count.load(Ordering::Relaxed), 10);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_fail_in_flight() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let (results, count) = requests_with_faulty_endpoint(ReqOptions::default()).await;
+
+        // By default, the requests sent to the faulty endpoint fail instead of being retried
+        let failed =
+            results.iter().filter(|res| matches!(res, Err(ReqError::Disconnected))).count();
+        assert!(failed > 0);
+        assert_eq!(failed + count.load(Ordering::Relaxed), 10);
+        for result in results.into_iter().filter_map(Result::ok) {
+            assert_eq!(result, Bytes::from("world"));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_invalid_load_balancer_index() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        /// Violates the contract of the trait by returning invalid indices.
+        struct OutOfRange;
+
+        impl LoadBalancer for OutOfRange {
+            fn select(&mut self, outstanding: &[usize]) -> usize {
+                outstanding.len() + 1
+            }
+        }
+
+        load_balanced_requests(OutOfRange).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_ignores_response_from_other_endpoint() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        // A slow endpoint, which all requests are sent to
+        let mut rep = RepSocket::new(Tcp::default());
+        rep.bind(localhost()).await.unwrap();
+        let addr = *rep.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Some(req) = rep.next().await {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                req.respond(Bytes::from("world")).unwrap();
+            }
+        });
+
+        // An endpoint that sends responses to requests it never received
+        let listener = tokio::net::TcpListener::bind(localhost()).await.unwrap();
+        let rogue_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            use futures::SinkExt;
+
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut framed = tokio_util::codec::Framed::new(stream, reqrep::Codec::new());
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            for id in 0..16 {
+                framed.send(reqrep::Message::new(id, 0, Bytes::from("spoofed"))).await.unwrap();
+            }
+            std::future::pending::<()>().await
+        });
+
+        /// Always picks the first endpoint.
+        struct First;
+
+        impl LoadBalancer for First {
+            fn select(&mut self, _outstanding: &[usize]) -> usize {
+                0
+            }
+        }
+
+        let mut req = ReqSocket::new(Tcp::default()).with_load_balancer(First);
+        req.connect(addr).await.unwrap();
+        req.connect(rogue_addr).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let res = req.request(Bytes::from("hello")).await.unwrap();
+        assert_eq!(res, Bytes::from("world"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_deadline_propagation() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::new(Tcp::default());
+        rep.bind(localhost()).await.unwrap();
+
+        let mut req = ReqSocket::new(Tcp::default());
+        req.connect(rep.local_addr().unwrap()).await.unwrap();
+
+        tokio::spawn(async move {
+            let req = rep.next().await.unwrap();
+
+            let remaining = req.deadline().unwrap() - std::time::Instant::now();
+            assert!(remaining <= Duration::from_secs(1));
+            assert!(!req.is_expired());
+
+            req.respond(Bytes::from("world")).unwrap();
+        });
+
+        let options = RequestOptions::default().timeout(Duration::from_secs(1));
+        let res = req.request_with(Bytes::from("hello"), options).await.unwrap();
+        assert_eq!(res, Bytes::from("world"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_cancel_on_timeout() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::new(Tcp::default());
+        rep.bind(localhost()).await.unwrap();
+
+        let mut req = ReqSocket::new(Tcp::default());
+        req.connect(rep.local_addr().unwrap()).await.unwrap();
+
+        let handler = tokio::spawn(async move {
+            let req = rep.next().await.unwrap();
+
+            // Never respond, and wait for the cancellation instead
+            while !req.is_cancelled() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+
+            assert!(req.is_expired());
+        });
+
+        let options = RequestOptions::default().timeout(Duration::from_millis(200));
+        let res = req.request_with(Bytes::from("hello"), options).await;
+        assert!(matches!(res, Err(ReqError::Timeout)));
+
+        tokio::time::timeout(Duration::from_secs(1), handler).await.unwrap().unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_cancel_on_drop() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::new(Tcp::default());
+        rep.bind(localhost()).await.unwrap();
+
+        let mut req = ReqSocket::new(Tcp::default());
+        req.connect(rep.local_addr().unwrap()).await.unwrap();
+
+        // Keep the socket alive, so that only the request future is dropped
+        let req = Arc::new(req);
+        let socket = Arc::clone(&req);
+        let request = tokio::spawn(async move { socket.request(Bytes::from("hello")).await });
+
+        let incoming = rep.next().await.unwrap();
+        assert!(!incoming.is_cancelled());
+
+        // Dropping the request future cancels the request
+        request.abort();
+
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while !incoming.is_cancelled() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert!(incoming.respond(Bytes::from("world")).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_error_response() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::new(Tcp::default());
+        rep.bind(localhost()).await.unwrap();
+
+        let mut req = ReqSocket::new(Tcp::default());
+        req.connect(rep.local_addr().unwrap()).await.unwrap();
+
+        tokio::spawn(async move {
+            let req = rep.next().await.unwrap();
+            req.respond_err(404, "not found").unwrap();
+        });
+
+        let res = req.request(Bytes::from("hello")).await;
+        assert!(
+            matches!(res, Err(ReqError::Remote { code: 404, ref message }) if message == "not found")
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn rep_decode_error() {
+        use futures::SinkExt;
+        use msg_wire::{compression::CompressionType, reqrep};
+        use tokio_util::codec::Framed;
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::new(Tcp::default());
+        rep.bind(localhost()).await.unwrap();
+
+        let stream = tokio::net::TcpStream::connect(rep.local_addr().unwrap()).await.unwrap();
+        let mut conn = Framed::new(stream, reqrep::Codec::new());
+
+        // A request that claims to be compressed, but isn't
+        let msg = reqrep::Message::new(7, CompressionType::Gzip as u8, Bytes::from("garbage"));
+        conn.send(msg).await.unwrap();
+
+        let response = conn.next().await.unwrap().unwrap();
+        assert_eq!(response.id(), 7);
+        assert_eq!(response.kind(), reqrep::Kind::Error);
+        assert_eq!(response.into_error().unwrap().0, reqrep::ERR_DECODE);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_authorizer() {
+        struct Auth;
+
+        impl Authenticator for Auth {
+            fn authenticate(&self, _id: &Bytes) -> bool {
+                true
+            }
+        }
+
+        /// Only lets the admin send requests other than reads.
+        struct AdminAuthorizer;
+
+        impl Authorizer for AdminAuthorizer {
+            fn authorize_request(&self, identity: &Identity, request: &Bytes) -> bool {
+                identity.id() == "admin" || request.starts_with(b"GET ")
+            }
+        }
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep =
+            RepSocket::new(Tcp::default()).with_auth(Auth).with_authorizer(AdminAuthorizer);
+        rep.bind(localhost()).await.unwrap();
+
+        let mut req = ReqSocket::with_options(
+            Tcp::default(),
+            ReqOptions::default().auth_token(Bytes::from("user")),
+        );
+        req.connect(rep.local_addr().unwrap()).await.unwrap();
+
+        tokio::spawn(async move {
+            while let Some(req) = rep.next().await {
+                assert_eq!(req.identity().id(), &Bytes::from("user"));
+                let msg = req.msg().clone();
+                req.respond(msg).unwrap();
+            }
+        });
+
+        let res = req.request(Bytes::from("GET foo")).await.unwrap();
+        assert_eq!(res, Bytes::from("GET foo"));
+
+        let err = req.request(Bytes::from("DELETE foo")).await.unwrap_err();
+        assert!(matches!(err, ReqError::Remote { code: msg_wire::reqrep::ERR_UNAUTHORIZED, .. }));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_async_auth() {
+        /// Looks up the tenant of the token, like an auth service would.
+        struct TenantAuth;
+
+        #[async_trait::async_trait]
+        impl AsyncAuthenticator<SocketAddr> for TenantAuth {
+            async fn authenticate(
+                &self,
+                request: &AuthRequest<SocketAddr>,
+            ) -> Result<Identity, String> {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+
+                if !request.addr().ip().is_loopback() {
+                    return Err("not a local client".to_string());
+                }
+
+                match request.token().as_ref() {
+                    b"token-a" => Ok(Identity::new("alice").with_attribute("tenant", "a")),
+                    _ => Err("unknown token".to_string()),
+                }
+            }
+        }
+
+        struct TenantAuthorizer;
+
+        impl Authorizer for TenantAuthorizer {
+            fn authorize_request(&self, identity: &Identity, request: &Bytes) -> bool {
+                identity
+                    .attribute("tenant")
+                    .is_some_and(|tenant| request.starts_with(tenant.as_bytes()))
+            }
+        }
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::new(Tcp::default())
+            .with_async_auth(TenantAuth)
+            .with_authorizer(TenantAuthorizer);
+        rep.bind(localhost()).await.unwrap();
+
+        let mut req = ReqSocket::with_options(
+            Tcp::default(),
+            ReqOptions::default().auth_token(Bytes::from("token-a")),
+        );
+        req.connect(rep.local_addr().unwrap()).await.unwrap();
+
+        tokio::spawn(async move {
+            while let Some(req) = rep.next().await {
+                assert_eq!(req.identity().id(), &Bytes::from("alice"));
+                req.respond(Bytes::from("ok")).unwrap();
+            }
+        });
+
+        let res = req.request(Bytes::from("a/foo")).await.unwrap();
+        assert_eq!(res, Bytes::from("ok"));
+
+        let err = req.request(Bytes::from("b/foo")).await.unwrap_err();
+        assert!(matches!(err, ReqError::Remote { code: msg_wire::reqrep::ERR_UNAUTHORIZED, .. }));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_challenge_auth() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::new(Tcp::default())
+            .with_async_auth(SharedSecrets::new().with_secret("client1", "secret"));
+        rep.bind(localhost()).await.unwrap();
+        let addr = *rep.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Some(req) = rep.next().await {
+                assert_eq!(req.identity().id(), &Bytes::from("client1"));
+                req.respond(Bytes::from("ok")).unwrap();
+            }
+        });
+
+        let mut req = ReqSocket::with_options(
+            Tcp::default(),
+            ReqOptions::default().auth_secret(Bytes::from("client1"), Bytes::from("secret")),
+        );
+        req.connect(addr).await.unwrap();
+
+        let res = req.request(Bytes::from("hello")).await.unwrap();
+        assert_eq!(res, Bytes::from("ok"));
+
+        // Neither a wrong secret nor a plain token are accepted
+        for options in [
+            ReqOptions::default().auth_secret(Bytes::from("client1"), Bytes::from("wrong")),
+            ReqOptions::default().auth_token(Bytes::from("client1")),
+        ] {
+            let mut req =
+                ReqSocket::with_options(Tcp::default(), options.timeout(Duration::from_millis(200)));
+            req.connect(addr).await.unwrap();
+            assert!(req.request(Bytes::from("hello")).await.is_err());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_noise() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let client_keys = Keypair::generate();
+        let client_key = Bytes::copy_from_slice(client_keys.public_key());
+
+        let mut rep = RepSocket::new(Noise::new(
+            Tcp::default(),
+            noise::Config::new(Keypair::generate()).with_trusted_key(client_keys.public_key()),
+        ));
+        rep.bind(localhost()).await.unwrap();
+
+        let mut req =
+            ReqSocket::new(Noise::new(Tcp::default(), noise::Config::new(client_keys.clone())));
+        req.connect(*rep.local_addr().unwrap()).await.unwrap();
+
+        tokio::spawn(async move {
+            while let Some(req) = rep.next().await {
+                // Without an authenticator, the identity is the static key of the client
+                assert_eq!(req.identity().id(), &client_key);
+                let msg = req.msg().clone();
+                req.respond(msg).unwrap();
+            }
+        });
+
+        let msg = Bytes::from(vec![7u8; 100_000]);
+        let res = req.request(msg.clone()).await.unwrap();
+        assert_eq!(res, msg);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_inproc() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::new(Inproc::default());
+        rep.bind("reqrep-inproc").await.unwrap();
+
+        let mut req = ReqSocket::new(Inproc::default());
+        req.connect("reqrep-inproc").await.unwrap();
+
+        tokio::spawn(async move {
+            while let Some(req) = rep.next().await {
+                let msg = req.msg().clone();
+                req.respond(msg).unwrap();
+            }
+        });
+
+        for i in 0..100u32 {
+            let msg = Bytes::copy_from_slice(&i.to_be_bytes());
+            let res = req.request(msg.clone()).await.unwrap();
+            assert_eq!(res, msg);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_stream() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::new(Tcp::default());
+        rep.bind(localhost()).await.unwrap();
+
+        let mut req = ReqSocket::new(Tcp::default());
+        req.connect(rep.local_addr().unwrap()).await.unwrap();
+
+        tokio::spawn(async move {
+            let req = rep.next().await.unwrap();
+            let chunks = futures::stream::iter((0..100).map(|i| Bytes::from(format!("chunk-{i}"))));
+            req.respond_stream(chunks).unwrap();
+        });
+
+        let stream = req.request_stream(Bytes::from("hello")).await.unwrap();
+        let chunks = stream.collect::<Vec<_>>().await;
+
+        assert_eq!(chunks.len(), 100);
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            assert_eq!(chunk.unwrap(), Bytes::from(format!("chunk-{i}")));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_stream_flow_control() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::new(Tcp::default());
+        rep.bind(localhost()).await.unwrap();
+
+        let mut req =
+            ReqSocket::with_options(Tcp::default(), ReqOptions::default().stream_window(4));
+        req.connect(rep.local_addr().unwrap()).await.unwrap();
+
+        let produced = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&produced);
+        tokio::spawn(async move {
+            let req = rep.next().await.unwrap();
+            let chunks = futures::stream::iter(0..20).map(move |i| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                Bytes::from(format!("chunk-{i}"))
+            });
+
+            req.respond_stream(chunks).unwrap();
+        });
+
+        let mut stream = req.request_stream(Bytes::from("hello")).await.unwrap();
+
+        // Without consuming, the reply socket can't get further than the window
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(produced.load(Ordering::Relaxed), 4);
+
+        let mut received = 0;
+        while let Some(chunk) = stream.next().await {
+            chunk.unwrap();
+            received += 1;
+        }
+
+        assert_eq!(received, 20);
+        assert_eq!(produced.load(Ordering::Relaxed), 20);
+    }
+
+    #[cfg(feature = "tower")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reqrep_tower_service() {
+        use tower::{Service, ServiceBuilder, ServiceExt};
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::new(Tcp::default());
+        rep.bind(localhost()).await.unwrap();
+        let addr = *rep.local_addr().unwrap();
+
+        let echo = tower::service_fn(|msg: Bytes| async move {
+            if msg == "fail" {
+                return Err("failed");
+            }
+
+            Ok(msg)
+        });
+        tokio::spawn(rep.serve(echo, 8));
+
+        let mut req = ReqSocket::new(Tcp::default());
+        req.connect(addr).await.unwrap();
+
+        // Use the request socket through a middleware stack
+        let mut service = ServiceBuilder::new().timeout(Duration::from_secs(1)).service(req);
+
+        let res = service.ready().await.unwrap().call(Bytes::from("hello")).await.unwrap();
+        assert_eq!(res, Bytes::from("hello"));
+
+        let err = service.ready().await.unwrap().call(Bytes::from("fail")).await.unwrap_err();
+        let err = err.downcast::<ReqError>().unwrap();
+        assert!(matches!(
+            *err,
+            ReqError::Remote { code: msg_wire::reqrep::ERR_SERVICE, ref message } if message == "failed"
+        ));
+    }
+
+    /// Sends `n` concurrent requests with the given request socket.
+    fn spawn_requests(
+        req: Arc<ReqSocket<Tcp, SocketAddr>>,
+        n: usize,
+    ) -> Vec<tokio::task::JoinHandle<()>> {
+        (0..n)
+            .map(|_| {
+                let req = Arc::clone(&req);
+                tokio::spawn(async move {
+                    let res = req.request(Bytes::from("hello")).await.unwrap();
+                    assert_eq!(res, Bytes::from("world"));
+                })
+            })
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn rep_max_pending_requests_per_peer() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep = RepSocket::with_options(
+            Tcp::default(),
+            RepOptions::default().max_pending_requests_per_peer(2),
+        );
+        rep.bind(localhost()).await.unwrap();
+
+        let mut req = ReqSocket::new(Tcp::default());
+        req.connect(rep.local_addr().unwrap()).await.unwrap();
+        let handles = spawn_requests(Arc::new(req), 10);
+
+        // Only 2 requests are read while none are responded to
+        let mut pending = Vec::new();
+        while let Ok(Some(req)) = tokio::time::timeout(Duration::from_millis(300), rep.next()).await
+        {
+            pending.push(req);
+        }
+
+        assert_eq!(pending.len(), 2);
+        assert_eq!(rep.stats().pending_requests(), 2);
+        assert!(rep.stats().throttled() > 0);
+
+        // Responding lets the remaining requests through
+        for req in pending {
+            req.respond(Bytes::from("world")).unwrap();
+        }
+
+        tokio::spawn(async move {
+            while let Some(req) = rep.next().await {
+                req.respond(Bytes::from("world")).unwrap();
+            }
+        });
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn rep_max_pending_requests() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut rep =
+            RepSocket::with_options(Tcp::default(), RepOptions::default().max_pending_requests(3));
+        rep.bind(localhost()).await.unwrap();
+        let addr = *rep.local_addr().unwrap();
+
+        let mut handles = Vec::new();
+        for _ in 0..2 {
+            let mut req = ReqSocket::new(Tcp::default());
+            req.connect(addr).await.unwrap();
+            handles.extend(spawn_requests(Arc::new(req), 5));
+        }
+
+        // Only 3 requests are read over both clients while none are responded to
+        let mut pending = Vec::new();
+        while let Ok(Some(req)) = tokio::time::timeout(Duration::from_millis(300), rep.next()).await
+        {
+            pending.push(req);
+        }
+
+        assert_eq!(pending.len(), 3);
+        assert_eq!(rep.stats().pending_requests(), 3);
+
+        for req in pending {
+            req.respond(Bytes::from("world")).unwrap();
+        }
+
+        tokio::spawn(async move {
+            while let Some(req) = rep.next().await {
+                req.respond(Bytes::from("world")).unwrap();
+            }
+        });
+
+        for handle in handles {
+            handle.await.unwrap();
+        }
+    }
 }
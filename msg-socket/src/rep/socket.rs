@@ -7,6 +7,8 @@ use std::{
     task::{Context, Poll},
 };
 
+#[cfg(feature = "tower")]
+use bytes::Bytes;
 use futures::{stream::FuturesUnordered, Stream};
 use tokio::{
     net::{lookup_host, ToSocketAddrs},
@@ -17,11 +19,12 @@ use tokio_stream::StreamMap;
 use tracing::{debug, warn};
 
 use crate::{
+    accept_stream_of,
     rep::{driver::RepDriver, SocketState, SocketStats, DEFAULT_BUFFER_SIZE},
-    Authenticator, PubError, RepOptions, Request,
+    AsyncAuthenticator, Authenticator, Authorizer, PubError, RepOptions, Request, StreamFn,
 };
 
-use msg_transport::{Address, Transport};
+use msg_transport::{inproc::InprocAddr, Address, MultiplexedTransport, Transport};
 use msg_wire::compression::Compressor;
 
 /// A reply socket. This socket implements [`Stream`] and yields incoming [`Request`]s.
@@ -37,11 +40,15 @@ pub struct RepSocket<T: Transport<A>, A: Address> {
     /// to the driver task once the socket is bound.
     transport: Option<T>,
     /// Optional connection authenticator.
-    auth: Option<Arc<dyn Authenticator>>,
+    auth: Option<Arc<dyn AsyncAuthenticator<A>>>,
+    /// Optional request authorizer.
+    authorizer: Option<Arc<dyn Authorizer>>,
     /// The local address this socket is bound to.
     local_addr: Option<A>,
     /// Optional message compressor.
     compressor: Option<Arc<dyn Compressor>>,
+    /// Accepts the streams that peers open for their requests, if enabled.
+    accept_stream: Option<StreamFn<T::Io, T::Error>>,
 }
 
 impl<T> RepSocket<T, SocketAddr>
@@ -64,6 +71,42 @@ where
     }
 }
 
+impl<T> RepSocket<T, InprocAddr>
+where
+    T: Transport<InprocAddr> + Send + Unpin + 'static,
+{
+    /// Binds the socket to the given in-process endpoint name.
+    ///
+    /// This method is only available for transports that support [`InprocAddr`] as address type,
+    /// like [`Inproc`](msg_transport::inproc::Inproc).
+    pub async fn bind(&mut self, name: impl Into<InprocAddr>) -> Result<(), PubError> {
+        self.try_bind(vec![name.into()]).await
+    }
+}
+
+impl<T, A> RepSocket<T, A>
+where
+    T: MultiplexedTransport<A> + Send + Unpin + 'static,
+    T::AcceptStream: 'static,
+    A: Address,
+{
+    /// Accepts the streams that request sockets open on their connections, and handles the
+    /// requests on them. This is needed for request sockets that send every request on its own
+    /// stream, see
+    /// [`ReqSocket::with_stream_per_request`](crate::ReqSocket::with_stream_per_request).
+    ///
+    /// Every stream is handled like a connection of its own, so
+    /// [`RepOptions::max_pending_requests_per_peer`] applies to each stream.
+    ///
+    /// # Panics
+    /// If the socket is bound already.
+    pub fn with_request_streams(mut self) -> Self {
+        assert!(self.transport.is_some(), "Socket is bound already");
+        self.accept_stream = Some(accept_stream_of::<T, A>);
+        self
+    }
+}
+
 impl<T, A> RepSocket<T, A>
 where
     T: Transport<A> + Send + Unpin + 'static,
@@ -83,7 +126,9 @@ where
             options: Arc::new(options),
             state: Arc::new(SocketState::default()),
             auth: None,
+            authorizer: None,
             compressor: None,
+            accept_stream: None,
         }
     }
 
@@ -93,6 +138,19 @@ where
         self
     }
 
+    /// Sets the asynchronous connection authenticator for this socket, which also has access to
+    /// the address of the client and the metadata of the connection.
+    pub fn with_async_auth<O: AsyncAuthenticator<A>>(mut self, authenticator: O) -> Self {
+        self.auth = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Sets the request authorizer for this socket, which is consulted on every request.
+    pub fn with_authorizer<O: Authorizer>(mut self, authorizer: O) -> Self {
+        self.authorizer = Some(Arc::new(authorizer));
+        self
+    }
+
     /// Sets the message compressor for this socket.
     pub fn with_compressor<C: Compressor + 'static>(mut self, compressor: C) -> Self {
         self.compressor = Some(Arc::new(compressor));
@@ -131,9 +189,13 @@ where
             peer_states: StreamMap::with_capacity(self.options.max_clients.unwrap_or(64)),
             to_socket,
             auth: self.auth.take(),
+            authorizer: self.authorizer.take(),
             auth_tasks: JoinSet::new(),
             conn_tasks: FuturesUnordered::new(),
             compressor: self.compressor.take(),
+            accept_stream: self.accept_stream.take(),
+            stream_tasks: FuturesUnordered::new(),
+            last_stream_id: 0,
         };
 
         tokio::spawn(backend);
@@ -152,6 +214,54 @@ where
     pub fn local_addr(&self) -> Option<&A> {
         self.local_addr.as_ref()
     }
+
+    /// Serves incoming requests with the given [`tower::Service`], handling at most
+    /// `concurrency` requests at the same time. Every request is handled in its own task. Service
+    /// errors are sent back to the requester with the
+    /// [`ERR_SERVICE`](msg_wire::reqrep::ERR_SERVICE) code, and requests that are expired or
+    /// cancelled before they are handled are skipped.
+    ///
+    /// Returns when the socket is closed, or with an error if the service fails to become ready.
+    #[cfg(feature = "tower")]
+    pub async fn serve<S>(mut self, mut service: S, concurrency: usize) -> Result<(), S::Error>
+    where
+        S: tower::Service<Bytes, Response = Bytes>,
+        S::Error: std::fmt::Display,
+        S::Future: Send + 'static,
+    {
+        use futures::{future::poll_fn, StreamExt};
+        use msg_wire::reqrep::ERR_SERVICE;
+
+        let concurrency = concurrency.max(1);
+        let mut in_flight = JoinSet::new();
+
+        while let Some(request) = self.next().await {
+            if request.is_expired() || request.is_cancelled() {
+                debug!("Skipping expired or cancelled request from {:?}", request.source());
+                continue;
+            }
+
+            // Wait for a free slot
+            while in_flight.len() >= concurrency {
+                in_flight.join_next().await;
+            }
+
+            poll_fn(|cx| service.poll_ready(cx)).await?;
+
+            let response = service.call(request.msg().clone());
+            in_flight.spawn(async move {
+                let _ = match response.await {
+                    Ok(response) => request.respond(response),
+                    Err(e) => request.respond_err(ERR_SERVICE, e.to_string()),
+                };
+            });
+        }
+
+        // Finish the requests that are still being handled
+        while in_flight.join_next().await.is_some() {}
+
+        Ok(())
+    }
 }
 
 impl<T: Transport<A> + Unpin, A: Address> Stream for RepSocket<T, A> {
@@ -12,6 +12,10 @@ pub struct SocketStats {
     active_clients: AtomicUsize,
     /// Total number of failed requests
     failed_requests: AtomicUsize,
+    /// Number of requests that have been received, but not responded to yet
+    pending_requests: AtomicUsize,
+    /// Number of times a session stopped reading because the pending requests limit was reached
+    throttled: AtomicUsize,
 }
 
 impl SocketStats {
@@ -40,6 +44,21 @@ impl SocketStats {
         self.failed_requests.fetch_add(1, Ordering::Relaxed);
     }
 
+    #[inline]
+    pub(crate) fn increment_pending_requests(&self) {
+        self.pending_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn decrement_pending_requests(&self, n: usize) {
+        self.pending_requests.fetch_sub(n, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn increment_throttled(&self) {
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+    }
+
     #[inline]
     pub fn bytes_tx(&self) -> usize {
         self.bytes_tx.load(Ordering::Relaxed)
@@ -59,4 +78,17 @@ impl SocketStats {
     pub fn failed_requests(&self) -> usize {
         self.failed_requests.load(Ordering::Relaxed)
     }
+
+    /// Returns the number of requests that have been received, but not responded to yet.
+    #[inline]
+    pub fn pending_requests(&self) -> usize {
+        self.pending_requests.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times a session stopped reading new requests because the pending
+    /// requests limit was reached.
+    #[inline]
+    pub fn throttled(&self) -> usize {
+        self.throttled.load(Ordering::Relaxed)
+    }
 }
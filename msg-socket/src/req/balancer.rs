@@ -0,0 +1,54 @@
+use rand::Rng;
+
+/// A load balancing policy, used by a [`ReqSocket`](crate::ReqSocket) to pick the endpoint that a
+/// request is sent to. Only endpoints with an active connection are considered.
+pub trait LoadBalancer: Send + Sync + Unpin + 'static {
+    /// Selects one of the active endpoints. `outstanding` contains the number of requests that
+    /// are waiting for a response for each endpoint, and is never empty. The returned value must
+    /// be a valid index into `outstanding`. An index that is out of range is wrapped around (modulo
+    /// the number of endpoints), and a warning is logged.
+    fn select(&mut self, outstanding: &[usize]) -> usize;
+}
+
+/// Distributes requests over the active endpoints in turn. This is the default policy.
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl LoadBalancer for RoundRobin {
+    fn select(&mut self, outstanding: &[usize]) -> usize {
+        let idx = self.next % outstanding.len();
+        self.next = idx + 1;
+        idx
+    }
+}
+
+/// Sends every request to the endpoint with the least outstanding requests.
+#[derive(Debug, Default)]
+pub struct LeastOutstanding;
+
+impl LoadBalancer for LeastOutstanding {
+    fn select(&mut self, outstanding: &[usize]) -> usize {
+        outstanding.iter().enumerate().min_by_key(|(_, n)| **n).map(|(idx, _)| idx).unwrap_or(0)
+    }
+}
+
+/// Picks 2 endpoints at random, and sends the request to the one with the least outstanding
+/// requests ("power of two choices").
+#[derive(Debug, Default)]
+pub struct RandomOfTwo;
+
+impl LoadBalancer for RandomOfTwo {
+    fn select(&mut self, outstanding: &[usize]) -> usize {
+        let mut rng = rand::thread_rng();
+        let a = rng.gen_range(0..outstanding.len());
+        let b = rng.gen_range(0..outstanding.len());
+
+        if outstanding[b] < outstanding[a] {
+            b
+        } else {
+            a
+        }
+    }
+}
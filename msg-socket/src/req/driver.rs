@@ -3,8 +3,8 @@ use std::{
     io,
     pin::Pin,
     sync::Arc,
-    task::{ready, Context, Poll},
-    time::{Duration, Instant},
+    task::{Context, Poll},
+    time::Duration,
 };
 
 use bytes::Bytes;
@@ -12,17 +12,19 @@ use futures::{Future, FutureExt, SinkExt, StreamExt};
 use rustc_hash::FxHashMap;
 use tokio::{
     sync::{mpsc, oneshot},
-    time::Interval,
+    time::{Instant, Interval},
 };
 use tokio_util::codec::Framed;
-use tracing::{debug, error, trace};
+use tracing::{debug, error, trace, warn};
 
-use super::{Command, ReqError, ReqOptions};
-use crate::{req::SocketState, ConnectionState, ExponentialBackoff};
+use super::{
+    substream::{Substream, SubstreamEvent},
+    Command, LoadBalancer, ReqError, ReqMessage, ReqOptions, RetryPolicy,
+};
+use crate::{authenticate_client, req::SocketState, ConnectionState, ExponentialBackoff, StreamFn};
 
 use msg_transport::{Address, Transport};
 use msg_wire::{
-    auth,
     compression::{try_decompress_payload, Compressor},
     reqrep,
 };
@@ -33,6 +35,41 @@ type ConnectionTask<Io, Err> = Pin<Box<dyn Future<Output = Result<Io, Err>> + Se
 /// A connection controller that manages the connection to a server with an exponential backoff.
 type ConnectionCtl<Io, Addr> = ConnectionState<Framed<Io, reqrep::Codec>, ExponentialBackoff, Addr>;
 
+/// The state of a single endpoint that the request socket is connected to.
+pub(crate) struct Peer<T: Transport<A>, A: Address> {
+    /// The address of the endpoint.
+    addr: A,
+    /// The connection task which handles the connection to the endpoint.
+    conn_task: Option<ConnectionTask<T::Io, T::Error>>,
+    /// The transport controller, wrapped in a [`ConnectionState`] for backoff.
+    /// The [`Framed`] object can send and receive messages from the socket.
+    conn_state: ConnectionCtl<T::Io, A>,
+    /// The outgoing message queue.
+    egress_queue: VecDeque<reqrep::Message>,
+    /// Whether or not the connection should be flushed
+    should_flush: bool,
+    /// The number of requests sent to this endpoint that are waiting for a response.
+    outstanding: usize,
+}
+
+impl<T: Transport<A>, A: Address> Peer<T, A> {
+    /// Creates a new, inactive peer. The connection will be started by the driver as soon as the
+    /// initial backoff has elapsed.
+    pub(crate) fn new(addr: A) -> Self {
+        Self {
+            conn_state: ConnectionState::Inactive {
+                addr: addr.clone(),
+                backoff: ExponentialBackoff::new(Duration::from_millis(20), 16),
+            },
+            addr,
+            conn_task: None,
+            egress_queue: VecDeque::new(),
+            should_flush: false,
+            outstanding: 0,
+        }
+    }
+}
+
 /// The request socket driver. Endless future that drives
 /// the the socket forward.
 pub(crate) struct ReqDriver<T: Transport<A>, A: Address> {
@@ -41,40 +78,81 @@ pub(crate) struct ReqDriver<T: Transport<A>, A: Address> {
     pub(crate) options: Arc<ReqOptions>,
     /// State shared with the socket.
     pub(crate) socket_state: Arc<SocketState>,
-    /// ID counter for outgoing requests.
-    pub(crate) id_counter: u32,
     /// Commands from the socket.
-    pub(crate) from_socket: mpsc::Receiver<Command>,
+    pub(crate) from_socket: mpsc::Receiver<Command<A>>,
     /// The transport for this socket.
     pub(crate) transport: T,
-    /// The address of the server.
-    pub(crate) addr: A,
-    /// The connection task which handles the connection to the server.
-    pub(crate) conn_task: Option<ConnectionTask<T::Io, T::Error>>,
-    /// The transport controller, wrapped in a [`ConnectionState`] for backoff.
-    /// The [`Framed`] object can send and receive messages from the socket.
-    pub(crate) conn_state: ConnectionCtl<T::Io, A>,
-    /// The outgoing message queue.
-    pub(crate) egress_queue: VecDeque<reqrep::Message>,
+    /// All the endpoints this socket is connected to.
+    pub(crate) peers: Vec<Peer<T, A>>,
+    /// The load balancing policy used to pick an endpoint for every request.
+    pub(crate) load_balancer: Box<dyn LoadBalancer>,
+    /// The IDs of the requests that are waiting to be assigned to an endpoint. This contains new
+    /// requests, and requests that were in-flight on a connection that dropped.
+    pub(crate) unassigned: VecDeque<u32>,
     /// The currently pending requests, if any. Uses [`FxHashMap`] for performance.
-    pub(crate) pending_requests: FxHashMap<u32, PendingRequest>,
+    pub(crate) pending_requests: FxHashMap<u32, PendingRequest<A>>,
     /// Interval for checking for request timeouts.
     pub(crate) timeout_check_interval: Interval,
-    /// Interval for flushing the connection. This is secondary to `should_flush`.
+    /// Interval for flushing the connections. This is secondary to `should_flush`.
     pub(crate) flush_interval: Option<tokio::time::Interval>,
-    /// Whether or not the connection should be flushed
-    pub(crate) should_flush: bool,
     /// Optional message compressor. This is shared with the socket to keep
     /// the API consistent with other socket types (e.g. `PubSocket`)
     pub(crate) compressor: Option<Arc<dyn Compressor>>,
+    /// Opens a stream for every request, if enabled. See
+    /// [`ReqSocket::with_stream_per_request`](super::ReqSocket::with_stream_per_request).
+    pub(crate) open_stream: Option<StreamFn<T::Io, T::Error>>,
+    /// Sender for the events of the [`Substream`]s, given to each of them.
+    pub(crate) substream_events: mpsc::UnboundedSender<SubstreamEvent<A>>,
+    /// The events of the [`Substream`]s.
+    pub(crate) from_substreams: mpsc::UnboundedReceiver<SubstreamEvent<A>>,
 }
 
 /// A pending request that is waiting for a response.
-pub(crate) struct PendingRequest {
+pub(crate) struct PendingRequest<A> {
     /// The timestamp when the request was sent.
     start: Instant,
+    /// The deadline of the request.
+    deadline: Instant,
     /// The response sender.
-    sender: oneshot::Sender<Result<Bytes, ReqError>>,
+    sender: ResponseSender,
+    /// The request message, kept around so that it can be sent to another endpoint.
+    message: reqrep::Message,
+    /// The endpoint the request was assigned to, if any.
+    peer: Option<A>,
+    /// The messages for the [`Substream`] of the request, if it's sent on its own stream.
+    substream: Option<mpsc::UnboundedSender<reqrep::Message>>,
+}
+
+/// Where the response to a pending request is sent.
+pub(crate) enum ResponseSender {
+    /// A single response.
+    Single(oneshot::Sender<Result<Bytes, ReqError>>),
+    /// A streaming response.
+    Stream {
+        /// The chunks of the response.
+        chunks: mpsc::UnboundedSender<Result<Bytes, ReqError>>,
+        /// The initial credit given to the endpoint.
+        window: u32,
+        /// Whether any chunk has been received yet.
+        started: bool,
+    },
+}
+
+impl ResponseSender {
+    /// Sends the final result of the request.
+    fn send(self, result: Result<Bytes, ReqError>) {
+        match self {
+            Self::Single(sender) => {
+                let _ = sender.send(result);
+            }
+            Self::Stream { chunks, .. } => {
+                // An empty end of stream message carries no final chunk
+                if !matches!(result, Ok(ref payload) if payload.is_empty()) {
+                    let _ = chunks.send(result);
+                }
+            }
+        }
+    }
 }
 
 impl<T, A> ReqDriver<T, A>
@@ -82,15 +160,17 @@ where
     T: Transport<A> + Send + Sync + 'static,
     A: Address,
 {
-    /// Start the connection task to the server, handling authentication if necessary.
-    /// The result will be polled by the driver and re-tried according to the backoff policy.
-    fn try_connect(&mut self, addr: A) {
+    /// Start the connection task to the peer at the given index, handling authentication if
+    /// necessary. The result will be polled by the driver and re-tried according to the backoff
+    /// policy.
+    fn try_connect(&mut self, idx: usize) {
+        let addr = self.peers[idx].addr.clone();
         trace!("Trying to connect to {:?}", addr);
 
         let connect = self.transport.connect(addr.clone());
-        let token = self.options.auth_token.clone();
+        let credentials = self.options.auth.clone();
 
-        self.conn_task = Some(Box::pin(async move {
+        self.peers[idx].conn_task = Some(Box::pin(async move {
             let mut io = match connect.await {
                 Ok(io) => io,
                 Err(e) => {
@@ -100,95 +180,344 @@ where
             };
 
             // Perform the authentication handshake
-            if let Some(token) = token {
-                let mut conn = Framed::new(&mut io, auth::Codec::new_client());
-
-                debug!("Sending auth message: {:?}", token);
-                conn.send(auth::Message::Auth(token)).await?;
-                conn.flush().await?;
-
-                debug!("Waiting for ACK from server...");
-
-                // Wait for the response
-                match conn.next().await {
-                    Some(res) => match res {
-                        Ok(auth::Message::Ack) => {
-                            debug!("Connected to {:?}", addr);
-                            Ok(io)
-                        }
-                        Ok(msg) => {
-                            error!(?msg, "Unexpected auth ACK result");
-                            Err(io::Error::new(io::ErrorKind::PermissionDenied, "rejected").into())
-                        }
-                        Err(e) => Err(io::Error::new(io::ErrorKind::PermissionDenied, e).into()),
-                    },
-                    None => {
-                        error!("Connection closed while waiting for ACK");
-                        Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed")
-                            .into())
-                    }
-                }
-            } else {
-                debug!("Connected to {:?}", addr);
-                Ok(io)
+            if let Some(credentials) = credentials {
+                authenticate_client(&mut io, credentials).await?;
             }
+
+            debug!("Connected to {:?}", addr);
+            Ok(io)
         }));
     }
 
-    /// Handle an incoming message from the connection.
-    fn on_message(&mut self, msg: reqrep::Message) {
-        if let Some(pending) = self.pending_requests.remove(&msg.id()) {
-            let rtt = pending.start.elapsed().as_micros() as usize;
-            let size = msg.size();
+    /// Handle an incoming message from the connection to the given endpoint.
+    fn on_message(&mut self, addr: &A, msg: reqrep::Message) {
+        let id = msg.id();
+        let kind = msg.kind();
+        if matches!(kind, reqrep::Kind::Cancel | reqrep::Kind::Credit) {
+            warn!(id, ?kind, "Unexpected message kind");
+            return;
+        }
+
+        let idle_deadline = Instant::now() + self.options.timeout;
+        let Some(pending) = self.pending_requests.get_mut(&id) else {
+            return;
+        };
+
+        // Only the endpoint the request was sent to can respond to it
+        if pending.peer.as_ref() != Some(addr) {
+            warn!(id, ?addr, "Response from an endpoint the request wasn't sent to, ignoring");
+            return;
+        }
+
+        let size = msg.size();
+        self.socket_state.stats.increment_rx(size);
+
+        let result = if kind == reqrep::Kind::Error {
+            match msg.into_error() {
+                Some((code, message)) => Err(ReqError::Remote { code, message }),
+                None => Err(ReqError::Wire(reqrep::Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid error response",
+                )))),
+            }
+        } else {
             let compression_type = msg.header().compression_type();
-            let mut payload = msg.into_payload();
 
             // decompress the response
-            match try_decompress_payload(compression_type, payload) {
-                Ok(decompressed) => payload = decompressed,
-                Err(e) => {
-                    error!(err = ?e, "Failed to decompress response payload");
-                    let _ = pending.sender.send(Err(ReqError::Wire(reqrep::Error::Io(
-                        io::Error::new(io::ErrorKind::Other, "Failed to decompress response"),
-                    ))));
-                    return;
-                }
+            try_decompress_payload(compression_type, msg.into_payload()).map_err(|e| {
+                error!(err = ?e, "Failed to decompress response payload");
+                ReqError::Wire(reqrep::Error::Io(io::Error::new(
+                    io::ErrorKind::Other,
+                    "Failed to decompress response",
+                )))
+            })
+        };
+
+        // A chunk of a streaming response keeps the request pending, and resets its deadline
+        if kind == reqrep::Kind::Chunk && result.is_ok() {
+            if let ResponseSender::Stream { ref chunks, ref mut started, .. } = pending.sender {
+                *started = true;
+                pending.deadline = idle_deadline;
+                let _ = chunks.send(result);
+                return;
             }
+        }
 
-            let _ = pending.sender.send(Ok(payload));
+        let pending = self.pending_requests.remove(&id).expect("pending request");
+        self.release(pending.peer.as_ref());
 
-            // Update stats
+        if result.is_ok() {
+            let rtt = pending.start.elapsed().as_micros() as usize;
             self.socket_state.stats.update_rtt(rtt);
-            self.socket_state.stats.increment_rx(size);
         }
+
+        pending.sender.send(result);
+    }
+
+    /// Compresses the message if a compressor is set, and the payload is larger than the
+    /// configured minimum size.
+    fn compress(&self, mut message: ReqMessage) -> ReqMessage {
+        let len_before = message.payload().len();
+        if len_before > self.options.min_compress_size {
+            if let Some(ref compressor) = self.compressor {
+                if let Err(e) = message.compress(compressor.as_ref()) {
+                    error!(err = ?e, "Failed to compress message");
+                }
+
+                debug!(
+                    "Compressed message from {} to {} bytes",
+                    len_before,
+                    message.payload().len()
+                );
+            }
+        }
+
+        message
     }
 
     /// Handle an incoming command from the socket frontend.
-    fn on_command(&mut self, cmd: Command) {
+    fn on_command(&mut self, cmd: Command<A>) {
         match cmd {
-            Command::Send { mut message, response } => {
-                let start = std::time::Instant::now();
-
-                let len_before = message.payload().len();
-                if len_before > self.options.min_compress_size {
-                    if let Some(ref compressor) = self.compressor {
-                        if let Err(e) = message.compress(compressor.as_ref()) {
-                            error!(err = ?e, "Failed to compress message");
-                        }
+            Command::Send { id, message, deadline, response } => {
+                let start = Instant::now();
+                let message = self.compress(message);
+
+                let msg = message.into_wire(id);
+                self.unassigned.push_back(id);
+                self.pending_requests.insert(
+                    id,
+                    PendingRequest {
+                        start,
+                        deadline,
+                        sender: ResponseSender::Single(response),
+                        message: msg,
+                        peer: None,
+                        substream: None,
+                    },
+                );
+            }
+            Command::SendStream { id, message, response } => {
+                let start = Instant::now();
+                let msg = self.compress(message).into_wire(id);
+                self.unassigned.push_back(id);
+                self.pending_requests.insert(
+                    id,
+                    PendingRequest {
+                        start,
+                        deadline: start + self.options.timeout,
+                        sender: ResponseSender::Stream {
+                            chunks: response,
+                            window: self.options.stream_window,
+                            started: false,
+                        },
+                        message: msg,
+                        peer: None,
+                        substream: None,
+                    },
+                );
+            }
+            Command::Credit { id, credit } => {
+                let Some(pending) = self.pending_requests.get(&id) else {
+                    return;
+                };
+
+                let msg = reqrep::Message::credit(id, credit);
+                if let Some(ref substream) = pending.substream {
+                    let _ = substream.send(msg);
+                } else if let Some(peer) = pending
+                    .peer
+                    .as_ref()
+                    .and_then(|addr| self.peers.iter_mut().find(|p| &p.addr == addr))
+                {
+                    peer.egress_queue.push_back(msg);
+                }
+            }
+            Command::Cancel { id } => {
+                let Some(pending) = self.pending_requests.remove(&id) else {
+                    return;
+                };
+
+                self.release(pending.peer.as_ref());
+
+                // Let the endpoint know, so that it can stop working on the request
+                if let Some(substream) = pending.substream {
+                    debug!(id, addr = ?pending.peer, "Cancelling request");
+                    let _ = substream.send(reqrep::Message::cancel(id));
+                } else if let Some(peer) =
+                    pending.peer.and_then(|addr| self.peers.iter_mut().find(|p| p.addr == addr))
+                {
+                    debug!(id, addr = ?peer.addr, "Cancelling request");
+                    peer.egress_queue.push_back(reqrep::Message::cancel(id));
+                }
+            }
+            Command::Connect { endpoint } => {
+                if self.peers.iter().any(|peer| peer.addr == endpoint) {
+                    debug!(?endpoint, "Endpoint already known, ignoring connect command");
+                    return;
+                }
+
+                self.peers.push(Peer::new(endpoint));
+            }
+        }
+    }
+
+    /// Assigns the unassigned requests to the active endpoints, according to the load balancing
+    /// policy. Returns `true` if any request was assigned.
+    fn assign_requests(&mut self) -> bool {
+        let mut assigned = false;
 
-                        debug!(
-                            "Compressed message from {} to {} bytes",
-                            len_before,
-                            message.payload().len()
-                        );
+        // Only active endpoints are considered
+        let active: Vec<usize> =
+            (0..self.peers.len()).filter(|&idx| self.peers[idx].conn_state.is_active()).collect();
+
+        if active.is_empty() {
+            return false;
+        }
+
+        let mut outstanding: Vec<usize> =
+            active.iter().map(|&idx| self.peers[idx].outstanding).collect();
+
+        while let Some(id) = self.unassigned.pop_front() {
+            // The request may have timed out in the meantime
+            let Some(pending) = self.pending_requests.get_mut(&id) else {
+                continue;
+            };
+
+            let mut choice = self.load_balancer.select(&outstanding);
+            if choice >= active.len() {
+                warn!(choice, n = active.len(), "Load balancer selected an invalid endpoint");
+                choice %= active.len();
+            }
+
+            let peer = &mut self.peers[active[choice]];
+
+            // Propagate the remaining time until the deadline (at least 1ms, as 0 means none)
+            let timeout_ms = pending
+                .deadline
+                .saturating_duration_since(Instant::now())
+                .as_millis()
+                .clamp(1, u32::MAX as u128) as u32;
+
+            let mut messages = vec![pending.message.clone().with_timeout_ms(timeout_ms)];
+
+            // Streaming responses need credit to be sent
+            if let ResponseSender::Stream { window, .. } = pending.sender {
+                messages.push(reqrep::Message::credit(id, window));
+            }
+
+            match (self.open_stream, &peer.conn_state) {
+                // Send the request on its own stream
+                (Some(open_stream), ConnectionState::Active { channel }) => {
+                    let (to_substream, from_driver) = mpsc::unbounded_channel();
+                    for msg in messages {
+                        let _ = to_substream.send(msg);
                     }
+
+                    tokio::spawn(Substream::new(
+                        id,
+                        peer.addr.clone(),
+                        open_stream(channel.get_ref()),
+                        from_driver,
+                        self.substream_events.clone(),
+                        Arc::clone(&self.socket_state),
+                    ));
+
+                    pending.substream = Some(to_substream);
                 }
+                _ => peer.egress_queue.extend(messages),
+            }
+
+            peer.outstanding += 1;
+            outstanding[choice] += 1;
+            pending.peer = Some(peer.addr.clone());
+
+            trace!(id, addr = ?peer.addr, "Assigned request");
+            assigned = true;
+        }
+
+        assigned
+    }
+
+    /// Decrements the outstanding requests counter of the given endpoint.
+    fn release(&mut self, addr: Option<&A>) {
+        if let Some(peer) = addr.and_then(|addr| self.peers.iter_mut().find(|p| &p.addr == addr)) {
+            peer.outstanding = peer.outstanding.saturating_sub(1);
+        }
+    }
 
-                let msg = message.into_wire(self.id_counter);
-                let msg_id = msg.id();
-                self.id_counter = self.id_counter.wrapping_add(1);
-                self.egress_queue.push_back(msg);
-                self.pending_requests.insert(msg_id, PendingRequest { start, sender: response });
+    /// Puts the requests assigned to the given endpoint back in the unassigned queue, in the order
+    /// they were originally sent, so that they are sent to a healthy endpoint. `unsent` contains
+    /// the requests that never left the egress queue. The requests that were sent fail with
+    /// [`ReqError::Disconnected`], unless the [`RetryPolicy`] allows them to be retried. Streaming
+    /// responses that already started can't be retried, and always fail.
+    fn reassign_requests(&mut self, addr: &A, unsent: &[u32]) {
+        let retry = self.options.retry_policy == RetryPolicy::Retry;
+        let broken = self
+            .pending_requests
+            .iter()
+            .filter(|(id, req)| {
+                req.peer.as_ref() == Some(addr) &&
+                    !unsent.contains(id) &&
+                    (!retry || matches!(req.sender, ResponseSender::Stream { started: true, .. }))
+            })
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+
+        if !broken.is_empty() {
+            debug!(?addr, n = broken.len(), "Failing in-flight requests of dropped connection");
+        }
+
+        for id in broken {
+            if let Some(pending) = self.pending_requests.remove(&id) {
+                pending.sender.send(Err(ReqError::Disconnected));
+            }
+        }
+
+        let mut ids: Vec<(Instant, u32)> = self
+            .pending_requests
+            .iter_mut()
+            .filter(|(_, req)| req.peer.as_ref() == Some(addr))
+            .map(|(&id, req)| {
+                req.peer = None;
+                req.substream = None;
+                (req.start, id)
+            })
+            .collect();
+
+        if ids.is_empty() {
+            return;
+        }
+
+        debug!(?addr, n = ids.len(), "Sending requests of dropped connection to another endpoint");
+
+        ids.sort_unstable();
+        for (_, id) in ids.into_iter().rev() {
+            self.unassigned.push_front(id);
+        }
+    }
+
+    /// Handles an event of the [`Substream`] of a request.
+    fn on_substream_event(&mut self, event: SubstreamEvent<A>) {
+        match event {
+            SubstreamEvent::Message { addr, msg } => self.on_message(&addr, msg),
+            SubstreamEvent::Closed { id } => {
+                // The substream of a request that is done, or that was sent again, is dropped by
+                // the driver. If the substream stopped by itself, the request can't complete.
+                let failed = self
+                    .pending_requests
+                    .get(&id)
+                    .and_then(|pending| pending.substream.as_ref())
+                    .is_some_and(|substream| substream.is_closed());
+
+                if !failed {
+                    return;
+                }
+
+                if let Some(pending) = self.pending_requests.remove(&id) {
+                    debug!(id, addr = ?pending.peer, "Request stream closed before the response");
+                    self.release(pending.peer.as_ref());
+                    pending.sender.send(Err(ReqError::Disconnected));
+                }
             }
         }
     }
@@ -198,25 +527,20 @@ where
         let timed_out_ids = self
             .pending_requests
             .iter()
-            .filter_map(|(&id, request)| {
-                if now.duration_since(request.start) > self.options.timeout {
-                    Some(id)
-                } else {
-                    None
-                }
-            })
+            .filter_map(|(&id, request)| if now > request.deadline { Some(id) } else { None })
             .collect::<Vec<_>>();
 
         for id in timed_out_ids {
             if let Some(pending_request) = self.pending_requests.remove(&id) {
-                let _ = pending_request.sender.send(Err(ReqError::Timeout));
+                self.release(pending_request.peer.as_ref());
+                pending_request.sender.send(Err(ReqError::Timeout));
             }
         }
     }
 
     #[inline]
     fn should_flush(&mut self, cx: &mut Context<'_>) -> bool {
-        if self.should_flush {
+        if self.peers.iter().any(|peer| peer.should_flush) {
             if let Some(interval) = self.flush_interval.as_mut() {
                 interval.poll_tick(cx).is_ready()
             } else {
@@ -233,130 +557,211 @@ where
         }
     }
 
+    /// Sets the connection of the peer at the given index to inactive, so that it will be
+    /// re-tried, and reassigns or fails its requests (see [`Self::reassign_requests`]).
     #[inline]
-    fn reset_connection(&mut self) {
-        self.conn_state = ConnectionState::Inactive {
-            addr: self.addr.clone(),
+    fn reset_connection(&mut self, idx: usize) {
+        let peer = &mut self.peers[idx];
+        peer.conn_state = ConnectionState::Inactive {
+            addr: peer.addr.clone(),
             backoff: ExponentialBackoff::new(Duration::from_millis(20), 16),
         };
-    }
-}
 
-impl<T, A> Future for ReqDriver<T, A>
-where
-    T: Transport<A> + Unpin + Send + Sync + 'static,
-    A: Address,
-{
-    type Output = ();
-
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.get_mut();
+        // Requests that are still queued never reached the endpoint
+        let unsent = peer
+            .egress_queue
+            .drain(..)
+            .filter(|msg| msg.kind() == reqrep::Kind::Data)
+            .map(|msg| msg.id())
+            .collect::<Vec<_>>();
+        peer.should_flush = false;
+        peer.outstanding = 0;
 
-        loop {
-            // Try to flush pending messages
-            if this.should_flush(cx) {
-                if let ConnectionState::Active { ref mut channel } = this.conn_state {
-                    if let Poll::Ready(Ok(_)) = channel.poll_flush_unpin(cx) {
-                        this.should_flush = false;
-                    }
-                }
-            }
+        let addr = peer.addr.clone();
+        self.reassign_requests(&addr, &unsent);
+    }
 
+    /// Polls all the peers: drives connection tasks and backoffs, reads incoming responses and
+    /// drains the egress queues.
+    ///
+    /// Returns `Poll::Ready` if any progress was made and this method should be called again.
+    /// Returns `Poll::Pending` if no progress was made.
+    fn poll_peers(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut progress = false;
+
+        // These should be fine as Vec::new() does not allocate
+        let mut responses = Vec::new();
+        let mut to_connect = Vec::new();
+        let mut to_reset = Vec::new();
+        let mut to_terminate = Vec::new();
+
+        for (idx, peer) in self.peers.iter_mut().enumerate() {
             // Poll the active connection task, if any
-            if let Some(ref mut conn_task) = this.conn_task {
+            if let Some(ref mut conn_task) = peer.conn_task {
                 if let Poll::Ready(result) = conn_task.poll_unpin(cx) {
                     // As soon as the connection task finishes, set it to `None`.
                     // - If it was successful, set the connection to active
                     // - If it failed, it will be re-tried until the backoff limit is reached.
-                    this.conn_task = None;
+                    peer.conn_task = None;
+                    progress = true;
 
                     if let Ok(io) = result {
                         let mut framed = Framed::new(io, reqrep::Codec::new());
-                        framed.set_backpressure_boundary(this.options.backpressure_boundary);
-                        this.conn_state = ConnectionState::Active { channel: framed };
+                        framed.set_backpressure_boundary(self.options.backpressure_boundary);
+                        peer.conn_state = ConnectionState::Active { channel: framed };
                     }
                 }
             }
 
-            // If the connection is inactive, try to connect to the server
-            // or poll the backoff timer if we're already trying to connect.
-            if let ConnectionState::Inactive { ref mut backoff, ref addr } = this.conn_state {
-                if let Poll::Ready(item) = backoff.poll_next_unpin(cx) {
-                    if let Some(duration) = item {
-                        if this.conn_task.is_none() {
-                            debug!(backoff = ?duration, "Retrying connection to {:?}", addr);
-                            this.try_connect(addr.clone());
+            match peer.conn_state {
+                // If the connection is inactive, try to connect to the server
+                // or poll the backoff timer if we're already trying to connect.
+                ConnectionState::Inactive { ref mut backoff, ref addr } => {
+                    if let Poll::Ready(item) = backoff.poll_next_unpin(cx) {
+                        if let Some(duration) = item {
+                            if peer.conn_task.is_none() {
+                                debug!(backoff = ?duration, "Retrying connection to {:?}", addr);
+                                to_connect.push(idx);
+                            } else {
+                                debug!(backoff = ?duration, "Not retrying connection to {:?} as there is already a connection task", addr);
+                            }
                         } else {
-                            debug!(backoff = ?duration, "Not retrying connection to {:?} as there is already a connection task", addr);
+                            error!(
+                                "Exceeded maximum number of retries for {:?}, terminating connection",
+                                addr
+                            );
+
+                            to_terminate.push(idx);
                         }
-                    } else {
-                        error!(
-                            "Exceeded maximum number of retries for {:?}, terminating connection",
-                            addr
-                        );
 
-                        return Poll::Ready(());
+                        progress = true;
                     }
                 }
+                ConnectionState::Active { ref mut channel } => {
+                    // Check for incoming messages from the socket
+                    loop {
+                        match channel.poll_next_unpin(cx) {
+                            Poll::Ready(Some(Ok(msg))) => {
+                                responses.push((peer.addr.clone(), msg));
+                                progress = true;
+                            }
+                            Poll::Ready(Some(Err(err))) => {
+                                if let reqrep::Error::Io(e) = err {
+                                    error!(err = ?e, addr = ?peer.addr, "Socket error");
+                                }
+
+                                to_reset.push(idx);
+                                progress = true;
+                                break;
+                            }
+                            Poll::Ready(None) => {
+                                debug!("Connection to {:?} closed, reconnecting", peer.addr);
+
+                                to_reset.push(idx);
+                                progress = true;
+                                break;
+                            }
+                            Poll::Pending => break,
+                        }
+                    }
+
+                    if to_reset.last() == Some(&idx) {
+                        continue;
+                    }
 
-                return Poll::Pending;
+                    // Drain the egress queue
+                    while !peer.egress_queue.is_empty() && channel.poll_ready_unpin(cx).is_ready() {
+                        let msg = peer.egress_queue.pop_front().expect("non-empty egress queue");
+                        let size = msg.size();
+                        debug!("Sending msg {} to {:?}", msg.id(), peer.addr);
+                        match channel.start_send_unpin(msg) {
+                            Ok(_) => {
+                                self.socket_state.stats.increment_tx(size);
+                                peer.should_flush = true;
+                                progress = true;
+                            }
+                            Err(e) => {
+                                error!(err = ?e, "Failed to send message to socket");
+
+                                // set the connection to inactive, so that it will be re-tried
+                                to_reset.push(idx);
+                                progress = true;
+                                break;
+                            }
+                        }
+                    }
+                }
             }
+        }
 
-            // If there is no active connection, continue polling the backoff
-            let ConnectionState::Active { ref mut channel } = this.conn_state else {
-                continue;
-            };
+        for (addr, msg) in responses {
+            self.on_message(&addr, msg);
+        }
 
-            // Check for incoming messages from the socket
-            match channel.poll_next_unpin(cx) {
-                Poll::Ready(Some(Ok(msg))) => {
-                    this.on_message(msg);
+        for idx in to_connect {
+            self.try_connect(idx);
+        }
 
-                    continue;
-                }
-                Poll::Ready(Some(Err(err))) => {
-                    if let reqrep::Error::Io(e) = err {
-                        error!(err = ?e, "Socket error");
-                        if e.kind() == std::io::ErrorKind::Other {
-                            error!(err = ?e, "Other error");
-                        }
-                    }
+        for idx in to_reset {
+            self.reset_connection(idx);
+        }
 
-                    // set the connection to inactive, so that it will be re-tried
-                    this.reset_connection();
+        // Remove unreachable peers in reverse order, so that the indices stay valid.
+        for idx in to_terminate.into_iter().rev() {
+            let peer = self.peers.remove(idx);
+            self.reassign_requests(&peer.addr, &[]);
+        }
 
-                    continue;
-                }
-                Poll::Ready(None) => {
-                    debug!("Connection to {:?} closed, shutting down driver", this.addr);
+        if progress {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
 
-                    return Poll::Ready(());
-                }
-                Poll::Pending => {}
-            }
+impl<T, A> Future for ReqDriver<T, A>
+where
+    T: Transport<A> + Unpin + Send + Sync + 'static,
+    A: Address,
+{
+    type Output = ();
 
-            // Check for outgoing messages to the socket
-            if channel.poll_ready_unpin(cx).is_ready() {
-                // Drain the egress queue
-                if let Some(msg) = this.egress_queue.pop_front() {
-                    // Generate the new message
-                    let size = msg.size();
-                    debug!("Sending msg {}", msg.id());
-                    match channel.start_send_unpin(msg) {
-                        Ok(_) => {
-                            this.socket_state.stats.increment_tx(size);
-                            this.should_flush = true;
-                        }
-                        Err(e) => {
-                            error!(err = ?e, "Failed to send message to socket");
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
 
-                            // set the connection to inactive, so that it will be re-tried
-                            this.reset_connection();
+        loop {
+            // Try to flush pending messages
+            if this.should_flush(cx) {
+                for peer in this.peers.iter_mut().filter(|peer| peer.should_flush) {
+                    if let ConnectionState::Active { ref mut channel } = peer.conn_state {
+                        if let Poll::Ready(Ok(_)) = channel.poll_flush_unpin(cx) {
+                            peer.should_flush = false;
                         }
                     }
+                }
+            }
 
-                    continue;
+            // Poll all the endpoints for connection updates, responses and outgoing messages
+            let had_peers = !this.peers.is_empty();
+            if this.poll_peers(cx).is_ready() {
+                if had_peers && this.peers.is_empty() {
+                    error!("All endpoints are unreachable, shutting down driver");
+                    return Poll::Ready(());
                 }
+
+                continue;
+            }
+
+            // Assign pending requests to the active endpoints
+            if this.assign_requests() {
+                continue;
+            }
+
+            // Handle the responses to requests that are sent on their own stream
+            if let Poll::Ready(Some(event)) = this.from_substreams.poll_recv(cx) {
+                this.on_substream_event(event);
+                continue;
             }
 
             // Check for request timeouts
@@ -372,11 +777,18 @@ where
                     continue;
                 }
                 Poll::Ready(None) => {
-                    debug!("Socket dropped, shutting down backend and flushing connection");
+                    debug!("Socket dropped, shutting down backend and flushing connections");
+
+                    let mut closing = false;
+                    for peer in this.peers.iter_mut() {
+                        if let ConnectionState::Active { ref mut channel } = peer.conn_state {
+                            closing |= channel.poll_close_unpin(cx).is_pending();
+                        }
+                    }
 
-                    if let ConnectionState::Active { ref mut channel } = this.conn_state {
-                        let _ = ready!(channel.poll_close_unpin(cx));
-                    };
+                    if closing {
+                        return Poll::Pending;
+                    }
 
                     return Poll::Ready(());
                 }
@@ -1,20 +1,30 @@
 use bytes::Bytes;
 use std::time::Duration;
 use thiserror::Error;
-use tokio::sync::oneshot;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
 
+use msg_transport::Address;
 use msg_wire::{
     compression::{CompressionType, Compressor},
     reqrep,
 };
 
+mod balancer;
+pub use balancer::*;
 mod driver;
 mod socket;
 mod stats;
+mod stream;
+mod substream;
 use driver::*;
 pub use socket::*;
+pub use stream::*;
 
 use self::stats::SocketStats;
+use crate::Credentials;
 
 const DEFAULT_BUFFER_SIZE: usize = 1024;
 
@@ -32,15 +42,51 @@ pub enum ReqError {
     Transport(#[from] Box<dyn std::error::Error + Send + Sync>),
     #[error("Request timed out")]
     Timeout,
+    #[error("Remote error {code}: {message}")]
+    Remote { code: u16, message: String },
+    #[error("Connection lost before the response was received")]
+    Disconnected,
+}
+
+/// What happens to the requests that were sent on a connection that dropped before their response
+/// was received. Requests that were still queued, and never sent, are always sent to another
+/// endpoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetryPolicy {
+    /// The requests fail with [`ReqError::Disconnected`]. This is the default, as the endpoint
+    /// may have processed a request before the connection dropped.
+    #[default]
+    Fail,
+    /// The requests are sent again to another endpoint (or to the same one once it reconnects).
+    /// Only use this if the requests are idempotent.
+    Retry,
 }
 
-pub enum Command {
-    Send { message: ReqMessage, response: oneshot::Sender<Result<Bytes, ReqError>> },
+pub enum Command<A: Address> {
+    /// Send a request to one of the connected endpoints.
+    Send {
+        id: u32,
+        message: ReqMessage,
+        deadline: Instant,
+        response: oneshot::Sender<Result<Bytes, ReqError>>,
+    },
+    /// Send a request that expects a streaming response.
+    SendStream {
+        id: u32,
+        message: ReqMessage,
+        response: mpsc::UnboundedSender<Result<Bytes, ReqError>>,
+    },
+    /// Allow the endpoint to send more chunks of a streaming response.
+    Credit { id: u32, credit: u32 },
+    /// Cancel the request with the given ID.
+    Cancel { id: u32 },
+    /// Connect to a new endpoint.
+    Connect { endpoint: A },
 }
 
 #[derive(Debug, Clone)]
 pub struct ReqOptions {
-    auth_token: Option<Bytes>,
+    auth: Option<Credentials>,
     /// Timeout duration for requests.
     timeout: std::time::Duration,
     /// Wether to block on initial connection to the target.
@@ -58,12 +104,24 @@ pub struct ReqOptions {
     /// Minimum payload size in bytes for compression to be used. If the payload is smaller than
     /// this threshold, it will not be compressed.
     min_compress_size: usize,
+    /// The number of chunks of a streaming response that can be received ahead of the consumer.
+    stream_window: u32,
+    /// What happens to in-flight requests when their connection drops.
+    retry_policy: RetryPolicy,
 }
 
 impl ReqOptions {
     /// Sets the authentication token for the socket.
     pub fn auth_token(mut self, auth_token: Bytes) -> Self {
-        self.auth_token = Some(auth_token);
+        self.auth = Some(Credentials::Token(auth_token));
+        self
+    }
+
+    /// Sets the ID and the secret this socket authenticates with. Instead of sending the secret,
+    /// the socket proves that it knows it by responding to a challenge from the reply socket, which
+    /// must have the same secret (see [`SharedSecrets`](crate::SharedSecrets)).
+    pub fn auth_secret(mut self, id: Bytes, secret: Bytes) -> Self {
+        self.auth = Some(Credentials::Secret { id, secret });
         self
     }
 
@@ -115,12 +173,27 @@ impl ReqOptions {
         self.min_compress_size = min_compress_size;
         self
     }
+
+    /// Sets the number of chunks of a streaming response that can be received ahead of the
+    /// consumer. The reply socket stops sending chunks when this limit is reached, until the
+    /// consumer catches up. Must be at least 1.
+    pub fn stream_window(mut self, stream_window: u32) -> Self {
+        self.stream_window = stream_window.max(1);
+        self
+    }
+
+    /// Sets what happens to the requests that were in-flight on a connection that dropped. By
+    /// default, they fail with [`ReqError::Disconnected`]. See [`RetryPolicy`].
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
 }
 
 impl Default for ReqOptions {
     fn default() -> Self {
         Self {
-            auth_token: None,
+            auth: None,
             timeout: std::time::Duration::from_secs(5),
             blocking_connect: true,
             backoff_duration: Duration::from_millis(200),
@@ -128,10 +201,34 @@ impl Default for ReqOptions {
             backpressure_boundary: 8192,
             retry_attempts: None,
             min_compress_size: 8192,
+            stream_window: 32,
+            retry_policy: RetryPolicy::Fail,
         }
     }
 }
 
+/// Options for a single request, see [`ReqSocket::request_with`].
+#[derive(Debug, Clone, Default)]
+pub struct RequestOptions {
+    /// The deadline of the request. Defaults to now + [`ReqOptions::timeout`].
+    deadline: Option<Instant>,
+}
+
+impl RequestOptions {
+    /// Sets the timeout for this request, overriding the socket timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    /// Sets the deadline for this request, overriding the socket timeout. The remaining time is
+    /// propagated to the reply socket, see [`Request::deadline`](crate::Request::deadline).
+    pub fn deadline(mut self, deadline: std::time::Instant) -> Self {
+        self.deadline = Some(Instant::from_std(deadline));
+        self
+    }
+}
+
 /// A message sent from a [`ReqSocket`] to the backend task.
 #[derive(Debug, Clone)]
 pub struct ReqMessage {
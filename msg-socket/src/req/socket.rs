@@ -1,34 +1,57 @@
 use bytes::Bytes;
 use rustc_hash::FxHashMap;
-use std::{io, marker::PhantomData, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    io,
+    marker::PhantomData,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
 use tokio::{
     net::{lookup_host, ToSocketAddrs},
     sync::{mpsc, oneshot},
+    time::Instant,
 };
 
-use msg_transport::{Address, Transport};
+use msg_transport::{inproc::InprocAddr, Address, MultiplexedTransport, Transport};
 use msg_wire::compression::Compressor;
 
-use super::{Command, ReqDriver, ReqError, ReqOptions, DEFAULT_BUFFER_SIZE};
+use super::{
+    Command, LoadBalancer, ReqDriver, ReqError, ReqOptions, RequestOptions, ResponseStream,
+    RoundRobin, DEFAULT_BUFFER_SIZE,
+};
 use crate::{
+    open_stream_of,
     req::{stats::SocketStats, SocketState},
-    ConnectionState, ExponentialBackoff, ReqMessage,
+    ReqMessage, StreamFn,
 };
 
-/// The request socket.
+/// The request socket. It can be connected to multiple endpoints, in which case every request is
+/// sent to one of them according to the configured [`LoadBalancer`].
 pub struct ReqSocket<T: Transport<A>, A: Address> {
     /// Command channel to the backend task.
-    to_driver: Option<mpsc::Sender<Command>>,
+    to_driver: Option<mpsc::Sender<Command<A>>>,
     /// The socket transport.
     transport: Option<T>,
     /// Options for the socket. These are shared with the backend task.
     options: Arc<ReqOptions>,
     /// Socket state. This is shared with the backend task.
     state: Arc<SocketState>,
+    /// ID counter for outgoing requests.
+    id_counter: AtomicU32,
     /// Optional message compressor. This is shared with the backend task.
     // NOTE: for now we're using dynamic dispatch, since using generics here
     // complicates the API a lot. We can always change this later for perf reasons.
     compressor: Option<Arc<dyn Compressor>>,
+    /// The load balancing policy. This value is temporary and will be moved to the backend task
+    /// once the socket is connected.
+    load_balancer: Option<Box<dyn LoadBalancer>>,
+    /// Opens a stream for every request, if enabled. Moved to the backend task once the socket is
+    /// connected.
+    open_stream: Option<StreamFn<T::Io, T::Error>>,
     /// Marker for the address type.
     _marker: PhantomData<A>,
 }
@@ -58,6 +81,37 @@ where
     }
 }
 
+impl<T> ReqSocket<T, InprocAddr>
+where
+    T: Transport<InprocAddr> + Send + Sync + Unpin + 'static,
+{
+    /// Connects to the target in-process endpoint with the default options.
+    pub async fn connect(&mut self, name: impl Into<InprocAddr>) -> Result<(), ReqError> {
+        self.try_connect(name.into()).await
+    }
+}
+
+impl<T, A> ReqSocket<T, A>
+where
+    T: MultiplexedTransport<A> + Send + Sync + Unpin + 'static,
+    T::OpenStream: 'static,
+    A: Address,
+{
+    /// Sends every request on its own stream of the connection to the endpoint, instead of on a
+    /// single stream that is shared by all requests. This way, a request that is stalled, e.g.
+    /// because it's large or its packets are lost, doesn't hold up the requests sent after it.
+    /// The reply socket must accept the streams with
+    /// [`RepSocket::with_request_streams`](crate::RepSocket::with_request_streams).
+    ///
+    /// # Panics
+    /// If the socket is connected already.
+    pub fn with_stream_per_request(mut self) -> Self {
+        assert!(self.to_driver.is_none(), "Socket is connected already");
+        self.open_stream = Some(open_stream_of::<T, A>);
+        self
+    }
+}
+
 impl<T, A> ReqSocket<T, A>
 where
     T: Transport<A> + Send + Sync + Unpin + 'static,
@@ -73,7 +127,10 @@ where
             transport: Some(transport),
             options: Arc::new(options),
             state: Arc::new(SocketState::default()),
+            id_counter: AtomicU32::new(0),
             compressor: None,
+            load_balancer: None,
+            open_stream: None,
             _marker: PhantomData,
         }
     }
@@ -84,40 +141,89 @@ where
         self
     }
 
+    /// Sets the load balancing policy used to pick an endpoint for every request. Defaults to
+    /// [`RoundRobin`].
+    pub fn with_load_balancer<L: LoadBalancer>(mut self, load_balancer: L) -> Self {
+        self.load_balancer = Some(Box::new(load_balancer));
+        self
+    }
+
     pub fn stats(&self) -> &SocketStats {
         &self.state.stats
     }
 
+    /// Sends a request and waits for the response, using the socket timeout.
     pub async fn request(&self, message: Bytes) -> Result<Bytes, ReqError> {
-        let (response_tx, response_rx) = oneshot::channel();
+        self.request_with(message, RequestOptions::default()).await
+    }
 
-        let msg = ReqMessage::new(message);
+    /// Sends a request with the given options and waits for the response. The remaining time
+    /// until the deadline is sent along with the request. If the deadline passes, or if the
+    /// returned future is dropped before the response arrives, the request is cancelled on the
+    /// reply socket.
+    pub async fn request_with(
+        &self,
+        message: Bytes,
+        options: RequestOptions,
+    ) -> Result<Bytes, ReqError> {
+        let to_driver = self.to_driver.as_ref().ok_or(ReqError::SocketClosed)?;
 
-        self.to_driver
-            .as_ref()
-            .ok_or(ReqError::SocketClosed)?
-            .send(Command::Send { message: msg, response: response_tx })
+        let deadline = options.deadline.unwrap_or_else(|| Instant::now() + self.options.timeout);
+        let id = self.id_counter.fetch_add(1, Ordering::Relaxed);
+
+        send_request(to_driver, id, message, deadline).await
+    }
+
+    /// Sends a request that expects a streaming response from
+    /// [`Request::respond_stream`](crate::Request::respond_stream), and returns the stream of
+    /// response chunks. The socket timeout applies to the time between chunks, rather than to
+    /// the whole response.
+    pub async fn request_stream(&self, message: Bytes) -> Result<ResponseStream<A>, ReqError> {
+        let to_driver = self.to_driver.as_ref().ok_or(ReqError::SocketClosed)?;
+
+        let id = self.id_counter.fetch_add(1, Ordering::Relaxed);
+        let (chunks_tx, chunks_rx) = mpsc::unbounded_channel();
+
+        to_driver
+            .send(Command::SendStream {
+                id,
+                message: ReqMessage::new(message),
+                response: chunks_tx,
+            })
             .await
             .map_err(|_| ReqError::SocketClosed)?;
 
-        response_rx.await.map_err(|_| ReqError::SocketClosed)?
+        Ok(ResponseStream::new(id, self.options.stream_window, chunks_rx, to_driver.clone()))
     }
 
     /// Tries to connect to the target endpoint with the default options.
-    /// A ReqSocket can only be connected to a single address.
+    /// A ReqSocket can be connected to multiple endpoints: every call adds a new endpoint.
+    /// Endpoints that become unreachable are removed. Their queued requests are sent to the
+    /// remaining ones, but the requests that were already sent fail with
+    /// [`ReqError::Disconnected`], unless retries are enabled with
+    /// [`RetryPolicy::Retry`](super::RetryPolicy::Retry).
     pub async fn try_connect(&mut self, endpoint: A) -> Result<(), ReqError> {
+        if self.to_driver.is_none() {
+            self.spawn_driver();
+        }
+
+        // The connection is initialized as inactive, and will be activated by the backend task.
+        self.to_driver
+            .as_ref()
+            .ok_or(ReqError::SocketClosed)?
+            .send(Command::Connect { endpoint })
+            .await
+            .map_err(|_| ReqError::SocketClosed)
+    }
+
+    /// Spawns the backend task, without any endpoints.
+    fn spawn_driver(&mut self) {
         // Initialize communication channels
         let (to_driver, from_socket) = mpsc::channel(DEFAULT_BUFFER_SIZE);
+        let (substream_events, from_substreams) = mpsc::unbounded_channel();
 
         let transport = self.transport.take().expect("Transport has been moved already");
 
-        // We initialize the connection as inactive, and let it be activated
-        // by the backend task as soon as the driver is spawned.
-        let conn_state = ConnectionState::Inactive {
-            addr: endpoint.clone(),
-            backoff: ExponentialBackoff::new(Duration::from_millis(20), 16),
-        };
-
         let timeout_check_interval = tokio::time::interval(self.options.timeout / 10);
 
         let flush_interval = self.options.flush_interval.map(tokio::time::interval);
@@ -128,27 +234,113 @@ where
 
         // Create the socket backend
         let driver: ReqDriver<T, A> = ReqDriver {
-            addr: endpoint,
             options: Arc::clone(&self.options),
             socket_state: Arc::clone(&self.state),
-            id_counter: 0,
             from_socket,
             transport,
-            conn_state,
+            peers: Vec::new(),
+            load_balancer: self
+                .load_balancer
+                .take()
+                .unwrap_or_else(|| Box::new(RoundRobin::default())),
+            unassigned: Default::default(),
             pending_requests,
             timeout_check_interval,
             flush_interval,
-            should_flush: false,
-            conn_task: None,
-            egress_queue: Default::default(),
             compressor: self.compressor.clone(),
+            open_stream: self.open_stream.take(),
+            substream_events,
+            from_substreams,
         };
 
         // Spawn the backend task
         tokio::spawn(driver);
 
         self.to_driver = Some(to_driver);
+    }
+}
+
+/// Sends the request with the given ID to the driver, and waits for the response until the
+/// deadline.
+async fn send_request<A: Address>(
+    to_driver: &mpsc::Sender<Command<A>>,
+    id: u32,
+    message: Bytes,
+    deadline: Instant,
+) -> Result<Bytes, ReqError> {
+    let (response_tx, response_rx) = oneshot::channel();
+
+    to_driver
+        .send(Command::Send {
+            id,
+            message: ReqMessage::new(message),
+            deadline,
+            response: response_tx,
+        })
+        .await
+        .map_err(|_| ReqError::SocketClosed)?;
+
+    // From here on, the request is cancelled if this future is dropped or times out.
+    let mut guard = CancelOnDrop { id, to_driver: Some(to_driver) };
+
+    let response = tokio::time::timeout_at(deadline, response_rx)
+        .await
+        .map_err(|_| ReqError::Timeout)?;
+
+    // The driver is done with the request, no need to cancel it
+    guard.to_driver = None;
+
+    response.map_err(|_| ReqError::SocketClosed)?
+}
 
-        Ok(())
+#[cfg(feature = "tower")]
+impl<T, A> tower::Service<Bytes> for ReqSocket<T, A>
+where
+    T: Transport<A> + Send + Sync + Unpin + 'static,
+    A: Address,
+{
+    type Response = Bytes;
+    type Error = ReqError;
+    type Future = std::pin::Pin<
+        Box<dyn std::future::Future<Output = Result<Bytes, ReqError>> + Send + 'static>,
+    >;
+
+    /// The socket is always ready as long as it's connected. Backpressure from the driver is
+    /// applied when the returned future is polled.
+    fn poll_ready(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        match self.to_driver {
+            Some(ref to_driver) if !to_driver.is_closed() => std::task::Poll::Ready(Ok(())),
+            _ => std::task::Poll::Ready(Err(ReqError::SocketClosed)),
+        }
+    }
+
+    /// Sends a request with the socket timeout, like [`ReqSocket::request`].
+    fn call(&mut self, message: Bytes) -> Self::Future {
+        let to_driver = self.to_driver.clone();
+        let deadline = Instant::now() + self.options.timeout;
+        let id = self.id_counter.fetch_add(1, Ordering::Relaxed);
+
+        Box::pin(async move {
+            let to_driver = to_driver.ok_or(ReqError::SocketClosed)?;
+            send_request(&to_driver, id, message, deadline).await
+        })
+    }
+}
+
+/// Cancels an in-flight request when dropped, unless disarmed by taking `to_driver`.
+struct CancelOnDrop<'a, A: Address> {
+    id: u32,
+    to_driver: Option<&'a mpsc::Sender<Command<A>>>,
+}
+
+impl<A: Address> Drop for CancelOnDrop<'_, A> {
+    fn drop(&mut self) {
+        if let Some(to_driver) = self.to_driver.take() {
+            // If the channel is full, the request will still be cleaned up after its deadline
+            let _ = to_driver.try_send(Command::Cancel { id: self.id });
+        }
     }
 }
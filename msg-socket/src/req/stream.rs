@@ -0,0 +1,103 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::Stream;
+use tokio::sync::mpsc;
+use tokio_util::sync::PollSender;
+use tracing::trace;
+
+use super::{Command, ReqError};
+use msg_transport::Address;
+
+/// A streaming response to a request, returned by
+/// [`ReqSocket::request_stream`](crate::ReqSocket::request_stream). Yields the chunks of the
+/// response until the reply socket ends the stream, or an error occurs.
+///
+/// The reply socket only sends as many chunks as this stream has room for, and more room is made
+/// as the chunks are consumed. Dropping this stream before it ends cancels the request.
+pub struct ResponseStream<A: Address> {
+    /// The ID of the request.
+    id: u32,
+    /// The chunks of the response, from the driver.
+    from_driver: mpsc::UnboundedReceiver<Result<Bytes, ReqError>>,
+    /// Channel to the driver, used to send credit and the cancellation.
+    to_driver: PollSender<Command<A>>,
+    /// The number of chunks consumed since the last credit was sent.
+    consumed: u32,
+    /// The number of consumed chunks after which new credit is sent.
+    threshold: u32,
+    /// Whether the stream has ended.
+    done: bool,
+}
+
+impl<A: Address> ResponseStream<A> {
+    pub(crate) fn new(
+        id: u32,
+        window: u32,
+        from_driver: mpsc::UnboundedReceiver<Result<Bytes, ReqError>>,
+        to_driver: mpsc::Sender<Command<A>>,
+    ) -> Self {
+        Self {
+            id,
+            from_driver,
+            to_driver: PollSender::new(to_driver),
+            consumed: 0,
+            threshold: (window / 2).max(1),
+            done: false,
+        }
+    }
+}
+
+impl<A: Address> Stream for ResponseStream<A> {
+    type Item = Result<Bytes, ReqError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        // Give back credit for the consumed chunks. If the driver is busy, we'll be woken up
+        // once there's room.
+        if this.consumed >= this.threshold {
+            if let Poll::Ready(Ok(())) = this.to_driver.poll_reserve(cx) {
+                trace!(id = this.id, credit = this.consumed, "Sending credit");
+                let credit = Command::Credit { id: this.id, credit: this.consumed };
+                if this.to_driver.send_item(credit).is_ok() {
+                    this.consumed = 0;
+                }
+            }
+        }
+
+        match this.from_driver.poll_recv(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                this.consumed += 1;
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                this.done = true;
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(None) => {
+                this.done = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<A: Address> Drop for ResponseStream<A> {
+    fn drop(&mut self) {
+        if !self.done {
+            if let Some(to_driver) = self.to_driver.get_ref() {
+                // If the channel is full, the request will still be cleaned up after its deadline
+                let _ = to_driver.try_send(Command::Cancel { id: self.id });
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use futures::{future::BoxFuture, Future, FutureExt, SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+};
+use tokio_util::codec::Framed;
+use tracing::{debug, error};
+
+use msg_transport::Address;
+use msg_wire::reqrep;
+
+use super::SocketState;
+
+/// An event of a [`Substream`], sent to the driver.
+#[derive(Debug)]
+pub(crate) enum SubstreamEvent<A> {
+    /// A message received from the endpoint.
+    Message { addr: A, msg: reqrep::Message },
+    /// The substream of the request with the given ID stopped.
+    Closed { id: u32 },
+}
+
+/// A single request that is sent on its own stream of a multiplexed connection, see
+/// [`ReqSocket::with_stream_per_request`](super::ReqSocket::with_stream_per_request). Sends the
+/// messages of the request that it receives from the driver, and forwards the messages of the
+/// endpoint to the driver.
+///
+/// Runs until the driver drops the sender of the messages, which it does once the request is done,
+/// or until the stream fails. The driver is notified when it stops.
+pub(crate) struct Substream<Io, E, A> {
+    /// The ID of the request.
+    id: u32,
+    /// The address of the endpoint.
+    addr: A,
+    /// The stream while it's being opened.
+    opening: BoxFuture<'static, Result<Io, E>>,
+    /// The stream once it's open.
+    conn: Option<Framed<Io, reqrep::Codec>>,
+    /// The messages of the request to send, from the driver.
+    from_driver: mpsc::UnboundedReceiver<reqrep::Message>,
+    /// The events for the driver.
+    to_driver: mpsc::UnboundedSender<SubstreamEvent<A>>,
+    /// State shared with the socket.
+    state: Arc<SocketState>,
+    /// Whether or not the stream should be flushed.
+    should_flush: bool,
+}
+
+impl<Io, E, A> Substream<Io, E, A> {
+    pub(crate) fn new(
+        id: u32,
+        addr: A,
+        opening: BoxFuture<'static, Result<Io, E>>,
+        from_driver: mpsc::UnboundedReceiver<reqrep::Message>,
+        to_driver: mpsc::UnboundedSender<SubstreamEvent<A>>,
+        state: Arc<SocketState>,
+    ) -> Self {
+        Self { id, addr, opening, conn: None, from_driver, to_driver, state, should_flush: false }
+    }
+}
+
+impl<Io, E, A> Future for Substream<Io, E, A>
+where
+    Io: AsyncRead + AsyncWrite + Unpin,
+    E: std::error::Error,
+    A: Address,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        let conn = match this.conn {
+            Some(ref mut conn) => conn,
+            None => match ready!(this.opening.poll_unpin(cx)) {
+                Ok(io) => this.conn.insert(Framed::new(io, reqrep::Codec::new())),
+                Err(e) => {
+                    error!(err = ?e, id = this.id, addr = ?this.addr, "Failed to open stream");
+                    let _ = this.to_driver.send(SubstreamEvent::Closed { id: this.id });
+                    return Poll::Ready(());
+                }
+            },
+        };
+
+        loop {
+            if this.should_flush {
+                if let Poll::Ready(result) = conn.poll_flush_unpin(cx) {
+                    this.should_flush = false;
+
+                    if let Err(e) = result {
+                        error!(err = ?e, id = this.id, "Failed to flush stream");
+                        break;
+                    }
+                }
+            }
+
+            // Send the messages of the request
+            if conn.poll_ready_unpin(cx).is_ready() {
+                match this.from_driver.poll_recv(cx) {
+                    Poll::Ready(Some(msg)) => {
+                        let size = msg.size();
+                        if let Err(e) = conn.start_send_unpin(msg) {
+                            error!(err = ?e, id = this.id, "Failed to send message to stream");
+                            break;
+                        }
+
+                        this.state.stats.increment_tx(size);
+                        this.should_flush = true;
+                        continue;
+                    }
+                    // The request is done, close the stream
+                    Poll::Ready(None) => {
+                        let _ = ready!(conn.poll_close_unpin(cx));
+                        break;
+                    }
+                    Poll::Pending => {}
+                }
+            }
+
+            // Forward the responses to the driver
+            match conn.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(msg))) => {
+                    let addr = this.addr.clone();
+                    let _ = this.to_driver.send(SubstreamEvent::Message { addr, msg });
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    error!(err = ?e, id = this.id, "Stream error");
+                    break;
+                }
+                Poll::Ready(None) => {
+                    debug!(id = this.id, addr = ?this.addr, "Stream closed by the endpoint");
+                    break;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        let _ = this.to_driver.send(SubstreamEvent::Closed { id: this.id });
+        Poll::Ready(())
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::{stream::FuturesUnordered, Future, StreamExt};
+use rustc_hash::FxHashMap;
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinSet,
+};
+use tokio_util::{codec::Framed, sync::PollSender};
+use tracing::{debug, error, info, trace, warn};
+
+use super::{
+    session::DealerSession, OutgoingMessage, RouterError, RouterMessage, RouterOptions, SocketState,
+};
+use crate::{authenticate, AsyncAuthenticator, AuthResult};
+use msg_transport::{Address, PeerAddress, PeerMetadata, Transport};
+use msg_wire::routerdealer;
+
+#[allow(clippy::type_complexity)]
+pub(crate) struct RouterDriver<T: Transport<A>, A: Address> {
+    /// Session ID counter. Used to generate peer identities if authentication is disabled.
+    pub(super) id_counter: u32,
+    /// The server transport used to accept incoming connections.
+    pub(super) transport: T,
+    /// The router socket options (shared with the socket)
+    pub(super) options: Arc<RouterOptions>,
+    /// The router socket state, shared with the socket front-end.
+    pub(crate) state: Arc<SocketState>,
+    /// Optional connection authenticator.
+    pub(super) auth: Option<Arc<dyn AsyncAuthenticator<A>>>,
+    /// A set of pending incoming connections, represented by [`Transport::Accept`].
+    pub(super) conn_tasks: FuturesUnordered<T::Accept>,
+    /// A joinset of authentication tasks.
+    pub(super) auth_tasks: JoinSet<Result<AuthResult<T::Io, A>, RouterError>>,
+    /// Outgoing messages from the socket.
+    pub(super) from_socket: mpsc::Receiver<OutgoingMessage>,
+    /// Sender for incoming messages to the socket. This is cloned into every session.
+    pub(super) to_socket: mpsc::Sender<RouterMessage<A>>,
+    /// All connected peers, keyed by their identity. The capacity of each channel is the peer
+    /// high-water mark.
+    pub(super) peers: FxHashMap<Bytes, mpsc::Sender<routerdealer::Message>>,
+    /// The tasks of the peer sessions, which return the identity of their peer when they end.
+    pub(super) session_tasks: JoinSet<Bytes>,
+}
+
+impl<T, A> Future for RouterDriver<T, A>
+where
+    T: Transport<A> + Unpin + 'static,
+    A: Address,
+{
+    type Output = Result<(), RouterError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        loop {
+            // Unregister the peers whose session has ended, unless a new session has already
+            // taken over their identity.
+            if let Poll::Ready(Some(Ok(peer_id))) = this.session_tasks.poll_join_next(cx) {
+                if this.peers.get(&peer_id).is_some_and(|sender| sender.is_closed()) {
+                    debug!(?peer_id, "Peer session ended, removing peer");
+                    this.peers.remove(&peer_id);
+                }
+
+                continue;
+            }
+
+            // Then poll the joinset of authentication tasks. If a new connection has been handled
+            // we spawn a new session for it, using the authenticated id as its identity.
+            if let Poll::Ready(Some(Ok(auth))) = this.auth_tasks.poll_join_next(cx) {
+                match auth {
+                    Ok(auth) => {
+                        debug!("Authentication passed for {:?} ({:?})", auth.identity.id(), auth.addr);
+                        this.spawn_session(auth.identity.id().clone(), auth.addr, auth.stream);
+                    }
+                    Err(e) => {
+                        error!(err = ?e, "Error authenticating client");
+                        this.state.stats.decrement_active_clients();
+                    }
+                }
+
+                continue;
+            }
+
+            // Then poll the incoming connection tasks. If a new connection has been accepted, spawn
+            // a new authentication task for it.
+            if let Poll::Ready(Some(incoming)) = this.conn_tasks.poll_next_unpin(cx) {
+                match incoming {
+                    Ok(io) => {
+                        if let Err(e) = this.on_incoming(io) {
+                            error!(err = ?e, "Error accepting incoming connection");
+                            this.state.stats.decrement_active_clients();
+                        }
+                    }
+                    Err(e) => {
+                        error!(err = ?e, "Error accepting incoming connection");
+
+                        // Active clients have already been incremented in the initial call to
+                        // `poll_accept`, so we need to decrement them here.
+                        this.state.stats.decrement_active_clients();
+                    }
+                }
+
+                continue;
+            }
+
+            // Poll the transport for new incoming connection futures and push them to the
+            // incoming connection tasks.
+            if let Poll::Ready(accept) = Pin::new(&mut this.transport).poll_accept(cx) {
+                if let Some(max) = this.options.max_clients {
+                    if this.state.stats.active_clients() >= max {
+                        warn!("Max connections reached ({}), rejecting incoming connection", max);
+                        continue;
+                    }
+                }
+
+                // Increment the active clients counter. If the authentication fails,
+                // this counter will be decremented.
+                this.state.stats.increment_active_clients();
+
+                this.conn_tasks.push(accept);
+
+                continue;
+            }
+
+            // Finally, route outgoing messages from the socket to their peers.
+            match this.from_socket.poll_recv(cx) {
+                Poll::Ready(Some(msg)) => {
+                    this.on_outgoing(msg);
+                    continue;
+                }
+                Poll::Ready(None) => {
+                    debug!("Socket dropped, shutting down driver");
+                    return Poll::Ready(Ok(()));
+                }
+                Poll::Pending => {}
+            }
+
+            return Poll::Pending;
+        }
+    }
+}
+
+impl<T, A> RouterDriver<T, A>
+where
+    T: Transport<A> + Unpin + 'static,
+    A: Address,
+{
+    /// Routes an outgoing message to the peer with the given identity. If the peer is unknown or
+    /// at its high-water mark, the message is dropped.
+    fn on_outgoing(&mut self, msg: OutgoingMessage) {
+        let Some(sender) = self.peers.get(msg.peer_id()) else {
+            debug!(peer_id = ?msg.peer_id(), "Unknown peer, dropping message");
+            self.state.stats.increment_dropped_messages();
+            return;
+        };
+
+        let peer_id = msg.peer_id().clone();
+        match sender.try_send(msg.into_wire()) {
+            Ok(()) => {
+                trace!(?peer_id, "Dispatched message to peer");
+            }
+            Err(TrySendError::Full(_)) => {
+                warn!(?peer_id, "Peer is at its high-water mark, dropping message");
+                self.state.stats.increment_dropped_messages();
+            }
+            Err(TrySendError::Closed(_)) => {
+                debug!(?peer_id, "Peer disconnected, dropping message");
+                self.state.stats.increment_dropped_messages();
+                self.peers.remove(&peer_id);
+            }
+        }
+    }
+
+    /// Spawns a new [`DealerSession`] for the given connection and registers it under the given
+    /// identity. If another live session is already registered with this identity, the new
+    /// connection is dropped.
+    fn spawn_session(&mut self, peer_id: Bytes, addr: A, io: T::Io) {
+        if self.peers.get(&peer_id).is_some_and(|sender| !sender.is_closed()) {
+            warn!(
+                ?peer_id,
+                ?addr,
+                "Peer with this identity is already connected, dropping connection"
+            );
+            self.state.stats.decrement_active_clients();
+            return;
+        }
+
+        let mut framed = Framed::new(io, routerdealer::Codec::new());
+        framed.set_backpressure_boundary(self.options.backpressure_boundary);
+
+        let (tx, rx) = mpsc::channel(self.options.peer_hwm);
+
+        let session = DealerSession {
+            peer_id: peer_id.clone(),
+            addr,
+            from_driver: rx,
+            to_socket: PollSender::new(self.to_socket.clone()),
+            pending_egress: None,
+            state: Arc::clone(&self.state),
+            conn: framed,
+            should_flush: false,
+            flush_interval: self.options.flush_interval.map(tokio::time::interval),
+        };
+
+        self.peers.insert(peer_id.clone(), tx);
+        self.session_tasks.spawn(async move {
+            session.await;
+            peer_id
+        });
+    }
+
+    /// Handles an incoming connection. If this returns an error, the active connections counter
+    /// should be decremented.
+    fn on_incoming(&mut self, io: T::Io) -> Result<(), io::Error> {
+        let addr = io.peer_addr()?;
+
+        info!("New connection from {:?}", addr);
+
+        // If authentication is enabled, start the authentication process
+        if let Some(ref auth) = self.auth {
+            let authenticator = Arc::clone(auth);
+            debug!("New connection from {:?}, authenticating", addr);
+            self.auth_tasks.spawn(async move {
+                authenticate(io, addr, authenticator).await.map_err(|e| RouterError::Auth(e.to_string()))
+            });
+        } else {
+            // Without authentication, peers are identified by the static key the transport
+            // authenticated them with, or else by their session ID.
+            let peer_id = match io.metadata().peer_public_key() {
+                Some(key) => Bytes::copy_from_slice(key),
+                None => {
+                    let peer_id = Bytes::copy_from_slice(&self.id_counter.to_be_bytes());
+                    self.id_counter = self.id_counter.wrapping_add(1);
+                    peer_id
+                }
+            };
+
+            debug!("New connection from {:?}, peer ID {:?}", addr, peer_id);
+            self.spawn_session(peer_id, addr, io);
+        }
+
+        Ok(())
+    }
+}
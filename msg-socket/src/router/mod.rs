@@ -0,0 +1,385 @@
+use std::{fmt, io, time::Duration};
+
+use bytes::Bytes;
+use thiserror::Error;
+
+mod driver;
+use msg_transport::Address;
+use msg_wire::{
+    compression::{CompressionType, Compressor},
+    routerdealer,
+};
+mod session;
+mod socket;
+mod stats;
+pub use socket::*;
+use stats::SocketStats;
+
+const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+#[derive(Debug, Error)]
+pub enum RouterError {
+    #[error("IO error: {0:?}")]
+    Io(#[from] io::Error),
+    #[error("Wire protocol error: {0:?}")]
+    Wire(#[from] routerdealer::Error),
+    #[error("Authentication error: {0}")]
+    Auth(String),
+    #[error("Socket closed")]
+    SocketClosed,
+    #[error("Command channel full")]
+    ChannelFull,
+    #[error("Transport error: {0:?}")]
+    Transport(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
+
+#[derive(Debug)]
+pub struct RouterOptions {
+    /// The maximum number of concurrent clients.
+    max_clients: Option<usize>,
+    /// The high-water mark for each connected peer. This is the maximum number of outgoing
+    /// messages that can be queued for a single peer. Messages to a peer that is at its
+    /// high-water mark are dropped.
+    peer_hwm: usize,
+    /// The maximum amount of incoming messages that will be buffered before the socket stops
+    /// reading from its peers.
+    ingress_buffer_size: usize,
+    /// The interval at which each session should be flushed. If this is `None`,
+    /// the session will be flushed on every send, which can add a lot of overhead.
+    flush_interval: Option<Duration>,
+    /// The maximum number of bytes that can be buffered in the session before being flushed.
+    /// This internally sets [`Framed::set_backpressure_boundary`](tokio_util::codec::Framed).
+    backpressure_boundary: usize,
+    /// Minimum payload size in bytes for compression to be used. If the payload is smaller than
+    /// this threshold, it will not be compressed.
+    min_compress_size: usize,
+}
+
+impl Default for RouterOptions {
+    fn default() -> Self {
+        Self {
+            max_clients: None,
+            peer_hwm: 1024,
+            ingress_buffer_size: DEFAULT_BUFFER_SIZE,
+            flush_interval: Some(Duration::from_micros(50)),
+            backpressure_boundary: 8192,
+            min_compress_size: 8192,
+        }
+    }
+}
+
+impl RouterOptions {
+    /// Sets the maximum number of concurrent clients.
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = Some(max_clients);
+        self
+    }
+
+    /// Sets the per-peer high-water mark. This is the amount of outgoing messages that can be
+    /// queued for a single peer. Once a peer reaches its high-water mark, new messages for that
+    /// peer are dropped until it has caught up.
+    pub fn peer_hwm(mut self, peer_hwm: usize) -> Self {
+        self.peer_hwm = peer_hwm;
+        self
+    }
+
+    /// Sets the ingress buffer size. This is the maximum amount of incoming messages that will be
+    /// buffered. If the consumer cannot keep up with the incoming messages, the socket stops
+    /// reading from its peers.
+    pub fn ingress_buffer_size(mut self, ingress_buffer_size: usize) -> Self {
+        self.ingress_buffer_size = ingress_buffer_size;
+        self
+    }
+
+    /// Sets the interval at which each session should be flushed. If this is `None`,
+    /// the session will be flushed on every send, which can add a lot of overhead.
+    pub fn flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = Some(flush_interval);
+        self
+    }
+
+    /// Sets the maximum number of bytes that can be buffered in the session before being flushed.
+    /// This internally sets [`Framed::set_backpressure_boundary`](tokio_util::codec::Framed).
+    pub fn backpressure_boundary(mut self, backpressure_boundary: usize) -> Self {
+        self.backpressure_boundary = backpressure_boundary;
+        self
+    }
+
+    /// Sets the minimum payload size in bytes for compression to be used. If the payload is smaller
+    /// than this threshold, it will not be compressed.
+    pub fn min_compress_size(mut self, min_compress_size: usize) -> Self {
+        self.min_compress_size = min_compress_size;
+        self
+    }
+}
+
+/// A message received by a [`RouterSocket`]. Includes the identity of the peer that sent it,
+/// which can be used to route a reply back to it with [`RouterSocket::send`].
+#[derive(Clone)]
+pub struct RouterMessage<A: Address> {
+    /// The identity of the peer.
+    peer_id: Bytes,
+    /// The address of the peer.
+    source: A,
+    /// The message payload.
+    payload: Bytes,
+}
+
+impl<A: Address> fmt::Debug for RouterMessage<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RouterMessage")
+            .field("peer_id", &self.peer_id)
+            .field("source", &self.source)
+            .field("payload_size", &self.payload.len())
+            .finish()
+    }
+}
+
+impl<A: Address> RouterMessage<A> {
+    pub fn new(peer_id: Bytes, source: A, payload: Bytes) -> Self {
+        Self { peer_id, source, payload }
+    }
+
+    /// Returns the identity of the peer that sent this message. If authentication is enabled,
+    /// this is the id the peer authenticated with.
+    #[inline]
+    pub fn peer_id(&self) -> &Bytes {
+        &self.peer_id
+    }
+
+    /// Returns the address of the peer that sent this message.
+    #[inline]
+    pub fn source(&self) -> &A {
+        &self.source
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    #[inline]
+    pub fn into_payload(self) -> Bytes {
+        self.payload
+    }
+}
+
+/// An outgoing message sent from a [`RouterSocket`] to the backend task.
+#[derive(Debug, Clone)]
+pub(crate) struct OutgoingMessage {
+    /// The identity of the peer this message should be routed to.
+    peer_id: Bytes,
+    /// The compression type used for the message payload.
+    compression_type: CompressionType,
+    /// The message payload.
+    payload: Bytes,
+}
+
+#[allow(unused)]
+impl OutgoingMessage {
+    pub fn new(peer_id: Bytes, payload: Bytes) -> Self {
+        Self {
+            peer_id,
+            // Initialize the compression type to None.
+            // The actual compression type will be set in the `compress` method.
+            compression_type: CompressionType::None,
+            payload,
+        }
+    }
+
+    #[inline]
+    pub fn peer_id(&self) -> &Bytes {
+        &self.peer_id
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    #[inline]
+    pub fn into_wire(self) -> routerdealer::Message {
+        routerdealer::Message::new(self.compression_type as u8, self.payload)
+    }
+
+    #[inline]
+    pub fn compress(&mut self, compressor: &dyn Compressor) -> Result<(), io::Error> {
+        self.payload = compressor.compress(&self.payload)?;
+        self.compression_type = compressor.compression_type();
+
+        Ok(())
+    }
+}
+
+/// The router socket state, shared between the backend task and the socket.
+#[derive(Debug, Default)]
+pub(crate) struct SocketState {
+    pub(crate) stats: SocketStats,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::StreamExt;
+    use msg_transport::{
+        quic::{self, Quic},
+        tcp::Tcp,
+    };
+    use msg_wire::compression::GzipCompressor;
+    use tracing::info;
+
+    use crate::{Authenticator, DealerOptions, DealerSocket};
+
+    use super::*;
+
+    struct Auth;
+
+    impl Authenticator for Auth {
+        fn authenticate(&self, id: &Bytes) -> bool {
+            info!("Auth request from: {:?}", id);
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn routerdealer_simple() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut router = RouterSocket::new(Tcp::default());
+        let mut dealer = DealerSocket::new(Tcp::default());
+
+        router.bind("0.0.0.0:0").await.unwrap();
+        dealer.connect(router.local_addr().unwrap()).await.unwrap();
+
+        dealer.send(Bytes::from("HELLO")).await.unwrap();
+
+        let msg = router.next().await.unwrap();
+        info!("Received message: {:?}", msg);
+        assert_eq!("HELLO", msg.payload());
+
+        // Messages can be sent at any time, not just as replies.
+        for _ in 0..3 {
+            router.send(msg.peer_id().clone(), Bytes::from("WORLD")).await.unwrap();
+        }
+
+        for _ in 0..3 {
+            let msg = dealer.next().await.unwrap();
+            assert_eq!("WORLD", msg.payload());
+        }
+    }
+
+    #[tokio::test]
+    async fn routerdealer_auth_identity_quic() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut router = RouterSocket::new(Quic::new(quic::Config::insecure())).with_auth(Auth);
+        router.bind("0.0.0.0:0").await.unwrap();
+        let addr = router.local_addr().unwrap();
+
+        let mut dealer1 = DealerSocket::with_options(
+            Quic::new(quic::Config::insecure()),
+            DealerOptions::default().auth_token(Bytes::from("client1")),
+        );
+        let mut dealer2 = DealerSocket::with_options(
+            Quic::new(quic::Config::insecure()),
+            DealerOptions::default().auth_token(Bytes::from("client2")),
+        );
+
+        dealer1.connect(addr).await.unwrap();
+        dealer2.connect(addr).await.unwrap();
+
+        dealer1.send(Bytes::from("ONE")).await.unwrap();
+        let msg = router.next().await.unwrap();
+        assert_eq!("client1", msg.peer_id());
+        assert_eq!("ONE", msg.payload());
+
+        dealer2.send(Bytes::from("TWO")).await.unwrap();
+        let msg = router.next().await.unwrap();
+        assert_eq!("client2", msg.peer_id());
+        assert_eq!("TWO", msg.payload());
+
+        // Route messages to specific peers by their authenticated id.
+        router.send(Bytes::from("client2"), Bytes::from("FOR-2")).await.unwrap();
+        router.send(Bytes::from("client1"), Bytes::from("FOR-1")).await.unwrap();
+
+        assert_eq!("FOR-1", dealer1.next().await.unwrap().payload());
+        assert_eq!("FOR-2", dealer2.next().await.unwrap().payload());
+    }
+
+    #[tokio::test]
+    async fn dealer_round_robin_compressed() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut router1 = RouterSocket::new(Tcp::default());
+        let mut router2 = RouterSocket::new(Tcp::default());
+
+        router1.bind("0.0.0.0:0").await.unwrap();
+        router2.bind("0.0.0.0:0").await.unwrap();
+
+        let mut dealer = DealerSocket::with_options(
+            Tcp::default(),
+            DealerOptions::default().min_compress_size(0),
+        )
+        .with_compressor(GzipCompressor::new(6));
+
+        dealer.connect(router1.local_addr().unwrap()).await.unwrap();
+        dealer.connect(router2.local_addr().unwrap()).await.unwrap();
+
+        // Wait for both connections to be established
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(dealer.stats().active_peers(), 2);
+
+        for i in 0..4 {
+            dealer.send(Bytes::from(format!("MSG-{i}"))).await.unwrap();
+        }
+
+        for _ in 0..2 {
+            assert!(router1.next().await.unwrap().payload().starts_with(b"MSG-"));
+            assert!(router2.next().await.unwrap().payload().starts_with(b"MSG-"));
+        }
+    }
+
+    #[tokio::test]
+    async fn router_peer_reconnect_same_identity() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut router = RouterSocket::new(Tcp::default()).with_auth(Auth);
+        router.bind("0.0.0.0:0").await.unwrap();
+        let addr = *router.local_addr().unwrap();
+
+        let options = DealerOptions::default().auth_token(Bytes::from("client1"));
+        let mut dealer = DealerSocket::with_options(Tcp::default(), options.clone());
+        dealer.connect(addr).await.unwrap();
+        dealer.send(Bytes::from("FIRST")).await.unwrap();
+        assert_eq!("FIRST", router.next().await.unwrap().payload());
+
+        // Wait for the session to end once the dealer is gone
+        drop(dealer);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(router.stats().active_clients(), 0);
+
+        // The identity is free again, so the peer can reconnect with it
+        let mut dealer = DealerSocket::with_options(Tcp::default(), options);
+        dealer.connect(addr).await.unwrap();
+        dealer.send(Bytes::from("SECOND")).await.unwrap();
+        assert_eq!("SECOND", router.next().await.unwrap().payload());
+
+        router.send(Bytes::from("client1"), Bytes::from("REPLY")).await.unwrap();
+        assert_eq!("REPLY", dealer.next().await.unwrap().payload());
+        assert_eq!(router.stats().dropped_messages(), 0);
+    }
+
+    #[tokio::test]
+    async fn router_unknown_peer() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut router = RouterSocket::new(Tcp::default());
+        router.bind("0.0.0.0:0").await.unwrap();
+
+        router.send(Bytes::from("nobody"), Bytes::from("HELLO")).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(router.stats().dropped_messages(), 1);
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::{Future, SinkExt, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+};
+use tokio_util::{codec::Framed, sync::PollSender};
+use tracing::{debug, error, trace, warn};
+
+use super::{RouterMessage, SocketState};
+use msg_transport::Address;
+use msg_wire::{compression::try_decompress_payload, routerdealer};
+
+/// Manages the state of a single connected dealer socket, represented as a [`Future`].
+#[must_use = "This future must be spawned"]
+pub(super) struct DealerSession<Io, A: Address> {
+    /// The identity of the peer.
+    pub(super) peer_id: Bytes,
+    /// The address of the peer.
+    pub(super) addr: A,
+    /// Messages from the driver. The capacity of this channel is the peer high-water mark.
+    pub(super) from_driver: mpsc::Receiver<routerdealer::Message>,
+    /// Incoming messages to the socket.
+    pub(super) to_socket: PollSender<RouterMessage<A>>,
+    /// Messages queued to be sent on the connection
+    pub(super) pending_egress: Option<routerdealer::Message>,
+    /// The socket state, shared between the backend task and the socket.
+    pub(super) state: Arc<SocketState>,
+    /// The framed connection.
+    pub(super) conn: Framed<Io, routerdealer::Codec>,
+    /// Whether or not the connection should be flushed (i.e. data was written).
+    pub(super) should_flush: bool,
+    /// Interval for flushing the connection. This is secondary to `should_flush`.
+    pub(super) flush_interval: Option<tokio::time::Interval>,
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address> DealerSession<Io, A> {
+    #[inline]
+    fn should_flush(&mut self, cx: &mut Context<'_>) -> bool {
+        if self.should_flush {
+            if let Some(interval) = self.flush_interval.as_mut() {
+                interval.poll_tick(cx).is_ready()
+            } else {
+                true
+            }
+        } else {
+            // If we shouldn't flush, reset the interval so we don't get woken up
+            // every time the interval expires
+            if let Some(interval) = self.flush_interval.as_mut() {
+                interval.reset()
+            }
+
+            false
+        }
+    }
+}
+
+impl<Io, A: Address> Drop for DealerSession<Io, A> {
+    fn drop(&mut self) {
+        self.state.stats.decrement_active_clients();
+    }
+}
+
+impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address + Unpin> Future for DealerSession<Io, A> {
+    type Output = ();
+
+    /// This poll implementation only reads from the connection when the socket has capacity for
+    /// a new message, which propagates backpressure to the dealer socket.
+    #[inline]
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        loop {
+            // First check if we should flush the connection. We only do this if we have written
+            // some data and the flush interval has elapsed. Only when we have succesfully flushed
+            // the data will we reset the `should_flush` flag.
+            if this.should_flush(cx) {
+                if let Poll::Ready(Ok(_)) = this.conn.poll_flush_unpin(cx) {
+                    this.should_flush = false;
+                }
+            }
+
+            // Then, try to drain the egress queue.
+            if this.pending_egress.is_some() && this.conn.poll_ready_unpin(cx).is_ready() {
+                let msg = this.pending_egress.take().expect("pending egress");
+                trace!(?msg, peer_id = ?this.peer_id, "Sending message");
+                let msg_len = msg.size();
+
+                match this.conn.start_send_unpin(msg) {
+                    Ok(_) => {
+                        this.state.stats.increment_tx(msg_len);
+
+                        this.should_flush = true;
+                        // We might be able to send more queued messages
+                        continue;
+                    }
+                    Err(e) => {
+                        error!(err = ?e, "Failed to send message to socket");
+                        let _ = this.conn.poll_close_unpin(cx);
+                        // End this session as we can't send any more messages
+                        return Poll::Ready(());
+                    }
+                }
+            }
+
+            // Poll outgoing messages. We only take a new message from the driver once the previous
+            // one has been handed to the connection, so that the channel capacity acts as the
+            // high-water mark for this peer.
+            if this.pending_egress.is_none() {
+                if let Poll::Ready(item) = this.from_driver.poll_recv(cx) {
+                    match item {
+                        Some(msg) => {
+                            this.pending_egress = Some(msg);
+                            continue;
+                        }
+                        None => {
+                            debug!(peer_id = ?this.peer_id, "Driver closed, shutting down session");
+                            let _ = this.conn.poll_close_unpin(cx);
+                            return Poll::Ready(());
+                        }
+                    }
+                }
+            }
+
+            // Finally, read incoming messages, but only if the socket has capacity for them.
+            match this.to_socket.poll_reserve(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(_)) => {
+                    debug!(peer_id = ?this.peer_id, "Socket dropped, shutting down session");
+                    let _ = this.conn.poll_close_unpin(cx);
+                    return Poll::Ready(());
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+
+            match this.conn.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(msg))) => {
+                    this.state.stats.increment_rx(msg.size());
+
+                    let payload = match try_decompress_payload(
+                        msg.compression_type(),
+                        msg.into_payload(),
+                    ) {
+                        Ok(payload) => payload,
+                        Err(e) => {
+                            error!(err = ?e, peer_id = ?this.peer_id, "Failed to decompress message");
+                            continue;
+                        }
+                    };
+
+                    let msg = RouterMessage::new(this.peer_id.clone(), this.addr.clone(), payload);
+                    if this.to_socket.send_item(msg).is_err() {
+                        debug!(peer_id = ?this.peer_id, "Socket dropped, shutting down session");
+                        let _ = this.conn.poll_close_unpin(cx);
+                        return Poll::Ready(());
+                    }
+
+                    continue;
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    error!(err = ?e, peer_id = ?this.peer_id, "Error reading from socket");
+                    let _ = this.conn.poll_close_unpin(cx);
+                    return Poll::Ready(());
+                }
+                Poll::Ready(None) => {
+                    warn!(peer_id = ?this.peer_id, "Connection closed, shutting down session");
+                    return Poll::Ready(());
+                }
+                Poll::Pending => {}
+            }
+
+            return Poll::Pending;
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::{stream::FuturesUnordered, Stream};
+use rustc_hash::FxHashMap;
+use tokio::{
+    net::{lookup_host, ToSocketAddrs},
+    sync::mpsc,
+    task::JoinSet,
+};
+use tracing::{debug, trace, warn};
+
+use super::{
+    driver::RouterDriver, stats::SocketStats, OutgoingMessage, RouterError, RouterMessage,
+    RouterOptions, SocketState, DEFAULT_BUFFER_SIZE,
+};
+use crate::{AsyncAuthenticator, Authenticator};
+
+use msg_transport::{inproc::InprocAddr, Address, Transport};
+use msg_wire::compression::Compressor;
+
+/// A router socket. Receives messages from any number of connected
+/// [`DealerSocket`](crate::DealerSocket)s, tagged with the identity of the sending peer, and can
+/// send messages to a specific peer at any time. This socket implements [`Stream`] and yields
+/// incoming [`RouterMessage`]s.
+pub struct RouterSocket<T: Transport<A>, A: Address> {
+    /// The router socket options, shared with the driver.
+    options: Arc<RouterOptions>,
+    /// The router socket state, shared with the driver.
+    state: Arc<SocketState>,
+    /// The transport used by this socket. This value is temporary and will be moved
+    /// to the driver task once the socket is bound.
+    transport: Option<T>,
+    /// Channel to the socket driver.
+    to_driver: Option<mpsc::Sender<OutgoingMessage>>,
+    /// Receiver for incoming messages from the peer sessions.
+    from_driver: Option<mpsc::Receiver<RouterMessage<A>>>,
+    /// Optional connection authenticator.
+    auth: Option<Arc<dyn AsyncAuthenticator<A>>>,
+    /// Optional message compressor.
+    // NOTE: for now we're using dynamic dispatch, since using generics here
+    // complicates the API a lot. We can always change this later for perf reasons.
+    compressor: Option<Arc<dyn Compressor>>,
+    /// The local address this socket is bound to.
+    local_addr: Option<A>,
+}
+
+impl<T> RouterSocket<T, SocketAddr>
+where
+    T: Transport<SocketAddr> + Send + Unpin + 'static,
+{
+    /// Binds the socket to the given socket addres
+    ///
+    /// This method is only available for transports that support [`SocketAddr`] as address type,
+    /// like [`Tcp`](msg_transport::tcp::Tcp) and [`Quic`](msg_transport::quic::Quic).
+    pub async fn bind(&mut self, addr: impl ToSocketAddrs) -> Result<(), RouterError> {
+        let addrs = lookup_host(addr).await?;
+        self.try_bind(addrs.collect()).await
+    }
+}
+
+impl<T> RouterSocket<T, PathBuf>
+where
+    T: Transport<PathBuf> + Send + Unpin + 'static,
+{
+    /// Binds the socket to the given path.
+    ///
+    /// This method is only available for transports that support [`PathBuf`] as address type,
+    /// like [`Ipc`](msg_transport::ipc::Ipc).
+    pub async fn bind(&mut self, path: impl Into<PathBuf>) -> Result<(), RouterError> {
+        self.try_bind(vec![path.into()]).await
+    }
+}
+
+impl<T> RouterSocket<T, InprocAddr>
+where
+    T: Transport<InprocAddr> + Send + Unpin + 'static,
+{
+    /// Binds the socket to the given in-process endpoint name.
+    ///
+    /// This method is only available for transports that support [`InprocAddr`] as address type,
+    /// like [`Inproc`](msg_transport::inproc::Inproc).
+    pub async fn bind(&mut self, name: impl Into<InprocAddr>) -> Result<(), RouterError> {
+        self.try_bind(vec![name.into()]).await
+    }
+}
+
+impl<T, A> RouterSocket<T, A>
+where
+    T: Transport<A> + Send + Unpin + 'static,
+    A: Address,
+{
+    /// Creates a new router socket with the default [`RouterOptions`].
+    pub fn new(transport: T) -> Self {
+        Self::with_options(transport, RouterOptions::default())
+    }
+
+    /// Creates a new router socket with the given transport and options.
+    pub fn with_options(transport: T, options: RouterOptions) -> Self {
+        Self {
+            local_addr: None,
+            to_driver: None,
+            from_driver: None,
+            options: Arc::new(options),
+            transport: Some(transport),
+            state: Arc::new(SocketState::default()),
+            auth: None,
+            compressor: None,
+        }
+    }
+
+    /// Sets the connection authenticator for this socket. The id every peer authenticates with
+    /// is used as its identity.
+    pub fn with_auth<O: Authenticator>(mut self, authenticator: O) -> Self {
+        self.auth = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Sets the asynchronous connection authenticator for this socket, which also has access to
+    /// the address of the client and the metadata of the connection.
+    pub fn with_async_auth<O: AsyncAuthenticator<A>>(mut self, authenticator: O) -> Self {
+        self.auth = Some(Arc::new(authenticator));
+        self
+    }
+
+    /// Sets the message compressor for this socket.
+    pub fn with_compressor<C: Compressor + 'static>(mut self, compressor: C) -> Self {
+        self.compressor = Some(Arc::new(compressor));
+        self
+    }
+
+    /// Binds the socket to the given addresses in order until one succeeds.
+    ///
+    /// This also spawns the socket driver task.
+    pub async fn try_bind(&mut self, addresses: Vec<A>) -> Result<(), RouterError> {
+        let (to_driver, from_socket) = mpsc::channel(DEFAULT_BUFFER_SIZE);
+        let (to_socket, from_driver) = mpsc::channel(self.options.ingress_buffer_size);
+
+        let mut transport = self.transport.take().expect("Transport has been moved already");
+
+        for addr in addresses {
+            match transport.bind(addr.clone()).await {
+                Ok(_) => break,
+                Err(e) => {
+                    warn!(err = ?e, "Failed to bind to {:?}, trying next address", addr);
+                    continue;
+                }
+            }
+        }
+
+        let Some(local_addr) = transport.local_addr() else {
+            return Err(RouterError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not bind to any valid address",
+            )));
+        };
+
+        debug!("Listening on {:?}", local_addr);
+
+        let backend = RouterDriver {
+            id_counter: 0,
+            transport,
+            options: Arc::clone(&self.options),
+            state: Arc::clone(&self.state),
+            auth: self.auth.take(),
+            auth_tasks: JoinSet::new(),
+            conn_tasks: FuturesUnordered::new(),
+            from_socket,
+            to_socket,
+            peers: FxHashMap::default(),
+            session_tasks: JoinSet::new(),
+        };
+
+        tokio::spawn(backend);
+
+        self.local_addr = Some(local_addr);
+        self.to_driver = Some(to_driver);
+        self.from_driver = Some(from_driver);
+
+        Ok(())
+    }
+
+    /// Sends a message to the peer with the given identity. If the peer is not connected, or it
+    /// is at its high-water mark, the message is dropped. This method will wait if the socket
+    /// queue itself is full.
+    pub async fn send(&self, peer_id: Bytes, message: Bytes) -> Result<(), RouterError> {
+        let msg = self.prepare(peer_id, message)?;
+
+        self.to_driver
+            .as_ref()
+            .ok_or(RouterError::SocketClosed)?
+            .send(msg)
+            .await
+            .map_err(|_| RouterError::SocketClosed)
+    }
+
+    /// Tries to send a message to the peer with the given identity immediately. Returns
+    /// [`RouterError::ChannelFull`] if the socket queue is full.
+    pub fn try_send(&self, peer_id: Bytes, message: Bytes) -> Result<(), RouterError> {
+        use mpsc::error::TrySendError::*;
+
+        let msg = self.prepare(peer_id, message)?;
+
+        self.to_driver.as_ref().ok_or(RouterError::SocketClosed)?.try_send(msg).map_err(|e| match e
+        {
+            Full(_) => RouterError::ChannelFull,
+            Closed(_) => RouterError::SocketClosed,
+        })
+    }
+
+    /// Wraps the payload in an [`OutgoingMessage`], compressing it if a compressor is set and the
+    /// payload is larger than the configured minimum size.
+    fn prepare(&self, peer_id: Bytes, message: Bytes) -> Result<OutgoingMessage, RouterError> {
+        let mut msg = OutgoingMessage::new(peer_id, message);
+
+        let len_before = msg.payload().len();
+        if len_before > self.options.min_compress_size {
+            if let Some(ref compressor) = self.compressor {
+                msg.compress(compressor.as_ref())?;
+
+                trace!("Compressed message from {} to {} bytes", len_before, msg.payload().len());
+            }
+        }
+
+        Ok(msg)
+    }
+
+    pub fn stats(&self) -> &SocketStats {
+        &self.state.stats
+    }
+
+    /// Returns the local address this socket is bound to. `None` if the socket is not bound.
+    pub fn local_addr(&self) -> Option<&A> {
+        self.local_addr.as_ref()
+    }
+}
+
+impl<T: Transport<A> + Unpin, A: Address> Stream for RouterSocket<T, A> {
+    type Item = RouterMessage<A>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().from_driver.as_mut().expect("Inactive socket").poll_recv(cx)
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Statistics for a router socket. These are shared between the driver task
+/// and the socket.
+#[derive(Debug, Default)]
+pub struct SocketStats {
+    /// Total bytes sent
+    bytes_tx: AtomicUsize,
+    /// Total bytes received
+    bytes_rx: AtomicUsize,
+    /// Total number of active dealer clients
+    active_clients: AtomicUsize,
+    /// Total number of outgoing messages that were dropped, either because the peer was unknown
+    /// or because it was at its high-water mark.
+    dropped_messages: AtomicUsize,
+}
+
+impl SocketStats {
+    #[inline]
+    pub(crate) fn increment_tx(&self, bytes: usize) {
+        self.bytes_tx.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn increment_rx(&self, bytes: usize) {
+        self.bytes_rx.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn increment_active_clients(&self) {
+        self.active_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn decrement_active_clients(&self) {
+        self.active_clients.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn increment_dropped_messages(&self) {
+        self.dropped_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn bytes_tx(&self) -> usize {
+        self.bytes_tx.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn bytes_rx(&self) -> usize {
+        self.bytes_rx.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn active_clients(&self) -> usize {
+        self.active_clients.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn dropped_messages(&self) -> usize {
+        self.dropped_messages.load(Ordering::Relaxed)
+    }
+}
@@ -1,27 +1,27 @@
 use std::{
     collections::HashSet,
-    io,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
 };
 
-use futures::{Future, SinkExt, StreamExt};
+use futures::{Future, StreamExt};
 use rustc_hash::FxHashMap;
 use tokio::sync::mpsc::{self, error::TrySendError};
 use tokio_util::codec::Framed;
 use tracing::{debug, error, info, warn};
 
 use super::{
+    sequence::SequenceTracker,
     session::{PublisherSession, SessionCommand},
-    stream::{PublisherStream, TopicMessage},
-    Command, PubMessage, SocketState, SubOptions,
+    stream::{DatagramStream, PublisherStream, TopicMessage},
+    Command, LogPosition, PubMessage, SocketState, SubOptions,
 };
-use crate::{ConnectionState, ExponentialBackoff};
+use crate::{authenticate_client, ConnectionState, DatagramsFn, ExponentialBackoff};
 
 use msg_common::{channel, Channel, JoinMap};
 use msg_transport::{Address, Transport};
-use msg_wire::{auth, compression::try_decompress_payload, pubsub};
+use msg_wire::{compression::try_decompress_payload, pubsub};
 
 /// Publisher channel type, used to send messages to the publisher session
 /// and receive messages to forward to the socket frontend.
@@ -42,8 +42,13 @@ pub(crate) struct SubDriver<T: Transport<A>, A: Address> {
     pub(super) subscribed_topics: HashSet<String>,
     /// All publisher sessions for this subscriber socket, keyed by address.
     pub(super) publishers: FxHashMap<A, ConnectionState<PubChannel, ExponentialBackoff, A>>,
+    /// The sequence numbers of the messages received from each publisher. Used to detect lost
+    /// messages, and to request a replay of the missed messages on reconnect.
+    pub(super) sequences: FxHashMap<A, SequenceTracker>,
     /// Socket state. This is shared with the backend task.
     pub(super) state: Arc<SocketState<A>>,
+    /// Returns the datagram handle of a connection, if datagrams are enabled.
+    pub(super) datagrams: Option<DatagramsFn<T::Io>>,
 }
 
 impl<T, A> Future for SubDriver<T, A>
@@ -156,6 +161,39 @@ where
         }
     }
 
+    /// Subscribes to a topic on all publishers, asking each of them to first send the messages
+    /// in its log from the given position.
+    fn catch_up(&mut self, topic: String, from: LogPosition) {
+        let mut inactive = Vec::new();
+
+        self.subscribed_topics.insert(topic.clone());
+
+        for (addr, publisher_state) in self.publishers.iter_mut() {
+            if let ConnectionState::Active { channel } = publisher_state {
+                // If the channel is closed on the other side, deactivate the publisher
+                if let Err(TrySendError::Closed(_)) =
+                    channel.try_send(SessionCommand::CatchUp(topic.clone(), from))
+                {
+                    warn!(publisher = ?addr, "Error trying to catch up on topic {topic}: publisher channel closed");
+                    inactive.push(addr.clone());
+                }
+            }
+        }
+
+        // Remove all inactive publishers
+        for addr in inactive {
+            // Move publisher to inactive state
+            self.reset_publisher(addr);
+        }
+
+        info!(
+            topic = topic.as_str(),
+            ?from,
+            n_publishers = self.publishers.len(),
+            "Subscribed to topic with catch-up"
+        );
+    }
+
     /// Unsubscribes from a topic on all publishers.
     fn unsubscribe(&mut self, topic: String) {
         let mut inactive = Vec::new();
@@ -199,6 +237,9 @@ where
             Command::Unsubscribe { topic } => {
                 self.unsubscribe(topic);
             }
+            Command::CatchUp { topic, from } => {
+                self.catch_up(topic, from);
+            }
             Command::Connect { endpoint } => {
                 if self.is_known(&endpoint) {
                     debug!(?endpoint, "Publisher already known, ignoring connect command");
@@ -214,6 +255,7 @@ where
             Command::Disconnect { endpoint } => {
                 if self.publishers.remove(&endpoint).is_some() {
                     debug!(?endpoint, "Disconnected from publisher");
+                    self.sequences.remove(&endpoint);
                     self.state.stats.remove(&endpoint);
                 } else {
                     debug!(?endpoint, "Not connected to publisher");
@@ -228,7 +270,7 @@ where
 
     fn connect(&mut self, addr: A) {
         let connect = self.transport.connect(addr.clone());
-        let token = self.options.auth_token.clone();
+        let credentials = self.options.auth.clone();
 
         self.connection_tasks.spawn(addr.clone(), async move {
             let io = match connect.await {
@@ -238,50 +280,10 @@ where
                 }
             };
 
-            if let Some(token) = token {
-                let mut conn = Framed::new(io, auth::Codec::new_client());
-
-                debug!("Sending auth message: {:?}", token);
-                // Send the authentication message
-                if let Err(e) = conn.send(auth::Message::Auth(token)).await {
-                    return (addr, Err(e.into()));
-                }
-
-                if let Err(e) = conn.flush().await {
-                    return (addr, Err(e.into()));
-                }
-
-                debug!("Waiting for ACK from server...");
-
-                // Wait for the response
-                let ack = match conn.next().await {
-                    Some(Ok(ack)) => ack,
-                    Some(Err(e)) => {
-                        return (
-                            addr,
-                            Err(io::Error::new(io::ErrorKind::PermissionDenied, e).into()),
-                        )
-                    }
-                    None => {
-                        return (
-                            addr,
-                            Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed")
-                                .into()),
-                        )
-                    }
-                };
-
-                if matches!(ack, auth::Message::Ack) {
-                    (addr, Ok(conn.into_inner()))
-                } else {
-                    (
-                        addr,
-                        Err(io::Error::new(
-                            io::ErrorKind::PermissionDenied,
-                            "Publisher denied connection",
-                        )
-                        .into()),
-                    )
+            if let Some(credentials) = credentials {
+                match authenticate_client(io, credentials).await {
+                    Ok(io) => (addr, Ok(io)),
+                    Err(e) => (addr, Err(e.into())),
                 }
             } else {
                 (addr, Ok(io))
@@ -298,12 +300,19 @@ where
 
         debug!("Connection to {:?} established, spawning session", addr);
 
+        let datagrams =
+            self.datagrams.and_then(|datagrams| datagrams(&io)).map(DatagramStream::new);
+
         let framed = Framed::with_capacity(io, pubsub::Codec::new(), self.options.read_buffer_size);
 
         let (driver_channel, mut publisher_channel) = channel(1024, 64);
 
-        let publisher_session =
-            PublisherSession::new(addr.clone(), PublisherStream::from(framed), driver_channel);
+        let publisher_session = PublisherSession::new(
+            addr.clone(),
+            PublisherStream::from(framed),
+            datagrams,
+            driver_channel,
+        );
 
         // Get the shared session stats.
         let session_stats = publisher_session.stats();
@@ -317,6 +326,15 @@ where
             }
         }
 
+        // If this is a reconnect, ask for the messages we missed. This is sent after the
+        // subscriptions, since the publisher only replays messages matching them.
+        if let Some(seq) = self.sequences.get(&addr).and_then(|sequence| sequence.last_seq()) {
+            debug!(publisher = ?addr, seq, "Requesting replay");
+            if publisher_channel.try_send(SessionCommand::Replay(seq)).is_err() {
+                error!(publisher = ?addr, "Error trying to request replay: publisher channel closed / full");
+            }
+        }
+
         self.publishers
             .insert(addr.clone(), ConnectionState::Active { channel: publisher_channel });
 
@@ -342,6 +360,26 @@ where
                 ConnectionState::Active { channel } => {
                     match channel.poll_recv(cx) {
                         Poll::Ready(Some(mut msg)) => {
+                            let seq = msg.seq;
+                            let sequence = self.sequences.entry(addr.clone()).or_default();
+
+                            // Datagrams may be reordered, drop the ones that arrive too late
+                            if msg.unreliable && sequence.is_late(&msg.topic, msg.topic_seq) {
+                                debug!(source = ?addr, topic = msg.topic, seq, "Late message");
+                                progress = true;
+                                continue;
+                            }
+
+                            let gap = sequence.track(
+                                seq,
+                                &msg.topic,
+                                msg.topic_seq,
+                            );
+                            if let Some(lost) = gap {
+                                warn!(source = ?addr, topic = msg.topic, lost, "Gap in message sequence");
+                                self.state.stats.record_gap(addr, lost);
+                            }
+
                             match try_decompress_payload(msg.compression_type, msg.payload) {
                                 Ok(decompressed) => msg.payload = decompressed,
                                 Err(e) => {
@@ -350,7 +388,9 @@ where
                                 }
                             };
 
-                            let msg = PubMessage::new(addr.clone(), msg.topic, msg.payload);
+                            let mut msg = PubMessage::new(addr.clone(), msg.topic, msg.payload);
+                            msg.seq = seq;
+                            msg.gap = gap;
 
                             debug!(source = ?msg.source, ?msg, "New message");
                             // TODO: queuing
@@ -407,6 +447,7 @@ where
         // Terminate publishers that are unreachable.
         for addr in to_terminate {
             self.publishers.remove(&addr);
+            self.sequences.remove(&addr);
         }
 
         if progress {
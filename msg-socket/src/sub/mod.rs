@@ -6,6 +6,8 @@ use thiserror::Error;
 mod driver;
 use driver::SubDriver;
 
+mod sequence;
+
 mod session;
 
 mod socket;
@@ -16,6 +18,7 @@ use stats::SocketStats;
 
 mod stream;
 
+use crate::Credentials;
 use msg_transport::Address;
 use msg_wire::pubsub;
 
@@ -43,6 +46,8 @@ enum Command<A: Address> {
     Subscribe { topic: String },
     /// Unsubscribe from a topic.
     Unsubscribe { topic: String },
+    /// Subscribe to a topic, catching up on the messages in the publisher logs first.
+    CatchUp { topic: String, from: LogPosition },
     /// Connect to a publisher socket.
     Connect { endpoint: A },
     /// Disconnect from a publisher socket.
@@ -51,10 +56,21 @@ enum Command<A: Address> {
     Shutdown,
 }
 
+/// The position in the durable log of a publisher to catch up from, see
+/// [`SubSocket::catch_up`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogPosition {
+    /// Start at the first message with at least the given sequence number.
+    Seq(u32),
+    /// Start at the first message that was published at or after the given UNIX timestamp in
+    /// microseconds.
+    Timestamp(u64),
+}
+
 #[derive(Debug, Clone)]
 pub struct SubOptions {
     /// Optional authentication token.
-    auth_token: Option<Bytes>,
+    auth: Option<Credentials>,
     /// The maximum amount of incoming messages that will be buffered before being dropped due to
     /// a slow consumer.
     ingress_buffer_size: usize,
@@ -68,7 +84,15 @@ impl SubOptions {
     /// Sets the authentication token for this socket. This will activate the authentication layer
     /// and send the token to the publisher.
     pub fn auth_token(mut self, auth_token: Bytes) -> Self {
-        self.auth_token = Some(auth_token);
+        self.auth = Some(Credentials::Token(auth_token));
+        self
+    }
+
+    /// Sets the ID and the secret this socket authenticates with. Instead of sending the secret,
+    /// the socket proves that it knows it by responding to a challenge from the publisher, which
+    /// must have the same secret (see [`SharedSecrets`](crate::SharedSecrets)).
+    pub fn auth_secret(mut self, id: Bytes, secret: Bytes) -> Self {
+        self.auth = Some(Credentials::Secret { id, secret });
         self
     }
 
@@ -96,7 +120,7 @@ impl SubOptions {
 impl Default for SubOptions {
     fn default() -> Self {
         Self {
-            auth_token: None,
+            auth: None,
             ingress_buffer_size: DEFAULT_BUFFER_SIZE,
             read_buffer_size: 8192,
             initial_backoff: Duration::from_millis(100),
@@ -115,6 +139,10 @@ pub struct PubMessage<A: Address> {
     topic: String,
     /// The message payload.
     payload: Bytes,
+    /// The sequence number of the message, assigned by the publisher.
+    seq: u32,
+    /// The number of messages on this topic that were lost right before this message.
+    gap: Option<u32>,
 }
 
 impl<A: Address> fmt::Debug for PubMessage<A> {
@@ -122,6 +150,8 @@ impl<A: Address> fmt::Debug for PubMessage<A> {
         f.debug_struct("PubMessage")
             .field("source", &self.source)
             .field("topic", &self.topic)
+            .field("seq", &self.seq)
+            .field("gap", &self.gap)
             .field("payload_size", &self.payload.len())
             .finish()
     }
@@ -129,7 +159,7 @@ impl<A: Address> fmt::Debug for PubMessage<A> {
 
 impl<A: Address> PubMessage<A> {
     pub fn new(source: A, topic: String, payload: Bytes) -> Self {
-        Self { source, topic, payload }
+        Self { source, topic, payload, seq: 0, gap: None }
     }
 
     #[inline]
@@ -151,6 +181,22 @@ impl<A: Address> PubMessage<A> {
     pub fn into_payload(self) -> Bytes {
         self.payload
     }
+
+    /// Returns the sequence number of the message, assigned by the publisher.
+    #[inline]
+    pub fn seq(&self) -> u32 {
+        self.seq
+    }
+
+    /// Returns the number of messages on this topic from the same publisher that were lost
+    /// right before this message, e.g. because the publisher dropped them for a slow subscriber.
+    /// `None` if no messages were lost. Messages from a single publisher are always received in
+    /// the order they were published. On datagram topics, messages that arrive after a newer one
+    /// are dropped (see [`SubSocket::with_datagrams`]).
+    #[inline]
+    pub fn gap(&self) -> Option<u32> {
+        self.gap
+    }
 }
 
 /// The request socket state, shared between the backend task and the socket.
@@ -0,0 +1,75 @@
+use rustc_hash::FxHashMap;
+
+use msg_common::seq_after;
+
+/// Tracks the sequence numbers of the messages received from a single publisher, to detect lost
+/// messages.
+#[derive(Debug, Default)]
+pub(super) struct SequenceTracker {
+    /// The sequence number of the last received message.
+    last_seq: Option<u32>,
+    /// The topic sequence number of the last received message of every topic.
+    topics: FxHashMap<String, u32>,
+}
+
+impl SequenceTracker {
+    /// Returns the sequence number of the last received message, if any.
+    pub(super) fn last_seq(&self) -> Option<u32> {
+        self.last_seq
+    }
+
+    /// Returns `true` if a newer message on the given topic has been received already.
+    pub(super) fn is_late(&self, topic: &str, topic_seq: u32) -> bool {
+        self.topics.get(topic).is_some_and(|last| !seq_after(topic_seq, *last))
+    }
+
+    /// Records a received message. Returns the number of messages on its topic that were lost
+    /// since the previous one, if any. Duplicate or older messages (i.e. from a catch-up read)
+    /// are not counted as a gap.
+    pub(super) fn track(&mut self, seq: u32, topic: &str, topic_seq: u32) -> Option<u32> {
+        self.last_seq = Some(seq);
+
+        let Some(last) = self.topics.get_mut(topic) else {
+            self.topics.insert(topic.to_owned(), topic_seq);
+            return None;
+        };
+
+        if !seq_after(topic_seq, *last) {
+            return None;
+        }
+
+        let lost = topic_seq.wrapping_sub(*last) - 1;
+        *last = topic_seq;
+
+        (lost > 0).then_some(lost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence_gaps() {
+        let mut tracker = SequenceTracker::default();
+
+        assert_eq!(tracker.track(10, "foo", 5), None);
+        assert_eq!(tracker.track(11, "bar", 0), None);
+        assert_eq!(tracker.track(12, "foo", 6), None);
+        assert_eq!(tracker.track(16, "foo", 9), Some(2));
+        assert_eq!(tracker.last_seq(), Some(16));
+        assert!(tracker.is_late("foo", 8));
+        assert!(tracker.is_late("foo", 9));
+        assert!(!tracker.is_late("foo", 10));
+        assert!(!tracker.is_late("baz", 0));
+
+        // Duplicates and older messages are not gaps
+        assert_eq!(tracker.track(16, "foo", 9), None);
+        assert_eq!(tracker.track(3, "foo", 1), None);
+        assert_eq!(tracker.track(17, "foo", 10), None);
+
+        assert_eq!(tracker.track(18, "bar", u32::MAX), None);
+        tracker.topics.insert("baz".to_string(), u32::MAX);
+        assert_eq!(tracker.track(19, "baz", 1), Some(1));
+    }
+}
@@ -16,12 +16,17 @@ use msg_wire::pubsub;
 
 use super::{
     stats::SessionStats,
-    stream::{PublisherStream, TopicMessage},
+    stream::{DatagramStream, PublisherStream, TopicMessage},
+    LogPosition,
 };
 
 pub(super) enum SessionCommand {
     Subscribe(String),
     Unsubscribe(String),
+    /// Subscribe to a topic, catching up on the messages in the publisher log first.
+    CatchUp(String, LogPosition),
+    /// Ask the publisher to replay the retained messages after the given sequence number.
+    Replay(u32),
 }
 
 /// Manages the state of a single publisher, represented as a [`Future`].
@@ -33,6 +38,9 @@ pub(super) struct PublisherSession<Io, A: Address> {
     egress: VecDeque<pubsub::Message>,
     /// The inner stream
     stream: PublisherStream<Io>,
+    /// The messages received as datagrams. `None` if disabled, or if the connection doesn't
+    /// support datagrams.
+    datagrams: Option<DatagramStream>,
     /// The session stats
     stats: Arc<SessionStats>,
     /// Channel for bi-directional communication with the driver. Sends new messages from the
@@ -44,12 +52,21 @@ impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address> PublisherSession<Io, A> {
     pub(super) fn new(
         addr: A,
         stream: PublisherStream<Io>,
+        datagrams: Option<DatagramStream>,
         channel: Channel<TopicMessage, SessionCommand>,
     ) -> Self {
+        let mut egress = VecDeque::with_capacity(4);
+
+        // Tell the publisher first that we receive datagrams
+        if datagrams.is_some() {
+            egress.push_back(pubsub::Message::new_datagrams());
+        }
+
         Self {
             addr,
             stream,
-            egress: VecDeque::with_capacity(4),
+            datagrams,
+            egress,
             stats: Arc::new(SessionStats::default()),
             driver_channel: channel,
         }
@@ -74,6 +91,23 @@ impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address> PublisherSession<Io, A> {
 
     /// Handles incoming messages. On a successful message, the session stats are updated and the
     /// message is forwarded to the driver.
+    /// Queues a catch-up message for this publisher.
+    /// On the next poll, the message will be attempted to be sent.
+    fn catch_up(&mut self, topic: String, from: LogPosition) {
+        let (seq, timestamp) = match from {
+            LogPosition::Seq(seq) => (seq, 0),
+            LogPosition::Timestamp(timestamp) => (0, timestamp),
+        };
+
+        self.egress.push_back(pubsub::Message::new_catch_up(Bytes::from(topic), seq, timestamp));
+    }
+
+    /// Queues a replay message for this publisher.
+    /// On the next poll, the message will be attempted to be sent.
+    fn replay(&mut self, seq: u32) {
+        self.egress.push_back(pubsub::Message::new_replay(seq));
+    }
+
     fn on_incoming(&mut self, incoming: Result<TopicMessage, pubsub::Error>) {
         match incoming {
             Ok(msg) => {
@@ -96,6 +130,8 @@ impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address> PublisherSession<Io, A> {
         match cmd {
             SessionCommand::Subscribe(topic) => self.subscribe(topic),
             SessionCommand::Unsubscribe(topic) => self.unsubscribe(topic),
+            SessionCommand::CatchUp(topic, from) => self.catch_up(topic, from),
+            SessionCommand::Replay(seq) => self.replay(seq),
         }
     }
 }
@@ -123,6 +159,20 @@ impl<Io: AsyncRead + AsyncWrite + Unpin, A: Address + Unpin> Future for Publishe
                 Poll::Pending => {}
             }
 
+            if let Some(ref mut datagrams) = this.datagrams {
+                match datagrams.poll_next_unpin(cx) {
+                    Poll::Ready(Some(result)) => {
+                        this.on_incoming(result);
+                        continue;
+                    }
+                    Poll::Ready(None) => {
+                        debug!(addr = ?this.addr, "Datagrams closed");
+                        this.datagrams = None;
+                    }
+                    Poll::Pending => {}
+                }
+            }
+
             let mut progress = false;
             while let Some(msg) = this.egress.pop_front() {
                 // TODO(perf): do we need to clone the message here?
@@ -16,10 +16,12 @@ use tokio::{
 };
 
 use msg_common::JoinMap;
-use msg_transport::{Address, Transport};
+use msg_transport::{inproc::InprocAddr, Address, DatagramTransport, Transport};
+
+use crate::datagrams_of;
 
 use super::{
-    Command, PubMessage, SocketState, SocketStats, SubDriver, SubError, SubOptions,
+    Command, LogPosition, PubMessage, SocketState, SocketStats, SubDriver, SubError, SubOptions,
     DEFAULT_BUFFER_SIZE,
 };
 
@@ -131,6 +133,54 @@ where
     }
 }
 
+impl<T> SubSocket<T, InprocAddr>
+where
+    T: Transport<InprocAddr> + Send + Sync + Unpin + 'static,
+{
+    /// Connects to the given in-process endpoint asynchronously.
+    pub async fn connect_inproc(&mut self, name: impl Into<InprocAddr>) -> Result<(), SubError> {
+        self.connect_inner(name.into()).await
+    }
+
+    /// Attempts to connect to the given in-process endpoint immediately.
+    pub fn try_connect_inproc(&mut self, name: impl Into<InprocAddr>) -> Result<(), SubError> {
+        self.try_connect_inner(name.into())
+    }
+
+    /// Disconnects from the given in-process endpoint asynchronously.
+    pub async fn disconnect_inproc(
+        &mut self,
+        name: impl Into<InprocAddr>,
+    ) -> Result<(), SubError> {
+        self.disconnect_inner(name.into()).await
+    }
+
+    /// Attempts to disconnect from the given in-process endpoint immediately.
+    pub fn try_disconnect_inproc(&mut self, name: impl Into<InprocAddr>) -> Result<(), SubError> {
+        self.try_disconnect_inner(name.into())
+    }
+}
+
+impl<T, A> SubSocket<T, A>
+where
+    T: DatagramTransport<A> + Send + Sync + Unpin + 'static,
+    A: Address,
+{
+    /// Receives the messages that publishers send as unreliable datagrams (see
+    /// [`PubSocket::with_datagram_topics`](crate::PubSocket::with_datagram_topics)), if the
+    /// transport has datagrams enabled. Lost messages, and messages that arrive after a newer
+    /// message on the same topic, show up as gaps (see [`PubMessage::gap`]). Without this,
+    /// publishers send all messages on the stream.
+    ///
+    /// # Panics
+    /// If the socket is connected already.
+    pub fn with_datagrams(mut self) -> Self {
+        let driver = self.driver.as_mut().expect("Socket is connected already");
+        driver.datagrams = Some(datagrams_of::<T, A>);
+        self
+    }
+}
+
 impl<T, A> SubSocket<T, A>
 where
     T: Transport<A> + Send + Sync + Unpin + 'static,
@@ -159,8 +209,10 @@ where
             to_socket,
             connection_tasks: JoinMap::new(),
             publishers,
+            sequences: FxHashMap::default(),
             subscribed_topics: HashSet::with_capacity(32),
             state: Arc::clone(&state),
+            datagrams: None,
         };
 
         Self {
@@ -227,6 +279,42 @@ where
         Ok(())
     }
 
+    /// Subscribes to the given topic, and asks all connected publishers to first send the
+    /// messages in their durable log from the given position (see
+    /// [`PubOptions::durable_log`](crate::PubOptions::durable_log)). Publishers without a log
+    /// only send new messages. Publishers that are connected after this call are subscribed to
+    /// without catching up.
+    pub async fn catch_up(
+        &mut self,
+        topic: impl Into<String>,
+        from: LogPosition,
+    ) -> Result<(), SubError> {
+        self.ensure_active_driver();
+
+        let topic = topic.into();
+        assert!(!topic.starts_with("MSG"), "MSG is a reserved topic");
+
+        self.send_command(Command::CatchUp { topic, from }).await?;
+
+        Ok(())
+    }
+
+    /// Immediately send a catch-up command to the driver.
+    pub fn try_catch_up(
+        &mut self,
+        topic: impl Into<String>,
+        from: LogPosition,
+    ) -> Result<(), SubError> {
+        self.ensure_active_driver();
+
+        let topic = topic.into();
+        assert!(!topic.starts_with("MSG"), "MSG is a reserved topic");
+
+        self.try_send_command(Command::CatchUp { topic, from })?;
+
+        Ok(())
+    }
+
     /// Unsubscribe from the given topic. This will unsubscribe from all connected publishers.
     pub async fn unsubscribe(&mut self, topic: impl Into<String>) -> Result<(), SubError> {
         self.ensure_active_driver();
@@ -44,6 +44,26 @@ impl<A: Address> SocketStats<A> {
     pub fn avg_latency(&self, session_addr: &A) -> Option<u64> {
         self.session_stats.read().get(session_addr).map(|stats| stats.avg_latency())
     }
+
+    /// Returns the number of gaps in the sequence of messages from the given session.
+    #[inline]
+    pub fn gaps(&self, session_addr: &A) -> Option<usize> {
+        self.session_stats.read().get(session_addr).map(|stats| stats.gaps())
+    }
+
+    /// Returns the number of messages lost in gaps from the given session.
+    #[inline]
+    pub fn lost_messages(&self, session_addr: &A) -> Option<usize> {
+        self.session_stats.read().get(session_addr).map(|stats| stats.lost_messages())
+    }
+
+    /// Records a gap of `lost` messages from the given session.
+    #[inline]
+    pub(crate) fn record_gap(&self, session_addr: &A, lost: u32) {
+        if let Some(stats) = self.session_stats.read().get(session_addr) {
+            stats.record_gap(lost);
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -54,6 +74,10 @@ pub struct SessionStats {
     latency: AtomicU64,
     /// Index used to calculate CA
     latency_idx: AtomicU64,
+    /// Total number of gaps in the message sequence
+    gaps: AtomicUsize,
+    /// Total number of messages lost in gaps
+    lost_messages: AtomicUsize,
 }
 
 impl SessionStats {
@@ -74,11 +98,27 @@ impl SessionStats {
         self.latency.store(new, Ordering::Relaxed);
     }
 
+    #[inline]
+    pub(crate) fn record_gap(&self, lost: u32) {
+        self.gaps.fetch_add(1, Ordering::Relaxed);
+        self.lost_messages.fetch_add(lost as usize, Ordering::Relaxed);
+    }
+
     #[inline]
     pub fn bytes_rx(&self) -> usize {
         self.bytes_rx.load(Ordering::Relaxed)
     }
 
+    #[inline]
+    pub fn gaps(&self) -> usize {
+        self.gaps.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn lost_messages(&self) -> usize {
+        self.lost_messages.load(Ordering::Relaxed)
+    }
+
     #[inline]
     pub fn avg_latency(&self) -> u64 {
         self.latency.load(Ordering::Relaxed)
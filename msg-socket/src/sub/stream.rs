@@ -1,7 +1,9 @@
 use bytes::Bytes;
-use futures::{SinkExt, Stream, StreamExt};
+use futures::{future::BoxFuture, FutureExt, SinkExt, Stream, StreamExt};
 use std::{
+    io,
     pin::Pin,
+    sync::Arc,
     task::{ready, Context, Poll},
 };
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -9,6 +11,7 @@ use tokio_util::codec::Framed;
 use tracing::{debug, trace};
 
 use super::SubError;
+use msg_transport::Datagrams;
 use msg_wire::pubsub;
 
 /// Wraps a framed connection to a publisher and exposes all the PUBSUB specific methods.
@@ -49,10 +52,28 @@ impl<Io: AsyncRead + AsyncWrite + Unpin> From<Framed<Io, pubsub::Codec>> for Pub
 }
 
 pub(super) struct TopicMessage {
+    pub seq: u32,
+    pub topic_seq: u32,
     pub timestamp: u64,
     pub compression_type: u8,
     pub topic: String,
     pub payload: Bytes,
+    /// Whether the message was received as datagrams.
+    pub unreliable: bool,
+}
+
+impl TopicMessage {
+    fn from_wire(msg: pubsub::Message, unreliable: bool) -> Self {
+        let seq = msg.seq();
+        let topic_seq = msg.topic_seq();
+        let timestamp = msg.timestamp();
+        let compression_type = msg.compression_type();
+        let (topic, payload) = msg.into_parts();
+
+        // TODO: this will allocate. Can we just return the `Cow`?
+        let topic = String::from_utf8_lossy(&topic).to_string();
+        Self { seq, topic_seq, compression_type, timestamp, topic, payload, unreliable }
+    }
 }
 
 impl<Io: AsyncRead + AsyncWrite + Unpin> Stream for PublisherStream<Io> {
@@ -68,18 +89,53 @@ impl<Io: AsyncRead + AsyncWrite + Unpin> Stream for PublisherStream<Io> {
             this.flush = false
         }
 
-        if let Some(result) = ready!(this.conn.poll_next_unpin(cx)) {
-            return Poll::Ready(Some(result.map(|msg| {
-                let timestamp = msg.timestamp();
-                let compression_type = msg.compression_type();
-                let (topic, payload) = msg.into_parts();
+        // The connection was closed by the publisher
+        let Some(result) = ready!(this.conn.poll_next_unpin(cx)) else {
+            return Poll::Ready(None);
+        };
 
-                // TODO: this will allocate. Can we just return the `Cow`?
-                let topic = String::from_utf8_lossy(&topic).to_string();
-                TopicMessage { compression_type, timestamp, topic, payload }
-            })));
-        }
+        Poll::Ready(Some(result.map(|msg| TopicMessage::from_wire(msg, false))))
+    }
+}
+
+/// Receives the messages that a publisher sends as datagrams, see
+/// [`SubSocket::with_datagrams`](super::SubSocket::with_datagrams).
+pub(super) struct DatagramStream {
+    /// The datagram handle of the connection.
+    datagrams: Arc<dyn Datagrams>,
+    /// Reassembles the messages that were fragmented into multiple datagrams.
+    reassembler: pubsub::Reassembler,
+    /// The pending receive of the next datagram.
+    recv: Option<BoxFuture<'static, io::Result<Bytes>>>,
+}
 
-        Poll::Pending
+impl DatagramStream {
+    pub(super) fn new(datagrams: Arc<dyn Datagrams>) -> Self {
+        Self { datagrams, reassembler: pubsub::Reassembler::new(), recv: None }
+    }
+}
+
+impl Stream for DatagramStream {
+    type Item = Result<TopicMessage, pubsub::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            let recv = this.recv.get_or_insert_with(|| this.datagrams.recv_datagram());
+            let result = ready!(recv.poll_unpin(cx));
+            this.recv = None;
+
+            // Receiving only fails once the connection is closed
+            let Ok(datagram) = result else {
+                return Poll::Ready(None);
+            };
+
+            match this.reassembler.push(datagram) {
+                Ok(Some(msg)) => return Poll::Ready(Some(Ok(TopicMessage::from_wire(msg, true)))),
+                Ok(None) => continue,
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
     }
 }
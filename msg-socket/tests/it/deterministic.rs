@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use msg_sim::{
+    deterministic::{Network, Runtime},
+    SimulationConfig,
+};
+use tokio::{task::JoinHandle, time::Instant};
+use tokio_stream::StreamExt;
+
+use msg_socket::{PubSocket, RepSocket, ReqError, ReqOptions, ReqSocket, SubSocket};
+
+const TOPIC: &str = "test";
+
+/// Spawns a publisher on the given node, which publishes a message every 100ms.
+async fn spawn_publisher(network: &Network, node: &str) -> JoinHandle<()> {
+    let mut publisher = PubSocket::new(network.transport(node));
+    publisher.bind("feed").await.unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            publisher.publish(TOPIC, Bytes::from("WORLD")).await.unwrap();
+        }
+    })
+}
+
+/// Requests time out while the nodes are partitioned, and succeed again once it's healed.
+#[test]
+fn reqrep_partition() {
+    Runtime::from_env().run(|network| async move {
+        let mut rep = RepSocket::new(network.transport("server"));
+        rep.bind("service").await.unwrap();
+
+        tokio::spawn(async move {
+            while let Some(req) = rep.next().await {
+                let msg = req.msg().clone();
+                // Requests that timed out during the partition are cancelled by the client
+                let _ = req.respond(msg);
+            }
+        });
+
+        let timeout = Duration::from_secs(5);
+        let mut req = ReqSocket::with_options(
+            network.transport("client"),
+            ReqOptions::default().timeout(timeout),
+        );
+        req.connect("service").await.unwrap();
+
+        let res = req.request(Bytes::from("hello")).await.unwrap();
+        assert_eq!(res, "hello");
+
+        network.partition("client", "server");
+
+        // The timeout is checked on an interval of a tenth of the timeout
+        let start = Instant::now();
+        let err = req.request(Bytes::from("hello")).await.unwrap_err();
+        assert!(matches!(err, ReqError::Timeout));
+        assert!(start.elapsed() >= timeout && start.elapsed() <= timeout + timeout / 10);
+
+        network.heal("client", "server");
+
+        let res = req.request(Bytes::from("world")).await.unwrap();
+        assert_eq!(res, "world");
+    });
+}
+
+/// The subscriber reconnects to a publisher after it restarts.
+#[test]
+fn pubsub_publisher_restart() {
+    Runtime::from_env().run(|network| async move {
+        let publisher = spawn_publisher(&network, "publisher").await;
+
+        let mut subscriber = SubSocket::new(network.transport("subscriber"));
+        subscriber.connect_inproc("feed").await.unwrap();
+        subscriber.subscribe(TOPIC).await.unwrap();
+
+        let msg = subscriber.next().await.unwrap();
+        assert_eq!("WORLD", msg.payload());
+
+        // Crash the publisher, and restart it a minute later
+        publisher.abort();
+        network.crash("publisher");
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        let _publisher = spawn_publisher(&network, "publisher").await;
+
+        let msg = subscriber.next().await.unwrap();
+        assert_eq!("WORLD", msg.payload());
+    });
+}
+
+/// Running a simulation with the same seed results in the same interleaving.
+#[test]
+fn simulation_replay() {
+    fn simulate(runtime: Runtime) -> Vec<String> {
+        runtime.run(|network| async move {
+            let publisher = spawn_publisher(&network, "publisher").await;
+
+            // A lossy link with jitter and random resets
+            let mut subscriber = SubSocket::new(network.simulated(
+                "subscriber",
+                SimulationConfig {
+                    latency: Some(Duration::from_millis(20)),
+                    jitter: Some(Duration::from_millis(50)),
+                    plr: Some(5.0),
+                    disconnect_rate: Some(1.0),
+                    ..Default::default()
+                },
+            ));
+            subscriber.connect_inproc("feed").await.unwrap();
+            subscriber.subscribe(TOPIC).await.unwrap();
+
+            let start = Instant::now();
+            let mut received = Vec::new();
+            for _ in 0..20 {
+                let msg = subscriber.next().await.unwrap();
+                received.push(format!("{:?} {}", start.elapsed(), msg.seq()));
+            }
+
+            publisher.abort();
+            received.extend(network.events().into_iter().map(|event| format!("{event:?}")));
+            received
+        })
+    }
+
+    let runtime = Runtime::from_env();
+    assert_eq!(simulate(runtime), simulate(runtime));
+}
@@ -1,3 +1,5 @@
+mod deterministic;
 mod pubsub;
+mod reqrep;
 
 fn main() {}
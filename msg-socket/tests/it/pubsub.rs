@@ -1,14 +1,19 @@
 use std::{collections::HashSet, net::IpAddr, time::Duration};
 
 use bytes::Bytes;
-use msg_sim::{Protocol, SimulationConfig, Simulator};
+use msg_sim::{Protocol, SimulatedTransport, SimulationConfig, Simulator};
 use rand::Rng;
 use tokio::{sync::mpsc, task::JoinSet};
 use tokio_stream::StreamExt;
 use tracing::info;
 
 use msg_socket::{PubSocket, SubSocket};
-use msg_transport::{quic::Quic, tcp::Tcp, Address, Transport};
+use msg_transport::{
+    inproc::Inproc,
+    quic::{self, Quic},
+    tcp::Tcp,
+    Address, Transport,
+};
 
 const TOPIC: &str = "test";
 
@@ -34,6 +39,7 @@ async fn pubsub_channel() {
             bw: None,
             plr: None,
             protocols: vec![Protocol::UDP, Protocol::TCP],
+            ..Default::default()
         },
     );
 
@@ -99,6 +105,7 @@ async fn pubsub_fan_out() {
             bw: None,
             plr: None,
             protocols: vec![Protocol::UDP, Protocol::TCP],
+            ..Default::default()
         },
     );
 
@@ -178,14 +185,17 @@ async fn pubsub_fan_in() {
             bw: None,
             plr: None,
             protocols: vec![Protocol::UDP, Protocol::TCP],
+            ..Default::default()
         },
     );
 
-    let result = pubsub_fan_in_transport(build_tcp, 20, "127.0.0.1:9881".parse().unwrap()).await;
+    let addrs = vec!["127.0.0.1:9881".parse().unwrap(); 20];
+    let result = pubsub_fan_in_transport(build_tcp, addrs).await;
 
     assert!(result.is_ok());
 
-    let result = pubsub_fan_in_transport(build_quic, 20, "127.0.0.1:9881".parse().unwrap()).await;
+    let addrs = vec!["127.0.0.1:9881".parse().unwrap(); 20];
+    let result = pubsub_fan_in_transport(build_quic, addrs).await;
 
     assert!(result.is_ok());
 
@@ -198,16 +208,15 @@ async fn pubsub_fan_in_transport<
     A: Address,
 >(
     new_transport: F,
-    publishers: usize,
-    addr: A,
+    addrs: Vec<A>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let publishers = addrs.len();
     let mut sub_tasks = JoinSet::new();
 
     let (tx, mut rx) = mpsc::channel(publishers);
 
-    for i in 0..publishers {
+    for (i, addr) in addrs.into_iter().enumerate() {
         let tx = tx.clone();
-        let addr = addr.clone();
         sub_tasks.spawn(async move {
             let mut publisher = PubSocket::new(new_transport());
             inject_delay((100 * (i + 1)) as u64).await;
@@ -264,12 +273,96 @@ async fn pubsub_fan_in_transport<
     Ok(())
 }
 
+/// Single publisher, single subscriber over the in-process transport, without any network setup.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn pubsub_channel_inproc() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let result = pubsub_channel_transport(Inproc::default, "pubsub-channel".into()).await;
+
+    assert!(result.is_ok());
+}
+
+/// Single publisher, multiple subscribers over the in-process transport.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn pubsub_fan_out_inproc() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let result = pubsub_fan_out_transport(Inproc::default, 10, "pubsub-fan-out".into()).await;
+
+    assert!(result.is_ok());
+}
+
+/// Multiple publishers, single subscriber over the in-process transport.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn pubsub_fan_in_inproc() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let addrs = (0..20).map(|i| format!("pubsub-fan-in-{i}").into()).collect();
+    let result = pubsub_fan_in_transport(Inproc::default, addrs).await;
+
+    assert!(result.is_ok());
+}
+
+/// Single publisher, single subscriber over an impaired in-process network, which works without
+/// any privileges.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn pubsub_channel_simulated() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let new_transport = || {
+        SimulatedTransport::new(
+            Inproc::default(),
+            SimulationConfig {
+                latency: Some(Duration::from_millis(50)),
+                jitter: Some(Duration::from_millis(20)),
+                bw: Some(10_000),
+                plr: Some(1.0),
+                ..Default::default()
+            },
+        )
+    };
+
+    let result = pubsub_channel_transport(new_transport, "pubsub-simulated".into()).await;
+
+    assert!(result.is_ok());
+}
+
+/// The subscriber reconnects when the connection is reset, and keeps receiving messages.
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn pubsub_simulated_disconnects() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    let mut publisher = PubSocket::new(Inproc::default());
+    publisher.bind("pubsub-simulated-disconnects").await.unwrap();
+
+    // Every connection is reset after a few messages
+    let mut subscriber = SubSocket::new(SimulatedTransport::new(
+        Inproc::default(),
+        SimulationConfig { disconnect_after: Some(512), ..Default::default() },
+    ));
+    subscriber.connect_inner("pubsub-simulated-disconnects".into()).await.unwrap();
+    subscriber.subscribe(TOPIC).await.unwrap();
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            publisher.publish(TOPIC, Bytes::from("WORLD")).await.unwrap();
+        }
+    });
+
+    for _ in 0..50 {
+        let msg = subscriber.next().await.unwrap();
+        assert_eq!("WORLD", msg.payload());
+    }
+}
+
 fn build_tcp() -> Tcp {
     Tcp::default()
 }
 
 fn build_quic() -> Quic {
-    Quic::default()
+    Quic::new(quic::Config::insecure())
 }
 
 fn random_delay(upper_ms: u64) -> Duration {
@@ -0,0 +1,64 @@
+use std::{sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use tokio_stream::StreamExt;
+
+use msg_socket::{RepOptions, RepSocket, ReqOptions, ReqSocket};
+use msg_transport::quic::{self, Quic};
+
+/// A request that stalls its stream doesn't block the requests on other streams of the same
+/// connection.
+#[tokio::test]
+async fn reqrep_stream_per_request() {
+    let _ = tracing_subscriber::fmt::try_init();
+
+    assert!(stalled_request_blocks_next(false).await);
+    assert!(!stalled_request_blocks_next(true).await);
+}
+
+/// Sends a request that stalls its stream, and returns whether it blocks the next request. The
+/// reply socket stops reading a stream while a request on it is pending, and only responds to the
+/// stalled request once the next one is responded to.
+async fn stalled_request_blocks_next(stream_per_request: bool) -> bool {
+    let options = RepOptions::default().max_pending_requests_per_peer(1);
+    let mut rep = RepSocket::with_options(Quic::new(quic::Config::insecure()), options);
+    if stream_per_request {
+        rep = rep.with_request_streams();
+    }
+    rep.bind("127.0.0.1:0").await.unwrap();
+    let addr = *rep.local_addr().unwrap();
+
+    let options = ReqOptions::default().timeout(Duration::from_secs(5));
+    let mut req = ReqSocket::with_options(Quic::new(quic::Config::insecure()), options);
+    if stream_per_request {
+        req = req.with_stream_per_request();
+    }
+    req.connect(addr).await.unwrap();
+    let req = Arc::new(req);
+
+    let request = |payload: &'static str| {
+        let req = Arc::clone(&req);
+        tokio::spawn(async move { req.request(Bytes::from(payload)).await })
+    };
+
+    let first = request("first");
+    let stalled = rep.next().await.unwrap();
+    assert_eq!(stalled.msg(), "first");
+
+    let second = request("second");
+    let blocked = match tokio::time::timeout(Duration::from_secs(1), rep.next()).await {
+        Ok(next) => {
+            let next = next.unwrap();
+            assert_eq!(next.msg(), "second");
+            next.respond(Bytes::from("second")).unwrap();
+            assert_eq!(second.await.unwrap().unwrap(), "second");
+            false
+        }
+        Err(_) => true,
+    };
+
+    stalled.respond(Bytes::from("first")).unwrap();
+    assert_eq!(first.await.unwrap().unwrap(), "first");
+
+    blocked
+}
@@ -0,0 +1,319 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream},
+    sync::mpsc,
+};
+use tracing::debug;
+
+use crate::{Acceptor, PeerAddress, PeerMetadata, Transport, TransportExt};
+
+use msg_common::async_error;
+
+/// The size of the buffer of each direction of a connection, in bytes.
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The name of an in-process endpoint, like `"my-service"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InprocAddr(String);
+
+impl InprocAddr {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    /// Returns the name of the endpoint.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for InprocAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&str> for InprocAddr {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+impl From<String> for InprocAddr {
+    fn from(name: String) -> Self {
+        Self(name)
+    }
+}
+
+/// The sender of the new connections to a bound endpoint.
+type Incoming = mpsc::UnboundedSender<InprocStream>;
+
+/// The process-global registry of bound endpoints.
+fn registry() -> &'static Mutex<HashMap<InprocAddr, Incoming>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<InprocAddr, Incoming>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// Removes the endpoint with the given address from the registry, if it's still registered with
+/// the given sender.
+fn unregister(addr: &InprocAddr, tx: &Incoming) {
+    let mut registry = registry().lock().expect("Registry lock poisoned");
+    if registry.get(addr).is_some_and(|registered| registered.same_channel(tx)) {
+        registry.remove(addr);
+    }
+}
+
+/// Returns a unique address for the connecting side of a connection.
+fn next_peer_addr() -> InprocAddr {
+    static NEXT_PEER: AtomicU64 = AtomicU64::new(0);
+    InprocAddr(format!("inproc-peer-{}", NEXT_PEER.fetch_add(1, Ordering::Relaxed)))
+}
+
+#[derive(Debug)]
+pub struct Config {
+    /// The size of the buffer of each direction of a connection, in bytes. Writes wait once the
+    /// buffer is full, until the other side reads.
+    pub buffer_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self { buffer_size: DEFAULT_BUFFER_SIZE }
+    }
+}
+
+/// An in-process transport, which connects endpoints in the same process by name through
+/// in-memory pipes. It doesn't use any network or file system resources, so it's well suited for
+/// tests and for components in the same process.
+///
+/// Bound endpoints are kept in a process-global registry until the transport is dropped. Each
+/// connection gets a unique peer address on the accepting side.
+///
+/// # Example
+/// ```no_run
+/// use msg_transport::{inproc::Inproc, Transport};
+///
+/// # async fn example() -> std::io::Result<()> {
+/// let mut server = Inproc::default();
+/// server.bind("my-service".into()).await?;
+///
+/// let mut client = Inproc::default();
+/// let stream = client.connect("my-service".into()).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct Inproc {
+    config: Config,
+    /// The address this transport is bound to.
+    addr: Option<InprocAddr>,
+    /// The new connections to the bound endpoint, and the sender registered for them.
+    incoming: Option<(Incoming, mpsc::UnboundedReceiver<InprocStream>)>,
+}
+
+impl Inproc {
+    pub fn new(config: Config) -> Self {
+        Self { config, addr: None, incoming: None }
+    }
+}
+
+impl Drop for Inproc {
+    fn drop(&mut self) {
+        if let (Some(addr), Some((tx, _))) = (&self.addr, &self.incoming) {
+            unregister(addr, tx);
+        }
+    }
+}
+
+/// One side of an in-process connection.
+pub struct InprocStream {
+    peer: InprocAddr,
+    stream: DuplexStream,
+}
+
+impl AsyncRead for InprocStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for InprocStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+impl PeerAddress<InprocAddr> for InprocStream {
+    fn peer_addr(&self) -> Result<InprocAddr, io::Error> {
+        Ok(self.peer.clone())
+    }
+}
+
+impl PeerMetadata for InprocStream {}
+
+#[async_trait]
+impl Transport<InprocAddr> for Inproc {
+    type Io = InprocStream;
+
+    type Error = io::Error;
+
+    type Connect = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+    type Accept = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+
+    fn local_addr(&self) -> Option<InprocAddr> {
+        self.addr.clone()
+    }
+
+    /// Registers the endpoint under the given name. Fails if the name is taken by another
+    /// transport already.
+    async fn bind(&mut self, addr: InprocAddr) -> Result<(), Self::Error> {
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        {
+            let mut registry = registry().lock().expect("Registry lock poisoned");
+            if registry.get(&addr).is_some_and(|registered| !registered.is_closed()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("Inproc address {addr} is already bound"),
+                ));
+            }
+
+            registry.insert(addr.clone(), tx.clone());
+        }
+
+        debug!(%addr, "Bound inproc endpoint");
+
+        // Unregister the previous address, if any
+        let previous = self.addr.replace(addr);
+        if let (Some(previous), Some((previous_tx, _))) = (previous, &self.incoming) {
+            unregister(&previous, previous_tx);
+        }
+
+        self.incoming = Some((tx, rx));
+        Ok(())
+    }
+
+    /// Connects to the endpoint with the given name. Fails if no endpoint is bound to it.
+    fn connect(&mut self, addr: InprocAddr) -> Self::Connect {
+        let incoming = registry().lock().expect("Registry lock poisoned").get(&addr).cloned();
+        let Some(incoming) = incoming else {
+            return async_error(io::ErrorKind::ConnectionRefused.into());
+        };
+
+        let (local, remote) = tokio::io::duplex(self.config.buffer_size);
+        let remote = InprocStream { peer: next_peer_addr(), stream: remote };
+
+        if incoming.send(remote).is_err() {
+            return async_error(io::ErrorKind::ConnectionRefused.into());
+        }
+
+        let stream = InprocStream { peer: addr, stream: local };
+        Box::pin(async move { Ok(stream) })
+    }
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Accept> {
+        let this = self.get_mut();
+
+        let Some((_, ref mut incoming)) = this.incoming else {
+            return Poll::Ready(async_error(io::ErrorKind::NotConnected.into()));
+        };
+
+        match incoming.poll_recv(cx) {
+            Poll::Ready(Some(stream)) => {
+                debug!(peer = %stream.peer, "Accepted inproc connection");
+                Poll::Ready(Box::pin(async move { Ok(stream) }))
+            }
+            // We hold a sender, so the channel is never closed
+            Poll::Ready(None) => unreachable!("Incoming channel closed"),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl TransportExt<InprocAddr> for Inproc {
+    fn accept(&mut self) -> Acceptor<'_, Self, InprocAddr>
+    where
+        Self: Sized + Unpin,
+    {
+        Acceptor::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn inproc_connection() {
+        let mut server = Inproc::default();
+        server.bind("inproc-connection".into()).await.unwrap();
+
+        let mut client = Inproc::default();
+        let mut stream = client.connect("inproc-connection".into()).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), InprocAddr::from("inproc-connection"));
+
+        let mut accepted = server.accept().await.unwrap();
+        assert_ne!(accepted.peer_addr().unwrap(), stream.peer_addr().unwrap());
+
+        stream.write_all(b"Hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        accepted.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"Hello");
+
+        accepted.write_all(b"World").await.unwrap();
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"World");
+    }
+
+    #[tokio::test]
+    async fn inproc_bind_and_drop() {
+        let mut client = Inproc::default();
+        let err = client.connect("inproc-bind".into()).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+
+        let mut server = Inproc::default();
+        server.bind("inproc-bind".into()).await.unwrap();
+
+        let err = Inproc::default().bind("inproc-bind".into()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        // The name is released once the transport is dropped
+        drop(server);
+        let err = client.connect("inproc-bind".into()).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+
+        let mut server = Inproc::default();
+        server.bind("inproc-bind".into()).await.unwrap();
+        assert!(client.connect("inproc-bind".into()).await.is_ok());
+    }
+}
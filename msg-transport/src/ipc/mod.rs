@@ -13,7 +13,7 @@ use tokio::{
 };
 use tracing::debug;
 
-use crate::{Acceptor, PeerAddress, Transport, TransportExt};
+use crate::{Acceptor, PeerAddress, PeerMetadata, Transport, TransportExt};
 
 use msg_common::async_error;
 
@@ -92,6 +92,8 @@ impl PeerAddress<PathBuf> for IpcStream {
     }
 }
 
+impl PeerMetadata for IpcStream {}
+
 #[async_trait]
 impl Transport<PathBuf> for Ipc {
     type Io = IpcStream;
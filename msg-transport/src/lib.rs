@@ -10,16 +10,20 @@ use std::{
     net::SocketAddr,
     path::PathBuf,
     pin::Pin,
-    task::{Context, Poll},
+    task::{ready, Context, Poll},
 };
 
 use async_trait::async_trait;
-use futures::{Future, FutureExt};
+use bytes::Bytes;
+use futures::{future::BoxFuture, Future, FutureExt};
 use tokio::io::{AsyncRead, AsyncWrite};
 
+pub mod inproc;
 pub mod ipc;
+pub mod noise;
 pub mod quic;
 pub mod tcp;
+pub mod tcp_tls;
 
 /// A trait for address types that can be used by any transport.
 pub trait Address: Clone + Debug + Send + Sync + Unpin + Hash + Eq + 'static {}
@@ -30,6 +34,9 @@ impl Address for SocketAddr {}
 /// File system path, used for IPC transport.
 impl Address for PathBuf {}
 
+/// Endpoint name, used for the in-process transport.
+impl Address for inproc::InprocAddr {}
+
 /// A transport provides connection-oriented communication between two peers through
 /// ordered and reliable streams of bytes.
 ///
@@ -40,7 +47,7 @@ pub trait Transport<A: Address> {
     ///
     /// The output type is transport-specific, and can be a handle to directly write to the
     /// connection, or it can be a substream multiplexer in the case of stream protocols.
-    type Io: AsyncRead + AsyncWrite + PeerAddress<A> + Send + Unpin;
+    type Io: AsyncRead + AsyncWrite + PeerAddress<A> + PeerMetadata + Send + Unpin;
 
     /// An error that occurred when setting up the connection.
     type Error: std::error::Error + From<io::Error> + Send + Sync;
@@ -68,6 +75,53 @@ pub trait Transport<A: Address> {
     fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Accept>;
 }
 
+/// A transport whose connections can carry multiple independent streams, so that one slow stream
+/// doesn't block the others (head-of-line blocking).
+///
+/// [`Transport::connect`] and [`Transport::poll_accept`] still return the first stream of a new
+/// connection. Drivers that want more streams can open them on the connection of an existing
+/// stream, and accept the streams opened by the peer in the same way.
+pub trait MultiplexedTransport<A: Address>: Transport<A> {
+    /// A pending stream opened with [`MultiplexedTransport::open_stream`].
+    type OpenStream: Future<Output = Result<Self::Io, Self::Error>> + Send;
+
+    /// A pending stream accepted with [`MultiplexedTransport::accept_stream`].
+    type AcceptStream: Future<Output = Result<Self::Io, Self::Error>> + Send;
+
+    /// Opens a new stream on the connection that the given stream belongs to.
+    fn open_stream(io: &Self::Io) -> Self::OpenStream;
+
+    /// Accepts the next stream opened by the peer on the connection that the given stream
+    /// belongs to.
+    fn accept_stream(io: &Self::Io) -> Self::AcceptStream;
+}
+
+/// A transport whose connections can also carry unreliable datagrams next to their streams.
+/// Datagrams may be lost, duplicated or reordered, and are never retransmitted.
+pub trait DatagramTransport<A: Address>: Transport<A> {
+    /// The handle to send and receive the datagrams of a connection.
+    type Datagrams: Datagrams;
+
+    /// Returns the datagram handle of the connection that the given stream belongs to. `None` if
+    /// datagrams are disabled locally, or not supported by the peer.
+    fn datagrams(io: &Self::Io) -> Option<Self::Datagrams>;
+}
+
+/// A handle to send and receive the unreliable datagrams of a connection, see
+/// [`DatagramTransport`].
+pub trait Datagrams: Send + Sync + 'static {
+    /// Returns the maximum size of a datagram that can currently be sent, which depends on the
+    /// path MTU. `None` if datagrams can't be sent.
+    fn max_datagram_size(&self) -> Option<usize>;
+
+    /// Queues a datagram to be sent. Fails if the datagram is larger than
+    /// [`Datagrams::max_datagram_size`], or if the connection is closed.
+    fn send_datagram(&self, datagram: Bytes) -> io::Result<()>;
+
+    /// Receives the next datagram. Fails once the connection is closed.
+    fn recv_datagram(&self) -> BoxFuture<'static, io::Result<Bytes>>;
+}
+
 pub trait TransportExt<A: Address>: Transport<A> {
     /// Async-friendly interface for accepting inbound connections.
     fn accept(&mut self) -> Acceptor<'_, Self, A>
@@ -78,14 +132,16 @@ pub trait TransportExt<A: Address>: Transport<A> {
     }
 }
 
-pub struct Acceptor<'a, T, A> {
+pub struct Acceptor<'a, T: Transport<A>, A: Address> {
     inner: &'a mut T,
+    /// The pending inbound connection, if it didn't resolve right away.
+    pending: Option<T::Accept>,
     _marker: PhantomData<A>,
 }
 
-impl<'a, T, A> Acceptor<'a, T, A> {
+impl<'a, T: Transport<A>, A: Address> Acceptor<'a, T, A> {
     fn new(inner: &'a mut T) -> Self {
-        Self { inner, _marker: PhantomData }
+        Self { inner, pending: None, _marker: PhantomData }
     }
 }
 
@@ -97,13 +153,16 @@ where
     type Output = Result<T::Io, T::Error>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        match Pin::new(&mut *self.get_mut().inner).poll_accept(cx) {
-            Poll::Ready(mut accept) => match accept.poll_unpin(cx) {
-                Poll::Ready(Ok(output)) => Poll::Ready(Ok(output)),
-                Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
-                Poll::Pending => Poll::Pending,
-            },
-            Poll::Pending => Poll::Pending,
+        let this = self.get_mut();
+
+        loop {
+            if let Some(ref mut accept) = this.pending {
+                let output = ready!(accept.poll_unpin(cx));
+                this.pending = None;
+                return Poll::Ready(output);
+            }
+
+            this.pending = Some(ready!(Pin::new(&mut *this.inner).poll_accept(cx)));
         }
     }
 }
@@ -112,3 +171,47 @@ where
 pub trait PeerAddress<A: Address> {
     fn peer_addr(&self) -> Result<A, io::Error>;
 }
+
+/// Metadata about an established connection, exposed by the transport.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionMetadata {
+    /// The DER-encoded certificate chain presented by the peer, leaf first.
+    peer_certificates: Option<Vec<Vec<u8>>>,
+    /// The static public key of the peer, authenticated by an encryption layer like
+    /// [`Noise`](noise::Noise).
+    peer_public_key: Option<Vec<u8>>,
+}
+
+impl ConnectionMetadata {
+    /// Sets the DER-encoded certificate chain presented by the peer.
+    pub fn with_peer_certificates(mut self, certificates: Vec<Vec<u8>>) -> Self {
+        self.peer_certificates = Some(certificates);
+        self
+    }
+
+    /// Returns the DER-encoded certificate chain presented by the peer, leaf first. `None` if
+    /// the transport doesn't use TLS, or the peer didn't present a certificate.
+    pub fn peer_certificates(&self) -> Option<&[Vec<u8>]> {
+        self.peer_certificates.as_deref()
+    }
+
+    /// Sets the static public key of the peer.
+    pub fn with_peer_public_key(mut self, key: Vec<u8>) -> Self {
+        self.peer_public_key = Some(key);
+        self
+    }
+
+    /// Returns the static public key of the peer. `None` if the transport doesn't authenticate
+    /// peers with static keys.
+    pub fn peer_public_key(&self) -> Option<&[u8]> {
+        self.peer_public_key.as_deref()
+    }
+}
+
+/// Trait for connection types that can return metadata about their connection. Transports that
+/// have no metadata to expose can rely on the default implementation.
+pub trait PeerMetadata {
+    fn metadata(&self) -> ConnectionMetadata {
+        ConnectionMetadata::default()
+    }
+}
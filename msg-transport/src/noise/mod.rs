@@ -0,0 +1,364 @@
+use std::{
+    collections::HashSet,
+    fmt, io,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use snow::{
+    params::DHChoice,
+    resolvers::{CryptoResolver, DefaultResolver},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::debug;
+
+use crate::{Address, Transport, TransportExt};
+
+mod stream;
+pub use stream::NoiseStream;
+
+/// The Noise protocol used by the handshake. With the XX pattern, both peers send their static
+/// public key to the other (encrypted), so neither needs to know the key of the other in advance.
+const PROTOCOL: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// The maximum size of a Noise message on the wire.
+const MAX_MESSAGE_LEN: usize = 65535;
+
+/// How long the handshake can take before the connection is dropped, if not configured.
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A static X25519 keypair, which identifies a peer.
+#[derive(Clone)]
+pub struct Keypair {
+    private: Vec<u8>,
+    public: Vec<u8>,
+}
+
+impl Keypair {
+    /// Generates a new random keypair.
+    pub fn generate() -> Self {
+        let keypair = snow::Builder::new(PROTOCOL.parse().expect("Valid Noise protocol"))
+            .generate_keypair()
+            .expect("Generates valid keypair");
+
+        Self { private: keypair.private, public: keypair.public }
+    }
+
+    /// Creates a keypair from the given 32-byte private key.
+    ///
+    /// # Panics
+    /// If the private key is not 32 bytes long.
+    pub fn from_private_key(private: &[u8]) -> Self {
+        assert_eq!(private.len(), 32, "X25519 private keys are 32 bytes long");
+
+        let mut dh =
+            DefaultResolver.resolve_dh(&DHChoice::Curve25519).expect("X25519 is supported");
+        dh.set(private);
+
+        Self { private: private.to_vec(), public: dh.pubkey().to_vec() }
+    }
+
+    /// Returns the public key.
+    pub fn public_key(&self) -> &[u8] {
+        &self.public
+    }
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keypair").field("public", &self.public).finish_non_exhaustive()
+    }
+}
+
+/// The configuration of the [`Noise`] transport.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The static keypair of this peer.
+    keypair: Keypair,
+    /// The static public keys of the peers we accept. If `None`, every peer is accepted.
+    trusted_keys: Option<HashSet<Vec<u8>>>,
+    /// How long the handshake can take before it fails.
+    handshake_timeout: Duration,
+}
+
+impl Config {
+    /// Creates a new configuration with the given static keypair. By default, peers with any
+    /// static key are accepted, and the handshake times out after 10 seconds.
+    pub fn new(keypair: Keypair) -> Self {
+        Self { keypair, trusted_keys: None, handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT }
+    }
+
+    /// Adds a trusted static public key. Once a key is added, the handshake fails with peers
+    /// whose static key is not trusted.
+    pub fn with_trusted_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.trusted_keys.get_or_insert_with(HashSet::new).insert(key.into());
+        self
+    }
+
+    /// Sets how long the handshake can take, after which it fails with
+    /// [`io::ErrorKind::TimedOut`]. This keeps peers that never complete the handshake from
+    /// holding on to connections.
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Returns `true` if the given static public key is trusted.
+    fn is_trusted(&self, key: &[u8]) -> bool {
+        self.trusted_keys.as_ref().map_or(true, |keys| keys.contains(key))
+    }
+}
+
+/// A transport that wraps another transport, and encrypts all connections with the
+/// [Noise protocol](https://noiseprotocol.org/noise.html).
+///
+/// When a connection is established, the peers run a Noise XX handshake, in which they
+/// authenticate each other with their static keys. After that, all data sent over the connection
+/// is encrypted. The static public key of the peer is exposed in the connection metadata (see
+/// [`ConnectionMetadata::peer_public_key`](crate::ConnectionMetadata::peer_public_key)).
+///
+/// # Example
+/// ```no_run
+/// use msg_transport::{noise::{self, Keypair, Noise}, tcp::Tcp};
+///
+/// let transport = Noise::new(Tcp::default(), noise::Config::new(Keypair::generate()));
+/// ```
+#[derive(Debug)]
+pub struct Noise<T> {
+    inner: T,
+    config: Arc<Config>,
+}
+
+impl<T> Noise<T> {
+    /// Wraps the given transport.
+    pub fn new(inner: T, config: Config) -> Self {
+        Self { inner, config: Arc::new(config) }
+    }
+
+    /// Returns the static public key of this peer.
+    pub fn public_key(&self) -> &[u8] {
+        self.config.keypair.public_key()
+    }
+}
+
+#[async_trait]
+impl<T, A> Transport<A> for Noise<T>
+where
+    T: Transport<A> + Send + Unpin,
+    T::Io: 'static,
+    T::Connect: 'static,
+    T::Accept: 'static,
+    A: Address,
+{
+    type Io = NoiseStream<T::Io>;
+
+    type Error = T::Error;
+
+    type Connect = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+    type Accept = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+
+    fn local_addr(&self) -> Option<A> {
+        self.inner.local_addr()
+    }
+
+    async fn bind(&mut self, addr: A) -> Result<(), Self::Error> {
+        self.inner.bind(addr).await
+    }
+
+    fn connect(&mut self, addr: A) -> Self::Connect {
+        let connect = self.inner.connect(addr);
+        let config = Arc::clone(&self.config);
+
+        Box::pin(async move {
+            let io = connect.await?;
+            Ok(handshake(io, &config, true).await?)
+        })
+    }
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Accept> {
+        let this = self.get_mut();
+
+        let accept = ready!(Pin::new(&mut this.inner).poll_accept(cx));
+        let config = Arc::clone(&this.config);
+
+        Poll::Ready(Box::pin(async move {
+            let io = accept.await?;
+            Ok(handshake(io, &config, false).await?)
+        }))
+    }
+}
+
+impl<T, A> TransportExt<A> for Noise<T>
+where
+    T: Transport<A> + Send + Unpin,
+    T::Io: 'static,
+    T::Connect: 'static,
+    T::Accept: 'static,
+    A: Address,
+{
+}
+
+/// Runs the Noise handshake on the given connection, as the initiator (the peer that connected)
+/// or the responder. Fails if the static key of the peer is not trusted, or if the handshake
+/// doesn't complete within the configured timeout.
+async fn handshake<S>(io: S, config: &Config, initiator: bool) -> io::Result<NoiseStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    tokio::time::timeout(config.handshake_timeout, run_handshake(io, config, initiator))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Noise handshake timed out"))?
+}
+
+async fn run_handshake<S>(
+    mut io: S,
+    config: &Config,
+    initiator: bool,
+) -> io::Result<NoiseStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let builder = snow::Builder::new(PROTOCOL.parse().expect("Valid Noise protocol"))
+        .local_private_key(&config.keypair.private);
+
+    let mut state = if initiator { builder.build_initiator() } else { builder.build_responder() }
+        .map_err(noise_error)?;
+
+    let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+    while !state.is_handshake_finished() {
+        if state.is_my_turn() {
+            let len = state.write_message(&[], &mut buf).map_err(noise_error)?;
+            io.write_u16(len as u16).await?;
+            io.write_all(&buf[..len]).await?;
+            io.flush().await?;
+        } else {
+            let len = io.read_u16().await? as usize;
+            let mut message = vec![0u8; len];
+            io.read_exact(&mut message).await?;
+            state.read_message(&message, &mut buf).map_err(noise_error)?;
+        }
+    }
+
+    let remote_public_key = state
+        .get_remote_static()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Missing remote static key"))?
+        .to_vec();
+
+    if !config.is_trusted(&remote_public_key) {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Untrusted static key"));
+    }
+
+    debug!("Noise handshake completed");
+
+    let state = state.into_transport_mode().map_err(noise_error)?;
+    Ok(NoiseStream::new(io, state, remote_public_key))
+}
+
+fn noise_error(e: snow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{tcp::Tcp, PeerMetadata, TransportExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn noise_connection() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let server_keys = Keypair::generate();
+        let client_keys = Keypair::generate();
+
+        let mut server = Noise::new(
+            Tcp::default(),
+            Config::new(server_keys.clone()).with_trusted_key(client_keys.public_key()),
+        );
+        server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        // Larger than a single Noise message
+        let data: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        let expected = data.clone();
+
+        let handle = tokio::spawn(async move {
+            let mut stream = server.accept().await.unwrap();
+            let key = stream.metadata().peer_public_key().map(|key| key.to_vec());
+
+            let mut received = vec![0u8; expected.len()];
+            stream.read_exact(&mut received).await.unwrap();
+            assert_eq!(received, expected);
+
+            stream.write_all(b"done").await.unwrap();
+            stream.flush().await.unwrap();
+            key
+        });
+
+        let mut client = Noise::new(Tcp::default(), Config::new(client_keys.clone()));
+        let mut stream = client.connect(addr).await.unwrap();
+        assert_eq!(stream.metadata().peer_public_key(), Some(server_keys.public_key()));
+
+        stream.write_all(&data).await.unwrap();
+        stream.flush().await.unwrap();
+
+        let mut done = [0u8; 4];
+        stream.read_exact(&mut done).await.unwrap();
+        assert_eq!(&done, b"done");
+
+        assert_eq!(handle.await.unwrap().as_deref(), Some(client_keys.public_key()));
+    }
+
+    #[tokio::test]
+    async fn noise_untrusted_key() {
+        let mut server = Noise::new(
+            Tcp::default(),
+            Config::new(Keypair::generate()).with_trusted_key(Keypair::generate().public_key()),
+        );
+        server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move { server.accept().await.map(|_| ()) });
+
+        let mut client = Noise::new(Tcp::default(), Config::new(Keypair::generate()));
+        let _ = client.connect(addr).await;
+
+        let err = handle.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn noise_handshake_timeout() {
+        // A peer that accepts the connection, but never responds to the handshake
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _stream = listener.accept().await.unwrap();
+            std::future::pending::<()>().await
+        });
+
+        let timeout = Duration::from_millis(100);
+        let mut client = Noise::new(
+            Tcp::default(),
+            Config::new(Keypair::generate()).with_handshake_timeout(timeout),
+        );
+
+        let err = client.connect(addr).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn keypair_from_private_key() {
+        let keypair = Keypair::generate();
+        let restored = Keypair::from_private_key(&keypair.private);
+        assert_eq!(restored.public_key(), keypair.public_key());
+    }
+}
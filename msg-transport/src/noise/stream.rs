@@ -0,0 +1,195 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use super::{noise_error, MAX_MESSAGE_LEN};
+use crate::{Address, ConnectionMetadata, PeerAddress, PeerMetadata};
+
+/// The size of the authentication tag appended to every encrypted message.
+const TAG_LEN: usize = 16;
+
+/// The maximum amount of plaintext in a single encrypted message.
+const MAX_PLAINTEXT_LEN: usize = MAX_MESSAGE_LEN - TAG_LEN;
+
+/// The size of the chunks read from the underlying stream.
+const READ_CHUNK_SIZE: usize = 8192;
+
+/// A stream encrypted with the Noise protocol, created by the [`Noise`](super::Noise) transport.
+///
+/// Data is sent in encrypted messages of at most 64 KiB, each prefixed with its length as a
+/// big-endian `u16`.
+pub struct NoiseStream<S> {
+    inner: S,
+    state: snow::TransportState,
+    /// The static public key of the peer, authenticated during the handshake.
+    remote_public_key: Vec<u8>,
+    /// Encrypted data read from the underlying stream, which doesn't form a full message yet.
+    ciphertext: Vec<u8>,
+    /// Decrypted data that hasn't been read yet, starting at `plaintext_pos`.
+    plaintext: Vec<u8>,
+    plaintext_pos: usize,
+    /// Encrypted messages that haven't been written to the underlying stream yet, starting at
+    /// `pending_pos`.
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+impl<S> NoiseStream<S> {
+    pub(super) fn new(inner: S, state: snow::TransportState, remote_public_key: Vec<u8>) -> Self {
+        Self {
+            inner,
+            state,
+            remote_public_key,
+            ciphertext: Vec::new(),
+            plaintext: Vec::new(),
+            plaintext_pos: 0,
+            pending: Vec::new(),
+            pending_pos: 0,
+        }
+    }
+
+    /// Returns the static public key of the peer.
+    pub fn remote_public_key(&self) -> &[u8] {
+        &self.remote_public_key
+    }
+
+    /// Decrypts the next full message in the ciphertext buffer into the plaintext buffer. Returns
+    /// `false` if there is no full message yet.
+    fn decrypt_message(&mut self) -> io::Result<bool> {
+        if self.ciphertext.len() < 2 {
+            return Ok(false);
+        }
+
+        let len = u16::from_be_bytes([self.ciphertext[0], self.ciphertext[1]]) as usize;
+        if self.ciphertext.len() < 2 + len {
+            return Ok(false);
+        }
+
+        self.plaintext.resize(len, 0);
+        let n = self
+            .state
+            .read_message(&self.ciphertext[2..2 + len], &mut self.plaintext)
+            .map_err(noise_error)?;
+
+        self.plaintext.truncate(n);
+        self.plaintext_pos = 0;
+        self.ciphertext.drain(..2 + len);
+
+        Ok(true)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> NoiseStream<S> {
+    /// Writes the pending encrypted messages to the underlying stream.
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.pending_pos < self.pending.len() {
+            let pending = &self.pending[self.pending_pos..];
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, pending))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+
+            self.pending_pos += n;
+        }
+
+        self.pending.clear();
+        self.pending_pos = 0;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for NoiseStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.plaintext_pos < this.plaintext.len() {
+                let n = buf.remaining().min(this.plaintext.len() - this.plaintext_pos);
+                buf.put_slice(&this.plaintext[this.plaintext_pos..this.plaintext_pos + n]);
+                this.plaintext_pos += n;
+
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.decrypt_message()? {
+                continue;
+            }
+
+            let mut chunk = [0u8; READ_CHUNK_SIZE];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+
+            if chunk_buf.filled().is_empty() {
+                // EOF, which is only clean between two messages
+                return if this.ciphertext.is_empty() {
+                    Poll::Ready(Ok(()))
+                } else {
+                    Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()))
+                };
+            }
+
+            this.ciphertext.extend_from_slice(chunk_buf.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for NoiseStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // Only encrypt new data once the previous messages are written, to bound the buffer
+        ready!(this.poll_write_pending(cx))?;
+
+        let n = buf.len().min(MAX_PLAINTEXT_LEN);
+
+        this.pending.resize(2 + n + TAG_LEN, 0);
+        let len =
+            this.state.write_message(&buf[..n], &mut this.pending[2..]).map_err(noise_error)?;
+        this.pending.truncate(2 + len);
+        this.pending[..2].copy_from_slice(&(len as u16).to_be_bytes());
+
+        // The data is accepted once it's encrypted, so the message may be written by later calls
+        if let Poll::Ready(Err(e)) = this.poll_write_pending(cx) {
+            return Poll::Ready(Err(e));
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+impl<S: PeerAddress<A>, A: Address> PeerAddress<A> for NoiseStream<S> {
+    fn peer_addr(&self) -> Result<A, io::Error> {
+        self.inner.peer_addr()
+    }
+}
+
+impl<S: PeerMetadata> PeerMetadata for NoiseStream<S> {
+    fn metadata(&self) -> ConnectionMetadata {
+        self.inner.metadata().with_peer_public_key(self.remote_public_key.clone())
+    }
+}
@@ -2,15 +2,29 @@ use std::{sync::Arc, time::Duration};
 
 use quinn::{congestion::ControllerFactory, IdleTimeout};
 
-use super::tls::{self_signed_certificate, unsafe_client_config};
+use super::{
+    tls::{
+        client_config, self_signed_certificate, server_config, system_root_certificates,
+        ServerVerification,
+    },
+    Error,
+};
 
 use msg_common::constants::MiB;
 
+/// The configuration of the [`Quic`](super::Quic) transport, created with the [`ConfigBuilder`],
+/// or with [`Config::insecure`] for testing.
+///
+/// `Config` doesn't implement [`Default`], because the former default skipped the verification of
+/// server certificates without saying so. `Config::insecure()` keeps that behavior.
 #[derive(Debug, Clone)]
 pub struct Config {
     pub endpoint_config: quinn::EndpointConfig,
     pub client_config: quinn::ClientConfig,
     pub server_config: quinn::ServerConfig,
+    /// The name used for SNI and to verify the certificate of servers. If `None`, the IP address
+    /// of the server is used.
+    pub server_name: Option<String>,
 }
 
 #[derive(Debug)]
@@ -21,13 +35,22 @@ pub struct ConfigBuilder<C> {
     expected_rtt: u32,
     max_idle_timeout: Duration,
     keep_alive_interval: Duration,
+    server_certificate: Option<(Vec<rustls::Certificate>, rustls::PrivateKey)>,
+    /// How servers are verified. If `None`, against the root certificates of the system.
+    server_verification: Option<ServerVerification>,
+    client_certificate: Option<(Vec<rustls::Certificate>, rustls::PrivateKey)>,
+    client_auth: Option<rustls::RootCertStore>,
+    server_name: Option<String>,
+    datagram_buffer_size: Option<usize>,
 }
 
 impl<C> ConfigBuilder<C>
 where
     C: ControllerFactory + Default + Send + Sync + 'static,
 {
-    /// Creates a new [`ConfigBuilder`] with sensible defaults.
+    /// Creates a new [`ConfigBuilder`] with sensible defaults. By default, servers present a
+    /// self-signed certificate, and clients verify servers against the root certificates of the
+    /// operating system.
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self {
@@ -37,6 +60,12 @@ where
             expected_rtt: 100,
             max_idle_timeout: Duration::from_secs(60 * 5),
             keep_alive_interval: Duration::from_secs(15),
+            server_certificate: None,
+            server_verification: None,
+            client_certificate: None,
+            client_auth: None,
+            server_name: None,
+            datagram_buffer_size: None,
         }
     }
 
@@ -76,8 +105,87 @@ where
         self
     }
 
-    /// Builds the QUIC [`Config`].
-    pub fn build(self) -> Config {
+    /// Sets the certificate chain (leaf first) and private key presented to clients, instead of a
+    /// self-signed certificate.
+    pub fn server_certificate(
+        mut self,
+        chain: Vec<rustls::Certificate>,
+        key: rustls::PrivateKey,
+    ) -> Self {
+        self.server_certificate = Some((chain, key));
+        self
+    }
+
+    /// Verifies the certificate chain of servers against the given root certificates, instead of
+    /// the root certificates of the operating system.
+    pub fn root_certificates(mut self, roots: rustls::RootCertStore) -> Self {
+        self.server_verification = Some(ServerVerification::Roots(roots));
+        self
+    }
+
+    /// Only accepts servers whose leaf certificate has one of the given SHA-256 fingerprints (see
+    /// [`certificate_fingerprint`](super::certificate_fingerprint)), e.g. to trust self-signed
+    /// certificates. Replaces the root certificates.
+    pub fn pinned_certificates(mut self, fingerprints: impl IntoIterator<Item = [u8; 32]>) -> Self {
+        let fingerprints = fingerprints.into_iter().collect();
+        self.server_verification = Some(ServerVerification::Pinned(fingerprints));
+        self
+    }
+
+    /// Skips the verification of server certificates. This is insecure, as anyone on the path can
+    /// impersonate the server, so it should only be used for testing.
+    pub fn insecure_skip_verification(mut self) -> Self {
+        self.server_verification = Some(ServerVerification::Insecure);
+        self
+    }
+
+    /// Sets the certificate chain (leaf first) and private key presented to servers that require
+    /// client authentication.
+    pub fn client_certificate(
+        mut self,
+        chain: Vec<rustls::Certificate>,
+        key: rustls::PrivateKey,
+    ) -> Self {
+        self.client_certificate = Some((chain, key));
+        self
+    }
+
+    /// Requires clients to present a certificate chain that is valid for the given root
+    /// certificates. The chain is exposed in the connection metadata.
+    pub fn client_auth(mut self, roots: rustls::RootCertStore) -> Self {
+        self.client_auth = Some(roots);
+        self
+    }
+
+    /// Sets the name used for SNI and to verify the certificate of servers. By default, the IP
+    /// address of the server is used.
+    pub fn server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Enables unreliable datagrams (see [`DatagramTransport`](crate::DatagramTransport)), with
+    /// the given maximum number of bytes buffered for sending and receiving. Once a buffer is
+    /// full, the oldest datagrams are dropped. Disabled by default.
+    pub fn datagrams(mut self, buffer_size: usize) -> Self {
+        self.datagram_buffer_size = Some(buffer_size);
+        self
+    }
+
+    /// Builds the QUIC [`Config`]. Fails if a certificate or private key is invalid, or if there
+    /// is no trusted certificate to verify servers with, e.g. because the root certificates of
+    /// the operating system can't be found.
+    pub fn build(self) -> Result<Config, Error> {
+        let server_verification = match self.server_verification {
+            Some(verification) => verification,
+            None => ServerVerification::Roots(system_root_certificates()?),
+        };
+
+        // Otherwise, the client would fail every handshake
+        if server_verification.trusts_nothing() {
+            return Err(Error::NoTrustedCertificates);
+        }
+
         let mut transport = quinn::TransportConfig::default();
 
         // Stream receive window
@@ -88,9 +196,9 @@ where
             .max_idle_timeout(Some(
                 IdleTimeout::try_from(self.max_idle_timeout).expect("Valid idle timeout"),
             ))
-            // Disable datagram support
-            .datagram_receive_buffer_size(None)
-            .datagram_send_buffer_size(0)
+            // Datagrams are disabled unless enabled explicitly
+            .datagram_receive_buffer_size(self.datagram_buffer_size)
+            .datagram_send_buffer_size(self.datagram_buffer_size.unwrap_or(0))
             .max_concurrent_uni_streams(0u32.into())
             .initial_mtu(self.initial_mtu)
             .min_mtu(self.initial_mtu)
@@ -101,24 +209,38 @@ where
             .send_window((8 * stream_rwnd).into());
 
         let transport = Arc::new(transport);
-        let (cert, key) = self_signed_certificate();
+        let (cert, key) = self.server_certificate.unwrap_or_else(self_signed_certificate);
 
-        let mut server_config =
-            quinn::ServerConfig::with_single_cert(cert, key).expect("Valid rustls config");
+        let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(server_config(
+            cert,
+            key,
+            self.client_auth,
+        )?));
 
         server_config.use_retry(true);
         server_config.transport_config(Arc::clone(&transport));
 
-        let mut client_config = quinn::ClientConfig::new(Arc::new(unsafe_client_config()));
+        let mut client_config = quinn::ClientConfig::new(Arc::new(client_config(
+            server_verification,
+            self.client_certificate,
+        )?));
 
         client_config.transport_config(transport);
 
-        Config { endpoint_config: quinn::EndpointConfig::default(), client_config, server_config }
+        Ok(Config {
+            endpoint_config: quinn::EndpointConfig::default(),
+            client_config,
+            server_config,
+            server_name: self.server_name,
+        })
     }
 }
 
-impl Default for Config {
-    fn default() -> Self {
+impl Config {
+    /// Returns a configuration for testing, with a self-signed server certificate and without
+    /// any verification of server certificates. This is insecure, as anyone on the path can
+    /// impersonate the server. Use the [`ConfigBuilder`] to verify servers.
+    pub fn insecure() -> Self {
         // The expected RTT in ms. This has a big impact on initial performance.
         const EXPECTED_RTT: u32 = 100;
         // The maximum bandwidth we expect to see in bytes per second
@@ -162,10 +284,17 @@ impl Default for Config {
         server_config.use_retry(true);
         server_config.transport_config(Arc::clone(&transport));
 
-        let mut client_config = quinn::ClientConfig::new(Arc::new(unsafe_client_config()));
+        let client_config = client_config(ServerVerification::Insecure, None)
+            .expect("Valid rustls config");
+        let mut client_config = quinn::ClientConfig::new(Arc::new(client_config));
 
         client_config.transport_config(transport);
 
-        Self { endpoint_config: quinn::EndpointConfig::default(), client_config, server_config }
+        Self {
+            endpoint_config: quinn::EndpointConfig::default(),
+            client_config,
+            server_config,
+            server_name: None,
+        }
     }
 }
@@ -6,12 +6,15 @@ use std::{
     task::{ready, Poll},
 };
 
+use bytes::Bytes;
 use futures::future::BoxFuture;
 use thiserror::Error;
 use tokio::sync::mpsc::{self, Receiver};
 use tracing::{debug, error};
 
-use crate::{Acceptor, Transport, TransportExt};
+use crate::{
+    Acceptor, DatagramTransport, Datagrams, MultiplexedTransport, Transport, TransportExt,
+};
 
 use msg_common::async_error;
 
@@ -19,9 +22,10 @@ mod config;
 pub use config::{Config, ConfigBuilder};
 
 mod stream;
-use stream::QuicStream;
+pub use stream::QuicStream;
 
 mod tls;
+pub use tls::certificate_fingerprint;
 
 /// A QUIC error.
 #[derive(Debug, Error)]
@@ -32,21 +36,33 @@ pub enum Error {
     Connect(#[from] quinn::ConnectError),
     #[error(transparent)]
     Connection(#[from] quinn::ConnectionError),
+    #[error(transparent)]
+    Tls(#[from] rustls::Error),
     #[error("Endpoint closed")]
     ClosedEndpoint,
+    #[error("No trusted root certificates or pinned certificates to verify servers with")]
+    NoTrustedCertificates,
 }
 
 /// A QUIC implementation built with [quinn] that implements the [`Transport`] and [`TransportExt`]
 /// traits.
 ///
 /// # Note on multiplexing
-/// This implementation does not yet support multiplexing. This means that each connection only
-/// supports a single bi-directional stream, which is returned as the I/O object when connecting or
-/// accepting.
+/// Connecting or accepting returns the first bi-directional stream of a new connection as the I/O
+/// object. More streams can be opened on the same connection with the [`MultiplexedTransport`]
+/// implementation, e.g. one stream per topic or per in-flight request, so that a stalled stream
+/// doesn't block the others.
+///
+/// # Note on datagrams
+/// If enabled with [`ConfigBuilder::datagrams`], connections can also carry unreliable datagrams
+/// through the [`DatagramTransport`] implementation.
 ///
-/// In a future release, we will add support for multiplexing, which will allow multiple streams per
-/// connection based on socket requirements / semantics.
-#[derive(Debug, Default)]
+/// # Note on certificates
+/// Servers are verified as configured with the [`ConfigBuilder`], by default against the root
+/// certificates of the operating system. [`Config::insecure`] skips verification and should only
+/// be used for testing. `Quic` doesn't implement [`Default`] anymore, as the default skipped
+/// verification; `Quic::new(Config::insecure())` is the equivalent.
+#[derive(Debug)]
 pub struct Quic {
     config: Config,
     endpoint: Option<quinn::Endpoint>,
@@ -121,22 +137,22 @@ impl Transport<SocketAddr> for Quic {
         };
 
         let client_config = self.config.client_config.clone();
+        let server_name = self.config.server_name.clone().unwrap_or_else(|| addr.ip().to_string());
 
         Box::pin(async move {
-            // This `"l"` seems necessary because an empty string is an invalid domain
-            // name. While we don't use domain names, the underlying rustls library
-            // is based upon the assumption that we do.
-            let connection =
-                endpoint.connect_with(client_config, addr, "l")?.await.map_err(Error::from)?;
+            let connection = endpoint
+                .connect_with(client_config, addr, &server_name)?
+                .await
+                .map_err(Error::from)?;
 
             debug!("Connected to {}, opening stream", addr);
 
-            // Open a bi-directional stream and return it. We'll think about multiplexing per topic
-            // later.
+            // Open the first bi-directional stream and return it. More streams can be opened
+            // with `MultiplexedTransport::open_stream`.
             connection
                 .open_bi()
                 .await
-                .map(|(send, recv)| QuicStream { peer: addr, send, recv })
+                .map(|streams| QuicStream::new(&connection, addr, streams))
                 .map_err(Error::from)
         })
     }
@@ -162,12 +178,12 @@ impl Transport<SocketAddr> for Quic {
                                 connection.remote_address()
                             );
 
-                            // Accept a bi-directional stream and return it. We'll think about
-                            // multiplexing per topic later.
+                            // Accept the first bi-directional stream and return it. More
+                            // streams can be accepted with `MultiplexedTransport::accept_stream`.
                             connection
                                 .accept_bi()
                                 .await
-                                .map(|(send, recv)| QuicStream { peer, send, recv })
+                                .map(|streams| QuicStream::new(&connection, peer, streams))
                                 .map_err(Error::from)
                         }));
                     }
@@ -220,11 +236,65 @@ impl TransportExt<SocketAddr> for Quic {
     }
 }
 
+impl MultiplexedTransport<SocketAddr> for Quic {
+    type OpenStream = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+    type AcceptStream = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+
+    /// Opens a new bi-directional stream on the connection of the given stream. Note that QUIC
+    /// only announces a stream to the peer once data is written to it.
+    fn open_stream(io: &Self::Io) -> Self::OpenStream {
+        let connection = io.connection.clone();
+        let peer = io.peer;
+
+        Box::pin(async move {
+            let streams = connection.open_bi().await?;
+            Ok(QuicStream::new(&connection, peer, streams))
+        })
+    }
+
+    /// Accepts the next bi-directional stream opened by the peer on the connection of the given
+    /// stream.
+    fn accept_stream(io: &Self::Io) -> Self::AcceptStream {
+        let connection = io.connection.clone();
+        let peer = io.peer;
+
+        Box::pin(async move {
+            let streams = connection.accept_bi().await?;
+            Ok(QuicStream::new(&connection, peer, streams))
+        })
+    }
+}
+
+impl DatagramTransport<SocketAddr> for Quic {
+    type Datagrams = quinn::Connection;
+
+    fn datagrams(io: &Self::Io) -> Option<Self::Datagrams> {
+        io.connection.max_datagram_size().map(|_| io.connection.clone())
+    }
+}
+
+impl Datagrams for quinn::Connection {
+    fn max_datagram_size(&self) -> Option<usize> {
+        quinn::Connection::max_datagram_size(self)
+    }
+
+    fn send_datagram(&self, datagram: Bytes) -> io::Result<()> {
+        quinn::Connection::send_datagram(self, datagram)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    fn recv_datagram(&self) -> BoxFuture<'static, io::Result<Bytes>> {
+        let connection = self.clone();
+        Box::pin(async move { connection.read_datagram().await.map_err(io::Error::from) })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
-    use crate::TransportExt;
+    use crate::{PeerMetadata, TransportExt};
+    use quinn::congestion::CubicConfig;
     use tokio::{
         io::{AsyncReadExt, AsyncWriteExt},
         sync::oneshot,
@@ -237,7 +307,7 @@ mod tests {
     async fn test_quic_connection() {
         let _ = tracing_subscriber::fmt::try_init();
 
-        let config = Config::default();
+        let config = Config::insecure();
 
         let mut server = Quic::new(config.clone());
         server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
@@ -277,11 +347,139 @@ mod tests {
         assert_eq!(rcv, *item);
     }
 
+    /// Issues a certificate for the given name, signed by the given CA.
+    fn issue(
+        ca: &rcgen::Certificate,
+        name: &str,
+    ) -> (Vec<rustls::Certificate>, rustls::PrivateKey) {
+        let params = rcgen::CertificateParams::new(vec![name.to_string()]);
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+
+        (
+            vec![rustls::Certificate(cert.serialize_der_with_signer(ca).unwrap())],
+            rustls::PrivateKey(cert.serialize_private_key_der()),
+        )
+    }
+
+    fn new_ca() -> (rcgen::Certificate, rustls::RootCertStore) {
+        let mut params = rcgen::CertificateParams::default();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(params).unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&rustls::Certificate(ca.serialize_der().unwrap())).unwrap();
+        (ca, roots)
+    }
+
+    /// Connects a client with the given configuration to a server with the given configuration,
+    /// and returns the certificate chain the server received from the client.
+    async fn connect(
+        server_config: Config,
+        client_config: Config,
+    ) -> Result<Option<Vec<Vec<u8>>>, Error> {
+        let mut server = Quic::new(server_config);
+        server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let handle = tokio::spawn(async move {
+            let mut stream = server.accept().await?;
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await?;
+            Ok::<_, Error>(stream.metadata().peer_certificates().map(|chain| chain.to_vec()))
+        });
+
+        let mut client = Quic::new(client_config);
+        let mut stream = client.connect(addr).await?;
+        stream.write_all(b"Hello").await?;
+        stream.flush().await?;
+
+        handle.await.unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_quic_verified_connection() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (ca, roots) = new_ca();
+        let (chain, key) = issue(&ca, "localhost");
+        let (client_chain, client_key) = issue(&ca, "client1");
+
+        let server_config = ConfigBuilder::<Arc<CubicConfig>>::new()
+            .server_certificate(chain, key)
+            .client_auth(roots.clone())
+            .build()
+            .unwrap();
+
+        let client_config = ConfigBuilder::<Arc<CubicConfig>>::new()
+            .root_certificates(roots)
+            .client_certificate(client_chain.clone(), client_key)
+            .server_name("localhost")
+            .build()
+            .unwrap();
+
+        let peer_chain = connect(server_config, client_config).await.unwrap();
+        assert_eq!(peer_chain, Some(vec![client_chain[0].0.clone()]));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_quic_rejects_untrusted_server() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (ca, _) = new_ca();
+        let (chain, key) = issue(&ca, "localhost");
+        let server_config = ConfigBuilder::<Arc<CubicConfig>>::new()
+            .server_certificate(chain, key)
+            .build()
+            .unwrap();
+
+        // Trusts another CA
+        let client_config = ConfigBuilder::<Arc<CubicConfig>>::new()
+            .root_certificates(new_ca().1)
+            .server_name("localhost")
+            .build()
+            .unwrap();
+
+        assert!(connect(server_config, client_config).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_quic_pinned_certificate() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let (cert, key) = tls::self_signed_certificate();
+        let fingerprint = certificate_fingerprint(&cert[0]);
+
+        let server_config = || {
+            ConfigBuilder::<Arc<CubicConfig>>::new()
+                .server_certificate(cert.clone(), key.clone())
+                .build()
+                .unwrap()
+        };
+
+        let client_config =
+            ConfigBuilder::<Arc<CubicConfig>>::new().pinned_certificates([fingerprint]).build();
+        assert!(connect(server_config(), client_config.unwrap()).await.is_ok());
+
+        let client_config =
+            ConfigBuilder::<Arc<CubicConfig>>::new().pinned_certificates([[0u8; 32]]).build();
+        assert!(connect(server_config(), client_config.unwrap()).await.is_err());
+    }
+
+    #[test]
+    fn test_quic_config_without_trusted_certificates() {
+        let config = ConfigBuilder::<Arc<CubicConfig>>::new()
+            .root_certificates(rustls::RootCertStore::empty());
+        assert!(matches!(config.build(), Err(Error::NoTrustedCertificates)));
+
+        let config = ConfigBuilder::<Arc<CubicConfig>>::new().pinned_certificates([]);
+        assert!(matches!(config.build(), Err(Error::NoTrustedCertificates)));
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn test_quic_connection_late_bind() {
         let _ = tracing_subscriber::fmt::try_init();
 
-        let config = Config::default();
+        let config = Config::insecure();
 
         let addr = SocketAddr::from(([127, 0, 0, 1], 9971));
 
@@ -299,4 +497,83 @@ mod tests {
 
         tokio::time::sleep(Duration::from_secs(5)).await;
     }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_quic_multiplexed_streams() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let mut server = Quic::new(Config::insecure());
+        server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let mut client = Quic::new(Config::insecure());
+        let mut first = client.connect(addr).await.unwrap();
+        first.write_all(b"first").await.unwrap();
+
+        let mut accepted = server.accept().await.unwrap();
+
+        let mut streams = Vec::new();
+        for i in 0..3u8 {
+            let mut stream = Quic::open_stream(&first).await.unwrap();
+            stream.write_all(&[i]).await.unwrap();
+            streams.push(stream);
+        }
+
+        // The other streams are independent of the first one, which isn't read yet
+        for i in 0..3u8 {
+            let mut stream = Quic::accept_stream(&accepted).await.unwrap();
+            let mut buf = [0u8; 1];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf[0], i);
+
+            stream.write_all(&[i]).await.unwrap();
+        }
+
+        let mut buf = [0u8; 5];
+        accepted.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"first");
+
+        for (i, stream) in streams.iter_mut().enumerate() {
+            let mut buf = [0u8; 1];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf[0] as usize, i);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_quic_datagrams() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let config = || {
+            ConfigBuilder::<Arc<CubicConfig>>::new()
+                .insecure_skip_verification()
+                .datagrams(1024 * 1024)
+                .build()
+                .unwrap()
+        };
+
+        let mut server = Quic::new(config());
+        server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let mut client = Quic::new(config());
+        let mut stream = client.connect(addr).await.unwrap();
+        stream.write_all(b"Hello").await.unwrap();
+
+        let accepted = server.accept().await.unwrap();
+
+        let datagrams = Quic::datagrams(&stream).unwrap();
+        assert!(datagrams.max_datagram_size().unwrap() > 1000);
+        Datagrams::send_datagram(&datagrams, Bytes::from_static(b"datagram")).unwrap();
+
+        let received = Quic::datagrams(&accepted).unwrap().recv_datagram().await.unwrap();
+        assert_eq!(received, Bytes::from_static(b"datagram"));
+
+        // Disabled by default
+        let mut server = Quic::new(Config::insecure());
+        server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+        let mut stream = client.connect(server.local_addr().unwrap()).await.unwrap();
+        stream.write_all(b"Hello").await.unwrap();
+        assert!(Quic::datagrams(&stream).is_none());
+    }
 }
@@ -2,13 +2,37 @@ use std::{net::SocketAddr, pin::Pin};
 
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::PeerAddress;
+use crate::{ConnectionMetadata, PeerAddress, PeerMetadata};
 
-/// A bi-directional QUIC stream that implements [`AsyncRead`] + [`AsyncWrite`].
+/// A bi-directional QUIC stream that implements [`AsyncRead`] + [`AsyncWrite`]. More streams can
+/// be opened on the same connection with [`MultiplexedTransport`](crate::MultiplexedTransport).
 pub struct QuicStream {
     pub(super) peer: SocketAddr,
     pub(super) send: quinn::SendStream,
     pub(super) recv: quinn::RecvStream,
+    /// The connection the stream belongs to, which can carry more streams.
+    pub(super) connection: quinn::Connection,
+    /// The metadata of the connection the stream belongs to.
+    pub(super) metadata: ConnectionMetadata,
+}
+
+impl QuicStream {
+    /// Creates a new stream on the given connection.
+    pub(super) fn new(
+        connection: &quinn::Connection,
+        peer: SocketAddr,
+        (send, recv): (quinn::SendStream, quinn::RecvStream),
+    ) -> Self {
+        let mut metadata = ConnectionMetadata::default();
+        if let Some(identity) = connection.peer_identity() {
+            if let Ok(certificates) = identity.downcast::<Vec<rustls::Certificate>>() {
+                metadata = metadata
+                    .with_peer_certificates(certificates.into_iter().map(|cert| cert.0).collect());
+            }
+        }
+
+        Self { peer, send, recv, connection: connection.clone(), metadata }
+    }
 }
 
 impl AsyncRead for QuicStream {
@@ -50,3 +74,9 @@ impl PeerAddress<SocketAddr> for QuicStream {
         Ok(self.peer)
     }
 }
+
+impl PeerMetadata for QuicStream {
+    fn metadata(&self) -> ConnectionMetadata {
+        self.metadata.clone()
+    }
+}
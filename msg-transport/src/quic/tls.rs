@@ -1,6 +1,39 @@
-use std::sync::Arc;
+use std::{io, sync::Arc};
 
-use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    server::AllowAnyAuthenticatedClient,
+};
+
+/// How the client verifies the certificate of the server.
+#[derive(Debug, Clone)]
+pub(crate) enum ServerVerification {
+    /// Verify the certificate chain against the given root certificates.
+    Roots(rustls::RootCertStore),
+    /// Only accept servers whose leaf certificate has one of the given SHA-256 fingerprints.
+    Pinned(Vec<[u8; 32]>),
+    /// Skip verification entirely.
+    Insecure,
+}
+
+impl ServerVerification {
+    /// Returns `true` if no server can pass the verification.
+    pub(crate) fn trusts_nothing(&self) -> bool {
+        match self {
+            Self::Roots(roots) => roots.is_empty(),
+            Self::Pinned(fingerprints) => fingerprints.is_empty(),
+            Self::Insecure => false,
+        }
+    }
+}
+
+/// Loads the root certificates of the operating system. Certificates that can't be parsed are
+/// skipped.
+pub(crate) fn system_root_certificates() -> io::Result<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_parsable_certificates(&rustls_native_certs::load_native_certs()?);
+    Ok(roots)
+}
 
 /// A server certificate verifier that automatically passes all checks.
 #[derive(Debug)]
@@ -20,13 +53,90 @@ impl ServerCertVerifier for SkipServerVerification {
     }
 }
 
-/// Returns a TLS configuration that skips all server verification and doesn't do any client
-/// authentication.
-pub(crate) fn unsafe_client_config() -> rustls::ClientConfig {
-    rustls::ClientConfig::builder()
-        .with_safe_defaults()
-        .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
-        .with_no_client_auth()
+/// A server certificate verifier that only accepts leaf certificates with a pinned fingerprint.
+/// The server name and the rest of the chain are not checked, so self-signed certificates can
+/// be used.
+#[derive(Debug)]
+pub(crate) struct PinnedServerVerification {
+    fingerprints: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedServerVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        if self.fingerprints.contains(&certificate_fingerprint(end_entity)) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(rustls::CertificateError::UnknownIssuer))
+        }
+    }
+}
+
+/// Returns the SHA-256 fingerprint of the given DER-encoded certificate, as used by
+/// [`ConfigBuilder::pinned_certificates`](super::ConfigBuilder::pinned_certificates).
+pub fn certificate_fingerprint(certificate: &rustls::Certificate) -> [u8; 32] {
+    let digest = ring::digest::digest(&ring::digest::SHA256, &certificate.0);
+    digest.as_ref().try_into().expect("SHA-256 digests are 32 bytes long")
+}
+
+/// Returns a QUIC-compatible TLS client configuration, which verifies servers as configured and
+/// presents the given certificate if the server requires client authentication.
+pub(crate) fn client_config(
+    verification: ServerVerification,
+    certificate: Option<(Vec<rustls::Certificate>, rustls::PrivateKey)>,
+) -> Result<rustls::ClientConfig, rustls::Error> {
+    let verifier: Arc<dyn ServerCertVerifier> = match verification {
+        ServerVerification::Roots(roots) => Arc::new(WebPkiVerifier::new(roots, None)),
+        ServerVerification::Pinned(fingerprints) => {
+            Arc::new(PinnedServerVerification { fingerprints })
+        }
+        ServerVerification::Insecure => Arc::new(SkipServerVerification),
+    };
+
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_custom_certificate_verifier(verifier);
+
+    let mut config = match certificate {
+        Some((chain, key)) => builder.with_client_auth_cert(chain, key)?,
+        None => builder.with_no_client_auth(),
+    };
+
+    config.enable_early_data = true;
+    Ok(config)
+}
+
+/// Returns a QUIC-compatible TLS server configuration, which presents the given certificate and
+/// requires clients to present a certificate valid for the given roots, if any.
+pub(crate) fn server_config(
+    chain: Vec<rustls::Certificate>,
+    key: rustls::PrivateKey,
+    client_auth: Option<rustls::RootCertStore>,
+) -> Result<rustls::ServerConfig, rustls::Error> {
+    let builder = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])?;
+
+    let builder = match client_auth {
+        Some(roots) => {
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(chain, key)?;
+    config.max_early_data_size = u32::MAX;
+    Ok(config)
 }
 
 /// Generates a self-signed certificate chain and private key.
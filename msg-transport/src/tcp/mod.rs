@@ -9,7 +9,7 @@ use tracing::debug;
 
 use msg_common::async_error;
 
-use crate::{Acceptor, PeerAddress, Transport, TransportExt};
+use crate::{Acceptor, PeerAddress, PeerMetadata, Transport, TransportExt};
 
 #[derive(Debug, Default)]
 pub struct Config;
@@ -33,6 +33,8 @@ impl PeerAddress<SocketAddr> for TcpStream {
     }
 }
 
+impl PeerMetadata for TcpStream {}
+
 #[async_trait::async_trait]
 impl Transport<SocketAddr> for Tcp {
     type Io = TcpStream;
@@ -0,0 +1,406 @@
+use futures::future::BoxFuture;
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tracing::debug;
+
+use msg_common::async_error;
+
+use crate::{Acceptor, ConnectionMetadata, PeerAddress, PeerMetadata, Transport, TransportExt};
+
+/// An error that occurred while building a TLS [`Config`].
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Tls(#[from] rustls::Error),
+    #[error("Invalid server name: {0}")]
+    InvalidServerName(String),
+}
+
+/// The configuration of the [`TcpTls`] transport. Use the [`ConfigBuilder`] to build one from
+/// certificates, or set the [`rustls`] configurations directly for more control.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The TLS configuration used to connect to servers.
+    pub client_config: Arc<rustls::ClientConfig>,
+    /// The TLS configuration used to accept clients. Required to bind.
+    pub server_config: Option<Arc<rustls::ServerConfig>>,
+    /// The name used for SNI and to verify the certificate of servers. If `None`, the IP address
+    /// of the server is used.
+    pub server_name: Option<rustls::ServerName>,
+}
+
+/// A builder for the TLS [`Config`].
+#[derive(Debug)]
+pub struct ConfigBuilder {
+    root_certificates: rustls::RootCertStore,
+    server_certificate: Option<(Vec<rustls::Certificate>, rustls::PrivateKey)>,
+    client_certificate: Option<(Vec<rustls::Certificate>, rustls::PrivateKey)>,
+    client_auth: Option<rustls::RootCertStore>,
+    server_name: Option<String>,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConfigBuilder {
+    /// Creates a new [`ConfigBuilder`], which doesn't trust any server and doesn't authenticate
+    /// clients.
+    pub fn new() -> Self {
+        Self {
+            root_certificates: rustls::RootCertStore::empty(),
+            server_certificate: None,
+            client_certificate: None,
+            client_auth: None,
+            server_name: None,
+        }
+    }
+
+    /// Sets the root certificates used to verify the certificate chain of servers.
+    pub fn root_certificates(mut self, roots: rustls::RootCertStore) -> Self {
+        self.root_certificates = roots;
+        self
+    }
+
+    /// Sets the certificate chain (leaf first) and private key presented to clients. Required to
+    /// accept connections.
+    pub fn server_certificate(
+        mut self,
+        chain: Vec<rustls::Certificate>,
+        key: rustls::PrivateKey,
+    ) -> Self {
+        self.server_certificate = Some((chain, key));
+        self
+    }
+
+    /// Sets the certificate chain (leaf first) and private key presented to servers that require
+    /// client authentication.
+    pub fn client_certificate(
+        mut self,
+        chain: Vec<rustls::Certificate>,
+        key: rustls::PrivateKey,
+    ) -> Self {
+        self.client_certificate = Some((chain, key));
+        self
+    }
+
+    /// Requires clients to present a certificate chain that is valid for the given root
+    /// certificates (mTLS). The chain is exposed in the connection metadata.
+    pub fn client_auth(mut self, roots: rustls::RootCertStore) -> Self {
+        self.client_auth = Some(roots);
+        self
+    }
+
+    /// Sets the name used for SNI and to verify the certificate of servers. By default, the IP
+    /// address of the server is used.
+    pub fn server_name(mut self, name: impl Into<String>) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// Builds the TLS [`Config`].
+    pub fn build(self) -> Result<Config, Error> {
+        let client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(self.root_certificates);
+
+        let client_config = match self.client_certificate {
+            Some((chain, key)) => client_config.with_client_auth_cert(chain, key)?,
+            None => client_config.with_no_client_auth(),
+        };
+
+        let server_config = match self.server_certificate {
+            Some((chain, key)) => {
+                let server_config = rustls::ServerConfig::builder().with_safe_defaults();
+                let server_config = match self.client_auth {
+                    Some(roots) => server_config.with_client_cert_verifier(
+                        rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed(),
+                    ),
+                    None => server_config.with_no_client_auth(),
+                };
+
+                Some(Arc::new(server_config.with_single_cert(chain, key)?))
+            }
+            None => None,
+        };
+
+        let server_name = self
+            .server_name
+            .map(|name| {
+                rustls::ServerName::try_from(name.as_str())
+                    .map_err(|_| Error::InvalidServerName(name.clone()))
+            })
+            .transpose()?;
+
+        Ok(Config { client_config: Arc::new(client_config), server_config, server_name })
+    }
+}
+
+/// A TCP transport secured with TLS, built with [rustls].
+///
+/// Server certificates are verified against the configured root certificates, and client
+/// certificates too if client authentication is enabled. The certificate chain presented by the
+/// peer is exposed in the connection metadata (see
+/// [`ConnectionMetadata::peer_certificates`](crate::ConnectionMetadata::peer_certificates)).
+#[derive(Debug)]
+pub struct TcpTls {
+    config: Config,
+    listener: Option<TcpListener>,
+}
+
+impl TcpTls {
+    /// Creates a new TLS transport with the given configuration.
+    pub fn new(config: Config) -> Self {
+        Self { config, listener: None }
+    }
+}
+
+/// A TCP stream secured with TLS.
+pub struct TcpTlsStream {
+    peer: SocketAddr,
+    inner: tokio_rustls::TlsStream<TcpStream>,
+}
+
+impl AsyncRead for TcpTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TcpTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl PeerAddress<SocketAddr> for TcpTlsStream {
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.peer)
+    }
+}
+
+impl PeerMetadata for TcpTlsStream {
+    fn metadata(&self) -> ConnectionMetadata {
+        let (_, session) = self.inner.get_ref();
+
+        match session.peer_certificates() {
+            Some(certificates) => ConnectionMetadata::default()
+                .with_peer_certificates(certificates.iter().map(|cert| cert.0.clone()).collect()),
+            None => ConnectionMetadata::default(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport<SocketAddr> for TcpTls {
+    type Io = TcpTlsStream;
+
+    type Error = io::Error;
+
+    type Connect = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+    type Accept = BoxFuture<'static, Result<Self::Io, Self::Error>>;
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.as_ref().and_then(|l| l.local_addr().ok())
+    }
+
+    async fn bind(&mut self, addr: SocketAddr) -> Result<(), Self::Error> {
+        if self.config.server_config.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A server certificate is required to bind",
+            ));
+        }
+
+        let listener = TcpListener::bind(addr).await?;
+
+        self.listener = Some(listener);
+
+        Ok(())
+    }
+
+    fn connect(&mut self, addr: SocketAddr) -> Self::Connect {
+        let connector = TlsConnector::from(Arc::clone(&self.config.client_config));
+        let server_name =
+            self.config.server_name.clone().unwrap_or(rustls::ServerName::IpAddress(addr.ip()));
+
+        Box::pin(async move {
+            let stream = TcpStream::connect(addr).await?;
+            stream.set_nodelay(true)?;
+
+            let stream = connector.connect(server_name, stream).await?;
+
+            Ok(TcpTlsStream { peer: addr, inner: stream.into() })
+        })
+    }
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Accept> {
+        let this = self.get_mut();
+
+        let (Some(ref listener), Some(ref server_config)) =
+            (&this.listener, &this.config.server_config)
+        else {
+            return Poll::Ready(async_error(io::ErrorKind::NotConnected.into()));
+        };
+
+        match listener.poll_accept(cx) {
+            Poll::Ready(Ok((io, addr))) => {
+                debug!("Accepted connection from {}", addr);
+
+                let acceptor = TlsAcceptor::from(Arc::clone(server_config));
+                Poll::Ready(Box::pin(async move {
+                    io.set_nodelay(true)?;
+                    let stream = acceptor.accept(io).await?;
+
+                    Ok(TcpTlsStream { peer: addr, inner: stream.into() })
+                }))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(async_error(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl TransportExt<SocketAddr> for TcpTls {
+    fn accept(&mut self) -> Acceptor<'_, Self, SocketAddr>
+    where
+        Self: Sized + Unpin,
+    {
+        Acceptor::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::{BasicConstraints, CertificateParams, IsCa};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// A certificate authority that issues certificates for the tests.
+    struct Ca(rcgen::Certificate);
+
+    impl Ca {
+        fn new() -> Self {
+            let mut params = CertificateParams::default();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            Self(rcgen::Certificate::from_params(params).unwrap())
+        }
+
+        fn roots(&self) -> rustls::RootCertStore {
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add(&rustls::Certificate(self.0.serialize_der().unwrap())).unwrap();
+            roots
+        }
+
+        fn issue(&self, name: &str) -> (Vec<rustls::Certificate>, rustls::PrivateKey) {
+            let cert = rcgen::Certificate::from_params(CertificateParams::new(vec![name.into()]))
+                .unwrap();
+
+            (
+                vec![rustls::Certificate(cert.serialize_der_with_signer(&self.0).unwrap())],
+                rustls::PrivateKey(cert.serialize_private_key_der()),
+            )
+        }
+    }
+
+    fn localhost() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 0))
+    }
+
+    #[tokio::test]
+    async fn tls_mutual_auth() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        let ca = Ca::new();
+        let (chain, key) = ca.issue("localhost");
+        let server_config =
+            ConfigBuilder::new().server_certificate(chain, key).client_auth(ca.roots()).build();
+
+        let mut server = TcpTls::new(server_config.unwrap());
+        server.bind(localhost()).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let (chain, key) = ca.issue("client1");
+        let client_chain = chain.clone();
+        let client_config = ConfigBuilder::new()
+            .root_certificates(ca.roots())
+            .client_certificate(chain, key)
+            .server_name("localhost")
+            .build();
+        let mut client = TcpTls::new(client_config.unwrap());
+
+        let handle = tokio::spawn(async move {
+            let mut stream = server.accept().await.unwrap();
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+
+            stream.metadata().peer_certificates().map(|chain| chain.to_vec())
+        });
+
+        let mut stream = client.connect(addr).await.unwrap();
+        assert!(stream.metadata().peer_certificates().is_some());
+        stream.write_all(b"hello").await.unwrap();
+        stream.flush().await.unwrap();
+
+        let peer_chain = handle.await.unwrap().unwrap();
+        assert_eq!(peer_chain, vec![client_chain[0].0.clone()]);
+    }
+
+    #[tokio::test]
+    async fn tls_rejects_untrusted_server() {
+        let (chain, key) = Ca::new().issue("localhost");
+        let mut server =
+            TcpTls::new(ConfigBuilder::new().server_certificate(chain, key).build().unwrap());
+        server.bind(localhost()).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let _ = server.accept().await;
+        });
+
+        // Trusts another CA
+        let client_config =
+            ConfigBuilder::new().root_certificates(Ca::new().roots()).server_name("localhost");
+        let mut client = TcpTls::new(client_config.build().unwrap());
+
+        let err = client.connect(addr).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn tls_bind_requires_certificate() {
+        let mut server = TcpTls::new(ConfigBuilder::new().build().unwrap());
+        assert!(server.bind(localhost()).await.is_err());
+    }
+}
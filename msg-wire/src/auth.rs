@@ -1,10 +1,21 @@
-use bytes::{Buf, BufMut, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
 use thiserror::Error;
 use tokio_util::codec::{Decoder, Encoder};
 
 /// The ID of the auth codec on the wire.
 const WIRE_ID: u8 = 0x01;
 
+/// The ID of the challenge-response messages of the auth codec on the wire. These have their own
+/// ID, so that a server can tell them apart from a plain [`Message::Auth`].
+const CHALLENGE_WIRE_ID: u8 = 0x06;
+
+/// The size in bytes of the challenge sent by the server.
+pub const CHALLENGE_SIZE: usize = 32;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("IO error: {0:?}")]
@@ -16,6 +27,17 @@ pub enum Error {
 }
 
 /// Authentication codec.
+///
+/// A client authenticates either by sending a token with [`Message::Auth`], or with a
+/// challenge-response handshake, which never sends the secret over the wire:
+/// 1. The client sends its ID with [`Message::Hello`].
+/// 2. The server responds with a random [`Message::Challenge`].
+/// 3. The client responds with [`Message::Response`], the MAC of the challenge and its ID, keyed
+///    with the secret it shares with the server (see [`sign_challenge`]).
+/// 4. The server verifies the MAC and responds with [`Message::Ack`] or [`Message::Reject`].
+///
+/// The client codec follows the handshake started by the first message it sends, and the server
+/// codec accepts both, so the mode is chosen by the client.
 pub struct Codec {
     state: State,
 }
@@ -39,6 +61,10 @@ impl Codec {
 enum State {
     /// Waiting for the client to send its ID
     AuthReceive,
+    /// Waiting for the client to respond to the challenge
+    ResponseReceive,
+    /// Waiting for the server to send a challenge
+    Challenge,
     /// Waiting for the server to send an ACK
     Ack,
 }
@@ -47,69 +73,154 @@ enum State {
 pub enum Message {
     /// The client sends the ID to the server
     Auth(Bytes),
+    /// The client sends its ID to the server to start a challenge-response handshake
+    Hello(Bytes),
+    /// The server sends a challenge to the client
+    Challenge(Bytes),
+    /// The client responds to the challenge with its MAC
+    Response(Bytes),
     /// The server responds with an ACK
     Ack,
     /// We reject the client
     Reject,
 }
 
+/// Generates a new random challenge of [`CHALLENGE_SIZE`] bytes.
+pub fn new_challenge() -> Bytes {
+    let mut challenge = vec![0u8; CHALLENGE_SIZE];
+    SystemRandom::new().fill(&mut challenge).expect("System RNG is available");
+    Bytes::from(challenge)
+}
+
+/// Computes the response to the challenge: the HMAC-SHA256 of the challenge and the client ID,
+/// keyed with the secret shared by the client and the server.
+pub fn sign_challenge(secret: &[u8], id: &[u8], challenge: &[u8]) -> Bytes {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let mut ctx = hmac::Context::with_key(&key);
+    ctx.update(challenge);
+    ctx.update(id);
+    Bytes::copy_from_slice(ctx.sign().as_ref())
+}
+
+/// Verifies the response to the challenge in constant time. See [`sign_challenge`].
+pub fn verify_challenge(secret: &[u8], id: &[u8], challenge: &[u8], response: &[u8]) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let mut msg = Vec::with_capacity(challenge.len() + id.len());
+    msg.extend_from_slice(challenge);
+    msg.extend_from_slice(id);
+    hmac::verify(&key, &msg, response).is_ok()
+}
+
+/// Decodes a length-prefixed payload with the given wire ID. Returns `None` if the frame is not
+/// complete yet.
+fn decode_payload(src: &mut BytesMut, expected_id: u8) -> Result<Option<Bytes>, Error> {
+    if src.is_empty() {
+        return Ok(None);
+    }
+
+    // Wire ID check (without advancing the cursor)
+    let wire_id = src[0];
+    if wire_id != expected_id {
+        return Err(Error::WireId(wire_id));
+    }
+
+    // We need at least 5 bytes to read the wire ID and the payload size
+    if src.len() < 5 {
+        return Ok(None);
+    }
+
+    let size = u32::from_be_bytes([src[1], src[2], src[3], src[4]]) as usize;
+    if src.len() < 5 + size {
+        return Ok(None);
+    }
+
+    src.advance(5);
+    Ok(Some(src.split_to(size).freeze()))
+}
+
+/// Decodes an ACK or a reject. Returns `None` if the frame is not complete yet.
+fn decode_ack(src: &mut BytesMut) -> Result<Option<Message>, Error> {
+    if src.len() < 2 {
+        return Ok(None);
+    }
+
+    // Wire ID check (without advancing the cursor)
+    let wire_id = src[0];
+    if wire_id != WIRE_ID {
+        return Err(Error::WireId(wire_id));
+    }
+
+    src.advance(1);
+
+    let ack = src.get_u8();
+
+    if ack == 0 {
+        return Err(Error::Rejected);
+    }
+
+    Ok(Some(Message::Ack))
+}
+
+/// Encodes a length-prefixed payload with the given wire ID.
+fn encode_payload(wire_id: u8, payload: Bytes, dst: &mut BytesMut) {
+    dst.reserve(1 + 4 + payload.len());
+    dst.put_u8(wire_id);
+    dst.put_u32(payload.len() as u32);
+    dst.put(payload);
+}
+
 impl Decoder for Codec {
     type Item = Message;
     type Error = Error;
 
-    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         match self.state {
             // We are the server, waiting for the client to send its auth message
             State::AuthReceive => {
-                // We need at least 5 bytes to read the wire ID and the auth ID
-                if src.is_empty() {
+                let Some(&wire_id) = src.first() else {
                     return Ok(None);
-                }
+                };
 
-                // Wire ID check (without advancing the cursor)
-                let wire_id = u8::from_be_bytes([src[0]]);
-                if wire_id != WIRE_ID {
-                    return Err(Error::WireId(wire_id));
-                }
+                if wire_id == CHALLENGE_WIRE_ID {
+                    let Some(id) = decode_payload(src, CHALLENGE_WIRE_ID)? else {
+                        return Ok(None);
+                    };
 
-                if src.len() < 4 {
-                    return Ok(None);
+                    self.state = State::ResponseReceive;
+                    return Ok(Some(Message::Hello(id)));
                 }
 
-                let id_size = u32::from_be_bytes([src[1], src[2], src[3], src[4]]);
-                if src.len() < id_size as usize {
+                let Some(id) = decode_payload(src, WIRE_ID)? else {
                     return Ok(None);
-                }
-
-                src.advance(1);
-                src.advance(4);
+                };
 
-                let id = src.split_to(id_size as usize).freeze();
                 self.state = State::Ack;
                 Ok(Some(Message::Auth(id)))
             }
-            // We are the client, and we are waiting for the server to send an ACK
-            State::Ack => {
-                if src.len() < 2 {
+            // We are the server, and we are waiting for the client to respond to the challenge
+            State::ResponseReceive => {
+                let Some(response) = decode_payload(src, CHALLENGE_WIRE_ID)? else {
                     return Ok(None);
-                }
+                };
 
-                // Wire ID check (without advancing the cursor)
-                let wire_id = u8::from_be_bytes([src[0]]);
-                if wire_id != WIRE_ID {
-                    return Err(Error::WireId(wire_id));
+                self.state = State::Ack;
+                Ok(Some(Message::Response(response)))
+            }
+            // We are the client, and we are waiting for the server to send a challenge. The server
+            // may also reject the handshake right away.
+            State::Challenge => {
+                if src.first() == Some(&WIRE_ID) {
+                    return decode_ack(src);
                 }
 
-                src.advance(1);
-
-                let ack = src.get_u8();
-
-                if ack == 0 {
-                    return Err(Error::Rejected);
-                }
+                let Some(challenge) = decode_payload(src, CHALLENGE_WIRE_ID)? else {
+                    return Ok(None);
+                };
 
-                Ok(Some(Message::Ack))
+                Ok(Some(Message::Challenge(challenge)))
             }
+            // We are the client, and we are waiting for the server to send an ACK
+            State::Ack => decode_ack(src),
         }
     }
 }
@@ -117,15 +228,26 @@ impl Decoder for Codec {
 impl Encoder<Message> for Codec {
     type Error = std::io::Error;
 
-    fn encode(&mut self, item: Message, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
         match item {
             // We are the client, and we are sending the ID to the server
             Message::Auth(id) => {
                 self.state = State::Ack;
-                dst.reserve(1 + 4 + id.len());
-                dst.put_u8(WIRE_ID);
-                dst.put_u32(id.len() as u32);
-                dst.put(id);
+                encode_payload(WIRE_ID, id, dst);
+            }
+            // We are the client, and we are starting a challenge-response handshake
+            Message::Hello(id) => {
+                self.state = State::Challenge;
+                encode_payload(CHALLENGE_WIRE_ID, id, dst);
+            }
+            // We are the server, and we are sending the challenge to the client
+            Message::Challenge(challenge) => {
+                encode_payload(CHALLENGE_WIRE_ID, challenge, dst);
+            }
+            // We are the client, and we are responding to the challenge
+            Message::Response(response) => {
+                self.state = State::Ack;
+                encode_payload(CHALLENGE_WIRE_ID, response, dst);
             }
             // We are the server, and we are sending an ACK to the client
             Message::Ack => {
@@ -143,3 +265,58 @@ impl Encoder<Message> for Codec {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenge_handshake() {
+        let mut client = Codec::new_client();
+        let mut server = Codec::new_server();
+        let mut buf = BytesMut::new();
+
+        client.encode(Message::Hello(Bytes::from("client1")), &mut buf).unwrap();
+        let Some(Message::Hello(id)) = server.decode(&mut buf).unwrap() else {
+            panic!("expected hello");
+        };
+        assert_eq!(id, "client1");
+
+        let challenge = new_challenge();
+        server.encode(Message::Challenge(challenge.clone()), &mut buf).unwrap();
+        let Some(Message::Challenge(received)) = client.decode(&mut buf).unwrap() else {
+            panic!("expected challenge");
+        };
+        assert_eq!(received, challenge);
+
+        let response = sign_challenge(b"secret", &id, &received);
+        client.encode(Message::Response(response), &mut buf).unwrap();
+        let Some(Message::Response(response)) = server.decode(&mut buf).unwrap() else {
+            panic!("expected response");
+        };
+
+        assert!(verify_challenge(b"secret", &id, &challenge, &response));
+        assert!(!verify_challenge(b"other", &id, &challenge, &response));
+        assert!(!verify_challenge(b"secret", b"client2", &challenge, &response));
+
+        server.encode(Message::Ack, &mut buf).unwrap();
+        assert!(matches!(client.decode(&mut buf).unwrap(), Some(Message::Ack)));
+    }
+
+    #[test]
+    fn partial_auth_frame() {
+        let mut client = Codec::new_client();
+        let mut server = Codec::new_server();
+        let mut buf = BytesMut::new();
+
+        client.encode(Message::Auth(Bytes::from("token")), &mut buf).unwrap();
+        let mut partial = buf.split_to(7);
+        assert!(server.decode(&mut partial).unwrap().is_none());
+
+        partial.unsplit(buf);
+        let Some(Message::Auth(token)) = server.decode(&mut partial).unwrap() else {
+            panic!("expected auth");
+        };
+        assert_eq!(token, "token");
+    }
+}
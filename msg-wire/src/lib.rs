@@ -3,7 +3,10 @@
 #![cfg_attr(not(test), warn(unused_crate_dependencies))]
 
 pub mod auth;
+pub mod payload;
 pub mod pubsub;
+pub mod pushpull;
 pub mod reqrep;
+pub mod routerdealer;
 
 pub mod compression;
@@ -0,0 +1,248 @@
+use core::fmt;
+
+use bytes::{Buf, BufMut, Bytes};
+use thiserror::Error;
+use tokio_util::codec::{Decoder, Encoder};
+
+use msg_common::unix_micros;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error: {0:?}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid wire ID: {0}")]
+    WireId(u8),
+}
+
+#[derive(Clone)]
+pub struct Message {
+    header: Header,
+    /// The message payload.
+    payload: Bytes,
+}
+
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut dbg = f.debug_struct("Message");
+        dbg.field("timestamp", &self.timestamp());
+        dbg.field("compression_type", &self.header.compression_type);
+        dbg.field("size", &self.size());
+        dbg.finish()
+    }
+}
+
+impl Message {
+    /// Creates a new message with the given payload. The timestamp is set to the current UNIX
+    /// timestamp in microseconds.
+    #[inline]
+    pub fn new(compression_type: u8, payload: Bytes) -> Self {
+        Self {
+            header: Header {
+                compression_type,
+                timestamp: unix_micros(),
+                size: payload.len() as u32,
+            },
+            payload,
+        }
+    }
+
+    #[inline]
+    pub fn payload_size(&self) -> u32 {
+        self.header.size
+    }
+
+    #[inline]
+    pub fn timestamp(&self) -> u64 {
+        self.header.timestamp
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.header.len() + self.payload_size() as usize
+    }
+
+    #[inline]
+    pub fn compression_type(&self) -> u8 {
+        self.header.compression_type
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    #[inline]
+    pub fn into_payload(self) -> Bytes {
+        self.payload
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    /// Compression type used for the message payload.
+    pub(crate) compression_type: u8,
+    /// The UNIX timestamp in microseconds.
+    pub(crate) timestamp: u64,
+    /// The size of the message. Max 4GiB.
+    pub(crate) size: u32,
+}
+
+impl Header {
+    /// Returns the length of the header in bytes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        1 + // compression type
+        8 + // timestamp
+        4 // size
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Default)]
+enum State {
+    #[default]
+    Header,
+    Payload(Header),
+}
+
+/// A codec for messages that only consist of a payload, which is shared by the socket types that
+/// don't need any other fields. Each of them has its own ID on the wire, so that sockets of
+/// different types can't be connected to each other.
+#[derive(Default)]
+pub struct Codec<const WIRE_ID: u8> {
+    /// The current state of the decoder.
+    state: State,
+}
+
+impl<const WIRE_ID: u8> Codec<WIRE_ID> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<const WIRE_ID: u8> Decoder for Codec<WIRE_ID> {
+    type Item = Message;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        loop {
+            match self.state {
+                State::Header => {
+                    if src.is_empty() {
+                        return Ok(None);
+                    }
+
+                    // Wire ID check (without advancing the cursor)
+                    let wire_id = u8::from_be_bytes([src[0]]);
+                    if wire_id != WIRE_ID {
+                        return Err(Error::WireId(wire_id));
+                    }
+
+                    // Wire ID (u8), compression type (u8), timestamp (u64), size (u32)
+                    if src.len() < 1 + 1 + 8 + 4 {
+                        return Ok(None);
+                    }
+
+                    // Only advance when we know we have enough bytes
+                    src.advance(1);
+
+                    let header = Header {
+                        compression_type: src.get_u8(),
+                        timestamp: src.get_u64(),
+                        size: src.get_u32(),
+                    };
+
+                    self.state = State::Payload(header);
+                }
+                State::Payload(header) => {
+                    if src.len() < header.size as usize {
+                        return Ok(None);
+                    }
+
+                    let payload = src.split_to(header.size as usize);
+                    let message = Message { header, payload: payload.freeze() };
+
+                    self.state = State::Header;
+                    return Ok(Some(message));
+                }
+            }
+        }
+    }
+}
+
+impl<const WIRE_ID: u8> Encoder<Message> for Codec<WIRE_ID> {
+    type Error = Error;
+
+    fn encode(&mut self, item: Message, dst: &mut bytes::BytesMut) -> Result<(), Self::Error> {
+        dst.reserve(1 + item.header.len() + item.payload_size() as usize);
+
+        dst.put_u8(WIRE_ID);
+        dst.put_u8(item.header.compression_type);
+        dst.put_u64(item.header.timestamp);
+        dst.put_u32(item.header.size);
+        dst.put(item.payload);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::{pushpull, routerdealer};
+
+    #[test]
+    fn payload_roundtrip() {
+        let mut codec = pushpull::Codec::new();
+        let mut buf = BytesMut::new();
+
+        let msg = Message::new(1, Bytes::from("hello"));
+        codec.encode(msg.clone(), &mut buf).unwrap();
+        codec.encode(Message::new(0, Bytes::new()), &mut buf).unwrap();
+        assert_eq!(buf.len(), 1 + msg.size() + 1 + msg.header.len());
+
+        let decoded = codec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(decoded.compression_type(), 1);
+        assert_eq!(decoded.timestamp(), msg.timestamp());
+        assert_eq!(decoded.payload(), &Bytes::from("hello"));
+
+        let empty = codec.decode(&mut buf).unwrap().unwrap();
+        assert!(empty.payload().is_empty());
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn partial_payload_frame() {
+        let mut codec = routerdealer::Codec::new();
+        let mut encoded = BytesMut::new();
+        codec.encode(Message::new(0, Bytes::from("partial frame")), &mut encoded).unwrap();
+
+        // Feed the frame one byte at a time
+        let mut buf = BytesMut::new();
+        for (i, byte) in encoded.iter().enumerate() {
+            buf.extend_from_slice(&[*byte]);
+            let decoded = codec.decode(&mut buf).unwrap();
+
+            if i < encoded.len() - 1 {
+                assert!(decoded.is_none());
+            } else {
+                assert_eq!(decoded.unwrap().payload(), &Bytes::from("partial frame"));
+            }
+        }
+    }
+
+    #[test]
+    fn payload_wire_id_mismatch() {
+        let mut buf = BytesMut::new();
+        pushpull::Codec::new().encode(Message::new(0, Bytes::from("push")), &mut buf).unwrap();
+
+        let err = routerdealer::Codec::new().decode(&mut buf).unwrap_err();
+        assert!(matches!(err, Error::WireId(0x04)));
+    }
+}
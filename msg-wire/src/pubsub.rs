@@ -1,4 +1,5 @@
 use core::fmt;
+use std::collections::VecDeque;
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use thiserror::Error;
@@ -9,12 +10,25 @@ use msg_common::unix_micros;
 /// The ID of the pub/sub codec on the wire.
 const WIRE_ID: u8 = 0x03;
 
+/// The ID of pub/sub datagram fragments on the wire.
+const DATAGRAM_WIRE_ID: u8 = 0x07;
+
+/// The size of the header of a datagram fragment: the wire ID, the sequence number of the
+/// message, the index of the fragment and the number of fragments.
+pub const FRAGMENT_HEADER_LEN: usize = 1 + 4 + 2 + 2;
+
+/// The maximum number of messages reassembled at the same time. When a fragment of another
+/// message arrives, the oldest incomplete message is dropped.
+const MAX_PARTIAL_MESSAGES: usize = 64;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("IO error: {0:?}")]
     Io(#[from] std::io::Error),
     #[error("Invalid wire ID: {0}")]
     WireId(u8),
+    #[error("Invalid datagram")]
+    InvalidDatagram,
 }
 
 #[derive(Clone)]
@@ -28,6 +42,7 @@ impl fmt::Debug for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut dbg = f.debug_struct("Message");
         dbg.field("seq", &self.seq());
+        dbg.field("topic_seq", &self.topic_seq());
         dbg.field("topic", &self.topic());
         dbg.field("timestamp", &self.timestamp());
         dbg.field("compression_type", &self.header.compression_type);
@@ -52,6 +67,7 @@ impl Message {
                 topic,
                 timestamp: unix_micros(),
                 seq,
+                topic_seq: 0,
                 size: payload.len() as u32,
             },
             payload,
@@ -76,11 +92,51 @@ impl Message {
         Self::new(0, prefix.freeze(), Bytes::new(), 0)
     }
 
+    /// Creates a new datagrams message, which tells the publisher that the subscriber can receive
+    /// messages as unreliable datagrams. The topic is `MSG.DATAGRAMS`.
+    #[inline]
+    pub fn new_datagrams() -> Self {
+        Self::new(0, Bytes::from_static(b"MSG.DATAGRAMS"), Bytes::new(), 0)
+    }
+
+    /// Creates a new replay message, which asks the publisher to resend all retained messages
+    /// after the given sequence number that match the current subscriptions. The topic is
+    /// `MSG.REPLAY`.
+    #[inline]
+    pub fn new_replay(seq: u32) -> Self {
+        Self::new(seq, Bytes::from_static(b"MSG.REPLAY"), Bytes::new(), 0)
+    }
+
+    /// Creates a new catch-up message for the given topic, which subscribes to the topic and asks
+    /// the publisher to first send the messages in its durable log, starting at the first
+    /// message with a sequence number of at least `seq` and a timestamp of at least `timestamp`.
+    /// The topic is prefixed with `MSG.CATCHUP.`.
+    #[inline]
+    pub fn new_catch_up(topic: Bytes, seq: u32, timestamp: u64) -> Self {
+        let mut prefix = BytesMut::from("MSG.CATCHUP.");
+        prefix.put(topic);
+        let mut msg = Self::new(seq, prefix.freeze(), Bytes::new(), 0);
+        msg.header.timestamp = timestamp;
+        msg
+    }
+
+    /// Sets the sequence number of the message within its topic.
+    #[inline]
+    pub fn with_topic_seq(mut self, topic_seq: u32) -> Self {
+        self.header.topic_seq = topic_seq;
+        self
+    }
+
     #[inline]
     pub fn seq(&self) -> u32 {
         self.header.seq
     }
 
+    #[inline]
+    pub fn topic_seq(&self) -> u32 {
+        self.header.topic_seq
+    }
+
     #[inline]
     pub fn payload_size(&self) -> u32 {
         self.header.size
@@ -120,6 +176,178 @@ impl Message {
     pub fn topic(&self) -> &Bytes {
         &self.header.topic
     }
+
+    /// Encodes the message into a [`Frame`], which can be written to many connections without
+    /// encoding it again.
+    #[inline]
+    pub fn encode(self) -> Frame {
+        let mut header = BytesMut::with_capacity(1 + self.header.len());
+        self.header.put(&mut header);
+
+        Frame { header: header.freeze(), payload: self.payload }
+    }
+}
+
+/// A message encoded for the wire, made of the encoded header (including the wire ID) and the
+/// payload. Both parts are reference-counted, so cloning a frame is cheap.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    header: Bytes,
+    payload: Bytes,
+}
+
+impl Frame {
+    #[inline]
+    pub fn header(&self) -> &Bytes {
+        &self.header
+    }
+
+    #[inline]
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    /// Returns the header and payload of the frame.
+    #[inline]
+    pub fn into_parts(self) -> (Bytes, Bytes) {
+        (self.header, self.payload)
+    }
+
+    /// Returns the size of the frame in bytes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.header.len() + self.payload.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Splits the frame into datagrams of at most `max_size` bytes, each prefixed with a fragment
+    /// header, which can be reassembled with a [`Reassembler`]. The sequence number identifies
+    /// the message the fragments belong to. Returns `None` if `max_size` doesn't leave room for
+    /// any data, or if the frame would need more than 65535 fragments.
+    pub fn fragment(&self, seq: u32, max_size: usize) -> Option<Vec<Bytes>> {
+        let chunk_size = max_size.checked_sub(FRAGMENT_HEADER_LEN).filter(|size| *size > 0)?;
+        let count = u16::try_from((self.len() + chunk_size - 1) / chunk_size).ok()?;
+
+        let mut data = BytesMut::with_capacity(self.len());
+        data.put_slice(&self.header);
+        data.put_slice(&self.payload);
+
+        let fragments = data
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut fragment = BytesMut::with_capacity(FRAGMENT_HEADER_LEN + chunk.len());
+                fragment.put_u8(DATAGRAM_WIRE_ID);
+                fragment.put_u32(seq);
+                fragment.put_u16(index as u16);
+                fragment.put_u16(count);
+                fragment.put_slice(chunk);
+                fragment.freeze()
+            })
+            .collect();
+
+        Some(fragments)
+    }
+}
+
+/// Reassembles the messages sent as datagram fragments (see [`Frame::fragment`]). Fragments may
+/// arrive in any order, and duplicates are ignored. Messages with lost fragments are dropped once
+/// enough newer messages are being reassembled.
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    /// The incomplete messages, oldest first.
+    partial: VecDeque<PartialMessage>,
+}
+
+#[derive(Debug)]
+struct PartialMessage {
+    /// The sequence number of the message.
+    seq: u32,
+    /// The fragments of the message, in order.
+    fragments: Vec<Option<Bytes>>,
+    /// The number of fragments that haven't been received yet.
+    missing: usize,
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a received datagram. Returns the message once all of its fragments are received.
+    pub fn push(&mut self, mut datagram: Bytes) -> Result<Option<Message>, Error> {
+        if datagram.len() < FRAGMENT_HEADER_LEN {
+            return Err(Error::InvalidDatagram);
+        }
+
+        let wire_id = datagram.get_u8();
+        if wire_id != DATAGRAM_WIRE_ID {
+            return Err(Error::WireId(wire_id));
+        }
+
+        let seq = datagram.get_u32();
+        let index = datagram.get_u16() as usize;
+        let count = datagram.get_u16() as usize;
+        if index >= count {
+            return Err(Error::InvalidDatagram);
+        }
+
+        // Fast path for messages that fit in a single datagram
+        if count == 1 {
+            return decode_datagram_message(BytesMut::from(&datagram[..])).map(Some);
+        }
+
+        let pos = match self.partial.iter().position(|partial| partial.seq == seq) {
+            Some(pos) => pos,
+            None => {
+                if self.partial.len() == MAX_PARTIAL_MESSAGES {
+                    self.partial.pop_front();
+                }
+
+                self.partial.push_back(PartialMessage {
+                    seq,
+                    fragments: vec![None; count],
+                    missing: count,
+                });
+
+                self.partial.len() - 1
+            }
+        };
+
+        let partial = &mut self.partial[pos];
+        if partial.fragments.len() != count {
+            return Err(Error::InvalidDatagram);
+        }
+
+        if partial.fragments[index].is_none() {
+            partial.fragments[index] = Some(datagram);
+            partial.missing -= 1;
+        }
+
+        if partial.missing > 0 {
+            return Ok(None);
+        }
+
+        let partial = self.partial.remove(pos).expect("Position is valid");
+        let mut data = BytesMut::new();
+        for fragment in partial.fragments.into_iter().flatten() {
+            data.extend_from_slice(&fragment);
+        }
+
+        decode_datagram_message(data).map(Some)
+    }
+}
+
+/// Decodes a message reassembled from datagrams, which must contain exactly one message.
+fn decode_datagram_message(mut data: BytesMut) -> Result<Message, Error> {
+    match Codec::new().decode(&mut data)? {
+        Some(msg) if data.is_empty() => Ok(msg),
+        _ => Err(Error::InvalidDatagram),
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -134,6 +362,8 @@ pub struct Header {
     pub(crate) timestamp: u64,
     /// The message sequence number.
     pub(crate) seq: u32,
+    /// The sequence number of the message within its topic.
+    pub(crate) topic_seq: u32,
     /// The size of the message. Max 4GiB.
     pub(crate) size: u32,
 }
@@ -145,6 +375,7 @@ impl Header {
         8 + // u64
         4 + // u32 
         4 + // u32 
+        4 + // u32 
         2 + // u16
         1 + // u8 
         self.topic_size as usize
@@ -153,6 +384,19 @@ impl Header {
     pub fn is_empty(&self) -> bool {
         self.topic_size == 0
     }
+
+    /// Writes the wire ID and the header to the buffer.
+    #[inline]
+    fn put(&self, dst: &mut BytesMut) {
+        dst.put_u8(WIRE_ID);
+        dst.put_u8(self.compression_type);
+        dst.put_u16(self.topic_size);
+        dst.put_slice(&self.topic);
+        dst.put_u64(self.timestamp);
+        dst.put_u32(self.seq);
+        dst.put_u32(self.topic_seq);
+        dst.put_u32(self.size);
+    }
 }
 
 #[derive(Default)]
@@ -215,8 +459,8 @@ impl Decoder for Codec {
                     cursor += 2;
 
                     // We don't have enough bytes to read the topic and the rest of the data
-                    // (timestamp u64, seq u32, size u32)
-                    if src.len() < cursor + topic_size as usize + 8 + 8 {
+                    // (timestamp u64, seq u32, topic seq u32, size u32)
+                    if src.len() < cursor + topic_size as usize + 8 + 12 {
                         return Ok(None);
                     }
 
@@ -232,6 +476,7 @@ impl Decoder for Codec {
                         topic,
                         timestamp: src.get_u64(),
                         seq: src.get_u32(),
+                        topic_seq: src.get_u32(),
                         size: src.get_u32(),
                     };
 
@@ -262,15 +507,90 @@ impl Encoder<Message> for Codec {
         // Reserve enough space for the wire ID, the header, and the payload
         dst.reserve(1 + item.header.len() + item.payload_size() as usize);
 
-        dst.put_u8(WIRE_ID);
-        dst.put_u8(item.header.compression_type);
-        dst.put_u16(item.header.topic_size);
-        dst.put(item.header.topic);
-        dst.put_u64(item.header.timestamp);
-        dst.put_u32(item.header.seq);
-        dst.put_u32(item.header.size);
+        item.header.put(dst);
         dst.put(item.payload);
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_matches_codec() {
+        let msg =
+            Message::new(7, Bytes::from("foo.bar"), Bytes::from("hello"), 0).with_topic_seq(3);
+
+        let mut encoded = BytesMut::new();
+        Codec::new().encode(msg.clone(), &mut encoded).unwrap();
+
+        let frame = msg.encode();
+        assert_eq!(frame.len(), encoded.len());
+        assert_eq!(frame.header()[..], encoded[..frame.header().len()]);
+        assert_eq!(frame.payload()[..], encoded[frame.header().len()..]);
+
+        let decoded = Codec::new().decode(&mut encoded).unwrap().unwrap();
+        assert_eq!(decoded.seq(), 7);
+        assert_eq!(decoded.topic_seq(), 3);
+        assert_eq!(decoded.payload(), &Bytes::from("hello"));
+    }
+
+    #[test]
+    fn fragment_and_reassemble() {
+        let payload: Bytes = (0..5000).map(|i| i as u8).collect::<Vec<_>>().into();
+        let frame = Message::new(42, Bytes::from("foo.bar"), payload.clone(), 0)
+            .with_topic_seq(3)
+            .encode();
+
+        let mut fragments = frame.fragment(42, 1200).unwrap();
+        assert_eq!(fragments.len(), 5);
+        assert!(fragments.iter().all(|fragment| fragment.len() <= 1200));
+
+        // Out of order, with a duplicate
+        fragments.reverse();
+        fragments.insert(1, fragments[0].clone());
+
+        let mut reassembler = Reassembler::new();
+        let (last, rest) = fragments.split_last().unwrap();
+        for fragment in rest {
+            assert!(reassembler.push(fragment.clone()).unwrap().is_none());
+        }
+
+        let msg = reassembler.push(last.clone()).unwrap().unwrap();
+        assert_eq!(msg.seq(), 42);
+        assert_eq!(msg.topic_seq(), 3);
+        assert_eq!(msg.payload(), &payload);
+
+        // Single fragment
+        let frame = Message::new(43, Bytes::from("foo"), Bytes::from("hello"), 0).encode();
+        let fragments = frame.fragment(43, 1200).unwrap();
+        assert_eq!(fragments.len(), 1);
+        let msg = reassembler.push(fragments[0].clone()).unwrap().unwrap();
+        assert_eq!(msg.payload(), &Bytes::from("hello"));
+
+        assert!(frame.fragment(43, FRAGMENT_HEADER_LEN).is_none());
+    }
+
+    #[test]
+    fn reassembler_drops_incomplete_messages() {
+        let mut reassembler = Reassembler::new();
+
+        let frame = Message::new(0, Bytes::from("foo"), Bytes::from(vec![0; 100]), 0).encode();
+        let first = frame.fragment(0, 64).unwrap();
+        assert!(reassembler.push(first[0].clone()).unwrap().is_none());
+
+        // Newer messages push out the incomplete one
+        for seq in 1..=MAX_PARTIAL_MESSAGES as u32 {
+            let fragments = frame.fragment(seq, 64).unwrap();
+            assert!(reassembler.push(fragments[0].clone()).unwrap().is_none());
+        }
+
+        for fragment in &first[1..] {
+            assert!(reassembler.push(fragment.clone()).unwrap().is_none());
+        }
+
+        assert!(reassembler.push(Bytes::from_static(b"foo")).is_err());
+    }
+}
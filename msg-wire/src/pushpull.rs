@@ -0,0 +1,7 @@
+pub use crate::payload::{Error, Message};
+
+/// The ID of the push/pull codec on the wire.
+const WIRE_ID: u8 = 0x04;
+
+/// The codec of push/pull messages.
+pub type Codec = crate::payload::Codec<WIRE_ID>;
@@ -1,16 +1,64 @@
-use bytes::{Buf, BufMut, Bytes};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use thiserror::Error;
 use tokio_util::codec::{Decoder, Encoder};
 
 /// The ID of the rep/req codec on the wire.
 const WIRE_ID: u8 = 0x02;
 
+/// Error code sent by a reply socket when a request could not be decoded (e.g. the payload could
+/// not be decompressed). Error codes below 100 are reserved for the protocol.
+pub const ERR_DECODE: u16 = 1;
+
+/// Error code sent by a reply socket when the service handling a request returned an error.
+pub const ERR_SERVICE: u16 = 2;
+
+/// Error code sent by a reply socket when the client is not authorized to send a request.
+pub const ERR_UNAUTHORIZED: u16 = 3;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("IO error: {0:?}")]
     Io(#[from] std::io::Error),
     #[error("Invalid wire ID: {0}")]
     WireId(u8),
+    #[error("Invalid message kind: {0}")]
+    Kind(u8),
+}
+
+/// The kind of a reqrep message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Kind {
+    /// A request or a response, carrying a payload.
+    Data = 0,
+    /// Cancels the request with the same ID. Has no payload.
+    Cancel = 1,
+    /// An error response. The payload contains the error code, followed by the error message.
+    /// Also ends a streaming response.
+    Error = 2,
+    /// A chunk of a streaming response. More chunks will follow.
+    Chunk = 3,
+    /// The end of a streaming response. May contain a final chunk.
+    End = 4,
+    /// Allows the reply socket to send more chunks of a streaming response. The payload contains
+    /// the number of chunks.
+    Credit = 5,
+}
+
+impl TryFrom<u8> for Kind {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Error> {
+        match value {
+            0 => Ok(Kind::Data),
+            1 => Ok(Kind::Cancel),
+            2 => Ok(Kind::Error),
+            3 => Ok(Kind::Chunk),
+            4 => Ok(Kind::End),
+            5 => Ok(Kind::Credit),
+            _ => Err(Error::Kind(value)),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -23,7 +71,87 @@ pub struct Message {
 impl Message {
     #[inline]
     pub fn new(id: u32, compression_type: u8, payload: Bytes) -> Self {
-        Self { header: Header { id, compression_type, size: payload.len() as u32 }, payload }
+        Self {
+            header: Header {
+                kind: Kind::Data,
+                compression_type,
+                id,
+                size: payload.len() as u32,
+                timeout_ms: 0,
+            },
+            payload,
+        }
+    }
+
+    /// Creates a message that cancels the request with the given ID.
+    #[inline]
+    pub fn cancel(id: u32) -> Self {
+        Self {
+            header: Header { kind: Kind::Cancel, compression_type: 0, id, size: 0, timeout_ms: 0 },
+            payload: Bytes::new(),
+        }
+    }
+
+    /// Creates an error response for the request with the given ID.
+    #[inline]
+    pub fn error(id: u32, code: u16, message: &str) -> Self {
+        let mut payload = BytesMut::with_capacity(2 + message.len());
+        payload.put_u16(code);
+        payload.put_slice(message.as_bytes());
+
+        Self::new(id, 0, payload.freeze()).with_kind(Kind::Error)
+    }
+
+    /// Creates a message that allows the reply socket to send `credit` more chunks of the
+    /// streaming response to the request with the given ID.
+    #[inline]
+    pub fn credit(id: u32, credit: u32) -> Self {
+        let mut payload = BytesMut::with_capacity(4);
+        payload.put_u32(credit);
+
+        Self::new(id, 0, payload.freeze()).with_kind(Kind::Credit)
+    }
+
+    /// Decodes the number of chunks of a credit message. Returns `None` if this is not a valid
+    /// credit message.
+    pub fn to_credit(&self) -> Option<u32> {
+        if self.header.kind != Kind::Credit || self.payload.len() < 4 {
+            return None;
+        }
+
+        Some(self.payload.clone().get_u32())
+    }
+
+    /// Sets the kind of this message.
+    #[inline]
+    pub fn with_kind(mut self, kind: Kind) -> Self {
+        self.header.kind = kind;
+        self
+    }
+
+    /// Decodes the error code and message of an error response. Returns `None` if this is not a
+    /// valid error response.
+    pub fn into_error(self) -> Option<(u16, String)> {
+        if self.header.kind != Kind::Error || self.payload.len() < 2 {
+            return None;
+        }
+
+        let mut payload = self.payload;
+        let code = payload.get_u16();
+        Some((code, String::from_utf8_lossy(&payload).into_owned()))
+    }
+
+    /// Sets the remaining time in milliseconds the sender is willing to wait for a response.
+    /// 0 means no deadline.
+    #[inline]
+    pub fn with_timeout_ms(mut self, timeout_ms: u32) -> Self {
+        self.header.timeout_ms = timeout_ms;
+        self
+    }
+
+    #[inline]
+    pub fn kind(&self) -> Kind {
+        self.header.kind
     }
 
     #[inline]
@@ -59,21 +187,27 @@ impl Message {
 
 #[derive(Debug, Clone, Copy)]
 pub struct Header {
+    /// The message kind.
+    pub(crate) kind: Kind,
     /// The compression type.
     pub(crate) compression_type: u8,
     /// The message ID.
     pub(crate) id: u32,
     /// The size of the message. Max 4GiB.
     pub(crate) size: u32,
+    /// The remaining time in milliseconds until the request deadline. 0 means no deadline.
+    pub(crate) timeout_ms: u32,
 }
 
 impl Header {
     /// Returns the length of the header in bytes.
     #[inline]
     pub fn len(&self) -> usize {
+        1 + // kind
+        1 + // compression type
         4 + // id
         4 + // size
-        1 // compression type
+        4 // timeout
     }
 
     #[inline]
@@ -85,6 +219,12 @@ impl Header {
     pub fn compression_type(&self) -> u8 {
         self.compression_type
     }
+
+    /// Returns the remaining time in milliseconds until the request deadline, if any.
+    #[inline]
+    pub fn timeout_ms(&self) -> Option<u32> {
+        (self.timeout_ms > 0).then_some(self.timeout_ms)
+    }
 }
 
 #[derive(Default)]
@@ -127,16 +267,19 @@ impl Decoder for Codec {
                         return Err(Error::WireId(wire_id));
                     }
 
-                    // The src is too small to read the compression type
-                    if src.len() < cursor + 1 {
+                    // The src is too small to read the kind and compression type
+                    if src.len() < cursor + 2 {
                         return Ok(None);
                     }
 
+                    let kind = Kind::try_from(src[cursor])?;
+                    cursor += 1;
+
                     let compression_type = u8::from_be_bytes([src[cursor]]);
 
                     cursor += 1;
 
-                    if src.len() < cursor + 8 {
+                    if src.len() < cursor + 12 {
                         return Ok(None);
                     }
 
@@ -144,8 +287,13 @@ impl Decoder for Codec {
                     src.advance(cursor);
 
                     // Construct the header
-                    let header =
-                        Header { compression_type, id: src.get_u32(), size: src.get_u32() };
+                    let header = Header {
+                        kind,
+                        compression_type,
+                        id: src.get_u32(),
+                        size: src.get_u32(),
+                        timeout_ms: src.get_u32(),
+                    };
 
                     self.state = State::Payload(header);
                 }
@@ -172,9 +320,11 @@ impl Encoder<Message> for Codec {
         dst.reserve(1 + item.header.len() + item.payload_size() as usize);
 
         dst.put_u8(WIRE_ID);
+        dst.put_u8(item.header.kind as u8);
         dst.put_u8(item.header.compression_type);
         dst.put_u32(item.header.id);
         dst.put_u32(item.header.size);
+        dst.put_u32(item.header.timeout_ms);
         dst.put(item.payload);
 
         Ok(())
@@ -0,0 +1,7 @@
+pub use crate::payload::{Error, Message};
+
+/// The ID of the router/dealer codec on the wire.
+const WIRE_ID: u8 = 0x05;
+
+/// The codec of router/dealer messages.
+pub type Codec = crate::payload::Codec<WIRE_ID>;
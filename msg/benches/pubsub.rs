@@ -14,7 +14,11 @@ use std::{
 use tokio::runtime::Runtime;
 
 use msg_socket::{PubOptions, PubSocket, SubOptions, SubSocket};
-use msg_transport::{quic::Quic, tcp::Tcp, Transport};
+use msg_transport::{
+    quic::{self, Quic},
+    tcp::Tcp,
+    Transport,
+};
 
 const N_REQS: usize = 10_000;
 const MSG_SIZE: usize = 512;
@@ -227,7 +231,7 @@ fn pubsub_single_thread_quic(c: &mut Criterion) {
     let buffer_size = 1024 * 64;
 
     let publisher = PubSocket::with_options(
-        Quic::default(),
+        Quic::new(quic::Config::insecure()),
         PubOptions::default()
             .flush_interval(Duration::from_micros(100))
             .backpressure_boundary(buffer_size)
@@ -235,7 +239,7 @@ fn pubsub_single_thread_quic(c: &mut Criterion) {
     );
 
     let subscriber = SubSocket::with_options(
-        Quic::default(),
+        Quic::new(quic::Config::insecure()),
         SubOptions::default().read_buffer_size(buffer_size).ingress_buffer_size(N_REQS * 2),
     );
 
@@ -267,7 +271,7 @@ fn pubsub_multi_thread_quic(c: &mut Criterion) {
     let buffer_size = 1024 * 64;
 
     let publisher = PubSocket::with_options(
-        Quic::default(),
+        Quic::new(quic::Config::insecure()),
         PubOptions::default()
             .flush_interval(Duration::from_micros(100))
             .backpressure_boundary(buffer_size)
@@ -275,7 +279,7 @@ fn pubsub_multi_thread_quic(c: &mut Criterion) {
     );
 
     let subscriber = SubSocket::with_options(
-        Quic::default(),
+        Quic::new(quic::Config::insecure()),
         SubOptions::default().read_buffer_size(buffer_size).ingress_buffer_size(N_REQS * 2),
     );
 
@@ -0,0 +1,33 @@
+use bytes::Bytes;
+use tokio_stream::StreamExt;
+
+use msg::{tcp::Tcp, PullSocket, PushOptions, PushSocket};
+
+#[tokio::main]
+async fn main() {
+    // Initialize the push socket (server side) with a transport.
+    // Each connected pull socket can have at most 128 messages queued.
+    let mut push = PushSocket::with_options(Tcp::default(), PushOptions::default().peer_hwm(128));
+    push.bind("0.0.0.0:4444").await.unwrap();
+
+    // Initialize 2 pull sockets (workers) with a transport
+    let mut worker1 = PullSocket::new(Tcp::default());
+    worker1.connect("0.0.0.0:4444").await.unwrap();
+
+    let mut worker2 = PullSocket::new(Tcp::default());
+    worker2.connect("0.0.0.0:4444").await.unwrap();
+
+    // Messages are distributed round-robin over the connected workers.
+    for i in 0..4 {
+        push.push(Bytes::from(format!("job-{i}"))).await.unwrap();
+    }
+
+    for _ in 0..2 {
+        // PullSocket implements `Stream`
+        let job = worker1.next().await.unwrap();
+        println!("Worker 1 received: {:?}", job.payload());
+
+        let job = worker2.next().await.unwrap();
+        println!("Worker 2 received: {:?}", job.payload());
+    }
+}
@@ -1,6 +1,9 @@
 use bytes::Bytes;
 use futures::StreamExt;
-use msg_transport::{quic::Quic, Transport};
+use msg_transport::{
+    quic::{self, Quic},
+    Transport,
+};
 use std::time::{Duration, Instant};
 use tracing::info;
 
@@ -47,7 +50,7 @@ async fn run_tcp() {
 async fn run_quic() {
     // Configure the publisher socket with options
     let mut pub_socket = PubSocket::with_options(
-        Quic::default(),
+        Quic::new(quic::Config::insecure()),
         PubOptions::default()
             .backpressure_boundary(8192)
             .session_buffer_size(1024)
@@ -57,7 +60,7 @@ async fn run_quic() {
     // Configure the subscribers with options
     let mut sub1 = SubSocket::with_options(
         // TCP transport with blocking connect, usually connection happens in the background.
-        Quic::default(),
+        Quic::new(quic::Config::insecure()),
         SubOptions::default().ingress_buffer_size(1024),
     );
 
@@ -0,0 +1,61 @@
+use bytes::Bytes;
+use tokio_stream::StreamExt;
+
+use msg::{tcp::Tcp, Authenticator, DealerOptions, DealerSocket, RouterSocket};
+
+#[derive(Default)]
+struct Auth;
+
+impl Authenticator for Auth {
+    fn authenticate(&self, id: &Bytes) -> bool {
+        println!("Auth request from: {:?}", id);
+        // Custom authentication logic
+        true
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // Initialize the router socket (server side) with a transport and an authenticator.
+    // The id every dealer authenticates with is used as its identity.
+    let mut router = RouterSocket::new(Tcp::default()).with_auth(Auth);
+    router.bind("0.0.0.0:4444").await.unwrap();
+
+    // Initialize 2 dealer sockets (clients) with a transport and their identity
+    let mut dealer1 = DealerSocket::with_options(
+        Tcp::default(),
+        DealerOptions::default().auth_token(Bytes::from("client1")),
+    );
+    dealer1.connect("0.0.0.0:4444").await.unwrap();
+
+    let mut dealer2 = DealerSocket::with_options(
+        Tcp::default(),
+        DealerOptions::default().auth_token(Bytes::from("client2")),
+    );
+    dealer2.connect("0.0.0.0:4444").await.unwrap();
+
+    tokio::spawn(async move {
+        // RouterSocket implements `Stream`
+        while let Some(msg) = router.next().await {
+            println!("Router received {:?} from {:?}", msg.payload(), msg.peer_id());
+
+            // Send 2 messages back to the peer that sent this one. There is no lockstep, so the
+            // router can send any number of messages to any peer at any time.
+            for i in 0..2 {
+                let reply = Bytes::from(format!("reply-{i}"));
+                router.send(msg.peer_id().clone(), reply).await.unwrap();
+            }
+        }
+    });
+
+    dealer1.send(Bytes::from("hello from 1")).await.unwrap();
+    dealer2.send(Bytes::from("hello from 2")).await.unwrap();
+
+    for _ in 0..2 {
+        let msg = dealer1.next().await.unwrap();
+        println!("Dealer 1 received: {:?}", msg.payload());
+
+        let msg = dealer2.next().await.unwrap();
+        println!("Dealer 2 received: {:?}", msg.payload());
+    }
+}